@@ -0,0 +1,5 @@
+fn main() {
+    // Release assets are matched to the target triple the binary was built for
+    println!("cargo:rustc-env=TARGET={}", std::env::var("TARGET").unwrap());
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use ethers::abi::{Abi, Event, ParamType, RawLog, Token};
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::humanize::{humanize, unit_for};
+
+/// Event name and named arguments decoded from a log
+#[derive(Debug, Clone)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub params: Map<String, Value>,
+    /// Readable renderings of arguments that look like amounts or durations
+    pub humanized: Map<String, Value>,
+    /// Set when the log was only partly decoded, e.g. a string argument
+    /// that isn't valid UTF-8 and was decoded lossily
+    pub error: Option<DecodeError>,
+}
+
+/// Why a log matching an ABI event couldn't be decoded. The record keeps
+/// the raw `topics` and `data`, so nothing is lost.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DecodeError {
+    /// Signature of the ABI event the log was decoded as,
+    /// e.g. `Transfer(address,address,uint256)`
+    pub event: String,
+    pub reason: String,
+}
+
+/// Decodes logs using the event definitions of a contract ABI
+#[derive(Debug, Clone, Default)]
+pub struct AbiDecoder {
+    events: HashMap<H256, Event>,
+    /// Whether amounts and durations are guessed from argument names
+    guess_units: bool,
+}
+
+impl AbiDecoder {
+    pub fn from_abi(abi: &Abi) -> Self {
+        let events = abi
+            .events()
+            .filter(|event| !event.anonymous)
+            .map(|event| (event.signature(), event.clone()))
+            .collect();
+        Self {
+            events,
+            guess_units: true,
+        }
+    }
+
+    /// Don't humanize arguments based on their names, for ABIs whose
+    /// amounts aren't in the native token
+    pub fn without_unit_guesses(mut self) -> Self {
+        self.guess_units = false;
+        self
+    }
+
+    /// Load a JSON ABI file. Both a bare ABI array and compiler artifacts
+    /// with an `abi` field (Hardhat, Foundry) are accepted.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ABI file {}", path.display()))?;
+
+        let mut json: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid JSON in ABI file {}", path.display()))?;
+        if let Some(abi) = json.get_mut("abi") {
+            json = abi.take();
+        }
+        let abi: Abi = serde_json::from_value(json)
+            .with_context(|| format!("Invalid ABI in {}", path.display()))?;
+
+        Ok(Self::from_abi(&abi))
+    }
+
+    /// The topic hash and signature of every non-anonymous event in the ABI
+    pub fn signatures(&self) -> Vec<(H256, String)> {
+        self.events.iter().map(|(topic0, event)| (*topic0, signature(event))).collect()
+    }
+
+    /// Signature of the ABI event with topic hash `topic0`,
+    /// e.g. `Transfer(address,address,uint256)`
+    pub fn event_signature(&self, topic0: &H256) -> Option<String> {
+        self.events.get(topic0).map(signature)
+    }
+
+    /// Decode `log` if its topic0 matches an event in the ABI. Amounts of
+    /// the native token are humanized with `native_symbol`.
+    ///
+    /// Returns `None` for logs of events not in the ABI. Logs that match an
+    /// event but don't fit it (wrong number of indexed topics, truncated or
+    /// malformed data) give a [`DecodeError`]; decoding never panics.
+    pub fn decode(&self, log: &Log, native_symbol: &str) -> Option<Result<DecodedEvent, DecodeError>> {
+        let event = self.events.get(log.topics.first()?)?;
+        let fail = |reason: String| DecodeError {
+            event: signature(event),
+            reason,
+        };
+
+        let decoded = catch_unwind(AssertUnwindSafe(|| decode_event(event, log, native_symbol, self.guess_units)));
+        Some(match decoded {
+            Ok(Ok(decoded)) => Ok(decoded),
+            Ok(Err(e)) => Err(fail(e.to_string())),
+            Err(_) => Err(fail("decoder panicked".to_string())),
+        })
+    }
+}
+
+fn decode_event(event: &Event, log: &Log, native_symbol: &str, guess_units: bool) -> ethers::abi::Result<DecodedEvent> {
+    let parsed = event.parse_log(RawLog {
+        topics: log.topics.clone(),
+        data: log.data.to_vec(),
+    })?;
+
+    let mut params = Map::new();
+    let mut humanized = Map::new();
+    let mut lossy = false;
+    for (i, (param, input)) in parsed.params.into_iter().zip(&event.inputs).enumerate() {
+        let name = if param.name.is_empty() {
+            format!("param{}", i)
+        } else {
+            param.name
+        };
+        let unit = unit_for(&name, &input.kind).filter(|_| guess_units);
+        if let Some(text) = unit.and_then(|unit| humanize(unit, &param.value, native_symbol)) {
+            humanized.insert(name.clone(), Value::String(text));
+        }
+        lossy |= has_replacement_char(&param.value);
+        params.insert(name, token_to_json(&param.value));
+    }
+
+    // Strings are decoded lossily; check whether a replacement character
+    // came from invalid UTF-8 rather than from the string itself
+    let error = lossy
+        .then(|| invalid_utf8_params(event, log))
+        .filter(|names| !names.is_empty())
+        .map(|names| DecodeError {
+            event: signature(event),
+            reason: format!(
+                "invalid UTF-8 in string argument(s) {}; replaced with U+FFFD",
+                names.join(", ")
+            ),
+        });
+
+    Ok(DecodedEvent {
+        name: event.name.clone(),
+        params,
+        humanized,
+        error,
+    })
+}
+
+/// Signature of `event`, e.g. `Transfer(address,address,uint256)`
+fn signature(event: &Event) -> String {
+    let types: Vec<String> = event.inputs.iter().map(|input| input.kind.to_string()).collect();
+    format!("{}({})", event.name, types.join(","))
+}
+
+fn has_replacement_char(token: &Token) -> bool {
+    match token {
+        Token::String(value) => value.contains(char::REPLACEMENT_CHARACTER),
+        Token::FixedArray(tokens) | Token::Array(tokens) | Token::Tuple(tokens) => {
+            tokens.iter().any(has_replacement_char)
+        }
+        _ => false,
+    }
+}
+
+/// Names of the non-indexed arguments of `log` holding strings that aren't
+/// valid UTF-8, found by decoding its data again with strings as bytes
+fn invalid_utf8_params(event: &Event, log: &Log) -> Vec<String> {
+    let inputs: Vec<_> = event.inputs.iter().enumerate().filter(|(_, input)| !input.indexed).collect();
+    let kinds: Vec<ParamType> = inputs.iter().map(|(_, input)| strings_as_bytes(&input.kind)).collect();
+    let Ok(tokens) = ethers::abi::decode(&kinds, &log.data) else {
+        return Vec::new();
+    };
+
+    inputs
+        .iter()
+        .zip(&tokens)
+        .filter(|((_, input), token)| is_invalid_utf8(&input.kind, token))
+        .map(|((i, input), _)| {
+            if input.name.is_empty() {
+                format!("param{}", i)
+            } else {
+                input.name.clone()
+            }
+        })
+        .collect()
+}
+
+fn strings_as_bytes(kind: &ParamType) -> ParamType {
+    match kind {
+        ParamType::String => ParamType::Bytes,
+        ParamType::Array(inner) => ParamType::Array(Box::new(strings_as_bytes(inner))),
+        ParamType::FixedArray(inner, len) => ParamType::FixedArray(Box::new(strings_as_bytes(inner)), *len),
+        ParamType::Tuple(kinds) => ParamType::Tuple(kinds.iter().map(strings_as_bytes).collect()),
+        other => other.clone(),
+    }
+}
+
+/// Whether `token`, decoded as [`strings_as_bytes`]`(kind)`, holds a string
+/// that isn't valid UTF-8
+fn is_invalid_utf8(kind: &ParamType, token: &Token) -> bool {
+    match (kind, token) {
+        (ParamType::String, Token::Bytes(bytes)) => std::str::from_utf8(bytes).is_err(),
+        (ParamType::Array(kind) | ParamType::FixedArray(kind, _), Token::Array(tokens) | Token::FixedArray(tokens)) => {
+            tokens.iter().any(|token| is_invalid_utf8(kind, token))
+        }
+        (ParamType::Tuple(kinds), Token::Tuple(tokens)) => {
+            kinds.iter().zip(tokens).any(|(kind, token)| is_invalid_utf8(kind, token))
+        }
+        _ => false,
+    }
+}
+
+/// Convert an ABI token to JSON. Integers are rendered as decimal strings
+/// since most exceed the precision of JSON numbers.
+pub fn token_to_json(token: &Token) -> Value {
+    match token {
+        Token::Address(address) => Value::String(format!("{:?}", address)),
+        Token::FixedBytes(bytes) | Token::Bytes(bytes) => {
+            Value::String(format!("0x{}", hex::encode(bytes)))
+        }
+        Token::Int(value) => Value::String(I256::from_raw(*value).to_string()),
+        Token::Uint(value) => Value::String(value.to_string()),
+        Token::Bool(value) => Value::Bool(*value),
+        Token::String(value) => Value::String(value.clone()),
+        Token::FixedArray(tokens) | Token::Array(tokens) | Token::Tuple(tokens) => {
+            Value::Array(tokens.iter().map(token_to_json).collect())
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Anomaly flags: a cheap heuristic for spotting unusual activity.
+//!
+//! Every event's raw data size, and the gas used by its transaction, is
+//! compared with the recent events of the same type (chain, contract, and
+//! topic0). Values far from the median, measured by the modified z-score
+//! (median absolute deviation rather than standard deviation, so earlier
+//! outliers don't mask later ones), get the event an `anomaly` field.
+//!
+//! The distribution is learned from the events seen since the listener
+//! started; nothing is flagged until a type has `min_samples` events.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::config::AnomalyConfig;
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::provider::ProviderManager;
+
+/// Modified z-score above which a value is an outlier, as suggested by
+/// Iglewicz and Hoaglin
+pub const DEFAULT_THRESHOLD: f64 = 3.5;
+
+/// Recent events per type the distribution is taken from
+pub const DEFAULT_WINDOW: usize = 1000;
+
+/// Events of a type seen before any of them is flagged
+pub const DEFAULT_MIN_SAMPLES: usize = 50;
+
+/// Why an event was flagged: the measures that are outliers for its type
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Anomaly {
+    /// Size of the raw log data in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_size: Option<Outlier>,
+    /// Gas used by the transaction that emitted the log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<Outlier>,
+}
+
+/// A value far from those of the recent events of the same type
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Outlier {
+    pub value: u64,
+    /// Median over the recent events of the type
+    pub median: f64,
+    /// Modified z-score; `None` when every recent event had the same value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+}
+
+/// (chain ID, contract, topic0) of an event
+type EventType = (Option<u64>, String, Option<String>);
+
+#[derive(Default)]
+struct History {
+    data_size: VecDeque<u64>,
+    gas_used: VecDeque<u64>,
+}
+
+/// Flags events whose data size or gas usage is an outlier for their type
+pub struct AnomalyDetector {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    gas: bool,
+    threshold: f64,
+    window: usize,
+    min_samples: usize,
+    history: Mutex<HashMap<EventType, History>>,
+    /// Gas used by the last transaction looked up; logs of one transaction
+    /// arrive together
+    last_receipt: Mutex<Option<(String, u64)>>,
+}
+
+impl AnomalyDetector {
+    /// Gas usage is looked up through `providers`, by chain ID
+    pub fn new(config: &AnomalyConfig, providers: HashMap<Option<u64>, Arc<ProviderManager>>) -> Self {
+        let window = config.window.unwrap_or(DEFAULT_WINDOW).max(1);
+        Self {
+            providers,
+            gas: config.gas.unwrap_or(true),
+            threshold: config.threshold.unwrap_or(DEFAULT_THRESHOLD),
+            window,
+            min_samples: config.min_samples.unwrap_or(DEFAULT_MIN_SAMPLES).clamp(1, window),
+            history: Mutex::new(HashMap::new()),
+            last_receipt: Mutex::new(None),
+        }
+    }
+
+    /// Gas used by the transaction of `event`, `None` when its chain has no
+    /// provider or the node doesn't know the transaction
+    async fn gas_used(&self, event: &EventData) -> Result<Option<u64>> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(None);
+        };
+        if let Some((ref hash, gas)) = *lock(&self.last_receipt) {
+            if *hash == event.transaction_hash {
+                return Ok(Some(gas));
+            }
+        }
+
+        let hash: H256 = event.transaction_hash.parse()?;
+        let gas = provider
+            .get_transaction_receipt(hash)
+            .await?
+            .and_then(|receipt| receipt.gas_used)
+            .map(|gas| gas.low_u64());
+        if let Some(gas) = gas {
+            *lock(&self.last_receipt) = Some((event.transaction_hash.clone(), gas));
+        }
+        Ok(gas)
+    }
+
+    /// Score `value` against `history`, then add it to the window
+    fn observe(&self, history: &mut VecDeque<u64>, value: u64) -> Option<Outlier> {
+        let outlier = if history.len() >= self.min_samples {
+            outlier(history, value, self.threshold)
+        } else {
+            None
+        };
+        if history.len() == self.window {
+            history.pop_front();
+        }
+        history.push_back(value);
+        outlier
+    }
+}
+
+#[async_trait]
+impl Enricher for AnomalyDetector {
+    fn name(&self) -> &str {
+        "Anomaly"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        // Looked up first so nothing is recorded when the lookup runs out of time
+        let gas_used = if self.gas { self.gas_used(event).await } else { Ok(None) };
+
+        let key = (
+            event.chain_id,
+            event.contract_address.to_lowercase(),
+            event.topics.first().map(|topic| topic.to_lowercase()),
+        );
+        let data_size = (event.data.trim_start_matches("0x").len() / 2) as u64;
+
+        let anomaly = {
+            let mut histories = lock(&self.history);
+            let history = histories.entry(key).or_default();
+            Anomaly {
+                data_size: self.observe(&mut history.data_size, data_size),
+                gas_used: match gas_used {
+                    Ok(Some(gas)) => self.observe(&mut history.gas_used, gas),
+                    _ => None,
+                },
+            }
+        };
+        if anomaly.data_size.is_some() || anomaly.gas_used.is_some() {
+            event.anomaly = Some(anomaly);
+        }
+
+        gas_used.map(|_| ())
+    }
+}
+
+/// `value` as an [`Outlier`] if its modified z-score against `samples` is
+/// above `threshold`. When most samples are equal, so the median absolute
+/// deviation is zero, the mean absolute deviation is used instead.
+fn outlier(samples: &VecDeque<u64>, value: u64, threshold: f64) -> Option<Outlier> {
+    let mut sorted: Vec<f64> = samples.iter().map(|&v| v as f64).collect();
+    sorted.sort_by(f64::total_cmp);
+    let median = median_of(&sorted);
+
+    let mut deviations: Vec<f64> = sorted.iter().map(|v| (v - median).abs()).collect();
+    deviations.sort_by(f64::total_cmp);
+    let mad = median_of(&deviations);
+    let mean_deviation = deviations.iter().sum::<f64>() / deviations.len() as f64;
+
+    let distance = value as f64 - median;
+    let score = if mad > 0.0 {
+        Some(0.6745 * distance / mad)
+    } else if mean_deviation > 0.0 {
+        Some(distance / (1.253314 * mean_deviation))
+    } else {
+        None
+    };
+
+    let is_outlier = match score {
+        Some(score) => score.abs() > threshold,
+        None => distance != 0.0,
+    };
+    is_outlier.then(|| Outlier {
+        value,
+        median,
+        score: score.map(|score| (score * 100.0).round() / 100.0),
+    })
+}
+
+/// Median of a sorted, non-empty slice
+fn median_of(sorted: &[f64]) -> f64 {
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
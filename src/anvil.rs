@@ -0,0 +1,192 @@
+//! Dev mode (`--anvil`): a local [Anvil](https://book.getfoundry.sh/anvil/)
+//! chain with a test contract that emits ERC-20 `Transfer` events on
+//! demand, so the listener and its sinks can be tried, and tested end to
+//! end, without an RPC provider.
+//!
+//! Anvil comes with Foundry and is looked up on the `PATH`, or taken from
+//! `ANVIL_BIN`. The chain lives as long as the [`Anvil`] value.
+
+use anyhow::{Context, Result};
+use ethers::abi::Token;
+use ethers::types::{Address, H256, U256};
+use serde_json::{json, Value};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Signature of the events the test contract emits
+pub const TEST_EVENT: &str = "Transfer(address,address,uint256)";
+
+/// Creation code of the test contract. Every call emits
+/// `Transfer(msg.sender, to, value)` for calldata `(uint256 value, address to)`:
+///
+/// ```text
+/// constructor: PUSH1 0x31 DUP1 PUSH1 0x0b PUSH1 0 CODECOPY PUSH1 0 RETURN
+/// runtime:     PUSH1 0 CALLDATALOAD PUSH1 0 MSTORE      ; data = value
+///              PUSH1 0x20 CALLDATALOAD CALLER           ; topic2 = to, topic1 = sender
+///              PUSH32 <Transfer topic0> PUSH1 0x20 PUSH1 0 LOG3 STOP
+/// ```
+const TEST_CONTRACT: &str = concat!(
+    "603180600b6000396000f3",
+    "600035600052",
+    "60203533",
+    "7fddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef",
+    "60206000a300",
+);
+
+/// How long Anvil gets to start answering
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running Anvil chain with the test contract deployed. Anvil is stopped
+/// when dropped.
+pub struct Anvil {
+    process: Child,
+    rpc: Rpc,
+    /// JSON-RPC endpoint, e.g. `http://127.0.0.1:41234`
+    pub url: String,
+    pub chain_id: u64,
+    /// The test contract
+    pub contract: Address,
+    /// Unlocked account the test transactions are sent from
+    pub sender: Address,
+}
+
+#[derive(Clone)]
+struct Rpc {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Anvil {
+    /// Start Anvil on a free local port and deploy the test contract
+    pub async fn start() -> Result<Self> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let binary = std::env::var("ANVIL_BIN").unwrap_or_else(|_| "anvil".to_string());
+        let mut process = Command::new(&binary)
+            .args(["--host", "127.0.0.1", "--port", &port.to_string(), "--silent"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to start {} (install Foundry, or set ANVIL_BIN)", binary))?;
+        let url = format!("http://127.0.0.1:{}", port);
+        let rpc = Rpc {
+            client: reqwest::Client::new(),
+            url: url.clone(),
+        };
+
+        let started = std::time::Instant::now();
+        let chain_id = loop {
+            match rpc.request("eth_chainId", json!([])).await {
+                Ok(id) => break parse_u64(&id)?,
+                Err(_) if started.elapsed() < STARTUP_TIMEOUT => {
+                    if let Some(status) = process.try_wait()? {
+                        anyhow::bail!("Anvil exited with {}", status);
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(e) => {
+                    let _ = process.kill();
+                    return Err(e.context("Anvil didn't start answering"));
+                }
+            }
+        };
+        // From here on, dropping the value stops Anvil
+        let mut anvil = Self {
+            process,
+            rpc,
+            url,
+            chain_id,
+            contract: Address::zero(),
+            sender: Address::zero(),
+        };
+
+        let accounts = anvil.rpc.request("eth_accounts", json!([])).await?;
+        anvil.sender = accounts[0]
+            .as_str()
+            .and_then(|account| account.parse().ok())
+            .context("Anvil has no unlocked accounts")?;
+        let transaction = json!({ "from": anvil.sender, "data": format!("0x{}", TEST_CONTRACT) });
+        let receipt = anvil.rpc.transact(transaction).await?;
+        anvil.contract = receipt["contractAddress"]
+            .as_str()
+            .and_then(|address| address.parse().ok())
+            .context("The test contract wasn't deployed")?;
+        Ok(anvil)
+    }
+
+    /// Emit `Transfer(sender, to, value)` from the test contract and wait
+    /// for it to be mined; returns the transaction hash
+    pub async fn emit(&self, to: Address, value: U256) -> Result<H256> {
+        emit(&self.rpc, self.sender, self.contract, to, value).await
+    }
+
+    /// Emit an event every `interval` in the background, with increasing
+    /// values to a few different recipients
+    pub fn emit_every(&self, interval: Duration) -> JoinHandle<()> {
+        let (rpc, sender, contract) = (self.rpc.clone(), self.sender, self.contract);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            for n in 1u64.. {
+                ticks.tick().await;
+                let to = Address::from_low_u64_be(0x1000 + n % 4);
+                if let Err(e) = emit(&rpc, sender, contract, to, U256::from(n) * U256::exp10(18)).await {
+                    warn!("Failed to emit a test event: {:#}", e);
+                }
+            }
+        })
+    }
+}
+
+impl Drop for Anvil {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+async fn emit(rpc: &Rpc, sender: Address, contract: Address, to: Address, value: U256) -> Result<H256> {
+    let data = ethers::abi::encode(&[Token::Uint(value), Token::Address(to)]);
+    let transaction = json!({ "from": sender, "to": contract, "data": format!("0x{}", hex::encode(data)) });
+    let receipt = rpc.transact(transaction).await?;
+    receipt["transactionHash"]
+        .as_str()
+        .and_then(|hash| hash.parse().ok())
+        .context("Invalid transaction receipt")
+}
+
+impl Rpc {
+    /// Send `transaction` from an unlocked account and wait for its receipt
+    async fn transact(&self, transaction: Value) -> Result<Value> {
+        let hash = self.request("eth_sendTransaction", json!([transaction])).await?;
+        // Anvil mines every transaction right away unless told otherwise
+        for _ in 0..50 {
+            let receipt = self.request("eth_getTransactionReceipt", json!([hash])).await?;
+            if !receipt.is_null() {
+                return Ok(receipt);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        anyhow::bail!("Transaction {} wasn't mined", hash)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let response: Value = self
+            .client
+            .post(&self.url)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("{} failed: {}", method, error);
+        }
+        Ok(response["result"].clone())
+    }
+}
+
+fn parse_u64(value: &Value) -> Result<u64> {
+    let hex = value.as_str().context("Expected a hex number")?;
+    Ok(u64::from_str_radix(hex.trim_start_matches("0x"), 16)?)
+}
@@ -0,0 +1,295 @@
+//! Append-only archival format for events.
+//!
+//! An archive is a directory of numbered segments. Each segment is a data
+//! file (`segment-000001.zst`) holding independently compressed zstd frames
+//! of JSON Lines, plus a sidecar index (`segment-000001.idx`) with one JSON
+//! line per frame giving its byte range, block range, and the topic0 values
+//! it contains. Readers use the index to decompress only the frames that can
+//! match a query.
+//!
+//! A frame is written to the data file before its index line, so after a
+//! crash any bytes past the last indexed frame are unreferenced; the writer
+//! truncates them when it reopens the segment.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::event::EventData;
+
+/// Segments are rolled over once their data file reaches this size
+pub const DEFAULT_SEGMENT_BYTES: u64 = 64 * 1024 * 1024;
+
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Index entry describing one compressed frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FrameIndex {
+    /// Byte offset of the frame in the data file
+    pub offset: u64,
+    /// Compressed length in bytes
+    pub length: u64,
+    pub events: usize,
+    pub first_block: u64,
+    pub last_block: u64,
+    /// Distinct topic0 values of the frame's events
+    pub topic0: Vec<String>,
+}
+
+/// Selects events from an archive; `None` fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveQuery {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    /// Lowercase 0x-prefixed topic hash
+    pub topic0: Option<String>,
+}
+
+impl ArchiveQuery {
+    fn matches_frame(&self, frame: &FrameIndex) -> bool {
+        self.from_block.is_none_or(|from| frame.last_block >= from)
+            && self.to_block.is_none_or(|to| frame.first_block <= to)
+            && self.topic0.as_ref().is_none_or(|topic| frame.topic0.contains(topic))
+    }
+
+    pub fn matches(&self, event: &EventData) -> bool {
+        self.from_block.is_none_or(|from| event.block_number >= from)
+            && self.to_block.is_none_or(|to| event.block_number <= to)
+            && self.topic0.as_ref().is_none_or(|topic| {
+                event.topics.first().is_some_and(|t| t.eq_ignore_ascii_case(topic))
+            })
+    }
+}
+
+/// Appends frames to the newest segment of an archive directory
+pub struct ArchiveWriter {
+    dir: PathBuf,
+    segment: u32,
+    data: File,
+    index: File,
+    /// End of the last indexed frame
+    offset: u64,
+    level: i32,
+    max_segment_bytes: u64,
+}
+
+impl ArchiveWriter {
+    /// Open (or create) the archive in `dir`, continuing its last segment
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create archive directory {}", dir.display()))?;
+
+        let segment = list_segments(&dir)?.last().copied().unwrap_or(1);
+        let (data, index, offset) = open_segment(&dir, segment)?;
+
+        Ok(Self {
+            dir,
+            segment,
+            data,
+            index,
+            offset,
+            level: DEFAULT_COMPRESSION_LEVEL,
+            max_segment_bytes: DEFAULT_SEGMENT_BYTES,
+        })
+    }
+
+    pub fn with_compression_level(mut self, level: i32) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_max_segment_bytes(mut self, bytes: u64) -> Self {
+        self.max_segment_bytes = bytes;
+        self
+    }
+
+    /// Compress `events` into one frame and index it
+    pub fn write_frame(&mut self, events: &[EventData]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        if self.offset >= self.max_segment_bytes {
+            self.roll_segment()?;
+        }
+
+        let mut lines = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+        let compressed = zstd::encode_all(&lines[..], self.level)?;
+
+        let topic0: BTreeSet<String> = events
+            .iter()
+            .filter_map(|e| e.topics.first())
+            .map(|t| t.to_lowercase())
+            .collect();
+        let entry = FrameIndex {
+            offset: self.offset,
+            length: compressed.len() as u64,
+            events: events.len(),
+            first_block: events.iter().map(|e| e.block_number).min().unwrap_or(0),
+            last_block: events.iter().map(|e| e.block_number).max().unwrap_or(0),
+            topic0: topic0.into_iter().collect(),
+        };
+
+        // Data first: an index line must never point past the data file
+        self.data.write_all(&compressed)?;
+        self.data.sync_data()?;
+        writeln!(self.index, "{}", serde_json::to_string(&entry)?)?;
+        self.index.sync_data()?;
+
+        self.offset += entry.length;
+        Ok(())
+    }
+
+    fn roll_segment(&mut self) -> Result<()> {
+        self.segment += 1;
+        let (data, index, offset) = open_segment(&self.dir, self.segment)?;
+        self.data = data;
+        self.index = index;
+        self.offset = offset;
+        Ok(())
+    }
+}
+
+/// Reads events from an archive directory using its indexes
+pub struct ArchiveReader {
+    segments: Vec<(PathBuf, Vec<FrameIndex>)>,
+}
+
+impl ArchiveReader {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut segments = Vec::new();
+        for segment in list_segments(dir)? {
+            let (data_path, index_path) = segment_paths(dir, segment);
+            let (frames, _) = read_index(&index_path)?;
+            segments.push((data_path, frames));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Total number of indexed events
+    pub fn len(&self) -> usize {
+        self.segments.iter().flat_map(|(_, frames)| frames).map(|f| f.events).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Events matching `query`, in the order they were written
+    pub fn read(&self, query: &ArchiveQuery) -> Result<Vec<EventData>> {
+        let mut events = Vec::new();
+
+        for (data_path, frames) in &self.segments {
+            let mut file = None;
+            for frame in frames.iter().filter(|f| query.matches_frame(f)) {
+                let file = match file {
+                    Some(ref mut file) => file,
+                    None => file.insert(
+                        File::open(data_path)
+                            .with_context(|| format!("Failed to open {}", data_path.display()))?,
+                    ),
+                };
+
+                let mut compressed = vec![0; frame.length as usize];
+                file.seek(SeekFrom::Start(frame.offset))?;
+                file.read_exact(&mut compressed)
+                    .with_context(|| format!("{}: truncated frame at {}", data_path.display(), frame.offset))?;
+                let lines = zstd::decode_all(&compressed[..])
+                    .with_context(|| format!("{}: corrupt frame at {}", data_path.display(), frame.offset))?;
+
+                for line in lines.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+                    let event: EventData = serde_json::from_slice(line)?;
+                    if query.matches(&event) {
+                        events.push(event);
+                    }
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+fn segment_paths(dir: &Path, segment: u32) -> (PathBuf, PathBuf) {
+    (
+        dir.join(format!("segment-{:06}.zst", segment)),
+        dir.join(format!("segment-{:06}.idx", segment)),
+    )
+}
+
+/// Segment numbers present in `dir`, ascending
+fn list_segments(dir: &Path) -> Result<Vec<u32>> {
+    let mut segments = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Failed to read archive {}", dir.display()))? {
+        let name = entry?.file_name();
+        let segment = name
+            .to_str()
+            .and_then(|n| n.strip_prefix("segment-"))
+            .and_then(|n| n.strip_suffix(".idx"))
+            .and_then(|n| n.parse().ok());
+        if let Some(segment) = segment {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/// Parse an index file, returning its frames and the byte length of its
+/// complete lines. A torn final line (from a crash mid-write) is ignored.
+fn read_index(path: &Path) -> Result<(Vec<FrameIndex>, u64)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {}", path.display())),
+    };
+
+    let mut frames = Vec::new();
+    let mut valid_len = 0;
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 {
+        if !line.ends_with('\n') {
+            break;
+        }
+        match serde_json::from_str(&line) {
+            Ok(frame) => frames.push(frame),
+            Err(_) => break,
+        }
+        valid_len += line.len() as u64;
+        line.clear();
+    }
+
+    Ok((frames, valid_len))
+}
+
+/// Open a segment for appending, dropping anything written after its last
+/// complete index entry
+fn open_segment(dir: &Path, segment: u32) -> Result<(File, File, u64)> {
+    let (data_path, index_path) = segment_paths(dir, segment);
+    let (frames, index_len) = read_index(&index_path)?;
+    let offset = frames.last().map_or(0, |f| f.offset + f.length);
+
+    let mut index = OpenOptions::new().create(true).write(true).truncate(false).open(&index_path)
+        .with_context(|| format!("Failed to open {}", index_path.display()))?;
+    index.set_len(index_len)?;
+
+    let mut data = OpenOptions::new().create(true).write(true).truncate(false).open(&data_path)
+        .with_context(|| format!("Failed to open {}", data_path.display()))?;
+    if data.metadata()?.len() < offset {
+        anyhow::bail!("{} is shorter than its index", data_path.display());
+    }
+    data.set_len(offset)?;
+
+    data.seek(SeekFrom::End(0))?;
+    index.seek(SeekFrom::End(0))?;
+    Ok((data, index, offset))
+}
@@ -0,0 +1,88 @@
+//! Block timestamps: the on-chain time of the block each event is in, from
+//! the block's header. An event's `timestamp` is when the listener saw it,
+//! which for backfilled events can be long after the fact.
+//!
+//! Headers are fetched once per block and the timestamps of recent blocks
+//! kept, so events of the same block cost one lookup.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::provider::ProviderManager;
+
+/// Blocks whose timestamps are kept
+const CACHE_SIZE: usize = 10_000;
+
+/// Identifies a block: (chain ID, block number)
+type BlockKey = (Option<u64>, u64);
+
+#[derive(Default)]
+struct Cache {
+    timestamps: HashMap<BlockKey, u64>,
+    /// Blocks in the order they were added, oldest first
+    order: VecDeque<BlockKey>,
+}
+
+/// Adds the timestamp of the event's block to every event
+pub struct BlockTimestamps {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    cache: Mutex<Cache>,
+}
+
+impl BlockTimestamps {
+    /// Headers are fetched through `providers`, by chain ID
+    pub fn new(providers: HashMap<Option<u64>, Arc<ProviderManager>>) -> Self {
+        Self {
+            providers,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    async fn timestamp(&self, provider: &ProviderManager, chain_id: Option<u64>, block: u64) -> Result<Option<u64>> {
+        if let Some(timestamp) = lock(&self.cache).timestamps.get(&(chain_id, block)) {
+            return Ok(Some(*timestamp));
+        }
+        let Some(timestamp) = provider.get_block_timestamp(block).await? else {
+            return Ok(None);
+        };
+
+        let mut cache = lock(&self.cache);
+        if cache.timestamps.insert((chain_id, block), timestamp).is_none() {
+            cache.order.push_back((chain_id, block));
+            if cache.order.len() > CACHE_SIZE {
+                if let Some(oldest) = cache.order.pop_front() {
+                    cache.timestamps.remove(&oldest);
+                }
+            }
+        }
+        Ok(Some(timestamp))
+    }
+}
+
+#[async_trait]
+impl Enricher for BlockTimestamps {
+    fn name(&self) -> &str {
+        "Block timestamp"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+        let Some(timestamp) = self.timestamp(provider, event.chain_id, event.block_number).await? else {
+            anyhow::bail!("block {} not found", event.block_number);
+        };
+        event.block_timestamp = DateTime::from_timestamp(timestamp as i64, 0)
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true));
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,99 @@
+//! Cold start from a snapshot: another instance's export is loaded into the
+//! database sinks and listening resumes where the snapshot ends, instead of
+//! backfilling the whole history from the chain.
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::event::EventData;
+use crate::hashchain::verify_chain;
+use crate::output::{read_events_file, read_record_lines};
+use crate::run::RunMetadata;
+use crate::sinks::Sink;
+
+/// Events written to a sink between flushes while loading a snapshot
+const FLUSH_EVERY: usize = 10_000;
+
+/// The events of a snapshot export
+pub struct Snapshot {
+    events: Vec<EventData>,
+}
+
+impl Snapshot {
+    /// Read a snapshot from any export [`read_events_file`] accepts. A
+    /// snapshot whose records are hash-chained must have an intact chain.
+    pub fn load(path: &str) -> Result<Self> {
+        let events = read_events_file(path).with_context(|| format!("Failed to read snapshot {}", path))?;
+        if events.is_empty() {
+            anyhow::bail!("Snapshot {} has no events", path);
+        }
+
+        if events.iter().any(|event| event.hash.is_some()) {
+            let records = read_record_lines(path)?;
+            if let Some((index, reason)) = verify_chain(&records)? {
+                anyhow::bail!("Snapshot {} failed verification at record {}: {}", path, index + 1, reason);
+            }
+        }
+
+        Ok(Self { events })
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Last block with events in the snapshot, by chain ID
+    pub fn end_blocks(&self) -> BTreeMap<Option<u64>, u64> {
+        let mut blocks = BTreeMap::new();
+        for event in &self.events {
+            let block = blocks.entry(event.chain_id).or_insert(event.block_number);
+            *block = (*block).max(event.block_number);
+        }
+        blocks
+    }
+
+    /// `(transaction_hash, log_index)` of the events in the last block of
+    /// each of `chain_ids`. Listening resumes at that block, which may have
+    /// been cut off in the snapshot, so its events that are already loaded
+    /// can be skipped.
+    pub fn end_block_events(&self, chain_ids: &HashSet<Option<u64>>) -> HashSet<(String, u64)> {
+        let end_blocks = self.end_blocks();
+        self.events
+            .iter()
+            .filter(|event| chain_ids.contains(&event.chain_id))
+            .filter(|event| end_blocks.get(&event.chain_id) == Some(&event.block_number))
+            .map(|event| (event.transaction_hash.clone(), event.log_index))
+            .collect()
+    }
+
+    /// `hash` of the last record, when the snapshot is hash-chained
+    pub fn last_hash(&self) -> Option<&str> {
+        self.events.last().and_then(|event| event.hash.as_deref())
+    }
+
+    /// Write every event into `sink`, along with the runs that produced
+    /// them, and flush it
+    pub async fn load_into(&self, sink: &mut dyn Sink) -> Result<()> {
+        let mut runs: Vec<&RunMetadata> = Vec::new();
+        for run in self.events.iter().filter_map(|event| event.run.as_ref()) {
+            if !runs.iter().any(|known| known.run_id == run.run_id) {
+                runs.push(run);
+            }
+        }
+        for run in runs {
+            sink.start_run(run).await?;
+        }
+
+        for (i, event) in self.events.iter().enumerate() {
+            sink.send(event).await?;
+            if (i + 1) % FLUSH_EVERY == 0 {
+                sink.flush().await?;
+            }
+        }
+        sink.flush().await
+    }
+}
@@ -0,0 +1,217 @@
+//! Scheduling of catch-up requests across chains.
+//!
+//! A listener that is more than one range behind the chain head fetches
+//! ranges back to back. When several chains come back from downtime at once
+//! that can exhaust a shared RPC budget, so catch-up ranges can be limited
+//! globally and per chain. Waiting listeners are served by chain priority,
+//! then in arrival order, and slots are kept free for listeners of
+//! higher-priority chains that are catching up, so a low-priority backlog
+//! only gets what they can't use. Listeners following the chain head never
+//! wait.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::oneshot;
+
+/// Shared limits on catch-up ranges fetched at once. Cloning gives another
+/// handle to the same scheduler.
+#[derive(Clone, Default)]
+pub struct CatchUpScheduler {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Most ranges fetched at once across all chains; `None` is unlimited
+    limit: Option<usize>,
+    running: usize,
+    chains: HashMap<Option<u64>, ChainState>,
+    waiting: Vec<Waiter>,
+    /// Arrival counter, so waiters of equal priority are served in order
+    next_seq: u64,
+}
+
+#[derive(Default)]
+struct ChainState {
+    priority: i32,
+    parallelism: Option<usize>,
+    running: usize,
+    /// Listeners of the chain that are catching up
+    catching_up: usize,
+}
+
+struct Waiter {
+    chain_id: Option<u64>,
+    seq: u64,
+    ready: oneshot::Sender<()>,
+}
+
+/// Held by a listener while it is catching up; see [`CatchUpScheduler::catching_up`]
+pub struct CatchingUp {
+    scheduler: CatchUpScheduler,
+    chain_id: Option<u64>,
+}
+
+/// Held while a catch-up range is fetched; the slot is freed when dropped
+pub struct CatchUpPermit {
+    scheduler: CatchUpScheduler,
+    chain_id: Option<u64>,
+    /// Set until the slot was granted
+    ready: Option<oneshot::Receiver<()>>,
+}
+
+impl CatchUpScheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the catch-up ranges fetched at once across all chains
+    pub fn set_limit(&self, limit: Option<usize>) {
+        let mut state = self.lock();
+        state.limit = limit.map(|limit| limit.max(1));
+        state.dispatch();
+    }
+
+    /// Set the priority (higher goes first) and the most catch-up ranges
+    /// fetched at once for the listeners of `chain_id`
+    pub fn set_chain(&self, chain_id: Option<u64>, priority: i32, parallelism: Option<usize>) {
+        let mut state = self.lock();
+        let chain = state.chains.entry(chain_id).or_default();
+        chain.priority = priority;
+        chain.parallelism = parallelism.map(|parallelism| parallelism.max(1));
+        state.dispatch();
+    }
+
+    /// Mark a listener of `chain_id` as catching up until the returned guard
+    /// is dropped. Slots are kept free for it between its ranges while
+    /// lower-priority chains wait.
+    pub fn catching_up(&self, chain_id: Option<u64>) -> CatchingUp {
+        self.lock().chains.entry(chain_id).or_default().catching_up += 1;
+        CatchingUp {
+            scheduler: self.clone(),
+            chain_id,
+        }
+    }
+
+    /// Wait for a slot to fetch a catch-up range of `chain_id`
+    pub async fn acquire(&self, chain_id: Option<u64>) -> CatchUpPermit {
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.lock();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiter {
+                chain_id,
+                seq,
+                ready: tx,
+            });
+            state.dispatch();
+        }
+
+        // Created before waiting so a slot granted to a listener that is
+        // stopped meanwhile is still freed
+        let mut permit = CatchUpPermit {
+            scheduler: self.clone(),
+            chain_id,
+            ready: Some(rx),
+        };
+        if let Some(ref mut ready) = permit.ready {
+            // The sender is only dropped after sending
+            let _ = ready.await;
+        }
+        permit.ready = None;
+        permit
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Drop for CatchingUp {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.lock();
+        if let Some(chain) = state.chains.get_mut(&self.chain_id) {
+            chain.catching_up = chain.catching_up.saturating_sub(1);
+        }
+        state.dispatch();
+    }
+}
+
+impl Drop for CatchUpPermit {
+    fn drop(&mut self) {
+        // Slots are granted under the lock, so this can't race with dispatch
+        let mut state = self.scheduler.lock();
+        let granted = match self.ready.take() {
+            Some(mut ready) => ready.try_recv().is_ok(),
+            None => true,
+        };
+        if granted {
+            state.release(self.chain_id);
+            state.dispatch();
+        }
+    }
+}
+
+impl State {
+    /// Hand out free slots to waiters, highest chain priority first. A waiter
+    /// whose chain is at its parallelism limit doesn't hold up other chains.
+    fn dispatch(&mut self) {
+        let chains = &self.chains;
+        self.waiting.sort_by(|a, b| {
+            priority_of(chains, &b.chain_id)
+                .cmp(&priority_of(chains, &a.chain_id))
+                .then(a.seq.cmp(&b.seq))
+        });
+
+        let mut i = 0;
+        while i < self.waiting.len() {
+            let Some(free) = self.free_for(priority_of(&self.chains, &self.waiting[i].chain_id)) else {
+                break;
+            };
+            let chain = self.chains.entry(self.waiting[i].chain_id).or_default();
+            if free == 0 || chain.parallelism.is_some_and(|parallelism| chain.running >= parallelism) {
+                i += 1;
+                continue;
+            }
+
+            let waiter = self.waiting.remove(i);
+            // A listener that was stopped while waiting doesn't take the slot
+            if waiter.ready.send(()).is_ok() {
+                chain.running += 1;
+                self.running += 1;
+            }
+        }
+    }
+
+    /// Slots a waiter of a chain with `priority` may take: the free ones
+    /// minus those kept for catching-up listeners of higher-priority chains
+    /// that aren't fetching right now. `None` when no slot is free at all.
+    fn free_for(&self, priority: i32) -> Option<usize> {
+        let Some(limit) = self.limit else {
+            return Some(usize::MAX);
+        };
+        let free = limit.checked_sub(self.running).filter(|&free| free > 0)?;
+        let reserved: usize = self
+            .chains
+            .values()
+            .filter(|chain| chain.priority > priority)
+            .map(|chain| {
+                let demand = chain.catching_up.min(chain.parallelism.unwrap_or(usize::MAX));
+                demand.saturating_sub(chain.running)
+            })
+            .sum();
+        Some(free.saturating_sub(reserved))
+    }
+
+    fn release(&mut self, chain_id: Option<u64>) {
+        self.running = self.running.saturating_sub(1);
+        if let Some(chain) = self.chains.get_mut(&chain_id) {
+            chain.running = chain.running.saturating_sub(1);
+        }
+    }
+}
+
+fn priority_of(chains: &HashMap<Option<u64>, ChainState>, chain_id: &Option<u64>) -> i32 {
+    chains.get(chain_id).map_or(0, |chain| chain.priority)
+}
@@ -0,0 +1,74 @@
+use anyhow::{Context, Result};
+
+/// A known EVM network and the environment variable holding its RPC URL
+#[derive(Debug, Clone, Copy)]
+pub struct ChainInfo {
+    pub chain_id: u64,
+    pub name: &'static str,
+    pub env_var: &'static str,
+    /// Symbol of the native token that gas and `value` are paid in
+    pub native_symbol: &'static str,
+    /// Block explorer, for links to transactions, blocks, and addresses
+    pub explorer_url: &'static str,
+    /// Coingecko's ID of the chain, for token prices by contract address
+    pub coingecko_platform: Option<&'static str>,
+}
+
+const CHAINS: &[ChainInfo] = &[
+    ChainInfo { chain_id: 1, name: "Ethereum Mainnet", env_var: "ETHEREUM_RPC_URL", native_symbol: "ETH", explorer_url: "https://etherscan.io", coingecko_platform: Some("ethereum") },
+    ChainInfo { chain_id: 137, name: "Polygon", env_var: "POLYGON_RPC_URL", native_symbol: "POL", explorer_url: "https://polygonscan.com", coingecko_platform: Some("polygon-pos") },
+    ChainInfo { chain_id: 42161, name: "Arbitrum One", env_var: "ARBITRUM_RPC_URL", native_symbol: "ETH", explorer_url: "https://arbiscan.io", coingecko_platform: Some("arbitrum-one") },
+    ChainInfo { chain_id: 8453, name: "Base", env_var: "BASE_RPC_URL", native_symbol: "ETH", explorer_url: "https://basescan.org", coingecko_platform: Some("base") },
+    ChainInfo { chain_id: 56, name: "Binance Smart Chain", env_var: "BSC_RPC_URL", native_symbol: "BNB", explorer_url: "https://bscscan.com", coingecko_platform: Some("binance-smart-chain") },
+    ChainInfo { chain_id: 10, name: "Optimism", env_var: "OPTIMISM_RPC_URL", native_symbol: "ETH", explorer_url: "https://optimistic.etherscan.io", coingecko_platform: Some("optimistic-ethereum") },
+    ChainInfo { chain_id: 43114, name: "Avalanche C-Chain", env_var: "AVALANCHE_RPC_URL", native_symbol: "AVAX", explorer_url: "https://snowtrace.io", coingecko_platform: Some("avalanche") },
+    ChainInfo { chain_id: 250, name: "Fantom", env_var: "FANTOM_RPC_URL", native_symbol: "FTM", explorer_url: "https://ftmscan.com", coingecko_platform: Some("fantom") },
+    // Testnets
+    ChainInfo { chain_id: 11155111, name: "Sepolia Testnet", env_var: "SEPOLIA_RPC_URL", native_symbol: "ETH", explorer_url: "https://sepolia.etherscan.io", coingecko_platform: None },
+    ChainInfo { chain_id: 80001, name: "Mumbai Testnet", env_var: "MUMBAI_RPC_URL", native_symbol: "MATIC", explorer_url: "https://mumbai.polygonscan.com", coingecko_platform: None },
+];
+
+pub fn chain_info(chain_id: u64) -> Option<&'static ChainInfo> {
+    CHAINS.iter().find(|chain| chain.chain_id == chain_id)
+}
+
+/// Native token symbol for `chain_id`, `ETH` for unknown chains
+pub fn native_symbol(chain_id: Option<u64>) -> &'static str {
+    chain_id.and_then(chain_info).map_or("ETH", |chain| chain.native_symbol)
+}
+
+/// Block explorer of `chain_id`, for known chains
+pub fn explorer_url(chain_id: Option<u64>) -> Option<&'static str> {
+    chain_id.and_then(chain_info).map(|chain| chain.explorer_url)
+}
+
+/// Resolve the RPC endpoints and display name for a known chain ID from the
+/// environment (e.g. `POLYGON_RPC_URL` / `POLYGON_RPC_URLS` for chain 137)
+pub fn get_rpc_urls_from_chain_id(chain_id: u64) -> Result<(Vec<String>, String)> {
+    let Some(chain) = chain_info(chain_id) else {
+        anyhow::bail!("Unsupported chain ID: {}. Add it to your .env file with CHAIN_{}_RPC_URL", chain_id, chain_id);
+    };
+
+    let rpc_urls = read_rpc_urls_env(chain.env_var)
+        .with_context(|| format!("Environment variable {} not found. Add it to your .env file", chain.env_var))?;
+
+    Ok((rpc_urls, chain.name.to_string()))
+}
+
+/// Read RPC endpoints for `env_var`, preferring the plural comma-separated
+/// form (e.g. `ETHEREUM_RPC_URLS`) over the single URL variable
+pub fn read_rpc_urls_env(env_var: &str) -> Option<Vec<String>> {
+    let urls: Vec<String> = std::env::var(format!("{}S", env_var))
+        .or_else(|_| std::env::var(env_var))
+        .ok()?
+        .split(',')
+        .map(|url| url.trim().to_string())
+        .filter(|url| !url.is_empty())
+        .collect();
+
+    if urls.is_empty() {
+        None
+    } else {
+        Some(urls)
+    }
+}
@@ -0,0 +1,262 @@
+//! Pausing and resuming a running listener, and asking it for its status,
+//! through SIGUSR1/SIGUSR2 or a local control socket (`--control-socket`).
+//!
+//! The socket speaks one command per connection: the client writes `pause`,
+//! `resume`, or `status` on a line and reads back one line of JSON. The
+//! `listener control` subcommand is such a client. Both are Unix only.
+//!
+//! Paused listeners finish the poll they're in and make no RPC calls until
+//! resumed, keeping their block cursor, so nothing is missed in between.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::state::Cursor;
+
+/// A command for the running listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+    Status,
+}
+
+impl std::str::FromStr for Command {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "pause" => Ok(Self::Pause),
+            "resume" => Ok(Self::Resume),
+            "status" => Ok(Self::Status),
+            other => anyhow::bail!("Unknown command {:?} (expected pause, resume, or status)", other),
+        }
+    }
+}
+
+/// A command received through a signal or the control socket
+pub struct Request {
+    pub command: Command,
+    /// Where the socket client waits for the response; `None` for signals
+    reply: Option<oneshot::Sender<String>>,
+}
+
+impl Request {
+    /// Answer the client, if there is one, with `response` as JSON
+    pub fn reply(self, response: &impl Serialize) {
+        if let Some(reply) = self.reply {
+            let _ = reply.send(serde_json::to_string(response).unwrap_or_default());
+        }
+    }
+}
+
+/// The answer to every command: the state after it was applied
+#[derive(Debug, Serialize)]
+pub struct Status {
+    /// `running` or `paused`
+    pub state: &'static str,
+    /// Seconds since polling was paused
+    pub paused_secs: Option<u64>,
+    pub run_id: String,
+    pub version: String,
+    pub started_at: String,
+    pub listeners: usize,
+    /// The block each contract would resume from
+    pub cursors: Vec<Cursor>,
+}
+
+/// Whether listeners may poll, shared with their tasks. Starts out resumed.
+#[derive(Clone)]
+pub struct PauseSwitch {
+    paused: Arc<watch::Sender<bool>>,
+}
+
+impl Default for PauseSwitch {
+    fn default() -> Self {
+        Self {
+            paused: Arc::new(watch::Sender::new(false)),
+        }
+    }
+}
+
+impl PauseSwitch {
+    /// Pause or resume; returns whether that changed anything
+    pub fn set(&self, paused: bool) -> bool {
+        self.paused.send_if_modified(|current| std::mem::replace(current, paused) != paused)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Wait until not paused
+    pub async fn wait(&self) {
+        let mut paused = self.paused.subscribe();
+        // The sender lives as long as `self`
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
+
+/// Commands for the running listener; the socket file is removed when dropped
+pub struct Control {
+    requests: mpsc::Receiver<Request>,
+    socket: Option<PathBuf>,
+}
+
+impl Control {
+    /// Take commands from SIGUSR1 (pause) and SIGUSR2 (resume), and from
+    /// clients of `socket` when given
+    pub fn start(socket: Option<&Path>) -> Result<Self> {
+        let (tx, requests) = mpsc::channel(16);
+        #[cfg(unix)]
+        {
+            unix::forward_signals(tx.clone())?;
+            if let Some(path) = socket {
+                unix::serve(path, tx)?;
+            }
+        }
+        #[cfg(not(unix))]
+        if socket.is_some() {
+            anyhow::bail!("The control socket is only supported on Unix");
+        }
+        Ok(Self {
+            requests,
+            socket: socket.map(Path::to_path_buf),
+        })
+    }
+
+    /// The next command
+    pub async fn recv(&mut self) -> Option<Request> {
+        self.requests.recv().await
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Send `command` to the listener serving `socket` and return its response
+pub async fn send(socket: &Path, command: Command) -> Result<String> {
+    #[cfg(unix)]
+    return unix::send(socket, command).await;
+    #[cfg(not(unix))]
+    {
+        let _ = (socket, command);
+        anyhow::bail!("The control socket is only supported on Unix")
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{Context, Result};
+    use std::path::Path;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{UnixListener, UnixStream};
+    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::sync::{mpsc, oneshot};
+    use tracing::warn;
+
+    use super::{Command, Request};
+
+    pub(super) fn forward_signals(tx: mpsc::Sender<Request>) -> Result<()> {
+        for (kind, command) in [
+            (SignalKind::user_defined1(), Command::Pause),
+            (SignalKind::user_defined2(), Command::Resume),
+        ] {
+            let mut signals = signal(kind).context("Failed to listen for SIGUSR1/SIGUSR2")?;
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                while signals.recv().await.is_some() {
+                    if tx.send(Request { command, reply: None }).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub(super) fn serve(path: &Path, tx: mpsc::Sender<Request>) -> Result<()> {
+        // A socket file left by a listener that didn't exit cleanly is
+        // replaced, one still being served is not
+        if path.exists() {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                anyhow::bail!("Control socket {} is in use by another listener", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("Failed to remove stale control socket {}", path.display()))?;
+        }
+        let listener =
+            UnixListener::bind(path).with_context(|| format!("Failed to bind control socket {}", path.display()))?;
+
+        tokio::spawn(async move {
+            loop {
+                let stream = match listener.accept().await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        warn!("Control socket stopped: {}", e);
+                        return;
+                    }
+                };
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, tx).await {
+                        warn!("Control socket client: {:#}", e);
+                    }
+                });
+            }
+        });
+        Ok(())
+    }
+
+    async fn handle(stream: UnixStream, tx: mpsc::Sender<Request>) -> Result<()> {
+        let (read, mut write) = stream.into_split();
+        let mut line = String::new();
+        BufReader::new(read).read_line(&mut line).await?;
+
+        let response = match line.parse::<Command>() {
+            Ok(command) => {
+                let (reply, response) = oneshot::channel();
+                tx.send(Request {
+                    command,
+                    reply: Some(reply),
+                })
+                .await
+                .ok()
+                .context("The listener is shutting down")?;
+                response.await.context("The listener is shutting down")?
+            }
+            Err(e) => serde_json::json!({ "error": e.to_string() }).to_string(),
+        };
+        write.write_all(response.as_bytes()).await?;
+        write.write_all(b"\n").await?;
+        Ok(())
+    }
+
+    pub(super) async fn send(socket: &Path, command: Command) -> Result<String> {
+        let stream = UnixStream::connect(socket)
+            .await
+            .with_context(|| format!("Failed to connect to control socket {}", socket.display()))?;
+        let (read, mut write) = stream.into_split();
+        let line = match command {
+            Command::Pause => "pause\n",
+            Command::Resume => "resume\n",
+            Command::Status => "status\n",
+        };
+        write.write_all(line.as_bytes()).await?;
+
+        let mut response = String::new();
+        BufReader::new(read).read_line(&mut response).await?;
+        if response.is_empty() {
+            anyhow::bail!("The listener closed the control socket without answering");
+        }
+        Ok(response.trim_end().to_string())
+    }
+}
@@ -0,0 +1,373 @@
+//! Coordination of several listener processes, possibly on different
+//! machines, through a shared PostgreSQL table.
+//!
+//! The table has a row per configured (chain, contract) with the block its
+//! listener has reached and a lease: the process holding an unexpired lease
+//! on a row is the only one listening to that contract. Every process renews
+//! its leases and records its cursors periodically, and claims free or
+//! expired rows up to an even share of the contracts among the live
+//! processes. When a process disappears its leases expire and the others
+//! take its contracts over from the recorded cursors; when one joins, the
+//! others release contracts beyond their new share for it to claim.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio_postgres::Client;
+
+use crate::config::{Config, CoordinationConfig};
+use crate::runtime::ContractKey;
+use crate::sinks::postgres::{connect, is_valid_table_name};
+
+const DEFAULT_TABLE: &str = "listener_cursors";
+const DEFAULT_LEASE: Duration = Duration::from_secs(30);
+
+/// Stored in place of an address for a chain's listeners without one
+const ANY_CONTRACT: &str = "*";
+
+/// Processes that haven't been seen for this many leases are forgotten
+const FORGET_AFTER_LEASES: u32 = 10;
+
+/// Contracts claimed, released, and lost to other processes by a
+/// [`Coordinator::heartbeat`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Rebalance {
+    pub claimed: usize,
+    pub released: usize,
+    /// Leases that expired and were claimed by another process
+    pub lost: usize,
+}
+
+impl Rebalance {
+    pub fn is_empty(&self) -> bool {
+        self.claimed == 0 && self.released == 0 && self.lost == 0
+    }
+}
+
+/// This process's leases in the shared cursor table
+pub struct Coordinator {
+    client: Client,
+    table: String,
+    nodes_table: String,
+    node_id: String,
+    lease: Duration,
+    /// Configured contracts, as stored in the table
+    contracts: Vec<(i64, String)>,
+    /// Leased contracts and the cursor each was claimed at
+    owned: HashMap<ContractKey, Option<u64>>,
+    /// Last time the leases were renewed
+    renewed: Instant,
+}
+
+impl Coordinator {
+    /// Connect and create the tables
+    pub async fn connect(config: &CoordinationConfig) -> Result<Self> {
+        let table = config.table.clone().unwrap_or_else(|| DEFAULT_TABLE.to_string());
+        if !is_valid_table_name(&table) {
+            anyhow::bail!("Invalid coordination table name: {}", table);
+        }
+        let nodes_table = format!("{}_nodes", table);
+        let lease = config.lease_secs.map(Duration::from_secs).unwrap_or(DEFAULT_LEASE);
+        if lease < Duration::from_secs(3) {
+            anyhow::bail!("Coordination leases must last at least 3 seconds");
+        }
+
+        let client = connect(&config.url).await?;
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    chain_id BIGINT NOT NULL,
+                    contract TEXT NOT NULL,
+                    cursor BIGINT,
+                    owner TEXT,
+                    lease_expires TIMESTAMPTZ,
+                    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                    PRIMARY KEY (chain_id, contract)
+                );
+                CREATE TABLE IF NOT EXISTS {nodes_table} (
+                    node_id TEXT PRIMARY KEY,
+                    last_seen TIMESTAMPTZ NOT NULL
+                );"
+            ))
+            .await
+            .with_context(|| format!("Failed to create coordination table {}", table))?;
+
+        Ok(Self {
+            client,
+            table,
+            nodes_table,
+            node_id: config.node_id.clone().unwrap_or_else(default_node_id),
+            lease,
+            contracts: Vec::new(),
+            owned: HashMap::new(),
+            renewed: Instant::now(),
+        })
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// How often [`heartbeat`](Self::heartbeat) should run: three times per lease
+    pub fn renew_interval(&self) -> Duration {
+        self.lease / 3
+    }
+
+    /// Leased contracts and the cursor each was claimed at, for
+    /// [`ListenerSet::assign`](crate::runtime::ListenerSet::assign)
+    pub fn assignment(&self) -> HashMap<ContractKey, Option<u64>> {
+        self.owned.clone()
+    }
+
+    /// Number of configured contracts
+    pub fn len(&self) -> usize {
+        self.contracts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contracts.is_empty()
+    }
+
+    /// Share the contracts of `config`. Leases on contracts that are no
+    /// longer configured are released by the next heartbeat.
+    pub fn set_contracts(&mut self, config: &Config) -> Result<()> {
+        let mut contracts = Vec::new();
+        for chain in &config.chains {
+            let chain_id = chain
+                .chain_id
+                .context("Coordination needs a chain_id for every chain")?;
+            for contract in &chain.contracts {
+                let key = row_key((Some(chain_id), contract.contract_filter()?.address()));
+                if !contracts.contains(&key) {
+                    contracts.push(key);
+                }
+            }
+        }
+        self.contracts = contracts;
+        Ok(())
+    }
+
+    /// Renew this process's leases, recording `cursors` for them, and
+    /// rebalance: release contracts beyond this process's share or no longer
+    /// configured, and claim free ones up to it
+    pub async fn heartbeat(&mut self, cursors: &HashMap<ContractKey, u64>) -> Result<Rebalance> {
+        let mut rebalance = Rebalance::default();
+        let lease_secs = self.lease.as_secs_f64();
+
+        self.client
+            .execute(
+                &format!(
+                    "INSERT INTO {} (node_id, last_seen) VALUES ($1, now())
+                     ON CONFLICT (node_id) DO UPDATE SET last_seen = now()",
+                    self.nodes_table
+                ),
+                &[&self.node_id],
+            )
+            .await?;
+
+        let (chain_ids, addresses): (Vec<i64>, Vec<String>) = self.contracts.iter().cloned().unzip();
+        self.client
+            .execute(
+                &format!(
+                    "INSERT INTO {} (chain_id, contract)
+                     SELECT * FROM unnest($1::bigint[], $2::text[])
+                     ON CONFLICT (chain_id, contract) DO NOTHING",
+                    self.table
+                ),
+                &[&chain_ids, &addresses],
+            )
+            .await?;
+
+        // Renew the leases still held; the others were taken over after expiring
+        let (keys, cursor_values) = rows_with_cursors(self.owned.keys().copied(), cursors);
+        let (owned_chain_ids, owned_addresses): (Vec<i64>, Vec<String>) = keys.into_iter().unzip();
+        let renewed = self
+            .client
+            .query(
+                &format!(
+                    "UPDATE {t} SET lease_expires = now() + make_interval(secs => $2),
+                         cursor = COALESCE(c.cursor, {t}.cursor), updated_at = now()
+                     FROM unnest($3::bigint[], $4::text[], $5::bigint[]) AS c(chain_id, contract, cursor)
+                     WHERE {t}.chain_id = c.chain_id AND {t}.contract = c.contract AND {t}.owner = $1
+                     RETURNING {t}.chain_id, {t}.contract",
+                    t = self.table
+                ),
+                &[&self.node_id, &lease_secs, &owned_chain_ids, &owned_addresses, &cursor_values],
+            )
+            .await?;
+        self.renewed = Instant::now();
+        let renewed: Vec<ContractKey> = renewed.iter().filter_map(|row| parse_key(row.get(0), row.get(1))).collect();
+        let before = self.owned.len();
+        self.owned.retain(|key, _| renewed.contains(key));
+        rebalance.lost = before - self.owned.len();
+
+        let live_nodes: i64 = self
+            .client
+            .query_one(
+                &format!(
+                    "SELECT count(*) FROM {} WHERE last_seen > now() - make_interval(secs => $1)",
+                    self.nodes_table
+                ),
+                &[&lease_secs],
+            )
+            .await?
+            .get(0);
+        let share = self.contracts.len().div_ceil(live_nodes.max(1) as usize);
+
+        // Release contracts no longer configured, then any beyond the share
+        let mut owned: Vec<ContractKey> = self.owned.keys().copied().collect();
+        owned.sort();
+        let (mut release, mut keep): (Vec<ContractKey>, Vec<ContractKey>) =
+            owned.into_iter().partition(|key| !self.is_configured(key));
+        if keep.len() > share {
+            release.extend(keep.split_off(share));
+        }
+        if !release.is_empty() {
+            rebalance.released = release.len();
+            self.release(release, cursors).await?;
+        }
+
+        // Claim free and expired contracts up to the share. Rows this node
+        // holds from before a restart count as free.
+        if self.owned.len() < share {
+            let (held_chain_ids, held_addresses): (Vec<i64>, Vec<String>) =
+                self.owned.keys().map(|key| row_key(*key)).unzip();
+            let limit = (share - self.owned.len()) as i64;
+            let claimed = self
+                .client
+                .query(
+                    &format!(
+                        "UPDATE {t} SET owner = $1, lease_expires = now() + make_interval(secs => $2), updated_at = now()
+                         WHERE (chain_id, contract) IN (
+                             SELECT chain_id, contract FROM {t}
+                             WHERE (chain_id, contract) IN (SELECT * FROM unnest($3::bigint[], $4::text[]))
+                               AND (chain_id, contract) NOT IN (SELECT * FROM unnest($5::bigint[], $6::text[]))
+                               AND (owner IS NULL OR owner = $1 OR lease_expires < now())
+                             ORDER BY chain_id, contract
+                             LIMIT $7
+                             FOR UPDATE SKIP LOCKED
+                         )
+                         RETURNING chain_id, contract, cursor",
+                        t = self.table
+                    ),
+                    &[
+                        &self.node_id,
+                        &lease_secs,
+                        &chain_ids,
+                        &addresses,
+                        &held_chain_ids,
+                        &held_addresses,
+                        &limit,
+                    ],
+                )
+                .await?;
+            for row in claimed {
+                if let Some(key) = parse_key(row.get(0), row.get(1)) {
+                    let cursor: Option<i64> = row.get(2);
+                    self.owned.insert(key, cursor.map(|block| block as u64));
+                    rebalance.claimed += 1;
+                }
+            }
+        }
+
+        self.client
+            .execute(
+                &format!(
+                    "DELETE FROM {} WHERE last_seen < now() - make_interval(secs => $1)",
+                    self.nodes_table
+                ),
+                &[&(lease_secs * FORGET_AFTER_LEASES as f64)],
+            )
+            .await?;
+
+        Ok(rebalance)
+    }
+
+    /// Give up every lease when they haven't been renewed for so long that
+    /// they may expire before the next attempt, so that no contract is
+    /// listened to by two processes. Returns whether any were given up.
+    pub fn expire(&mut self) -> bool {
+        if self.owned.is_empty() || self.renewed.elapsed() < self.lease - self.renew_interval() {
+            return false;
+        }
+        self.owned.clear();
+        true
+    }
+
+    /// Release every lease, recording `cursors`, so that other processes can
+    /// take the contracts over without waiting for the leases to expire
+    pub async fn release_all(&mut self, cursors: &HashMap<ContractKey, u64>) -> Result<()> {
+        let owned = self.owned.keys().copied().collect();
+        self.release(owned, cursors).await?;
+        self.client
+            .execute(&format!("DELETE FROM {} WHERE node_id = $1", self.nodes_table), &[&self.node_id])
+            .await?;
+        Ok(())
+    }
+
+    /// Release the leases on `keys`, recording their `cursors`
+    async fn release(&mut self, keys: Vec<ContractKey>, cursors: &HashMap<ContractKey, u64>) -> Result<()> {
+        let (rows, cursor_values) = rows_with_cursors(keys.iter().copied(), cursors);
+        let (chain_ids, addresses): (Vec<i64>, Vec<String>) = rows.into_iter().unzip();
+        self.client
+            .execute(
+                &format!(
+                    "UPDATE {t} SET owner = NULL, lease_expires = NULL,
+                         cursor = COALESCE(c.cursor, {t}.cursor), updated_at = now()
+                     FROM unnest($2::bigint[], $3::text[], $4::bigint[]) AS c(chain_id, contract, cursor)
+                     WHERE {t}.chain_id = c.chain_id AND {t}.contract = c.contract AND {t}.owner = $1",
+                    t = self.table
+                ),
+                &[&self.node_id, &chain_ids, &addresses, &cursor_values],
+            )
+            .await?;
+        for key in &keys {
+            self.owned.remove(key);
+        }
+        Ok(())
+    }
+
+    fn is_configured(&self, key: &ContractKey) -> bool {
+        self.contracts.contains(&row_key(*key))
+    }
+}
+
+/// Table keys of `keys`, and their cursors in `cursors`
+fn rows_with_cursors(
+    keys: impl Iterator<Item = ContractKey>,
+    cursors: &HashMap<ContractKey, u64>,
+) -> (Vec<(i64, String)>, Vec<Option<i64>>) {
+    keys.map(|key| (row_key(key), cursors.get(&key).map(|block| *block as i64)))
+        .unzip()
+}
+
+/// A contract as stored in the table: chain ID and lowercase address, or
+/// `*` for the chain's listeners without an address
+fn row_key((chain_id, contract): ContractKey) -> (i64, String) {
+    let contract = match contract {
+        Some(address) => format!("{:?}", address),
+        None => ANY_CONTRACT.to_string(),
+    };
+    (chain_id.unwrap_or_default() as i64, contract)
+}
+
+fn parse_key(chain_id: i64, contract: &str) -> Option<ContractKey> {
+    let address = match contract {
+        ANY_CONTRACT => None,
+        address => Some(address.parse().ok()?),
+    };
+    Some((Some(chain_id as u64), address))
+}
+
+/// The host name with a random suffix, so that two processes on one host
+/// never share an ID
+fn default_node_id() -> String {
+    let host = std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "listener".to_string());
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+    format!("{}-{}", host, &suffix[..8])
+}
@@ -0,0 +1,97 @@
+//! Event coverage: which events declared in an ABI were observed in a range
+//! of blocks and which never fired, to check that a monitoring setup
+//! actually exercises the events it's meant to cover.
+//!
+//! Anonymous events have no topic0 to recognize them by and aren't part of
+//! the report.
+
+use ethers::types::H256;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::abi::AbiDecoder;
+use crate::discover::TopicStats;
+use crate::event::EventData;
+
+/// How often one declared event was observed
+#[derive(Debug, Clone, Serialize)]
+pub struct EventCoverage {
+    pub signature: String,
+    pub topic0: H256,
+    pub count: usize,
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+}
+
+/// Declared events by how often they were observed, and the logs that
+/// matched none of them
+#[derive(Debug, Clone, Serialize)]
+pub struct CoverageReport {
+    /// Observed events first, most frequent first; then those that never
+    /// fired, by signature
+    pub events: Vec<EventCoverage>,
+    /// Logs whose topic0 isn't declared in the ABI
+    pub undeclared_logs: usize,
+    /// Distinct topic0 values of those logs (anonymous logs count as one)
+    pub undeclared_topics: usize,
+}
+
+impl CoverageReport {
+    /// Match `stats` of the scanned logs against the events of `decoder`
+    pub fn new(decoder: &AbiDecoder, stats: &[TopicStats]) -> Self {
+        let mut observed: HashMap<Option<H256>, &TopicStats> = stats.iter().map(|s| (s.topic0, s)).collect();
+
+        let mut events: Vec<EventCoverage> = decoder
+            .signatures()
+            .into_iter()
+            .map(|(topic0, signature)| {
+                let stats = observed.remove(&Some(topic0));
+                EventCoverage {
+                    signature,
+                    topic0,
+                    count: stats.map_or(0, |s| s.count),
+                    first_block: stats.map(|s| s.first_block),
+                    last_block: stats.map(|s| s.last_block),
+                }
+            })
+            .collect();
+        events.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.signature.cmp(&b.signature)));
+
+        Self {
+            events,
+            undeclared_logs: observed.values().map(|s| s.count).sum(),
+            undeclared_topics: observed.len(),
+        }
+    }
+
+    /// Declared events that were never observed
+    pub fn never_fired(&self) -> impl Iterator<Item = &EventCoverage> {
+        self.events.iter().filter(|event| event.count == 0)
+    }
+}
+
+/// Count exported `events` by topic0, most frequent first, as
+/// [`discover_topics`](crate::discover::discover_topics) counts logs
+pub fn topic_stats(events: &[EventData]) -> Vec<TopicStats> {
+    let mut stats: HashMap<Option<H256>, TopicStats> = HashMap::new();
+    for event in events {
+        let topic0 = event.topics.first().and_then(|topic| topic.parse().ok());
+        let block = event.block_number;
+        let entry = stats.entry(topic0).or_insert_with(|| TopicStats {
+            topic0,
+            count: 0,
+            topic_counts: BTreeSet::new(),
+            first_block: block,
+            last_block: block,
+            signature: None,
+        });
+        entry.count += 1;
+        entry.topic_counts.insert(event.topics.len());
+        entry.first_block = entry.first_block.min(block);
+        entry.last_block = entry.last_block.max(block);
+    }
+
+    let mut stats: Vec<TopicStats> = stats.into_values().collect();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.topic0.cmp(&b.topic0)));
+    stats
+}
@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use crate::config::DedupConfig;
+use crate::event::EventData;
+
+/// Events remembered by default
+pub const DEFAULT_WINDOW: usize = 10_000;
+
+/// The events delivered most recently, by block hash, transaction hash,
+/// and log index, so one fetched again is dropped rather than delivered
+/// twice: after a restart that starts before the last delivered block, or
+/// when a range is fetched again after a provider error.
+///
+/// The window is kept in a file next to the state file and survives
+/// restarts. Events without a block hash are always delivered. A log that
+/// a reorg moved to another block has a new block hash, so it is delivered
+/// again.
+pub struct Dedup {
+    path: PathBuf,
+    window: usize,
+    keys: HashSet<String>,
+    /// `keys` oldest first
+    order: VecDeque<String>,
+    changed: bool,
+}
+
+/// Contents of the dedup file
+#[derive(Serialize, Deserialize, Default)]
+struct DedupFile {
+    /// `block_hash:transaction_hash:log_index` of each event, oldest first
+    events: Vec<String>,
+}
+
+impl Dedup {
+    /// Load the window saved in `config.file`, by default next to
+    /// `state_file`, starting empty if there is none
+    pub fn open(config: &DedupConfig, state_file: &str) -> Result<Self> {
+        let path = match config.file {
+            Some(ref file) => PathBuf::from(file),
+            None => Path::new(state_file).with_extension("dedup.json"),
+        };
+        let window = config.window.unwrap_or(DEFAULT_WINDOW).max(1);
+        let saved: DedupFile = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid dedup file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DedupFile::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read dedup file {}", path.display())),
+        };
+
+        let mut dedup = Self {
+            path,
+            window,
+            keys: HashSet::new(),
+            order: VecDeque::new(),
+            changed: false,
+        };
+        let skip = saved.events.len().saturating_sub(window);
+        for key in saved.events.into_iter().skip(skip) {
+            dedup.remember(key);
+        }
+        Ok(dedup)
+    }
+
+    /// Events remembered
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// File the window is saved in
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether `event` hasn't been delivered yet; remembers it if so
+    pub fn check(&mut self, event: &EventData) -> bool {
+        let Some(key) = key(event) else {
+            return true;
+        };
+        if self.keys.contains(&key) {
+            return false;
+        }
+        self.remember(key);
+        self.changed = true;
+        true
+    }
+
+    fn remember(&mut self, key: String) {
+        if self.keys.insert(key.clone()) {
+            self.order.push_back(key);
+        }
+        while self.order.len() > self.window {
+            if let Some(oldest) = self.order.pop_front() {
+                self.keys.remove(&oldest);
+            }
+        }
+    }
+
+    /// Write the window if events were remembered since it was last
+    /// written, atomically (temp file + rename)
+    pub fn save(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        let file = DedupFile {
+            events: self.order.iter().cloned().collect(),
+        };
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&file)?)
+            .with_context(|| format!("Failed to write dedup file {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write dedup file {}", self.path.display()))?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// `block_hash:transaction_hash:log_index` of `event`, lowercase
+fn key(event: &EventData) -> Option<String> {
+    let block_hash = event.block_hash.as_deref()?;
+    Some(format!("{}:{}:{}", block_hash, event.transaction_hash, event.log_index).to_lowercase())
+}
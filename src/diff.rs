@@ -0,0 +1,84 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::event::EventData;
+
+/// Identity of an on-chain log: (block number, transaction hash, log index)
+type EventKey = (u64, String, u64);
+
+/// Differences between two exports of the same block range
+#[derive(Debug, Default, Serialize)]
+pub struct DiffReport {
+    /// Events present on the left but not on the right
+    pub missing: Vec<EventData>,
+    /// Events present on the right but not on the left
+    pub extra: Vec<EventData>,
+    pub changed: Vec<ChangedEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedEvent {
+    pub left: EventData,
+    pub right: EventData,
+    pub fields: Vec<&'static str>,
+}
+
+impl DiffReport {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two sets of events by log identity. Detection time and run
+/// metadata are ignored since they naturally differ between runs.
+pub fn diff_events(left: Vec<EventData>, right: Vec<EventData>) -> DiffReport {
+    let mut left = index_events(left);
+    let right = index_events(right);
+    let mut report = DiffReport::default();
+
+    for (key, right_event) in right {
+        match left.remove(&key) {
+            Some(left_event) => {
+                let fields = changed_fields(&left_event, &right_event);
+                if !fields.is_empty() {
+                    report.changed.push(ChangedEvent {
+                        left: left_event,
+                        right: right_event,
+                        fields,
+                    });
+                }
+            }
+            None => report.extra.push(right_event),
+        }
+    }
+    report.missing = left.into_values().collect();
+
+    report
+}
+
+fn index_events(events: Vec<EventData>) -> BTreeMap<EventKey, EventData> {
+    events
+        .into_iter()
+        .map(|e| ((e.block_number, e.transaction_hash.to_lowercase(), e.log_index), e))
+        .collect()
+}
+
+fn changed_fields(left: &EventData, right: &EventData) -> Vec<&'static str> {
+    let mut fields = Vec::new();
+    if left.chain_id != right.chain_id {
+        fields.push("chain_id");
+    }
+    if !left.contract_address.eq_ignore_ascii_case(&right.contract_address) {
+        fields.push("contract_address");
+    }
+    if left.topics != right.topics {
+        fields.push("topics");
+    }
+    if left.data != right.data {
+        fields.push("data");
+    }
+    if left.event_signature != right.event_signature {
+        fields.push("event_signature");
+    }
+    fields
+}
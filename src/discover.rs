@@ -0,0 +1,67 @@
+use anyhow::Result;
+use ethers::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::provider::ProviderManager;
+
+/// Blocks requested per `eth_getLogs` call while scanning
+pub const DEFAULT_CHUNK_SIZE: u64 = 2000;
+
+/// How often one topic0 occurred in a scanned range
+#[derive(Debug, Clone, Serialize)]
+pub struct TopicStats {
+    /// `None` for anonymous logs without topics
+    pub topic0: Option<H256>,
+    pub count: usize,
+    /// Distinct numbers of topics seen with this topic0 (1 + indexed parameters)
+    pub topic_counts: BTreeSet<usize>,
+    pub first_block: u64,
+    pub last_block: u64,
+    /// Guessed event signature, if one is known
+    pub signature: Option<String>,
+}
+
+/// Fetch the logs of `contract` between `from_block` and `to_block`
+/// (inclusive) in chunks and count them by topic0, most frequent first.
+/// `on_progress` is called with the last block scanned after every chunk.
+pub async fn discover_topics(
+    provider: &ProviderManager,
+    contract: Address,
+    from_block: u64,
+    to_block: u64,
+    chunk_size: u64,
+    mut on_progress: impl FnMut(u64),
+) -> Result<Vec<TopicStats>> {
+    let mut stats: HashMap<Option<H256>, TopicStats> = HashMap::new();
+
+    let mut start = from_block;
+    while start <= to_block {
+        let end = to_block.min(start.saturating_add(chunk_size.max(1) - 1));
+        let filter = Filter::new().address(contract).from_block(start).to_block(end);
+
+        for log in provider.get_logs(&filter).await? {
+            let block = log.block_number.map(|n| n.as_u64()).unwrap_or(0);
+            let topic0 = log.topics.first().copied();
+            let entry = stats.entry(topic0).or_insert_with(|| TopicStats {
+                topic0,
+                count: 0,
+                topic_counts: BTreeSet::new(),
+                first_block: block,
+                last_block: block,
+                signature: None,
+            });
+            entry.count += 1;
+            entry.topic_counts.insert(log.topics.len());
+            entry.first_block = entry.first_block.min(block);
+            entry.last_block = entry.last_block.max(block);
+        }
+
+        on_progress(end);
+        start = end + 1;
+    }
+
+    let mut stats: Vec<TopicStats> = stats.into_values().collect();
+    stats.sort_by(|a, b| b.count.cmp(&a.count).then(a.topic0.cmp(&b.topic0)));
+    Ok(stats)
+}
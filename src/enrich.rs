@@ -0,0 +1,99 @@
+//! Enrichment: extra context looked up for every event, such as token
+//! metadata, ENS names, or prices.
+//!
+//! Lookups run under time limits so a slow RPC node or API delays an event
+//! by at most the per-event budget instead of stalling delivery. An event
+//! whose lookups timed out or failed is delivered anyway, marked with
+//! `partial_enrichment: true`.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+use crate::config::EnrichmentConfig;
+use crate::event::EventData;
+
+/// Longest a single enricher may take per event
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Longest all enrichers together may take per event
+pub const DEFAULT_BUDGET: Duration = Duration::from_millis(1000);
+
+/// Adds fields to events from an external source
+#[async_trait]
+pub trait Enricher: Send + Sync {
+    /// Short name used in log messages
+    fn name(&self) -> &str;
+
+    /// Add this enricher's fields to `event`. The future is dropped when it
+    /// runs out of time, so fields should only be set once all lookups for
+    /// the event are done.
+    async fn enrich(&self, event: &mut EventData) -> Result<()>;
+}
+
+/// The enrichers applied to every event, in order, and their time limits
+pub struct Enrichment {
+    enrichers: Vec<(Box<dyn Enricher>, AtomicBool)>,
+    timeout: Duration,
+    budget: Duration,
+}
+
+impl Enrichment {
+    pub fn new(config: &EnrichmentConfig) -> Self {
+        Self {
+            enrichers: Vec::new(),
+            timeout: config.timeout_ms.map(Duration::from_millis).unwrap_or(DEFAULT_TIMEOUT),
+            budget: config.budget_ms.map(Duration::from_millis).unwrap_or(DEFAULT_BUDGET),
+        }
+    }
+
+    pub fn add(&mut self, enricher: Box<dyn Enricher>) {
+        self.enrichers.push((enricher, AtomicBool::new(false)));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.enrichers.is_empty()
+    }
+
+    /// Run every enricher on `event` within the time limits. Enrichers that
+    /// time out, fail, or get no time left because earlier ones used up the
+    /// budget are skipped and the event is marked `partial_enrichment`.
+    pub async fn apply(&self, event: &mut EventData) {
+        let deadline = Instant::now() + self.budget;
+
+        for (enricher, degraded) in &self.enrichers {
+            let time_left = deadline.saturating_duration_since(Instant::now()).min(self.timeout);
+            let outcome = if time_left.is_zero() {
+                Err("per-event budget used up by earlier lookups".to_string())
+            } else {
+                match tokio::time::timeout(time_left, enricher.enrich(event)).await {
+                    Ok(Ok(())) => Ok(()),
+                    Ok(Err(e)) => Err(format!("{:#}", e)),
+                    Err(_) => Err(format!("timed out after {:?}", time_left)),
+                }
+            };
+
+            // Report when an enricher starts and stops falling behind rather than for every event
+            match outcome {
+                Ok(()) => {
+                    if degraded.swap(false, Ordering::Relaxed) {
+                        info!("{} enrichment recovered", enricher.name());
+                    }
+                }
+                Err(reason) => {
+                    event.partial_enrichment = true;
+                    if !degraded.swap(true, Ordering::Relaxed) {
+                        warn!(
+                            "{} enrichment skipped ({}); events are delivered with partial_enrichment until it recovers",
+                            enricher.name(),
+                            reason
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
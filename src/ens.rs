@@ -0,0 +1,113 @@
+//! ENS names: the contract and the address arguments of each event are
+//! reverse-resolved to their primary ENS name on Ethereum mainnet, whatever
+//! chain the event is from.
+//!
+//! Only names that resolve back to the address are used, so a name can't be
+//! claimed by setting a reverse record alone. Names are cached, including
+//! the absence of one, as most events involve the same few addresses.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::Address;
+use futures::future::join_all;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::provider::ProviderManager;
+
+/// How long a name is reused (default for `cache_secs`)
+pub const DEFAULT_CACHE: Duration = Duration::from_secs(3600);
+
+/// Adds the ENS names of an event's addresses
+pub struct EnsResolver {
+    /// An Ethereum mainnet endpoint
+    provider: Arc<ProviderManager>,
+    cache_for: Duration,
+    /// Name and when it was looked up; `None` for addresses without one
+    names: Mutex<HashMap<Address, (Instant, Option<String>)>>,
+}
+
+impl EnsResolver {
+    pub fn new(provider: Arc<ProviderManager>, cache_for: Duration) -> Self {
+        Self {
+            provider,
+            cache_for,
+            names: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn name(&self, address: Address) -> Result<Option<String>> {
+        if let Some((looked_up, name)) = lock(&self.names).get(&address) {
+            if looked_up.elapsed() < self.cache_for {
+                return Ok(name.clone());
+            }
+        }
+        let name = self.provider.lookup_address(address).await?;
+        lock(&self.names).insert(address, (Instant::now(), name.clone()));
+        Ok(name)
+    }
+}
+
+#[async_trait]
+impl Enricher for EnsResolver {
+    fn name(&self) -> &str {
+        "ENS"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let mut addresses = BTreeSet::new();
+        addresses.insert(event.contract_address.parse::<Address>()?);
+        let arguments: Vec<(String, Address)> = event
+            .decoded
+            .iter()
+            .flatten()
+            .filter_map(|(name, value)| Some((name.clone(), parse_address(value.as_str()?)?)))
+            .collect();
+        addresses.extend(arguments.iter().map(|(_, address)| *address));
+        // The zero address is the mint or burn side of a transfer
+        addresses.remove(&Address::zero());
+
+        // Looked up together, and set only once all are known
+        let lookups = join_all(addresses.iter().map(|address| self.name(*address))).await;
+        let mut names = HashMap::new();
+        for (address, name) in addresses.into_iter().zip(lookups) {
+            if let Some(name) = name? {
+                names.insert(address, name);
+            }
+        }
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        let humanized = event.humanized.get_or_insert_with(Default::default);
+        for (argument, address) in &arguments {
+            if let Some(name) = names.get(address) {
+                // An address label, set before, is kept
+                humanized.entry(argument.clone()).or_insert_with(|| name.clone().into());
+            }
+        }
+        event.ens = Some(
+            names
+                .into_iter()
+                .map(|(address, name)| (format!("{:?}", address), name.into()))
+                .collect(),
+        );
+        Ok(())
+    }
+}
+
+/// `value` as an address when it's written as one (`0x` and 40 hex digits)
+fn parse_address(value: &str) -> Option<Address> {
+    if value.len() != 42 || !value.starts_with("0x") {
+        return None;
+    }
+    value.parse().ok()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
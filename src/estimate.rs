@@ -0,0 +1,186 @@
+//! Estimates of a backfill's size before it starts.
+//!
+//! Listening from a distant start block can mean millions of RPC calls and
+//! gigabytes of events. Rather than scanning the whole range up front, a few
+//! evenly spaced sub-ranges are fetched with the listeners' filters and
+//! their event counts, sizes, and response times extrapolated to the range.
+//! The busier a contract was in some period, the further off the estimate
+//! may be, so it's meant to tell hours from weeks, not to be exact.
+
+use anyhow::Result;
+use ethers::prelude::*;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+use crate::config::ChainConfig;
+use crate::event::{compute_event_topic, log_filter, log_to_event_data, ContractFilter, TopicFilter};
+use crate::provider::ProviderManager;
+
+/// Sub-ranges fetched per contract (default for `backfill.samples`)
+pub const DEFAULT_SAMPLES: usize = 5;
+
+/// Estimated events above which the listener asks before backfilling
+/// (default for `backfill.confirm_above`)
+pub const DEFAULT_CONFIRM_ABOVE: u64 = 1_000_000;
+
+/// A contract's logs as its listener fetches them
+#[derive(Debug, Clone)]
+pub struct BackfillTarget {
+    pub contract: ContractFilter,
+    /// topic0 of each filtered event; empty for all events
+    pub topics: Vec<H256>,
+    /// Accepted values of the indexed arguments
+    pub topic_filter: TopicFilter,
+}
+
+impl BackfillTarget {
+    /// The contracts of `chain` as their listeners will fetch them
+    pub fn from_chain(chain: &ChainConfig) -> Result<Vec<Self>> {
+        chain
+            .contracts
+            .iter()
+            .map(|contract| {
+                Ok(Self {
+                    contract: contract.contract_filter()?,
+                    topics: contract.events.iter().map(|event| compute_event_topic(event.signature())).collect(),
+                    topic_filter: contract.topic_filter()?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Extrapolated size of a backfill
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BackfillEstimate {
+    pub from_block: u64,
+    pub to_block: u64,
+    /// Blocks actually fetched, per contract
+    pub sampled_blocks: u64,
+    /// Events found in the sampled blocks
+    pub sampled_events: u64,
+    pub events: u64,
+    /// Size of the events as JSON Lines, before decoding and enrichment
+    pub bytes: u64,
+    /// `eth_getLogs` calls of all listeners together; each is preceded by
+    /// an `eth_blockNumber` call
+    pub get_logs_calls: u64,
+    /// Listeners fetching the range
+    pub listeners: usize,
+    /// Mean response time of the sampled `eth_getLogs` calls
+    pub call_latency: Duration,
+}
+
+impl BackfillEstimate {
+    pub fn blocks(&self) -> u64 {
+        self.to_block - self.from_block + 1
+    }
+
+    /// Every RPC call of the backfill
+    pub fn rpc_calls(&self) -> u64 {
+        self.get_logs_calls * 2
+    }
+
+    /// Time to fetch the range with `parallelism` listeners fetching at once
+    pub fn duration(&self, parallelism: usize) -> Duration {
+        let parallelism = parallelism.clamp(1, self.listeners.max(1)) as u32;
+        let calls = u32::try_from(self.get_logs_calls.div_ceil(parallelism as u64)).unwrap_or(u32::MAX);
+        self.call_latency.saturating_mul(calls)
+    }
+}
+
+/// Estimate the backfill of `targets` from `from_block` to `to_block`
+/// (inclusive) by fetching `samples` evenly spaced sub-ranges of at most
+/// `max_block_range` blocks from each. Ranges no larger than the samples
+/// together are fetched completely, which makes the estimate exact.
+#[allow(clippy::too_many_arguments)]
+pub async fn estimate_backfill(
+    provider: &ProviderManager,
+    chain_id: Option<u64>,
+    chain_name: &str,
+    targets: &[BackfillTarget],
+    from_block: u64,
+    to_block: u64,
+    max_block_range: u64,
+    samples: usize,
+) -> Result<BackfillEstimate> {
+    if to_block < from_block {
+        anyhow::bail!("Start block {} is after the chain head {}", from_block, to_block);
+    }
+    let blocks = to_block - from_block + 1;
+    let max_block_range = max_block_range.max(1);
+    let samples = (samples.max(1) as u64).min(blocks);
+    let window = max_block_range.min(blocks / samples).max(1);
+
+    let ranges: Vec<(u64, u64)> = if window * samples >= blocks {
+        (from_block..=to_block)
+            .step_by(max_block_range as usize)
+            .map(|start| (start, to_block.min(start + max_block_range - 1)))
+            .collect()
+    } else {
+        // Spread evenly, the first starting at from_block and the last
+        // ending at to_block
+        let spacing = (blocks - window) / (samples - 1).max(1);
+        (0..samples)
+            .map(|i| from_block + i * spacing)
+            .map(|start| (start, start + window - 1))
+            .collect()
+    };
+    let sampled_blocks: u64 = ranges.iter().map(|(start, end)| end - start + 1).sum();
+
+    let mut sampled_events = 0u64;
+    let mut sampled_bytes = 0u64;
+    let mut calls = 0u32;
+    let mut elapsed = Duration::ZERO;
+    for target in targets {
+        for &(start, end) in &ranges {
+            let filter = log_filter(&target.contract, &target.topics, &target.topic_filter)
+                .from_block(start)
+                .to_block(end);
+
+            let started = Instant::now();
+            let logs = provider.get_logs(&filter).await?;
+            elapsed += started.elapsed();
+            calls += 1;
+
+            for log in logs.iter().filter(|log| target.contract.matches(&log.address)) {
+                let event = log_to_event_data(log, chain_id, chain_name, &log.address, None);
+                sampled_bytes += serde_json::to_vec(&event)?.len() as u64 + 1;
+                sampled_events += 1;
+            }
+        }
+    }
+
+    let extrapolate = |sampled: u64| (sampled as u128 * blocks as u128 / sampled_blocks as u128) as u64;
+    let ranges_per_listener = blocks.div_ceil(max_block_range);
+    // One listener per contract, fetching all of its events
+    let listeners = targets.len();
+
+    Ok(BackfillEstimate {
+        from_block,
+        to_block,
+        sampled_blocks,
+        sampled_events,
+        events: extrapolate(sampled_events),
+        bytes: extrapolate(sampled_bytes),
+        get_logs_calls: ranges_per_listener * listeners as u64,
+        listeners,
+        call_latency: elapsed.checked_div(calls).unwrap_or_default(),
+    })
+}
+
+/// A byte count in the largest binary unit below it, e.g. `1.5 GiB`
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,240 @@
+use chrono::Local;
+use ethers::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::abi::DecodeError;
+use crate::anomaly::Anomaly;
+use crate::proxy::ProxyInfo;
+use crate::run::RunMetadata;
+use crate::token::TokenMetadata;
+use crate::tx::TransactionContext;
+
+/// Structured event data for JSON output and integrations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventData {
+    pub timestamp: String,
+    pub chain_id: Option<u64>,
+    pub chain_name: String,
+    pub block_number: u64,
+    /// On-chain time of the block, RFC 3339 in UTC; `timestamp` is when
+    /// the event was detected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_timestamp: Option<String>,
+    /// Hash of the block, which tells a log apart from the same log
+    /// included again in another block after a reorg
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>,
+    pub transaction_hash: String,
+    pub log_index: u64,
+    pub contract_address: String,
+    pub topics: Vec<String>,
+    pub data: String,
+    pub event_signature: Option<String>,
+    /// Signature looked up by topic0 for a log no ABI decoded, which
+    /// `event_name` and `decoded` are a best guess from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guessed_signature: Option<String>,
+    /// Event name from the ABI, when the log could be decoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_name: Option<String>,
+    /// Decoded arguments by name, when the log could be decoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decoded: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Readable amounts and durations for decoded arguments (`--humanize`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humanized: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Why the log couldn't be (fully) decoded with the ABI; the raw
+    /// `topics` and `data` are kept either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decode_error: Option<DecodeError>,
+    /// Set when enrichment lookups ran out of time or failed, so some
+    /// enriched fields are missing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial_enrichment: bool,
+    /// Set when the data size or gas usage is an outlier for the event type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<Anomaly>,
+    /// Symbol, name, and decimals of the emitting contract
+    /// (`--enrich token-metadata`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<TokenMetadata>,
+    /// Sender, recipient, value, and gas cost of the emitting transaction
+    /// (`--enrich tx`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<TransactionContext>,
+    /// Implementation behind the emitting contract, when it's a proxy
+    /// (`--resolve-proxies`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyInfo>,
+    /// Approximate USD value of decoded token amounts, by argument name
+    /// (`--enrich usd-price`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usd: Option<serde_json::Map<String, serde_json::Value>>,
+    /// ENS names of the contract and address arguments, by lowercase
+    /// address (`--resolve-ens`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ens: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Labels of the contract and address arguments from the labels file,
+    /// by lowercase address (`--labels`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<serde_json::Map<String, serde_json::Value>>,
+    /// The log exactly as the node returned it, for re-decoding downstream;
+    /// the CLI drops it unless `--include-raw` is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<Log>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<RunMetadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prev_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl EventData {
+    /// The label of `address` from the labels file, else its ENS name
+    pub fn address_name(&self, address: &str) -> Option<&str> {
+        let address = address.to_lowercase();
+        [&self.labels, &self.ens]
+            .into_iter()
+            .find_map(|names| names.as_ref()?.get(&address)?.as_str())
+    }
+}
+
+/// Accepted values of topics 1 to 3 of a log; an empty list matches any
+pub type TopicFilter = [Vec<H256>; 3];
+
+/// Contracts whose logs a listener fetches: one contract, or any contract
+/// of the chain (only those in `allow` when it isn't empty) apart from
+/// those in `deny`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ContractFilter {
+    Address(Address),
+    Any { allow: Vec<Address>, deny: Vec<Address> },
+}
+
+impl ContractFilter {
+    /// The one contract, `None` for any contract
+    pub fn address(&self) -> Option<Address> {
+        match self {
+            ContractFilter::Address(address) => Some(*address),
+            ContractFilter::Any { .. } => None,
+        }
+    }
+
+    /// Whether logs emitted by `address` are wanted. The node only narrows
+    /// logs down to the allowed contracts; denied ones are dropped here.
+    pub fn matches(&self, address: &Address) -> bool {
+        match self {
+            ContractFilter::Address(contract) => contract == address,
+            ContractFilter::Any { allow, deny } => {
+                (allow.is_empty() || allow.contains(address)) && !deny.contains(address)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ContractFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContractFilter::Address(address) => write!(f, "{:?}", address),
+            ContractFilter::Any { allow, .. } if !allow.is_empty() => write!(f, "{} contracts", allow.len()),
+            ContractFilter::Any { .. } => write!(f, "any contract"),
+        }
+    }
+}
+
+pub fn compute_event_topic(event_sig: &str) -> H256 {
+    use ethers::utils::keccak256;
+    let hash = keccak256(event_sig.as_bytes());
+    H256::from_slice(&hash)
+}
+
+/// Parse an indexed argument value for a topic filter: an address, padded to
+/// 32 bytes as in the log, or a 32-byte hex word
+pub fn parse_topic(value: &str) -> anyhow::Result<H256> {
+    let hex = value.trim().trim_start_matches("0x");
+    let bytes = hex::decode(hex).map_err(|e| anyhow::anyhow!("Invalid topic value {}: {}", value, e))?;
+    match bytes.len() {
+        20 => Ok(H256::from(Address::from_slice(&bytes))),
+        32 => Ok(H256::from_slice(&bytes)),
+        n => anyhow::bail!("Invalid topic value {}: expected an address or 32 bytes, got {} bytes", value, n),
+    }
+}
+
+/// The `eth_getLogs` filter of a listener, without its block range: logs of
+/// `contract` with one of the `topic0` values (any when empty) and indexed
+/// arguments matching `topics`
+pub fn log_filter(contract: &ContractFilter, topic0: &[H256], topics: &TopicFilter) -> Filter {
+    let mut filter = with_contract_filter(Filter::new(), contract);
+    if !topic0.is_empty() {
+        filter = filter.topic0(topic0.to_vec());
+    }
+    with_topic_filter(filter, topics)
+}
+
+/// `filter` narrowed to the contract of `contract`, or its allowed contracts
+pub fn with_contract_filter(filter: Filter, contract: &ContractFilter) -> Filter {
+    match contract {
+        ContractFilter::Address(address) => filter.address(*address),
+        ContractFilter::Any { allow, .. } if !allow.is_empty() => filter.address(allow.clone()),
+        ContractFilter::Any { .. } => filter,
+    }
+}
+
+/// `filter` narrowed to the non-empty topics of `topics`
+pub fn with_topic_filter(mut filter: Filter, topics: &TopicFilter) -> Filter {
+    let [ref topic1, ref topic2, ref topic3] = *topics;
+    if !topic1.is_empty() {
+        filter = filter.topic1(topic1.clone());
+    }
+    if !topic2.is_empty() {
+        filter = filter.topic2(topic2.clone());
+    }
+    if !topic3.is_empty() {
+        filter = filter.topic3(topic3.clone());
+    }
+    filter
+}
+
+pub fn log_to_event_data(
+    log: &Log,
+    chain_id: Option<u64>,
+    chain_name: &str,
+    contract_address: &Address,
+    event_signature: Option<&str>,
+) -> EventData {
+    EventData {
+        timestamp: Local::now().to_rfc3339(),
+        chain_id,
+        chain_name: chain_name.to_string(),
+        block_number: log.block_number.map(|n| n.as_u64()).unwrap_or(0),
+        block_timestamp: None,
+        block_hash: log.block_hash.map(|h| format!("{:?}", h)),
+        transaction_hash: log
+            .transaction_hash
+            .map(|h| format!("{:?}", h))
+            .unwrap_or_default(),
+        log_index: log.log_index.map(|n| n.as_u64()).unwrap_or(0),
+        contract_address: format!("{:?}", contract_address),
+        topics: log.topics.iter().map(|t| format!("{:?}", t)).collect(),
+        data: hex::encode(&log.data),
+        event_signature: event_signature.map(String::from),
+        guessed_signature: None,
+        event_name: None,
+        decoded: None,
+        humanized: None,
+        decode_error: None,
+        partial_enrichment: false,
+        anomaly: None,
+        token: None,
+        tx: None,
+        proxy: None,
+        usd: None,
+        ens: None,
+        labels: None,
+        raw: Some(log.clone()),
+        run: None,
+        prev_hash: None,
+        hash: None,
+    }
+}
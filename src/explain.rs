@@ -0,0 +1,100 @@
+//! `--explain-filter`: for every listener, the `eth_getLogs` filter sent to
+//! the node and the criteria checked on the returned logs, to find out why
+//! expected events don't show up.
+
+use anyhow::{Context, Result};
+use ethers::types::H256;
+
+use crate::config::{ChainConfig, ContractConfig};
+use crate::event::{compute_event_topic, log_filter, ContractFilter};
+use crate::factory::ADDRESS_FILTER_LIMIT;
+
+/// How one listener's events are selected
+#[derive(Debug, Clone)]
+pub struct Explanation {
+    /// The contract, or the contracts matched without an address
+    pub contract: String,
+    /// The filter sent to the node, as JSON; `fromBlock` and `toBlock` are
+    /// set per range
+    pub node_filter: serde_json::Value,
+    /// Each filtered signature with its topic0
+    pub topics: Vec<(String, H256)>,
+    /// Criteria applied to the logs the node returns, in order
+    pub local: Vec<String>,
+}
+
+/// The explanation of every contract of `chain`
+pub fn explain_chain(chain: &ChainConfig) -> Result<Vec<Explanation>> {
+    chain
+        .contracts
+        .iter()
+        .map(|contract| explain(contract).with_context(|| format!("Invalid settings of {}", contract.label())))
+        .collect()
+}
+
+/// How the listener of `contract` selects its events
+pub fn explain(contract: &ContractConfig) -> Result<Explanation> {
+    let contract_filter = contract.contract_filter()?;
+    let topics: Vec<(String, H256)> = contract
+        .events
+        .iter()
+        .map(|event| (event.signature().to_string(), compute_event_topic(event.signature())))
+        .collect();
+    let topic0: Vec<H256> = topics.iter().map(|(_, topic)| *topic).collect();
+    let filter = log_filter(&contract_filter, &topic0, &contract.topic_filter()?);
+
+    let mut local = Vec::new();
+    if let ContractFilter::Any { ref deny, .. } = contract_filter {
+        if !deny.is_empty() {
+            local.push(format!(
+                "Drop logs of {}",
+                deny.iter().map(|address| format!("{:?}", address)).collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+    if let Some(ref abi) = contract.abi {
+        local.push(format!("Decode with {}", abi));
+    } else if let Some(preset) = contract.preset {
+        local.push(format!("Decode with the {} preset's events", preset));
+    }
+    if let Some(filter) = contract.event_filter()? {
+        let step = format!("Keep events matching `{}`", filter.source());
+        if contract.abi.is_none() && contract.preset.is_none() && filter.names().contains(&"event_name") {
+            // Set by decoding only, so comparisons with it are always false
+            local.push(format!("{} (event_name is only known with an ABI)", step));
+        } else {
+            local.push(step);
+        }
+    }
+    if let (Some(factory), Some(settings)) = (contract.factory()?, contract.factory.as_ref()) {
+        local.push(format!(
+            "Read the address of each created contract from argument {} of the events above",
+            factory.child_argument
+        ));
+        let child_topics = factory
+            .child_events
+            .iter()
+            .map(|signature| format!("{} ({:?})", signature, compute_event_topic(signature)))
+            .collect::<Vec<_>>();
+        local.push(format!(
+            "Fetch the created contracts' {} with a second eth_getLogs per range, by address \
+             (by topic alone above {} contracts, picking their logs out here)",
+            if child_topics.is_empty() {
+                "events".to_string()
+            } else {
+                child_topics.join(", ")
+            },
+            ADDRESS_FILTER_LIMIT
+        ));
+        if let Some(ref abi) = settings.abi {
+            local.push(format!("Decode the created contracts' events with {}", abi));
+        }
+    }
+
+    Ok(Explanation {
+        contract: contract_filter.to_string(),
+        node_filter: serde_json::to_value(&filter)?,
+        topics,
+        local,
+    })
+}
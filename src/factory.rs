@@ -0,0 +1,48 @@
+//! Factory contracts: contracts created by a factory (AMM pairs, vaults,
+//! ...) are discovered from the factory's creation events and listened to
+//! along with it.
+//!
+//! The created contract's address is read from one of the creation event's
+//! arguments, by position, so no ABI is needed: the indexed arguments
+//! (topics 1 to 3) come first, then the 32-byte words of the data. For
+//! `PairCreated(address indexed token0, address indexed token1, address
+//! pair, uint256)`, the pair is argument 2.
+
+use ethers::types::{Address, Log};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+/// Created contracts above which their logs are fetched by topic alone and
+/// picked out locally, as nodes reject long address lists
+pub const ADDRESS_FILTER_LIMIT: usize = 1000;
+
+/// How the contracts created by a factory are found and listened to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Factory {
+    /// Position of the created contract's address among the creation
+    /// event's arguments
+    pub child_argument: usize,
+    /// Event signatures listened to on the created contracts; empty for all
+    /// events
+    pub child_events: Vec<String>,
+}
+
+/// Contracts created by a factory so far, shared so a listener restarted
+/// after a crash or a config change keeps them
+pub type Children = Arc<Mutex<HashSet<Address>>>;
+
+/// Argument `argument` of `log` as an address: `None` when the log has no
+/// such argument or it isn't an address (its first 12 bytes aren't zero)
+pub fn child_address(log: &Log, argument: usize) -> Option<Address> {
+    let indexed = log.topics.len().saturating_sub(1);
+    let word: &[u8] = if argument < indexed {
+        log.topics[argument + 1].as_bytes()
+    } else {
+        let start = (argument - indexed) * 32;
+        log.data.get(start..start + 32)?
+    };
+    if word[..12].iter().any(|&byte| byte != 0) {
+        return None;
+    }
+    Some(Address::from_slice(&word[12..]))
+}
@@ -0,0 +1,467 @@
+//! Filter expressions over decoded event arguments, applied by the listener
+//! after decoding, e.g. `value > 1e18 && to == 0xabc...`.
+//!
+//! Names refer to decoded arguments first and then to the record's own
+//! fields (`event_name`, `block_number`, `contract_address`, ...).
+//! Integers compare as 256-bit numbers, so amounts in wei work as written;
+//! hex values such as addresses compare case-insensitively. Comparisons
+//! with a missing argument, or between values of different kinds, are
+//! false.
+//!
+//! ```text
+//! expr     = or
+//! or       = and ("||" and)*
+//! and      = not ("&&" not)*
+//! not      = "!" not | compare
+//! compare  = operand (("==" | "!=" | "<" | "<=" | ">" | ">=") operand)?
+//! operand  = "(" expr ")" | number | hex | string | "true" | "false" | name
+//! ```
+
+use anyhow::Result;
+use ethers::types::U256;
+use serde_json::Value as Json;
+use std::cmp::Ordering;
+
+use crate::event::EventData;
+
+/// Fields of the record itself that expressions can refer to
+pub const EVENT_FIELDS: &[&str] = &[
+    "event_name",
+    "event_signature",
+    "chain_id",
+    "chain_name",
+    "block_number",
+    "transaction_hash",
+    "log_index",
+    "contract_address",
+];
+
+/// Digits of the largest number a uint256 or int256 holds
+const MAX_DIGITS: usize = 78;
+
+/// A parsed filter expression
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    source: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    /// A bare operand, true when it is the boolean `true`
+    Truthy(Operand),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Name(String),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A value being compared
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number { negative: bool, magnitude: U256 },
+    Text(String),
+    Bool(bool),
+}
+
+impl EventFilter {
+    pub fn parse(source: &str) -> Result<Self> {
+        let tokens = tokenize(source).map_err(|e| anyhow::anyhow!("Invalid filter expression `{}`: {}", source, e))?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser
+            .expr()
+            .and_then(|expr| match parser.peek() {
+                None => Ok(expr),
+                Some(token) => Err(format!("unexpected {}", token)),
+            })
+            .map_err(|e| anyhow::anyhow!("Invalid filter expression `{}`: {}", source, e))?;
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// The expression as written
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Names the expression refers to, in order of appearance
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.expr.names(&mut names);
+        names
+    }
+
+    /// Names that aren't fields of the record, so must be decoded arguments
+    pub fn argument_names(&self) -> Vec<&str> {
+        self.names().into_iter().filter(|name| !EVENT_FIELDS.contains(name)).collect()
+    }
+
+    pub fn matches(&self, event: &EventData) -> bool {
+        self.expr.eval(event)
+    }
+}
+
+impl Expr {
+    fn eval(&self, event: &EventData) -> bool {
+        match self {
+            Expr::Or(a, b) => a.eval(event) || b.eval(event),
+            Expr::And(a, b) => a.eval(event) && b.eval(event),
+            Expr::Not(a) => !a.eval(event),
+            Expr::Compare(a, op, b) => match (a.resolve(event), b.resolve(event)) {
+                (Some(a), Some(b)) => compare(&a, *op, &b),
+                _ => false,
+            },
+            Expr::Truthy(a) => a.resolve(event) == Some(Value::Bool(true)),
+        }
+    }
+
+    fn names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        let mut add = |operand: &'a Operand| {
+            if let Operand::Name(name) = operand {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        };
+        match self {
+            Expr::Or(a, b) | Expr::And(a, b) => {
+                a.names(names);
+                b.names(names);
+            }
+            Expr::Not(a) => a.names(names),
+            Expr::Compare(a, _, b) => {
+                add(a);
+                add(b);
+            }
+            Expr::Truthy(a) => add(a),
+        }
+    }
+}
+
+impl Operand {
+    fn resolve(&self, event: &EventData) -> Option<Value> {
+        let name = match self {
+            Operand::Literal(value) => return Some(value.clone()),
+            Operand::Name(name) => name.as_str(),
+        };
+        if let Some(value) = event.decoded.as_ref().and_then(|decoded| decoded.get(name)) {
+            return Value::from_json(value);
+        }
+        let text = |value: &str| Some(Value::from_text(value));
+        let number = |value: u64| Some(Value::number(U256::from(value)));
+        match name {
+            "event_name" => event.event_name.as_deref().and_then(text),
+            "event_signature" => event.event_signature.as_deref().and_then(text),
+            "chain_id" => event.chain_id.and_then(number),
+            "chain_name" => text(&event.chain_name),
+            "block_number" => number(event.block_number),
+            "transaction_hash" => text(&event.transaction_hash),
+            "log_index" => number(event.log_index),
+            "contract_address" => text(&event.contract_address),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    fn number(magnitude: U256) -> Self {
+        Value::Number {
+            negative: false,
+            magnitude,
+        }
+    }
+
+    /// Decoded integers are decimal strings; anything else stays text
+    fn from_text(text: &str) -> Self {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(magnitude) = U256::from_dec_str(digits) {
+                return Value::Number {
+                    negative: negative && !magnitude.is_zero(),
+                    magnitude,
+                };
+            }
+        }
+        Value::Text(text.to_string())
+    }
+
+    fn from_json(value: &Json) -> Option<Self> {
+        match value {
+            Json::String(text) => Some(Value::from_text(text)),
+            Json::Bool(value) => Some(Value::Bool(*value)),
+            Json::Number(number) => Some(Value::from_text(&number.to_string())),
+            _ => None,
+        }
+    }
+}
+
+fn compare(a: &Value, op: Op, b: &Value) -> bool {
+    let ordering = match (a, b) {
+        (
+            Value::Number {
+                negative: a_negative,
+                magnitude: a,
+            },
+            Value::Number {
+                negative: b_negative,
+                magnitude: b,
+            },
+        ) => match (a_negative, b_negative) {
+            (false, false) => a.cmp(b),
+            (true, true) => b.cmp(a),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        },
+        (Value::Text(a), Value::Text(b)) if a.starts_with("0x") && b.starts_with("0x") => {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        }
+        (Value::Text(a), Value::Text(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) if matches!(op, Op::Eq | Op::Ne) => a.cmp(b),
+        _ => return false,
+    };
+    match op {
+        Op::Eq => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Literal(Value),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Name(name) => write!(f, "`{}`", name),
+            Token::Literal(_) => write!(f, "value"),
+            Token::Op(op) => write!(f, "`{}`", op.symbol()),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Open => write!(f, "`(`"),
+            Token::Close => write!(f, "`)`"),
+        }
+    }
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+        }
+    }
+}
+
+fn tokenize(source: &str) -> std::result::Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let (token, len) = match (c, next) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ('&', Some('&')) => (Token::And, 2),
+            ('|', Some('|')) => (Token::Or, 2),
+            ('=', Some('=')) => (Token::Op(Op::Eq), 2),
+            ('!', Some('=')) => (Token::Op(Op::Ne), 2),
+            ('<', Some('=')) => (Token::Op(Op::Le), 2),
+            ('>', Some('=')) => (Token::Op(Op::Ge), 2),
+            ('<', _) => (Token::Op(Op::Lt), 1),
+            ('>', _) => (Token::Op(Op::Gt), 1),
+            ('!', _) => (Token::Not, 1),
+            ('(', _) => (Token::Open, 1),
+            (')', _) => (Token::Close, 1),
+            ('"' | '\'', _) => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .ok_or_else(|| format!("unterminated string at position {}", i + 1))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+                (Token::Literal(Value::Text(text)), end + 2)
+            }
+            ('0', Some('x' | 'X')) => {
+                let len = 2 + chars[i + 2..].iter().take_while(|c| c.is_ascii_hexdigit()).count();
+                let text: String = chars[i..i + len].iter().collect();
+                (Token::Literal(Value::Text(text.to_lowercase())), len)
+            }
+            (c, _) if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) => {
+                let len = 1 + chars[i + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_'))
+                    .count();
+                let text: String = chars[i..i + len].iter().collect();
+                let value = parse_number(&text).ok_or_else(|| format!("invalid number {} at position {}", text, i + 1))?;
+                (Token::Literal(value), len)
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let len = chars[i..].iter().take_while(|c| c.is_alphanumeric() || **c == '_').count();
+                let name: String = chars[i..i + len].iter().collect();
+                let token = match name.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    _ => Token::Name(name),
+                };
+                (token, len)
+            }
+            (c, _) => return Err(format!("unexpected `{}` at position {}", c, i + 1)),
+        };
+        tokens.push(token);
+        i += len;
+    }
+
+    Ok(tokens)
+}
+
+/// An integer written in decimal, optionally with `_` separators and an
+/// exponent, e.g. `1_000`, `1e18`, or `2.5e6`
+fn parse_number(text: &str) -> Option<Value> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let text = text.replace('_', "");
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, exponent.parse::<usize>().ok()?),
+        None => (text.as_str(), 0),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if whole.is_empty() || fraction.len() > exponent || !format!("{}{}", whole, fraction).bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Too large for any comparison, before the digits are written out
+    let significant = format!("{}{}", whole, fraction).trim_start_matches('0').len();
+    if exponent > MAX_DIGITS || significant + exponent - fraction.len() > MAX_DIGITS {
+        return None;
+    }
+
+    let digits = format!("{}{}{}", whole, fraction, "0".repeat(exponent - fraction.len()));
+    let magnitude = U256::from_dec_str(&digits).ok()?;
+    Some(Value::Number {
+        negative: negative && !magnitude.is_zero(),
+        magnitude,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+type ParseResult<T> = std::result::Result<T, String>;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> ParseResult<Expr> {
+        let mut expr = self.not()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> ParseResult<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> ParseResult<Expr> {
+        let left = match self.next() {
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                return match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    Some(token) => Err(format!("expected `)`, found {}", token)),
+                    None => Err("expected `)`".to_string()),
+                };
+            }
+            Some(token) => self.operand(token)?,
+            None => return Err("unexpected end of expression".to_string()),
+        };
+
+        match self.peek() {
+            Some(&Token::Op(op)) => {
+                self.pos += 1;
+                let right = match self.next() {
+                    Some(token) => self.operand(token)?,
+                    None => return Err(format!("expected a value after `{}`", op.symbol())),
+                };
+                Ok(Expr::Compare(left, op, right))
+            }
+            _ => Ok(Expr::Truthy(left)),
+        }
+    }
+
+    fn operand(&self, token: Token) -> ParseResult<Operand> {
+        match token {
+            Token::Name(name) => Ok(Operand::Name(name)),
+            Token::Literal(value) => Ok(Operand::Literal(value)),
+            token => Err(format!("expected a name or value, found {}", token)),
+        }
+    }
+}
@@ -0,0 +1,292 @@
+//! StreamingFast Firehose as the source of a chain's logs
+//! (`--firehose-endpoint`), in place of `eth_getLogs`.
+//!
+//! Each range a listener polls is streamed as whole blocks from the
+//! endpoint's `sf.firehose.v2.Stream/Blocks` service, and the logs of the
+//! blocks' successful transactions are matched against the listener's
+//! filter locally. The endpoint is asked to strip the blocks down to the
+//! filter's contracts and events, which Ethereum endpoints do. Blocks the
+//! stream undoes are dropped. The chain head, and everything enrichment
+//! looks up, still come from the JSON-RPC endpoints.
+//!
+//! Only the Ethereum block model (`sf.ethereum.type.v2.Block`) is
+//! understood; Substreams packages aren't run.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::types::{Address, Filter, Log, ValueOrArray, H256, U256, U64};
+use prost::Message;
+use std::time::Duration;
+use tonic::codec::ProstCodec;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+
+use crate::config::FirehoseConfig;
+use crate::listener::LogSource;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const BLOCKS_PATH: &str = "/sf.firehose.v2.Stream/Blocks";
+const BLOCK_TYPE: &str = "type.googleapis.com/sf.ethereum.type.v2.Block";
+const FILTER_TYPE: &str = "type.googleapis.com/sf.ethereum.transform.v1.CombinedFilter";
+
+/// A Firehose endpoint serving Ethereum blocks
+pub struct FirehoseSource {
+    channel: Channel,
+    api_key: Option<String>,
+    token: Option<String>,
+}
+
+impl FirehoseSource {
+    /// Connects lazily, on the first range fetched
+    pub fn new(config: &FirehoseConfig) -> Result<Self> {
+        let mut endpoint = Endpoint::from_shared(config.endpoint.clone())
+            .with_context(|| format!("Invalid Firehose endpoint: {}", config.endpoint))?
+            .connect_timeout(CONNECT_TIMEOUT);
+        if config.endpoint.starts_with("https://") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new().with_webpki_roots())?;
+        }
+        Ok(Self {
+            channel: endpoint.connect_lazy(),
+            api_key: config.api_key.clone(),
+            token: config.token.clone(),
+        })
+    }
+
+    fn request(&self, filter: &Filter) -> Result<tonic::Request<Request>> {
+        let from = filter.get_from_block().context("Firehose ranges need a first block")?;
+        let to = filter.get_to_block().context("Firehose ranges need a last block")?;
+        let mut request = tonic::Request::new(Request {
+            start_block_num: from.as_u64() as i64,
+            stop_block_num: to.as_u64(),
+            transforms: combined_filter(filter).into_iter().collect(),
+            ..Default::default()
+        });
+        let metadata = request.metadata_mut();
+        if let Some(ref key) = self.api_key {
+            metadata.insert("x-api-key", MetadataValue::try_from(key.as_str()).context("Invalid Firehose API key")?);
+        }
+        if let Some(ref token) = self.token {
+            let bearer = MetadataValue::try_from(format!("Bearer {}", token)).context("Invalid Firehose token")?;
+            metadata.insert("authorization", bearer);
+        }
+        Ok(request)
+    }
+}
+
+#[async_trait]
+impl LogSource for FirehoseSource {
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>> {
+        let request = self.request(filter)?;
+        let mut client = tonic::client::Grpc::new(self.channel.clone());
+        client.ready().await.context("Firehose endpoint unavailable")?;
+        let mut stream = client
+            .server_streaming(request, PathAndQuery::from_static(BLOCKS_PATH), ProstCodec::<Request, Response>::default())
+            .await
+            .context("Firehose request failed")?
+            .into_inner();
+
+        let mut logs: Vec<Log> = Vec::new();
+        while let Some(response) = stream.message().await.context("Firehose stream failed")? {
+            let Some(block) = response.block else {
+                continue;
+            };
+            if block.type_url != BLOCK_TYPE {
+                anyhow::bail!("Firehose endpoint serves {} rather than Ethereum blocks", block.type_url);
+            }
+            let block = Block::decode(block.value.as_slice()).context("Invalid Firehose block")?;
+            let hash = H256::from_slice(&block.hash);
+            if response.step == ForkStep::Undo as i32 {
+                logs.retain(|log| log.block_hash != Some(hash));
+                continue;
+            }
+            logs.extend(block_logs(&block, hash).filter(|log| matches(filter, log)));
+        }
+        Ok(logs)
+    }
+}
+
+/// The logs of `block`'s transactions that didn't fail or revert
+fn block_logs(block: &Block, hash: H256) -> impl Iterator<Item = Log> + '_ {
+    block
+        .transaction_traces
+        .iter()
+        .filter(|trace| trace.status != TransactionStatus::Failed as i32 && trace.status != TransactionStatus::Reverted as i32)
+        .flat_map(move |trace| {
+            trace.receipt.iter().flat_map(|receipt| &receipt.logs).map(move |log| Log {
+                address: Address::from_slice(&log.address),
+                topics: log.topics.iter().map(|topic| H256::from_slice(topic)).collect(),
+                data: log.data.clone().into(),
+                block_hash: Some(hash),
+                block_number: Some(U64::from(block.number)),
+                transaction_hash: Some(H256::from_slice(&trace.hash)),
+                transaction_index: Some(U64::from(trace.index)),
+                log_index: Some(U256::from(log.block_index)),
+                transaction_log_index: Some(U256::from(log.index)),
+                removed: Some(false),
+                ..Default::default()
+            })
+        })
+}
+
+/// Whether `log` matches the addresses and topics of `filter`, as a node
+/// would match them
+fn matches(filter: &Filter, log: &Log) -> bool {
+    let address = match filter.address {
+        Some(ValueOrArray::Value(address)) => address == log.address,
+        Some(ValueOrArray::Array(ref addresses)) => addresses.is_empty() || addresses.contains(&log.address),
+        None => true,
+    };
+    address
+        && filter.topics.iter().enumerate().all(|(i, topic)| {
+            let values = match topic {
+                None => return true,
+                Some(ValueOrArray::Value(value)) => std::slice::from_ref(value),
+                Some(ValueOrArray::Array(values)) => values.as_slice(),
+            };
+            values.is_empty() || values.iter().any(|value| value.is_none() || log.topics.get(i) == value.as_ref())
+        })
+}
+
+/// The transform that strips blocks down to the logs of `filter`'s
+/// contracts and events; `None` when it matches any of them
+fn combined_filter(filter: &Filter) -> Option<prost_types::Any> {
+    let addresses: Vec<Vec<u8>> = match filter.address {
+        Some(ValueOrArray::Value(address)) => vec![address.as_bytes().to_vec()],
+        Some(ValueOrArray::Array(ref addresses)) => addresses.iter().map(|address| address.as_bytes().to_vec()).collect(),
+        None => Vec::new(),
+    };
+    let event_signatures: Vec<Vec<u8>> = match filter.topics[0] {
+        Some(ValueOrArray::Value(Some(topic))) => vec![topic.as_bytes().to_vec()],
+        Some(ValueOrArray::Array(ref topics)) if topics.iter().all(Option::is_some) => {
+            topics.iter().flatten().map(|topic| topic.as_bytes().to_vec()).collect()
+        }
+        _ => Vec::new(),
+    };
+    if addresses.is_empty() && event_signatures.is_empty() {
+        return None;
+    }
+    let transform = CombinedFilter {
+        log_filters: vec![LogFilter {
+            addresses,
+            event_signatures,
+        }],
+    };
+    Some(prost_types::Any {
+        type_url: FILTER_TYPE.to_string(),
+        value: transform.encode_to_vec(),
+    })
+}
+
+/// `sf.firehose.v2.Request`
+#[derive(Clone, PartialEq, Message)]
+struct Request {
+    #[prost(int64, tag = "1")]
+    start_block_num: i64,
+    #[prost(string, tag = "2")]
+    cursor: String,
+    /// Inclusive
+    #[prost(uint64, tag = "3")]
+    stop_block_num: u64,
+    #[prost(bool, tag = "4")]
+    final_blocks_only: bool,
+    #[prost(message, repeated, tag = "10")]
+    transforms: Vec<prost_types::Any>,
+}
+
+/// `sf.firehose.v2.Response`
+#[derive(Clone, PartialEq, Message)]
+struct Response {
+    #[prost(message, optional, tag = "1")]
+    block: Option<prost_types::Any>,
+    #[prost(enumeration = "ForkStep", tag = "6")]
+    step: i32,
+    #[prost(string, tag = "10")]
+    cursor: String,
+}
+
+/// `sf.firehose.v2.ForkStep`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum ForkStep {
+    Unset = 0,
+    New = 1,
+    Undo = 2,
+    Final = 3,
+}
+
+/// `sf.ethereum.type.v2.Block`, without the fields logs don't need
+#[derive(Clone, PartialEq, Message)]
+struct Block {
+    #[prost(bytes = "vec", tag = "2")]
+    hash: Vec<u8>,
+    #[prost(uint64, tag = "3")]
+    number: u64,
+    #[prost(message, repeated, tag = "10")]
+    transaction_traces: Vec<TransactionTrace>,
+}
+
+/// `sf.ethereum.type.v2.TransactionTrace`
+#[derive(Clone, PartialEq, Message)]
+struct TransactionTrace {
+    #[prost(uint32, tag = "20")]
+    index: u32,
+    #[prost(bytes = "vec", tag = "21")]
+    hash: Vec<u8>,
+    #[prost(enumeration = "TransactionStatus", tag = "30")]
+    status: i32,
+    #[prost(message, optional, tag = "31")]
+    receipt: Option<TransactionReceipt>,
+}
+
+/// `sf.ethereum.type.v2.TransactionTraceStatus`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+enum TransactionStatus {
+    Unknown = 0,
+    Succeeded = 1,
+    Failed = 2,
+    Reverted = 3,
+}
+
+/// `sf.ethereum.type.v2.TransactionReceipt`
+#[derive(Clone, PartialEq, Message)]
+struct TransactionReceipt {
+    #[prost(message, repeated, tag = "4")]
+    logs: Vec<FirehoseLog>,
+}
+
+/// `sf.ethereum.type.v2.Log`
+#[derive(Clone, PartialEq, Message)]
+struct FirehoseLog {
+    #[prost(bytes = "vec", tag = "1")]
+    address: Vec<u8>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    topics: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", tag = "3")]
+    data: Vec<u8>,
+    /// Position in the transaction
+    #[prost(uint32, tag = "4")]
+    index: u32,
+    /// Position in the block
+    #[prost(uint32, tag = "6")]
+    block_index: u32,
+}
+
+/// `sf.ethereum.transform.v1.CombinedFilter`
+#[derive(Clone, PartialEq, Message)]
+struct CombinedFilter {
+    #[prost(message, repeated, tag = "1")]
+    log_filters: Vec<LogFilter>,
+}
+
+/// `sf.ethereum.transform.v1.LogFilter`: logs of any of `addresses` with
+/// any of `event_signatures` as topic0; empty matches any
+#[derive(Clone, PartialEq, Message)]
+struct LogFilter {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    addresses: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    event_signatures: Vec<Vec<u8>>,
+}
@@ -0,0 +1,214 @@
+//! Signature guesses for events no ABI decoded: topic0 is looked up in the
+//! bundled list of well-known signatures ([`KNOWN_EVENTS`](crate::signatures::KNOWN_EVENTS)) and optionally
+//! in the openchain.xyz signature database, and the log is decoded as the
+//! first candidate it fits.
+//!
+//! A signature doesn't say which arguments are indexed, so that is guessed
+//! too: the log's topic count fixes how many are, and of the choices whose
+//! topics and data re-encode to exactly the log's bytes, the one indexing
+//! the most addresses, then the earliest arguments, is taken. The argument
+//! names are unknown and given as `param0`, `param1`, and so on.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::abi::{Abi, Event, HumanReadableParser, ParamType};
+use ethers::types::{Bytes, Log, H256};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::abi::AbiDecoder;
+use crate::chains::native_symbol;
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::signatures::guess_signature;
+
+pub const DEFAULT_OPENCHAIN_URL: &str = "https://api.openchain.xyz/signature-database/v1";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Arguments beyond which the indexed ones aren't guessed
+const MAX_ARGUMENTS: usize = 12;
+
+/// The openchain.xyz signature database and the candidates it returned
+struct Openchain {
+    client: reqwest::Client,
+    url: String,
+    /// Candidate signatures by topic0, possibly none
+    signatures: Mutex<HashMap<H256, Vec<String>>>,
+}
+
+/// Names and decodes events no ABI decoded with a guessed signature
+pub struct SignatureGuesser {
+    openchain: Option<Openchain>,
+}
+
+impl SignatureGuesser {
+    /// Guesses from the bundled signatures, and from openchain.xyz when
+    /// `openchain` is set
+    pub fn new(openchain: bool) -> Result<Self> {
+        let openchain = match openchain {
+            true => Some(Openchain {
+                client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+                url: DEFAULT_OPENCHAIN_URL.to_string(),
+                signatures: Mutex::new(HashMap::new()),
+            }),
+            false => None,
+        };
+        Ok(Self { openchain })
+    }
+}
+
+#[async_trait]
+impl Enricher for SignatureGuesser {
+    fn name(&self) -> &str {
+        "Signature guess"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        if event.event_name.is_some() || event.decode_error.is_some() {
+            return Ok(());
+        }
+        let Some(topic0) = event.topics.first().and_then(|topic| topic.parse::<H256>().ok()) else {
+            return Ok(());
+        };
+        let log = Log {
+            topics: event.topics.iter().map(|topic| topic.parse()).collect::<Result<_, _>>()?,
+            data: Bytes::from(hex::decode(event.data.trim_start_matches("0x"))?),
+            ..Default::default()
+        };
+
+        // The filtered signature is certain, only its indexed arguments aren't
+        let mut candidates: Vec<String> = event.event_signature.iter().cloned().collect();
+        candidates.extend(guess_signature(&topic0).map(String::from));
+        if candidates.iter().any(|signature| apply(event, &log, signature)) {
+            return Ok(());
+        }
+        if let Some(ref openchain) = self.openchain {
+            for signature in openchain.lookup(topic0).await? {
+                if apply(event, &log, &signature) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Openchain {
+    /// The event signatures the database has for `topic0`
+    async fn lookup(&self, topic0: H256) -> Result<Vec<String>> {
+        if let Some(signatures) = lock(&self.signatures).get(&topic0) {
+            return Ok(signatures.clone());
+        }
+
+        let topic = format!("{:?}", topic0);
+        let response = self
+            .client
+            .get(format!("{}/lookup", self.url))
+            .query(&[("event", topic.as_str()), ("filter", "true")])
+            .send()
+            .await
+            .context("openchain.xyz request failed")?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("openchain.xyz answered {}", status);
+        }
+
+        let body: serde_json::Value = response.json().await.context("Invalid openchain.xyz response")?;
+        let signatures: Vec<String> = body
+            .pointer(&format!("/result/event/{}", topic))
+            .and_then(|entries| entries.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.get("name")?.as_str())
+            .filter(|signature| ethers::utils::keccak256(signature.as_bytes()) == topic0.0)
+            .map(String::from)
+            .collect();
+        lock(&self.signatures).insert(topic0, signatures.clone());
+        Ok(signatures)
+    }
+}
+
+/// Decode `log` into `event` as `signature`, if it fits
+fn apply(event: &mut EventData, log: &Log, signature: &str) -> bool {
+    let Some(decoder) = fit(signature, log) else {
+        return false;
+    };
+    let Some(Ok(decoded)) = decoder.decode(log, native_symbol(event.chain_id)) else {
+        return false;
+    };
+    event.guessed_signature = Some(signature.to_string());
+    event.event_name = Some(decoded.name);
+    event.decoded = Some(decoded.params);
+    true
+}
+
+/// A decoder for `log` as the event `signature`, with the likeliest indexed
+/// arguments that fit the log's topics and data
+fn fit(signature: &str, log: &Log) -> Option<AbiDecoder> {
+    let mut event = HumanReadableParser::parse_event(&format!("event {}", signature)).ok()?;
+    let arguments = event.inputs.len();
+    let indexed = log.topics.len().checked_sub(1)?;
+    if indexed > arguments || arguments > MAX_ARGUMENTS {
+        return None;
+    }
+
+    let mut choices: Vec<Vec<usize>> = (0u32..1 << arguments)
+        .filter(|mask| mask.count_ones() as usize == indexed)
+        .map(|mask| (0..arguments).filter(|i| mask & (1 << i) != 0).collect())
+        .collect();
+    // Addresses are what's usually indexed, amounts rarely
+    let addresses = |choice: &Vec<usize>| choice.iter().filter(|&&i| event.inputs[i].kind == ParamType::Address).count();
+    choices.sort_by_key(|choice| (std::cmp::Reverse(addresses(choice)), choice.clone()));
+    let choice = choices.into_iter().find(|choice| {
+        for (i, input) in event.inputs.iter_mut().enumerate() {
+            input.indexed = choice.contains(&i);
+        }
+        fits(&event, log)
+    })?;
+    for (i, input) in event.inputs.iter_mut().enumerate() {
+        input.indexed = choice.contains(&i);
+        // Logs are decoded by argument name, so they must differ
+        input.name = format!("param{}", i);
+    }
+
+    let mut abi = Abi::default();
+    abi.events.insert(event.name.clone(), vec![event]);
+    Some(AbiDecoder::from_abi(&abi).without_unit_guesses())
+}
+
+/// Whether the indexed arguments of `event` are well-formed topics of `log`
+/// and the others exactly its data. Dynamic indexed arguments are hashes,
+/// which any topic can be.
+fn fits(event: &Event, log: &Log) -> bool {
+    let mut topics = log.topics.iter().skip(1);
+    let mut data = Vec::new();
+    for input in &event.inputs {
+        if !input.indexed {
+            data.push(input.kind.clone());
+            continue;
+        }
+        let Some(topic) = topics.next() else {
+            return false;
+        };
+        if !input.kind.is_dynamic() && !reencodes(std::slice::from_ref(&input.kind), topic.as_bytes()) {
+            return false;
+        }
+    }
+    reencodes(&data, &log.data)
+}
+
+/// Whether `bytes` decodes as `kinds` and encodes back to the same bytes,
+/// which rules out padding that isn't zero and trailing data
+fn reencodes(kinds: &[ParamType], bytes: &[u8]) -> bool {
+    catch_unwind(AssertUnwindSafe(|| {
+        ethers::abi::decode(kinds, bytes).is_ok_and(|tokens| ethers::abi::encode(&tokens) == bytes)
+    }))
+    .unwrap_or(false)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,95 @@
+use anyhow::Result;
+use ethers::utils::keccak256;
+
+use crate::event::EventData;
+use crate::state::StateFile;
+
+/// `prev_hash` of the first record in a chain
+pub const GENESIS_HASH: &str = "0x0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Rolling hash chain over emitted records for tamper evidence.
+///
+/// Each record carries the previous record's hash in `prev_hash` and its own
+/// `hash`, its last field: keccak256 of the record's JSON line as written,
+/// without the `hash` field. Verification hashes the stored lines
+/// themselves, so a field added to a record breaks the chain, and records
+/// verify whichever version wrote them. The chain head is kept in the
+/// state file so the chain continues across restarts; [`save`](Self::save)
+/// writes it.
+pub struct HashChain {
+    state: StateFile,
+    changed: bool,
+}
+
+impl HashChain {
+    pub fn new(state: StateFile) -> Self {
+        Self { state, changed: false }
+    }
+
+    /// Link `event` to the chain
+    pub fn append(&mut self, event: &mut EventData) -> Result<()> {
+        let prev = self
+            .state
+            .state
+            .hash_chain_head
+            .clone()
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        event.prev_hash = Some(prev);
+        event.hash = None;
+        // The line written for the record is this one with `hash` appended,
+        // as `hash` is the last field of `EventData`
+        let hash = record_hash(&serde_json::to_vec(event)?);
+        event.hash = Some(hash.clone());
+
+        self.state.state.hash_chain_head = Some(hash);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Write the chain head if records were appended since it was last
+    /// written
+    pub fn save(&mut self) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.state.save()?;
+        self.changed = false;
+        Ok(())
+    }
+}
+
+/// Hash of a record whose JSON line without its `hash` field is `unhashed`
+fn record_hash(unhashed: &[u8]) -> String {
+    format!("0x{}", hex::encode(keccak256(unhashed)))
+}
+
+/// Check that the JSON lines `records` form an unbroken chain. Returns the
+/// index and reason of the first broken link.
+pub fn verify_chain(records: &[String]) -> Result<Option<(usize, String)>> {
+    let mut expected_prev: Option<String> = None;
+
+    for (i, line) in records.iter().enumerate() {
+        let line = line.trim_end();
+        let record: serde_json::Value = serde_json::from_str(line)?;
+        let (Some(prev), Some(hash)) = (record["prev_hash"].as_str(), record["hash"].as_str()) else {
+            return Ok(Some((i, "record is not hash-chained".to_string())));
+        };
+        if let Some(ref expected) = expected_prev {
+            if prev != expected {
+                return Ok(Some((i, format!("prev_hash {} does not match previous record {}", prev, expected))));
+            }
+        }
+        let suffix = format!(",\"hash\":{}}}", serde_json::to_string(hash)?);
+        let Some(unhashed) = line.strip_suffix(&suffix) else {
+            return Ok(Some((i, "hash is not the last field of the record".to_string())));
+        };
+        let actual = record_hash(format!("{}}}", unhashed).as_bytes());
+        if actual != hash {
+            return Ok(Some((i, format!("hash {} does not match contents ({})", hash, actual))));
+        }
+        expected_prev = Some(hash.to_string());
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,273 @@
+//! Liveness and readiness probes (`--health-addr`), served as JSON at
+//! `/healthz` and `/readyz`.
+//!
+//! - `/healthz` fails once a listener hasn't finished a poll, successful or
+//!   not, for [`STALLED_AFTER`]: its task is wedged and the process should
+//!   be restarted
+//! - `/readyz` fails until every listener has polled successfully, and
+//!   whenever a listener's last poll failed, a listener is more than the
+//!   maximum lag behind the chain head, or a sink's last delivery failed
+//!
+//! Paused listeners aren't stalled, but the listener isn't ready while
+//! paused.
+//!
+//! Like the metrics, the state is process-wide and only kept once [`serve`]
+//! has started the endpoint.
+
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long a listener may go without finishing a poll before it's
+/// considered wedged
+pub const STALLED_AFTER: Duration = Duration::from_secs(300);
+
+/// Blocks a listener may be behind the chain head and still be ready,
+/// unless configured otherwise
+pub const DEFAULT_MAX_LAG: u64 = 100;
+
+static HEALTH: OnceLock<Health> = OnceLock::new();
+
+struct Health {
+    max_lag: u64,
+    paused: AtomicBool,
+    listeners: Mutex<BTreeMap<String, ListenerHealth>>,
+    /// The error of each sink's last delivery, `None` when it succeeded
+    sinks: Mutex<BTreeMap<String, Option<String>>>,
+}
+
+struct ListenerHealth {
+    /// When watching started, or listeners were last resumed
+    registered: Instant,
+    /// When the last poll finished, successful or not
+    last_poll: Option<Instant>,
+    /// Blocks behind the chain head after the last successful poll
+    block_lag: Option<u64>,
+    /// Why the last poll failed, `None` when it succeeded
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    status: &'static str,
+    paused: bool,
+    listeners: Vec<ListenerReport>,
+    sinks: Vec<SinkReport>,
+}
+
+#[derive(Serialize)]
+struct ListenerReport {
+    listener: String,
+    /// Seconds since the last poll finished
+    last_poll_secs: Option<u64>,
+    block_lag: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SinkReport {
+    sink: String,
+    error: Option<String>,
+}
+
+/// Start keeping listener and sink health and serve it on `addr` in the
+/// background. Listeners more than `max_lag` blocks behind aren't ready.
+pub fn serve(addr: SocketAddr, max_lag: u64) -> Result<()> {
+    let server = Server::try_bind(&addr).with_context(|| format!("Failed to bind health endpoint to {}", addr))?;
+    let health = Health {
+        max_lag,
+        paused: AtomicBool::new(false),
+        listeners: Mutex::default(),
+        sinks: Mutex::default(),
+    };
+    HEALTH
+        .set(health)
+        .map_err(|_| anyhow::anyhow!("Health endpoint is already running"))?;
+
+    let service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    let server = server.serve(service);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("Health endpoint stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/healthz") => probe(|health, now| health.live(now), "ok", "stalled"),
+        (&Method::GET, "/readyz") => probe(|health, now| health.ready(now), "ready", "unavailable"),
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("Not found\n")),
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// The report, with 200 when `check` passes and 503 otherwise
+fn probe(
+    check: impl Fn(&Health, Instant) -> bool,
+    passed: &'static str,
+    failed: &'static str,
+) -> hyper::http::Result<Response<Body>> {
+    let Some(health) = HEALTH.get() else {
+        return Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).body(Body::empty());
+    };
+    let now = Instant::now();
+    let ok = check(health, now);
+    let report = health.report(now, if ok { passed } else { failed });
+    Response::builder()
+        .status(if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE })
+        .header("Content-Type", "application/json")
+        .body(Body::from(serde_json::to_vec_pretty(&report).unwrap_or_default()))
+}
+
+impl Health {
+    /// Whether no listener is wedged
+    fn live(&self, now: Instant) -> bool {
+        if self.paused.load(Ordering::Relaxed) {
+            return true;
+        }
+        lock(&self.listeners).values().all(|listener| {
+            let since = listener.last_poll.map_or(listener.registered, |at| at.max(listener.registered));
+            now.duration_since(since) < STALLED_AFTER
+        })
+    }
+
+    /// Whether every listener has caught up with its chain through working
+    /// endpoints, and every sink is delivering
+    fn ready(&self, now: Instant) -> bool {
+        if !self.live(now) || self.paused.load(Ordering::Relaxed) {
+            return false;
+        }
+        let listeners = lock(&self.listeners);
+        !listeners.is_empty()
+            && listeners.values().all(|listener| {
+                listener.error.is_none() && listener.block_lag.is_some_and(|lag| lag <= self.max_lag)
+            })
+            && lock(&self.sinks).values().all(Option::is_none)
+    }
+
+    fn report(&self, now: Instant, status: &'static str) -> Report {
+        let listeners = lock(&self.listeners)
+            .iter()
+            .map(|(label, listener)| ListenerReport {
+                listener: label.clone(),
+                last_poll_secs: listener.last_poll.map(|at| now.duration_since(at).as_secs()),
+                block_lag: listener.block_lag,
+                error: listener.error.clone(),
+            })
+            .collect();
+        let sinks = lock(&self.sinks)
+            .iter()
+            .map(|(name, error)| SinkReport {
+                sink: name.clone(),
+                error: error.clone(),
+            })
+            .collect();
+        Report {
+            status,
+            paused: self.paused.load(Ordering::Relaxed),
+            listeners,
+            sinks,
+        }
+    }
+}
+
+/// Start watching the listener labeled `listener`, which isn't ready until
+/// it has polled successfully
+pub fn register(listener: &str) {
+    if let Some(health) = HEALTH.get() {
+        lock(&health.listeners).insert(
+            listener.to_string(),
+            ListenerHealth {
+                registered: Instant::now(),
+                last_poll: None,
+                block_lag: None,
+                error: None,
+            },
+        );
+    }
+}
+
+/// Stop watching a listener that was stopped
+pub fn forget(listener: &str) {
+    if let Some(health) = HEALTH.get() {
+        lock(&health.listeners).remove(listener);
+    }
+}
+
+/// Record that listeners were paused or resumed. Resumed listeners have
+/// [`STALLED_AFTER`] to finish a poll again.
+pub fn set_paused(paused: bool) {
+    let Some(health) = HEALTH.get() else {
+        return;
+    };
+    health.paused.store(paused, Ordering::Relaxed);
+    if !paused {
+        let now = Instant::now();
+        for listener in lock(&health.listeners).values_mut() {
+            listener.registered = now;
+        }
+    }
+}
+
+/// Record a successful poll that left `listener` `block_lag` blocks behind
+/// the chain head
+pub fn polled(listener: &str, block_lag: u64) {
+    update(listener, |state| {
+        state.block_lag = Some(block_lag);
+        state.error = None;
+    });
+}
+
+/// Record a poll of `listener` that failed with `error`
+pub fn poll_failed(listener: &str, error: &dyn std::fmt::Display) {
+    update(listener, |state| state.error = Some(error.to_string()));
+}
+
+fn update(listener: &str, apply: impl FnOnce(&mut ListenerHealth)) {
+    let Some(health) = HEALTH.get() else {
+        return;
+    };
+    let mut listeners = lock(&health.listeners);
+    if let Some(state) = listeners.get_mut(listener) {
+        state.last_poll = Some(Instant::now());
+        apply(state);
+    }
+}
+
+/// Record the outcome of a delivery to the sink named `sink`
+pub fn sink_result<T>(sink: &str, result: &Result<T>) {
+    match result {
+        Ok(_) => sink_delivered(sink),
+        Err(e) => sink_failed(sink, e),
+    }
+}
+
+/// Record a successful delivery to the sink named `sink`
+pub fn sink_delivered(sink: &str) {
+    set_sink(sink, None);
+}
+
+/// Record a failed delivery to the sink named `sink`
+pub fn sink_failed(sink: &str, error: impl std::fmt::Display) {
+    set_sink(sink, Some(format!("{:#}", error)));
+}
+
+fn set_sink(sink: &str, error: Option<String>) {
+    if let Some(health) = HEALTH.get() {
+        lock(&health.sinks).insert(sink.to_string(), error);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
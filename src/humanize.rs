@@ -0,0 +1,128 @@
+use ethers::abi::{ParamType, Token};
+use ethers::prelude::*;
+use ethers::utils::format_units;
+
+/// What an integer argument is taken to measure, judged from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// An amount of the native token in wei (10^-18)
+    Wei,
+    /// A gas price in wei, shown in gwei
+    GasPrice,
+    /// An amount of the native token in gwei (10^-9)
+    Gwei,
+    /// A duration in seconds
+    Seconds,
+}
+
+/// Guess the unit of an unsigned integer parameter from its name.
+/// Amounts are assumed to use 18 decimals.
+pub fn unit_for(name: &str, kind: &ParamType) -> Option<Unit> {
+    if !matches!(kind, ParamType::Uint(_)) {
+        return None;
+    }
+    let name = name.to_lowercase();
+    let has = |words: &[&str]| words.iter().any(|word| name.contains(word));
+
+    if has(&["gasprice", "basefee", "priorityfee", "feepergas"]) {
+        Some(Unit::GasPrice)
+    } else if has(&["gwei"]) {
+        Some(Unit::Gwei)
+    } else if has(&["duration", "period", "delay", "interval", "timeout", "ttl", "cooldown", "locktime"]) {
+        Some(Unit::Seconds)
+    } else if has(&["wei", "wad", "eth", "value", "amount", "balance"]) {
+        Some(Unit::Wei)
+    } else {
+        None
+    }
+}
+
+/// Human-readable rendering of `token` in `unit`, e.g. `1.0 ETH` or `1 day`.
+/// Native token amounts are labelled with `native_symbol`.
+pub fn humanize(unit: Unit, token: &Token, native_symbol: &str) -> Option<String> {
+    let Token::Uint(value) = token else {
+        return None;
+    };
+
+    match unit {
+        Unit::Wei => Some(format!("{} {}", trim_units(format_units(*value, 18).ok()?), native_symbol)),
+        Unit::Gwei => Some(format!("{} {}", trim_units(format_units(*value, 9).ok()?), native_symbol)),
+        Unit::GasPrice => Some(format!("{} gwei", trim_units(format_units(*value, 9).ok()?))),
+        Unit::Seconds => {
+            if *value > U256::from(u64::MAX) {
+                return None;
+            }
+            Some(format_duration(value.as_u64()))
+        }
+    }
+}
+
+/// A token amount with `decimals` decimals and thousands separators, e.g.
+/// `1,500.25 USDC`
+pub fn format_token_amount(value: U256, decimals: u32, symbol: &str) -> Option<String> {
+    let amount = trim_units(format_units(value, decimals).ok()?);
+    let (whole, fraction) = amount.split_once('.').unwrap_or((&amount, ""));
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    // Whole-unit tokens (no decimals) are shown without a fraction
+    if decimals > 0 {
+        grouped = format!("{}.{}", grouped, fraction);
+    }
+    Some(if symbol.is_empty() { grouped } else { format!("{} {}", grouped, symbol) })
+}
+
+/// An approximate dollar amount, shortened with a magnitude suffix above a
+/// thousand, e.g. `$2.3M`
+pub fn format_usd(value: f64) -> String {
+    const SUFFIXES: [(f64, &str); 4] = [(1e12, "T"), (1e9, "B"), (1e6, "M"), (1e3, "K")];
+
+    match SUFFIXES.iter().find(|(size, _)| value.abs() >= *size) {
+        Some((size, suffix)) => format!("${:.1}{}", value / size, suffix),
+        None => format!("${:.2}", value),
+    }
+}
+
+/// Drop trailing zeros from a formatted decimal, keeping one decimal place
+fn trim_units(formatted: String) -> String {
+    match formatted.split_once('.') {
+        Some((whole, fraction)) => {
+            let fraction = fraction.trim_end_matches('0');
+            format!("{}.{}", whole, if fraction.is_empty() { "0" } else { fraction })
+        }
+        None => formatted,
+    }
+}
+
+/// The two largest non-zero units of a duration, e.g. `1 day 2 hours`
+pub fn format_duration(seconds: u64) -> String {
+    const UNITS: [(u64, &str); 5] = [
+        (365 * 86400, "year"),
+        (86400, "day"),
+        (3600, "hour"),
+        (60, "minute"),
+        (1, "second"),
+    ];
+
+    if seconds == 0 {
+        return "0 seconds".to_string();
+    }
+
+    let mut rest = seconds;
+    let mut parts = Vec::new();
+    for (size, name) in UNITS {
+        let count = rest / size;
+        if count > 0 {
+            parts.push(format!("{} {}{}", count, name, if count == 1 { "" } else { "s" }));
+            rest %= size;
+        }
+        if parts.len() == 2 {
+            break;
+        }
+    }
+    parts.join(" ")
+}
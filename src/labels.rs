@@ -0,0 +1,82 @@
+//! Address labels: names for known addresses ("Binance 14", "Team
+//! Multisig") from a local JSON file, added to events whose contract or
+//! address arguments are labeled.
+//!
+//! The file is a JSON object of names by address, in any letter case:
+//!
+//! ```json
+//! { "0x28C6c06298d514Db089934071355E5743bf21d60": "Binance 14" }
+//! ```
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::types::Address;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::enrich::Enricher;
+use crate::event::EventData;
+
+/// Adds the labels of an event's addresses
+pub struct Labels {
+    names: HashMap<Address, String>,
+}
+
+impl Labels {
+    /// Read the labels file at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read labels file {}", path.display()))?;
+        let entries: BTreeMap<String, String> = serde_json::from_str(&content)
+            .with_context(|| format!("Labels file {} is not a JSON object of names by address", path.display()))?;
+        let names = entries
+            .into_iter()
+            .map(|(address, name)| {
+                let address = address
+                    .parse()
+                    .with_context(|| format!("Invalid address in labels file {}: {}", path.display(), address))?;
+                Ok((address, name))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { names })
+    }
+}
+
+#[async_trait]
+impl Enricher for Labels {
+    fn name(&self) -> &str {
+        "Labels"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let label = |value: &str| value.parse::<Address>().ok().and_then(|address| self.names.get(&address));
+
+        let mut labels = serde_json::Map::new();
+        if let Some(name) = label(&event.contract_address) {
+            labels.insert(event.contract_address.to_lowercase(), name.clone().into());
+        }
+        let mut arguments = Vec::new();
+        for (argument, value) in event.decoded.iter().flatten() {
+            // Only values written as addresses, not numbers that parse as one
+            let Some(value) = value.as_str().filter(|value| value.len() == 42 && value.starts_with("0x")) else {
+                continue;
+            };
+            if let Some(name) = label(value) {
+                labels.insert(value.to_lowercase(), name.clone().into());
+                arguments.push((argument.clone(), name.clone()));
+            }
+        }
+        if labels.is_empty() {
+            return Ok(());
+        }
+
+        if !arguments.is_empty() {
+            let humanized = event.humanized.get_or_insert_with(Default::default);
+            for (argument, name) in arguments {
+                humanized.insert(argument, name.into());
+            }
+        }
+        event.labels = Some(labels);
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Smart contract event listener.
+//!
+//! The CLI in `main.rs` turns its subcommands and config into the listeners,
+//! pipeline, and sinks of this library. At its core is [`EventListener`],
+//! which can also be embedded directly in another service:
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! use futures::StreamExt;
+//! use listener::EventListener;
+//!
+//! let mut events = EventListener::builder()
+//!     .chain_id(137)
+//!     .contract("0x3c499c542cEF5E3811e1192ce70d8cC03d5c3359".parse()?)
+//!     .build()?;
+//!
+//! while let Some(event) = events.next().await {
+//!     println!("{:?}", event);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod abi;
+pub mod anomaly;
+pub mod anvil;
+pub mod archive;
+pub mod blocktime;
+pub mod bootstrap;
+pub mod catchup;
+pub mod chains;
+pub mod config;
+pub mod control;
+#[cfg(feature = "postgres")]
+pub mod coordination;
+pub mod coverage;
+pub mod dedup;
+pub mod diff;
+pub mod discover;
+pub mod enrich;
+pub mod ens;
+pub mod estimate;
+pub mod event;
+pub mod explain;
+pub mod factory;
+pub mod filter;
+#[cfg(feature = "firehose")]
+pub mod firehose;
+pub mod guess;
+pub mod hashchain;
+pub mod health;
+pub mod humanize;
+pub mod labels;
+pub mod listener;
+pub mod metrics;
+pub mod notification;
+pub mod output;
+pub mod pipeline;
+pub mod presets;
+pub mod price;
+pub mod provider;
+pub mod proxy;
+pub mod render;
+pub mod retry;
+pub mod rotate;
+pub mod routing;
+pub mod ruletest;
+pub mod run;
+pub mod runtime;
+pub mod siem;
+pub mod signatures;
+pub mod sinks;
+pub mod state;
+pub mod supervisor;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod tls;
+pub mod template;
+pub mod token;
+pub mod transport;
+pub mod tx;
+pub mod update;
+pub mod webhook;
+
+pub use config::Config;
+pub use event::EventData;
+pub use listener::{EventListener, EventListenerBuilder};
+pub use provider::ProviderManager;
+pub use retry::RetryPolicy;
+pub use run::RunMetadata;
@@ -0,0 +1,809 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::prelude::*;
+use futures::stream::{self, Stream};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+
+use crate::abi::AbiDecoder;
+use crate::catchup::{CatchUpScheduler, CatchingUp};
+use crate::chains::{get_rpc_urls_from_chain_id, native_symbol};
+use crate::control::PauseSwitch;
+use crate::event::{compute_event_topic, log_filter, log_to_event_data, ContractFilter, EventData, TopicFilter};
+use crate::factory::{child_address, Children, Factory, ADDRESS_FILTER_LIMIT};
+use crate::filter::EventFilter;
+use crate::health;
+use crate::metrics;
+use crate::provider::{is_pruned, is_range_too_large, ProviderManager};
+use crate::retry::RetryPolicy;
+use crate::run::RunMetadata;
+
+type IdleCallback = Box<dyn Fn(u64) + Send + Sync>;
+
+/// Default for [`EventListenerBuilder::max_block_range`]
+pub const DEFAULT_MAX_BLOCK_RANGE: u64 = 2000;
+
+/// How often progress is reported while catching up
+const CATCH_UP_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Ranges fetched in a row before a range that was split is doubled again
+const RANGE_GROWTH_FETCHES: u32 = 10;
+
+/// Where a listener reads logs from instead of its provider's
+/// `eth_getLogs`, such as a Firehose endpoint. The chain head still comes
+/// from the provider.
+#[async_trait]
+pub trait LogSource: Send + Sync {
+    /// Logs matching `filter`, whose block range is always set
+    async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>>;
+}
+
+/// Polls a contract for new logs and yields them as [`EventData`]. The
+/// stream ends after its end block, when it has one.
+/// Created through [`EventListener::builder`].
+pub struct EventListener {
+    inner: Pin<Box<dyn Stream<Item = EventData> + Send>>,
+}
+
+impl EventListener {
+    pub fn builder() -> EventListenerBuilder {
+        EventListenerBuilder::default()
+    }
+}
+
+impl Stream for EventListener {
+    type Item = EventData;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Option<EventData>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+/// Builder for [`EventListener`]
+#[derive(Default)]
+pub struct EventListenerBuilder {
+    contract: Option<ContractFilter>,
+    event_signatures: Vec<String>,
+    topic_filter: TopicFilter,
+    filter: Option<EventFilter>,
+    chain_id: Option<u64>,
+    chain_name: Option<String>,
+    native_symbol: Option<String>,
+    rpc_urls: Vec<String>,
+    provider: Option<Arc<ProviderManager>>,
+    archive: Option<Arc<ProviderManager>>,
+    log_source: Option<Arc<dyn LogSource>>,
+    start_block: Option<u64>,
+    end_block: Option<u64>,
+    poll_interval: Option<Duration>,
+    max_block_range: Option<u64>,
+    catch_up: Option<CatchUpScheduler>,
+    pause: Option<PauseSwitch>,
+    retry: RetryPolicy,
+    run: Option<RunMetadata>,
+    decoder: Option<Arc<AbiDecoder>>,
+    factory: Option<(Factory, Children)>,
+    child_decoder: Option<Arc<AbiDecoder>>,
+    on_idle: Option<IdleCallback>,
+}
+
+impl EventListenerBuilder {
+    /// Contract address to listen to (required unless event signatures or a
+    /// topic filter are given)
+    pub fn contract(mut self, address: Address) -> Self {
+        self.contract = Some(ContractFilter::Address(address));
+        self
+    }
+
+    /// Contracts to listen to. [`ContractFilter::Any`] matches the event
+    /// signatures and topic filter on every contract of the chain (the
+    /// default when either is given without a contract).
+    pub fn contract_filter(mut self, contract: ContractFilter) -> Self {
+        self.contract = Some(contract);
+        self
+    }
+
+    /// Only yield logs matching this event signature,
+    /// e.g. `Transfer(address,address,uint256)`. Additional signatures are
+    /// fetched with the same `eth_getLogs` calls; each event is tagged with
+    /// the one it matched.
+    pub fn event_signature(mut self, signature: impl Into<String>) -> Self {
+        self.event_signatures.push(signature.into());
+        self
+    }
+
+    pub fn event_signatures(mut self, signatures: impl IntoIterator<Item = String>) -> Self {
+        self.event_signatures.extend(signatures);
+        self
+    }
+
+    /// Only yield logs whose indexed arguments match: topic `i + 1` must be
+    /// one of `filter[i]`, or anything when that list is empty. Matched by
+    /// the node, e.g. Transfers to one address with `[vec![], vec![to], vec![]]`.
+    pub fn topic_filter(mut self, filter: TopicFilter) -> Self {
+        self.topic_filter = filter;
+        self
+    }
+
+    /// Only yield events matching `filter`, evaluated after decoding. Unlike
+    /// the topic filter, logs are fetched from the node either way.
+    pub fn filter(mut self, filter: EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Chain ID, stamped on every event. Also used to look up RPC URLs from
+    /// the environment when no endpoint or provider is given.
+    pub fn chain_id(mut self, chain_id: u64) -> Self {
+        self.chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn chain_name(mut self, name: impl Into<String>) -> Self {
+        self.chain_name = Some(name.into());
+        self
+    }
+
+    /// Symbol of the chain's native token, used when rendering amounts
+    /// (defaults to the chain registry's symbol for `chain_id`, or `ETH`)
+    pub fn native_symbol(mut self, symbol: impl Into<String>) -> Self {
+        self.native_symbol = Some(symbol.into());
+        self
+    }
+
+    /// Add an RPC endpoint. Additional endpoints are used for failover.
+    pub fn rpc_url(mut self, url: impl Into<String>) -> Self {
+        self.rpc_urls.push(url.into());
+        self
+    }
+
+    pub fn rpc_urls(mut self, urls: impl IntoIterator<Item = String>) -> Self {
+        self.rpc_urls.extend(urls);
+        self
+    }
+
+    /// Use an existing provider manager instead of connecting to `rpc_url`s
+    pub fn provider(mut self, provider: Arc<ProviderManager>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// Fetch ranges the main provider has pruned from this archive provider.
+    /// Without one, the listener skips ahead to the oldest block the main
+    /// provider still has.
+    pub fn archive_provider(mut self, provider: Arc<ProviderManager>) -> Self {
+        self.archive = Some(provider);
+        self
+    }
+
+    /// Read logs from `source` rather than the provider, which then only
+    /// serves the chain head
+    pub fn log_source(mut self, source: Arc<dyn LogSource>) -> Self {
+        self.log_source = Some(source);
+        self
+    }
+
+    /// First block to scan (defaults to the latest block)
+    pub fn start_block(mut self, block: u64) -> Self {
+        self.start_block = Some(block);
+        self
+    }
+
+    /// Last block to fetch; the stream ends once it has been scanned
+    pub fn end_block(mut self, block: u64) -> Self {
+        self.end_block = Some(block);
+        self
+    }
+
+    /// Delay between polls (default: 1 second)
+    pub fn poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    /// Most blocks fetched by one `eth_getLogs` call (default: 2000). When
+    /// the chain is further ahead, the listener catches up range by range
+    /// without waiting for the poll interval. A range the node refuses as
+    /// too large is split in half until it's accepted.
+    pub fn max_block_range(mut self, blocks: u64) -> Self {
+        self.max_block_range = Some(blocks);
+        self
+    }
+
+    /// Fetch ranges while catching up only when `scheduler` grants a slot
+    /// for this listener's chain. Polls at the chain head don't wait.
+    pub fn catch_up_scheduler(mut self, scheduler: CatchUpScheduler) -> Self {
+        self.catch_up = Some(scheduler);
+        self
+    }
+
+    /// Hold off polling while `pause` is paused; the cursor stays where it was
+    pub fn pause_switch(mut self, pause: PauseSwitch) -> Self {
+        self.pause = Some(pause);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Decode logs with the contract's ABI, filling `event_name` and
+    /// `decoded`, or `decode_error` for logs that don't fit the ABI
+    pub fn decoder(mut self, decoder: Arc<AbiDecoder>) -> Self {
+        self.decoder = Some(decoder);
+        self
+    }
+
+    /// Treat the contract as a factory: every event matching the event
+    /// signatures announces a contract, whose address is read from the
+    /// event's arguments, and the created contracts are listened to as well.
+    /// `children` holds the contracts found so far and is added to.
+    pub fn factory(mut self, factory: Factory, children: Children) -> Self {
+        self.factory = Some((factory, children));
+        self
+    }
+
+    /// Decode the logs of the contracts created by the factory with their ABI
+    pub fn child_decoder(mut self, decoder: Arc<AbiDecoder>) -> Self {
+        self.child_decoder = Some(decoder);
+        self
+    }
+
+    /// Stamp every event with the given run metadata
+    pub fn run_metadata(mut self, run: RunMetadata) -> Self {
+        self.run = Some(run);
+        self
+    }
+
+    /// Called with the last scanned block whenever a poll finds no events
+    pub fn on_idle(mut self, callback: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        self.on_idle = Some(Box::new(callback));
+        self
+    }
+
+    pub fn build(self) -> Result<EventListener> {
+        // Without a contract, the node must filter on something narrower than the chain
+        let narrowed = !self.event_signatures.is_empty() || self.topic_filter.iter().any(|values| !values.is_empty());
+        let contract = match self.contract {
+            Some(contract) => contract,
+            None if narrowed => ContractFilter::Any {
+                allow: Vec::new(),
+                deny: Vec::new(),
+            },
+            None => anyhow::bail!("A contract address, event signature, or topic filter is required"),
+        };
+        if contract.address().is_none() && !narrowed {
+            anyhow::bail!("Listening to any contract needs event signatures or a topic filter");
+        }
+        if self.factory.is_some() && (contract.address().is_none() || self.event_signatures.is_empty()) {
+            anyhow::bail!("A factory needs its contract address and the signatures of its creation events");
+        }
+        let child_decoder = self.child_decoder;
+        let factory = self.factory.map(|(factory, children)| FactoryState {
+            child_argument: factory.child_argument,
+            child_topics: topics_of(&factory.child_events),
+            children,
+            decoder: child_decoder,
+        });
+
+        let (provider, default_name) = match self.provider {
+            Some(provider) => (provider, "Custom".to_string()),
+            None => {
+                let (rpc_urls, name) = if !self.rpc_urls.is_empty() {
+                    (self.rpc_urls, "Custom".to_string())
+                } else if let Some(chain_id) = self.chain_id {
+                    get_rpc_urls_from_chain_id(chain_id)?
+                } else {
+                    anyhow::bail!("An RPC URL, chain ID, or provider is required");
+                };
+                let provider = ProviderManager::new(&rpc_urls)?.with_retry_policy(self.retry);
+                (Arc::new(provider), name)
+            }
+        };
+
+        let max_block_range = self.max_block_range.unwrap_or(DEFAULT_MAX_BLOCK_RANGE).max(1);
+        let state = PollState {
+            provider,
+            archive: self.archive,
+            on_archive: false,
+            log_source: self.log_source,
+            contract,
+            event_topics: topics_of(&self.event_signatures),
+            event_signatures: self.event_signatures,
+            topic_filter: self.topic_filter,
+            filter: self.filter,
+            chain_id: self.chain_id,
+            chain_name: self.chain_name.unwrap_or(default_name),
+            native_symbol: self
+                .native_symbol
+                .unwrap_or_else(|| native_symbol(self.chain_id).to_string()),
+            current_block: self.start_block,
+            end_block: self.end_block,
+            poll_interval: self.poll_interval.unwrap_or(Duration::from_secs(1)),
+            max_block_range,
+            block_range: max_block_range,
+            accepted_ranges: 0,
+            scheduler: self.catch_up,
+            pause: self.pause,
+            run: self.run,
+            decoder: self.decoder,
+            factory,
+            on_idle: self.on_idle,
+            pending: VecDeque::new(),
+            polled: false,
+            catch_up: None,
+        };
+        health::register(&state.label());
+
+        let inner = stream::unfold(state, |mut state| async move {
+            let event = state.next_event().await?;
+            Some((event, state))
+        });
+
+        Ok(EventListener {
+            inner: Box::pin(inner),
+        })
+    }
+}
+
+/// Cursor and buffered events behind an [`EventListener`] stream
+struct PollState {
+    provider: Arc<ProviderManager>,
+    archive: Option<Arc<ProviderManager>>,
+    /// Whether the last range came from the archive provider
+    on_archive: bool,
+    log_source: Option<Arc<dyn LogSource>>,
+    contract: ContractFilter,
+    /// Filtered event signatures; empty for all events
+    event_signatures: Vec<String>,
+    /// Signature of each filtered event by topic0
+    event_topics: HashMap<H256, String>,
+    topic_filter: TopicFilter,
+    filter: Option<EventFilter>,
+    chain_id: Option<u64>,
+    chain_name: String,
+    native_symbol: String,
+    current_block: Option<u64>,
+    end_block: Option<u64>,
+    poll_interval: Duration,
+    max_block_range: u64,
+    /// Most blocks fetched at once, below `max_block_range` after the node
+    /// refused a range as too large
+    block_range: u64,
+    /// Ranges fetched since `block_range` was last changed
+    accepted_ranges: u32,
+    scheduler: Option<CatchUpScheduler>,
+    pause: Option<PauseSwitch>,
+    run: Option<RunMetadata>,
+    decoder: Option<Arc<AbiDecoder>>,
+    factory: Option<FactoryState>,
+    on_idle: Option<IdleCallback>,
+    pending: VecDeque<EventData>,
+    polled: bool,
+    /// Set while the chain head is more than one range ahead
+    catch_up: Option<CatchUp>,
+}
+
+/// The contracts created by a factory, and how to read their logs
+struct FactoryState {
+    child_argument: usize,
+    /// Signature of each of the created contracts' filtered events by topic0
+    child_topics: HashMap<H256, String>,
+    children: Children,
+    decoder: Option<Arc<AbiDecoder>>,
+}
+
+struct CatchUp {
+    started: Instant,
+    reported: Instant,
+    /// Keeps catch-up slots free for this listener between ranges
+    _scheduled: Option<CatchingUp>,
+}
+
+impl PollState {
+    /// The next event, or `None` once the end block has been scanned
+    async fn next_event(&mut self) -> Option<EventData> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if let (Some(end), Some(current)) = (self.end_block, self.current_block) {
+                if current > end {
+                    return None;
+                }
+            }
+            self.poll().await;
+        }
+    }
+
+    async fn poll(&mut self) {
+        // While catching up, the next range is fetched right away
+        if self.polled && self.catch_up.is_none() {
+            tokio::time::sleep(self.poll_interval).await;
+        }
+        if let Some(ref pause) = self.pause {
+            pause.wait().await;
+        }
+        self.polled = true;
+
+        let span = info_span!(
+            "poll",
+            chain = %self.chain_name,
+            contract = %self.contract,
+            from_block = Empty,
+            to_block = Empty,
+            events = Empty
+        );
+        self.scan().instrument(span).await;
+    }
+
+    /// Fetch the next range of blocks and queue its events
+    async fn scan(&mut self) {
+        // Get the latest block number
+        let latest_block = match self.provider.get_block_number().await {
+            Ok(block) => block,
+            Err(e) => {
+                // Wait for the poll interval before trying again, even when catching up
+                warn!("{}: error fetching block number: {}", self.label(), e);
+                health::poll_failed(&self.label(), &e);
+                self.catch_up = None;
+                return;
+            }
+        };
+        let current_block = *self.current_block.get_or_insert(latest_block);
+
+        // The head block is left for the next poll, unless it's the last one to fetch
+        if latest_block < current_block || (latest_block == current_block && self.end_block != Some(current_block)) {
+            metrics::block_lag(&self.chain_name, &self.contract.to_string(), latest_block, 0);
+            health::polled(&self.label(), 0);
+            return;
+        }
+
+        // Fetch at most one range per poll so a burst of blocks doesn't
+        // turn into a single huge request. A range the node refuses as too
+        // large is split in half and fetched again right away.
+        let (to_block, logs) = loop {
+            let to_block = latest_block
+                .min(current_block.saturating_add(self.block_range - 1))
+                .min(self.end_block.unwrap_or(u64::MAX));
+            Span::current().record("from_block", current_block).record("to_block", to_block);
+
+            // Filter for the new blocks; several signatures match any of them
+            let topic0: Vec<H256> = self.event_topics.keys().copied().collect();
+            let filter = log_filter(&self.contract, &topic0, &self.topic_filter)
+                .from_block(current_block)
+                .to_block(to_block);
+
+            // Ranges behind the chain head wait for a catch-up slot, held until they're fetched
+            let _permit = match self.scheduler {
+                Some(ref scheduler) if to_block < latest_block => Some(scheduler.acquire(self.chain_id).await),
+                _ => None,
+            };
+
+            let logs = match self.log_source {
+                Some(ref source) => source.get_logs(&filter).await,
+                None => match self.provider.get_logs(&filter).await {
+                    Err(e) if is_pruned_error(&e) => match self.archive {
+                        Some(ref archive) => {
+                            if !self.on_archive {
+                                info!(
+                                    "{}: blocks from {} are pruned on the node, fetching them from the archive endpoint",
+                                    self.label(),
+                                    current_block
+                                );
+                                self.on_archive = true;
+                            }
+                            archive.get_logs(&filter).await
+                        }
+                        None => {
+                            self.skip_pruned(current_block, latest_block, &filter).await;
+                            return;
+                        }
+                    },
+                    logs => {
+                        if logs.is_ok() {
+                            self.on_archive = false;
+                        }
+                        logs
+                    }
+                },
+            };
+
+            match logs {
+                Ok(logs) => {
+                    // The created contracts' logs of the same range, now that
+                    // the contracts created in it are known too
+                    if self.factory.is_none() {
+                        break (to_block, logs);
+                    }
+                    match self.with_child_logs(logs, current_block, to_block).await {
+                        Ok(logs) => break (to_block, logs),
+                        Err(e) if is_range_error(&e) && to_block > current_block => {
+                            self.split_range(current_block, to_block, &e)
+                        }
+                        Err(e) => {
+                            warn!("{}: error fetching logs of created contracts: {}", self.label(), e);
+                            health::poll_failed(&self.label(), &e);
+                            self.catch_up = None;
+                            return;
+                        }
+                    }
+                }
+                Err(e) if is_range_error(&e) && to_block > current_block => self.split_range(current_block, to_block, &e),
+                Err(e) => {
+                    // Keep the cursor so the same range is fetched again next
+                    // poll, after the poll interval even when catching up
+                    warn!("{}: error fetching logs: {}", self.label(), e);
+                    health::poll_failed(&self.label(), &e);
+                    self.catch_up = None;
+                    return;
+                }
+            }
+        };
+        self.grow_range();
+
+        let events: Vec<EventData> = logs.iter().filter_map(|log| self.to_event(log)).collect();
+        Span::current().record("events", events.len());
+        if events.is_empty() {
+            if let Some(ref on_idle) = self.on_idle {
+                on_idle(to_block);
+            }
+        }
+        self.pending.extend(events);
+
+        self.current_block = Some(to_block + 1);
+        self.report_progress(to_block, latest_block);
+    }
+
+    /// Fetch ranges half the size of `from_block`–`to_block`, which the
+    /// node refused with `error`
+    fn split_range(&mut self, from_block: u64, to_block: u64, error: &anyhow::Error) {
+        let refused = to_block - from_block + 1;
+        self.block_range = (refused / 2).max(1);
+        self.accepted_ranges = 0;
+        // Only the first refusal is worth noting; the node's limit is then
+        // probed again every few ranges
+        if refused == self.max_block_range {
+            info!(
+                "{}: the node refused blocks {}–{} as too large, fetching fewer blocks at a time: {}",
+                self.label(),
+                from_block,
+                to_block,
+                error
+            );
+        } else {
+            debug!(
+                "{}: the node refused blocks {}–{}, fetching {} blocks at a time",
+                self.label(),
+                from_block,
+                to_block,
+                self.block_range
+            );
+        }
+    }
+
+    /// Double a range that was split once the node has accepted
+    /// [`RANGE_GROWTH_FETCHES`] ranges in a row, up to `max_block_range`,
+    /// as the results a range holds vary over the chain's history
+    fn grow_range(&mut self) {
+        if self.block_range >= self.max_block_range {
+            return;
+        }
+        self.accepted_ranges += 1;
+        if self.accepted_ranges >= RANGE_GROWTH_FETCHES {
+            self.block_range = self.block_range.saturating_mul(2).min(self.max_block_range);
+            self.accepted_ranges = 0;
+            debug!("{}: fetching {} blocks at a time", self.label(), self.block_range);
+        }
+    }
+
+    /// `log` as an event, or `None` when it doesn't match the filters.
+    /// Logs of contracts created by the factory are tagged and decoded with
+    /// the created contracts' events and ABI, and aren't subject to the
+    /// `where` filter.
+    fn to_event(&self, log: &Log) -> Option<EventData> {
+        let child = !self.contract.matches(&log.address);
+        let (topics, decoder) = match self.factory {
+            Some(ref factory) if child => (&factory.child_topics, factory.decoder.as_ref()),
+            None if child => return None,
+            _ => (&self.event_topics, self.decoder.as_ref()),
+        };
+
+        let signature = log.topics.first().and_then(|topic0| topics.get(topic0));
+        let mut event = log_to_event_data(
+            log,
+            self.chain_id,
+            &self.chain_name,
+            &log.address,
+            signature.map(String::as_str),
+        );
+        event.run = self.run.clone();
+        match decoder.and_then(|d| d.decode(log, &self.native_symbol)) {
+            Some(Ok(decoded)) => {
+                event.event_name = Some(decoded.name);
+                event.decoded = Some(decoded.params);
+                if !decoded.humanized.is_empty() {
+                    event.humanized = Some(decoded.humanized);
+                }
+                event.decode_error = decoded.error;
+            }
+            Some(Err(e)) => event.decode_error = Some(e),
+            None => {}
+        }
+
+        match self.filter {
+            Some(ref filter) if !child && !filter.matches(&event) => None,
+            _ => Some(event),
+        }
+    }
+
+    /// `logs` of the factory, with the contracts they announce added to its
+    /// children, merged with the children's logs from `from_block` to `to_block`
+    async fn with_child_logs(&self, mut logs: Vec<Log>, from_block: u64, to_block: u64) -> Result<Vec<Log>> {
+        let Some(ref factory) = self.factory else {
+            return Ok(logs);
+        };
+
+        let children: Vec<Address> = {
+            let mut children = lock(&factory.children);
+            for log in &logs {
+                match child_address(log, factory.child_argument) {
+                    Some(child) if children.insert(child) => info!(
+                        "{}: contract {:?} created at block {}, listening to it",
+                        self.label(),
+                        child,
+                        log.block_number.unwrap_or_default()
+                    ),
+                    Some(_) => {}
+                    None => warn!(
+                        "{}: argument {} of the event in transaction {:?} isn't an address",
+                        self.label(),
+                        factory.child_argument,
+                        log.transaction_hash.unwrap_or_default()
+                    ),
+                }
+            }
+            children.iter().copied().collect()
+        };
+        if children.is_empty() {
+            return Ok(logs);
+        }
+
+        let mut filter = Filter::new().from_block(from_block).to_block(to_block);
+        if children.len() <= ADDRESS_FILTER_LIMIT {
+            filter = filter.address(children.clone());
+        }
+        if !factory.child_topics.is_empty() {
+            filter = filter.topic0(factory.child_topics.keys().copied().collect::<Vec<_>>());
+        }
+        let provider = match self.archive {
+            Some(ref archive) if self.on_archive => archive,
+            _ => &self.provider,
+        };
+        let child_logs = match self.log_source {
+            Some(ref source) => source.get_logs(&filter).await?,
+            None => provider.get_logs(&filter).await?,
+        };
+        let children: HashSet<Address> = children.into_iter().collect();
+        logs.extend(child_logs.into_iter().filter(|log| children.contains(&log.address)));
+        logs.sort_by_key(|log| (log.block_number, log.log_index));
+        Ok(logs)
+    }
+
+    /// Track whether the listener is behind the chain head and report
+    /// progress while it catches up
+    fn report_progress(&mut self, scanned: u64, latest_block: u64) {
+        let behind = latest_block - scanned;
+        metrics::block_lag(&self.chain_name, &self.contract.to_string(), latest_block, behind);
+        health::polled(&self.label(), behind);
+        match self.catch_up {
+            None if behind > 0 => {
+                info!(
+                    "{}: {} blocks behind, catching up {} blocks at a time",
+                    self.label(),
+                    behind,
+                    self.block_range
+                );
+                let now = Instant::now();
+                self.catch_up = Some(CatchUp {
+                    started: now,
+                    reported: now,
+                    _scheduled: self.scheduler.as_ref().map(|scheduler| scheduler.catching_up(self.chain_id)),
+                });
+            }
+            None => {}
+            Some(ref catch_up) if behind == 0 => {
+                info!(
+                    "{}: caught up at block {} after {:.0?}",
+                    self.label(),
+                    scanned,
+                    catch_up.started.elapsed()
+                );
+                self.catch_up = None;
+            }
+            Some(ref mut catch_up) if catch_up.reported.elapsed() >= CATCH_UP_REPORT_INTERVAL => {
+                catch_up.reported = Instant::now();
+                info!("{}: at block {}, {} blocks behind", self.label(), scanned, behind);
+            }
+            Some(_) => {}
+        }
+    }
+
+    /// Move the cursor from `from_block` to the oldest block up to
+    /// `latest_block` that the node hasn't pruned, found by bisection with
+    /// single-block queries like `filter`
+    async fn skip_pruned(&mut self, from_block: u64, latest_block: u64, filter: &Filter) {
+        let (mut low, mut high) = (from_block, latest_block);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.provider.get_logs(&filter.clone().from_block(mid).to_block(mid)).await {
+                Ok(_) => high = mid,
+                Err(e) if is_pruned_error(&e) => low = mid + 1,
+                Err(e) => {
+                    // Keep the cursor and search again next poll
+                    warn!("{}: error looking for the oldest available block: {}", self.label(), e);
+                    return;
+                }
+            }
+        }
+        if low == from_block {
+            return;
+        }
+
+        warn!(
+            "{}: the node has pruned blocks {}–{}, skipping to block {}; \
+             set archive_rpc_urls (--archive-rpc-url) to fetch them",
+            self.label(),
+            from_block,
+            low.saturating_sub(1),
+            low
+        );
+        self.current_block = Some(low);
+    }
+
+    fn label(&self) -> String {
+        format!("{} {} ({})", self.chain_name, self.contract, signatures_label(&self.event_signatures))
+    }
+}
+
+impl Drop for PollState {
+    fn drop(&mut self) {
+        health::forget(&self.label());
+    }
+}
+
+/// Each signature by its topic0
+fn topics_of(signatures: &[String]) -> HashMap<H256, String> {
+    signatures
+        .iter()
+        .map(|signature| (compute_event_topic(signature), signature.clone()))
+        .collect()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// The filtered event signatures for log messages, e.g. `Deposit(...), Withdraw(...)`
+pub fn signatures_label(signatures: &[String]) -> String {
+    if signatures.is_empty() {
+        "all events".to_string()
+    } else {
+        signatures.join(", ")
+    }
+}
+
+fn is_pruned_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ProviderError>().is_some_and(is_pruned)
+}
+
+/// Whether the node refused a range of logs as too large
+fn is_range_error(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ProviderError>().is_some_and(is_range_too_large)
+}
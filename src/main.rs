@@ -1,321 +1,3558 @@
 use anyhow::{Context, Result};
-use chrono::Local;
-use clap::Parser;
-use ethers::prelude::*;
-use serde::{Deserialize, Serialize};
+use clap::{ArgGroup, Parser, Subcommand};
+use ethers::types::{Address, Log};
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+use tracing_subscriber::filter::{filter_fn, FilterExt};
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::EnvFilter;
+
+use listener::chains::{chain_info, get_rpc_urls_from_chain_id, native_symbol, read_rpc_urls_env};
+use listener::config::{
+    watch_config, ChainConfig, Config, ContractConfig, EventConfig, FactoryConfig, FirehoseConfig, ProxyConfig, RotationConfig,
+    SinkConfig, TemplateRef,
+};
+#[cfg(feature = "postgres")]
+use listener::config::CoordinationConfig;
+use listener::control::{self, Control, Status};
+#[cfg(feature = "postgres")]
+use listener::coordination::Coordinator;
+use listener::coverage::{topic_stats, CoverageReport};
+use listener::archive::ArchiveQuery;
+use listener::blocktime::BlockTimestamps;
+use listener::bootstrap::Snapshot;
+use listener::abi::AbiDecoder;
+use listener::anvil::{Anvil, TEST_EVENT};
+use listener::anomaly::AnomalyDetector;
+use listener::dedup::Dedup;
+use listener::diff::diff_events;
+use listener::discover::{discover_topics, DEFAULT_CHUNK_SIZE};
+use listener::enrich::{Enricher, Enrichment};
+use listener::ens::{self, EnsResolver};
+use listener::guess::SignatureGuesser;
+use listener::labels::Labels;
+use listener::metrics as prometheus;
+use listener::estimate::{estimate_backfill, format_size, BackfillTarget, DEFAULT_CONFIRM_ABOVE, DEFAULT_SAMPLES};
+use listener::event::{compute_event_topic, log_to_event_data, parse_topic};
+use listener::explain::explain_chain;
+use listener::hashchain::{verify_chain, HashChain};
+use listener::health;
+use listener::humanize::format_duration;
+use listener::listener::{signatures_label, DEFAULT_MAX_BLOCK_RANGE};
+use listener::presets::{erc20_contracts, Preset, TokenAmounts};
+use listener::output::{print_json, read_events_file, read_events_range, read_record_lines};
+use listener::pipeline::{self, Enrich, Outputs, Pipeline};
+use listener::provider::mask_api_key;
+use listener::render::EventTemplate;
+use listener::rotate::RotatingFile;
+use listener::routing::Router;
+use listener::ruletest::run_test;
+use listener::run::config_hash;
+use listener::runtime::{ContractKey, ListenerSet};
+use listener::siem::SiemFormat;
+use listener::signatures::{compute_selector, guess_signature, normalize_signature};
+use listener::sinks::{self, Sink, SinkKind, SinkWorker};
+use listener::state::{Cursor, StateFile};
+use listener::supervisor::SupervisorMetrics;
+use listener::price::PriceEnricher;
+use listener::proxy::{fetch_abi, ProxyResolver};
+use listener::token::{TokenCache, TokenMetadataEnricher};
+use listener::transport;
+use listener::tx::TransactionEnricher;
+#[cfg(feature = "otel")]
+use listener::telemetry::{self, Telemetry};
+use listener::template::{apply_templates, AppliedTemplate, Pin, Registry, DEFAULT_TEMPLATE_DIR};
+use listener::update::{self, Releases};
+use listener::webhook::WebhookQueue;
+use listener::{ProviderManager, RetryPolicy, RunMetadata};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Smart Contract Event Listener", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// Smart contract address to listen to
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Without a subcommand the listener listens, as with `listen`, so
+    // commands written before the subcommands existed keep working
+    #[command(flatten)]
+    listen: ListenArgs,
+
+    /// Diagnostics written to stderr: error, warn, info, debug, or trace
+    /// (default: info, or RUST_LOG when set)
+    #[arg(long, value_name = "LEVEL", global = true, value_parser = ["error", "warn", "info", "debug", "trace"])]
+    log_level: Option<String>,
+
+    /// Format of the diagnostics: text or json (one object per line)
+    #[arg(long, value_name = "FORMAT", global = true, default_value = "text", value_parser = ["text", "json"])]
+    log_format: String,
+}
+
+/// Options of `listen` and `backfill`
+#[derive(clap::Args, Debug)]
+#[command(group(ArgGroup::new("listener").args(["contract", "event", "preset", "topic1", "topic2", "topic3"]).multiple(true)))]
+struct ListenArgs {
+    /// Config file (TOML or YAML) defining chains, contracts, events, and sinks.
+    /// CLI flags override values from the file
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Profile of the config file to apply, e.g. prod
+    #[arg(long, requires = "config")]
+    profile: Option<String>,
+
+    /// Smart contract address to listen to (required unless --config,
+    /// --template, --event, --preset, or --topic1..3 is given; without it,
+    /// the events and topics are matched on every contract of the chain)
+    #[arg(short, long, required_unless_present_any = ["config", "template", "listener", "anvil"])]
+    contract: Option<String>,
+
+    /// Without --contract, only events emitted by these contracts
+    /// (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',', requires = "listener", conflicts_with = "contract")]
+    allow_address: Vec<String>,
+
+    /// Without --contract, ignore events emitted by these contracts
+    /// (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',', requires = "listener", conflicts_with = "contract")]
+    deny_address: Vec<String>,
+
+    /// Protocol template to listen to, by name, path, or URL (repeatable)
+    #[arg(long)]
+    template: Vec<String>,
+
+    /// Directory templates given by name are looked up in (default: templates)
+    #[arg(long)]
+    template_dir: Option<String>,
+
+    /// Chain ID (e.g., 1=Ethereum, 137=Polygon, 42161=Arbitrum, 8453=Base, 56=BSC)
+    #[arg(long)]
+    chain_id: Option<u64>,
+
+    /// RPC endpoint URL (optional, overrides chain-id)
+    /// Repeat the flag or pass a comma-separated list to enable failover
+    #[arg(short, long, value_delimiter = ',')]
+    rpc_url: Vec<String>,
+
+    /// Dev mode: start a local Anvil chain with a test contract that emits
+    /// Transfer events, and listen to it (needs Foundry's anvil)
+    #[arg(long, conflicts_with_all = ["rpc_url", "chain_id", "contract"])]
+    anvil: bool,
+
+    /// Seconds between the test events of --anvil (default: 2)
+    #[arg(long, requires = "anvil", value_parser = clap::value_parser!(u64).range(1..))]
+    anvil_interval: Option<u64>,
+
+    /// Archive RPC endpoint for blocks the --rpc-url nodes have pruned
+    /// (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    archive_rpc_url: Vec<String>,
+
+    /// Firehose gRPC endpoint to stream logs from instead of eth_getLogs
+    /// (needs the `firehose` feature)
+    #[arg(long)]
+    firehose_endpoint: Option<String>,
+
+    /// API key for --firehose-endpoint
+    #[arg(long)]
+    firehose_api_key: Option<String>,
+
+    /// Event signature to filter (optional, e.g., "Transfer(address,address,uint256)")
+    /// Repeat to listen to several events with the same RPC calls; if not
+    /// provided, will listen to all events
     #[arg(short, long)]
-    contract: String,
+    event: Vec<String>,
+
+    /// Only events whose first indexed argument is one of these addresses or
+    /// 32-byte hex values, matched by the node (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    topic1: Vec<String>,
+
+    /// Like --topic1, for the second indexed argument (e.g. the recipient of
+    /// a Transfer)
+    #[arg(long, value_delimiter = ',')]
+    topic2: Vec<String>,
+
+    /// Like --topic1, for the third indexed argument
+    #[arg(long, value_delimiter = ',')]
+    topic3: Vec<String>,
+
+    /// Only events matching this expression over the decoded arguments,
+    /// e.g. "value > 1e18 && to == 0xabc..." (needs --abi for arguments)
+    #[arg(long = "where", value_name = "EXPR", requires = "listener")]
+    filter: Option<String>,
+
+    /// JSON ABI file (or Hardhat/Foundry artifact) used to decode event arguments
+    #[arg(long)]
+    abi: Option<String>,
+
+    /// Token standard to listen to: its events (unless --event is given)
+    /// are decoded without --abi, and ERC-20 amounts formatted with the
+    /// token's decimals (erc20, erc721, or erc1155)
+    #[arg(long)]
+    preset: Option<Preset>,
+
+    /// Print the eth_getLogs filter each listener sends to the node and the
+    /// criteria applied to the returned logs, then exit
+    #[arg(long)]
+    explain_filter: bool,
+
+    /// Treat --contract as a factory: every --event announces a new
+    /// contract, which is listened to from then on
+    #[arg(long, requires_all = ["contract", "event", "child_argument"])]
+    factory: bool,
+
+    /// Position of the new contract's address among the creation event's
+    /// arguments, indexed ones first (e.g. 2 for Uniswap V2's PairCreated)
+    #[arg(long, requires = "factory")]
+    child_argument: Option<usize>,
+
+    /// Event signature to listen to on the contracts created by the factory
+    /// (repeatable; default: all events)
+    #[arg(long, requires = "factory")]
+    child_event: Vec<String>,
+
+    /// JSON ABI file used to decode the events of the created contracts
+    #[arg(long, requires = "factory")]
+    child_abi: Option<String>,
+
+    /// Start block number (optional, defaults to latest)
+    #[arg(short, long)]
+    start_block: Option<u64>,
+
+    /// Poll interval in milliseconds (default: 1000ms = 1 second)
+    #[arg(short, long)]
+    poll_interval_ms: Option<u64>,
+
+    /// Maximum retries (with exponential backoff) for a failed RPC call (default: 5)
+    #[arg(long)]
+    max_retries: Option<u32>,
+
+    /// Most blocks fetched per eth_getLogs call; further behind, the
+    /// listener catches up range by range, splitting ranges the node
+    /// refuses as too large (default: 2000)
+    #[arg(long)]
+    max_block_range: Option<u64>,
+
+    /// Most eth_getLogs ranges fetched at once across all chains while
+    /// catching up; chains with a higher catch_up_priority go first
+    /// (default: unlimited)
+    #[arg(long)]
+    catch_up_concurrency: Option<usize>,
+
+    /// Events queued in front of each processing stage (enrich, deliver)
+    /// before fetching waits for them (default: 1024)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    queue_size: Option<u64>,
+
+    /// Events enriched at once; they're still delivered in order (default: 8)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    enrich_concurrency: Option<u64>,
+
+    /// Sub-ranges sampled per contract to estimate a backfill from
+    /// --start-block before it starts; 0 skips the estimate (default: 5)
+    #[arg(long)]
+    backfill_samples: Option<usize>,
+
+    /// Ask for confirmation before a backfill expected to yield more events
+    /// than this (default: 1000000)
+    #[arg(long)]
+    confirm_backfill_above: Option<u64>,
+
+    /// Start a backfill without asking, however large its estimate
+    #[arg(short, long)]
+    yes: bool,
+
+    /// Output format: pretty, json, compact, logfmt, template, cef, leef, or
+    /// parquet (default: pretty). Parquet files are written into the
+    /// --output-file directory (needs the `parquet` feature)
+    #[arg(long)]
+    output_format: Option<String>,
+
+    /// Template file (MiniJinja) each event is rendered with for stdout and
+    /// --output-file; implies --output-format template
+    #[arg(long, value_name = "FILE")]
+    output_template: Option<String>,
+
+    /// Show decoded amounts (wei/gwei) and durations in readable units
+    /// next to the raw values, in every output format (needs --abi)
+    #[arg(long)]
+    humanize: bool,
+
+    /// Include the log exactly as the node returned it (topics, data,
+    /// removed flag, block hash, transaction index, ...) as `raw` in JSON
+    /// outputs
+    #[arg(long)]
+    include_raw: bool,
+
+    /// Output file path (optional, writes to stdout if not provided)
+    #[arg(long)]
+    output_file: Option<String>,
+
+    /// Output file mode: jsonl, or archive for a zstd-compressed, indexed
+    /// archive in the --output-file directory (default: jsonl)
+    #[arg(long)]
+    file_mode: Option<String>,
+
+    /// Roll over --output-file before it grows past this size, e.g. 100M or 1G
+    #[arg(long)]
+    rotate_size: Option<String>,
+
+    /// Roll over --output-file at local midnight
+    #[arg(long)]
+    rotate_daily: bool,
+
+    /// gzip rotated output files
+    #[arg(long)]
+    rotate_compress: bool,
+
+    /// Rotated output files to keep; older ones are deleted (default: all)
+    #[arg(long)]
+    rotate_keep: Option<usize>,
+
+    /// Webhook URL to POST events to (optional)
+    #[arg(long)]
+    webhook_url: Option<String>,
+
+    /// Directory webhook deliveries are queued in until accepted
+    /// (default: webhook-queue)
+    #[arg(long)]
+    webhook_queue_dir: Option<String>,
+
+    /// Attempts per webhook delivery before it's moved to the dead-letter
+    /// file (default: 10)
+    #[arg(long)]
+    webhook_max_attempts: Option<u32>,
+
+    /// JSON Lines file for webhook deliveries that failed on every attempt
+    /// (default: dead-letter.jsonl in the queue directory)
+    #[arg(long)]
+    webhook_dead_letter: Option<String>,
+
+    /// Sign webhook payloads with HMAC-SHA256 under this secret, sent in the
+    /// X-Signature header as sha256=<hex>
+    #[arg(long)]
+    webhook_secret: Option<String>,
+
+    /// Send up to this many webhook events per POST as a JSON array
+    /// (default: 100 with --webhook-batch-ms)
+    #[arg(long)]
+    webhook_batch_size: Option<usize>,
+
+    /// Wait this long after the first event of a webhook batch for more, in
+    /// milliseconds (default: 200 with --webhook-batch-size)
+    #[arg(long)]
+    webhook_batch_ms: Option<u64>,
+
+    /// Most webhook requests in flight at once; each contract's deliveries
+    /// still go out in order (default: 16)
+    #[arg(long)]
+    webhook_concurrency: Option<usize>,
+
+    /// Client certificate (PEM) presented to webhook receivers that require
+    /// mTLS
+    #[arg(long, requires = "webhook_client_key")]
+    webhook_client_cert: Option<String>,
+
+    /// Private key (PEM) of --webhook-client-cert
+    #[arg(long, requires = "webhook_client_cert")]
+    webhook_client_key: Option<String>,
+
+    /// CA certificates (PEM) trusted for webhook receivers, in addition to
+    /// the system roots
+    #[arg(long)]
+    webhook_ca_cert: Option<String>,
+
+    /// Extra header sent with every webhook delivery, as KEY=VALUE (repeatable)
+    #[arg(long = "webhook-header", value_name = "KEY=VALUE", value_parser = parse_header)]
+    webhook_headers: Vec<(String, String)>,
+
+    /// Slack incoming webhook to post events to as formatted messages
+    #[arg(long)]
+    slack_webhook: Option<String>,
+
+    /// Discord webhook to post events to as formatted messages
+    #[arg(long)]
+    discord_webhook: Option<String>,
+
+    /// Telegram bot token; events are sent to --telegram-chat-id
+    #[arg(long)]
+    telegram_token: Option<String>,
+
+    /// Telegram chat, group, or channel (@name) to send events to
+    #[arg(long)]
+    telegram_chat_id: Option<String>,
+
+    /// Telegram message template (HTML formatting) with placeholders such as
+    /// {event}, {chain}, {tx_url}, or {args.NAME}
+    #[arg(long)]
+    telegram_template: Option<String>,
+
+    /// Telegram messages per event name and minute; 0 for no limit (default: 10)
+    #[arg(long)]
+    telegram_rate_limit: Option<usize>,
+
+    /// SMTP server to mail events through; needs --smtp-from and --smtp-to
+    #[arg(long)]
+    smtp_host: Option<String>,
+
+    /// SMTP port (default: 587 with starttls, 465 with tls, 25 with none)
+    #[arg(long)]
+    smtp_port: Option<u16>,
+
+    /// SMTP TLS mode: starttls (default), tls, or none
+    #[arg(long)]
+    smtp_tls: Option<String>,
+
+    /// SMTP username
+    #[arg(long)]
+    smtp_username: Option<String>,
+
+    /// SMTP password (default: the SMTP_PASSWORD environment variable)
+    #[arg(long)]
+    smtp_password: Option<String>,
+
+    /// Sender address of event emails
+    #[arg(long)]
+    smtp_from: Option<String>,
+
+    /// Recipient of event emails (repeatable or comma-separated)
+    #[arg(long, value_delimiter = ',')]
+    smtp_to: Vec<String>,
+
+    /// Subject of single-event emails, with placeholders such as {title} or
+    /// {args.NAME} (default: {title})
+    #[arg(long)]
+    smtp_subject: Option<String>,
+
+    /// Mail the events of every this many minutes as one digest email
+    #[arg(long)]
+    smtp_digest_minutes: Option<u64>,
+
+    /// PagerDuty integration (routing) key; the --alert-on events open incidents
+    #[arg(long)]
+    pagerduty_routing_key: Option<String>,
+
+    /// Opsgenie API key; the --alert-on events open alerts
+    #[arg(long)]
+    opsgenie_api_key: Option<String>,
+
+    /// Event signature, name, or topic0 that opens a PagerDuty/Opsgenie
+    /// incident, as EVENT=SEVERITY with severity critical (default), error,
+    /// warning, or info (repeatable); also sets the journald PRIORITY, the
+    /// event log entry type, the syslog severity, and the CEF/LEEF severity
+    #[arg(long, value_name = "EVENT=SEVERITY", value_parser = parse_alert)]
+    alert_on: Vec<(String, String)>,
+
+    /// Log events to the systemd journal with structured fields
+    #[arg(long)]
+    journald: bool,
+
+    /// SYSLOG_IDENTIFIER of the journal entries (default: listener)
+    #[arg(long)]
+    journald_identifier: Option<String>,
+
+    /// Write events to the Windows event log (Application log)
+    #[arg(long)]
+    eventlog: bool,
+
+    /// Event source of the event log entries, registered with `eventlog
+    /// register` (default: listener)
+    #[arg(long)]
+    eventlog_source: Option<String>,
+
+    /// Syslog collector to send RFC 5424 messages to: udp://host:514,
+    /// tcp://host:601, or tls://host:6514
+    #[arg(long, value_name = "URL")]
+    syslog_address: Option<String>,
+
+    /// Syslog facility: user (default), daemon, local0 to local7, ...
+    #[arg(long)]
+    syslog_facility: Option<String>,
+
+    /// Syslog message format: rfc5424 (default, fields as structured data),
+    /// cef, or leef
+    #[arg(long)]
+    syslog_format: Option<String>,
+
+    /// PostgreSQL connection string; events are upserted into --postgres-table
+    #[arg(long)]
+    postgres_url: Option<String>,
+
+    /// PostgreSQL table for events (default: events)
+    #[arg(long)]
+    postgres_table: Option<String>,
+
+    /// Events per PostgreSQL INSERT (default: 100)
+    #[arg(long)]
+    postgres_batch_size: Option<usize>,
+
+    /// NOTIFY this PostgreSQL channel with the id of each stored event
+    #[arg(long, value_name = "CHANNEL")]
+    postgres_notify: Option<String>,
+
+    /// Kafka bootstrap servers; events are published to --kafka-topic
+    #[arg(long)]
+    kafka_brokers: Option<String>,
+
+    /// Kafka topic for events
+    #[arg(long)]
+    kafka_topic: Option<String>,
+
+    /// Produce to Kafka in transactions under this ID, for exactly-once delivery
+    #[arg(long, value_name = "ID")]
+    kafka_transactional_id: Option<String>,
+
+    /// NATS server URL; events are published to --nats-subject
+    #[arg(long)]
+    nats_url: Option<String>,
+
+    /// NATS subject for events
+    #[arg(long)]
+    nats_subject: Option<String>,
+
+    /// JetStream stream for at-least-once delivery (created if missing)
+    #[arg(long)]
+    nats_stream: Option<String>,
+
+    /// Redis URL; events are added to --redis-stream and/or published to --redis-channel
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Redis stream to XADD events to
+    #[arg(long)]
+    redis_stream: Option<String>,
+
+    /// Redis pub/sub channel to PUBLISH events to
+    #[arg(long)]
+    redis_channel: Option<String>,
+
+    /// MQTT broker (mqtt://host:port or mqtts://); events are published to --mqtt-topic
+    #[arg(long)]
+    mqtt_broker: Option<String>,
+
+    /// MQTT topic for events
+    #[arg(long)]
+    mqtt_topic: Option<String>,
+
+    /// MQTT QoS: 0, 1, or 2 (default: 1)
+    #[arg(long)]
+    mqtt_qos: Option<u8>,
+
+    /// Google Cloud Pub/Sub topic to publish events to, ordered per contract
+    #[arg(long)]
+    pubsub_topic: Option<String>,
+
+    /// Google Cloud project of --pubsub-topic (default: GOOGLE_CLOUD_PROJECT)
+    #[arg(long)]
+    pubsub_project: Option<String>,
+
+    /// Create the Pub/Sub topic if it doesn't exist
+    #[arg(long)]
+    pubsub_create_topic: bool,
+
+    /// Azure event hub to send events to; the connection string is read from
+    /// EVENTHUBS_CONNECTION_STRING unless configured
+    #[arg(long)]
+    eventhubs_name: Option<String>,
+
+    /// Partition key template for --eventhubs-name, e.g. "{contract}:{event}"
+    /// (default: {contract})
+    #[arg(long)]
+    eventhubs_partition_key: Option<String>,
+
+    /// S3 or GCS location (s3://bucket/prefix, gs://bucket/prefix) to upload
+    /// batches of gzip-compressed JSON Lines to
+    #[arg(long)]
+    object_storage_url: Option<String>,
+
+    /// Events per uploaded object (default: 10000)
+    #[arg(long)]
+    object_storage_batch_size: Option<usize>,
+
+    /// Seconds after which a batch is uploaded even if not full (default: 600)
+    #[arg(long)]
+    object_storage_interval: Option<u64>,
+
+    /// SQLite database file to store events in (read back with `query`)
+    #[arg(long)]
+    sqlite: Option<String>,
+
+    /// Keep hourly event counts and daily ERC-20 transfer volumes in rollup
+    /// tables of the PostgreSQL and SQLite sinks
+    #[arg(long)]
+    rollups: bool,
+
+    /// Add prev_hash/hash fields linking every record to the previous one
+    #[arg(long)]
+    hash_chain: bool,
+
+    /// Drop events already delivered, by block hash, transaction hash, and
+    /// log index, also across restarts
+    #[arg(long)]
+    dedup: bool,
+
+    /// Most recently delivered events --dedup remembers (default: 10000)
+    #[arg(long, value_name = "EVENTS")]
+    dedup_window: Option<usize>,
+
+    /// Check GitHub for a newer release on startup and warn if there is one
+    #[arg(long)]
+    check_updates: bool,
+
+    /// Serve Prometheus metrics at http://ADDR/metrics, e.g. 0.0.0.0:9090
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<String>,
+
+    /// Serve liveness and readiness probes at http://ADDR/healthz and
+    /// http://ADDR/readyz, e.g. 0.0.0.0:8080
+    #[arg(long, value_name = "ADDR")]
+    health_addr: Option<String>,
+
+    /// Blocks a listener may be behind the chain head and still be ready
+    /// (default: 100)
+    #[arg(long, value_name = "BLOCKS")]
+    health_max_lag: Option<u64>,
+
+    /// Take pause, resume, and status commands on this Unix socket (see the
+    /// `control` subcommand)
+    #[arg(long, value_name = "PATH")]
+    control_socket: Option<String>,
+
+    /// File used to persist listener state (default: listener-state.json)
+    #[arg(long)]
+    state_file: Option<String>,
+
+    /// Load a snapshot export from another instance (JSON Lines, archive, or
+    /// SQLite) into the database sinks, then listen from its last block
+    #[arg(long)]
+    bootstrap: Option<String>,
+
+    /// PostgreSQL database shared with other listener processes; the
+    /// contracts are partitioned between them through leases in it
+    #[arg(long)]
+    coordination_url: Option<String>,
+
+    /// Name of this process among the coordinated ones (default: host name
+    /// and a random suffix)
+    #[arg(long)]
+    node_id: Option<String>,
+
+    /// Seconds a coordination lease lasts without renewal; a stopped
+    /// process's contracts are taken over after this (default: 30)
+    #[arg(long)]
+    lease_secs: Option<u64>,
+
+    /// Enrichment to apply to every event (repeatable or comma-separated):
+    /// token-metadata adds the emitting contract's symbol, name, and
+    /// decimals and formats its token amounts; usd-price adds the
+    /// approximate USD value of token amounts; tx adds the sender,
+    /// recipient, value, and gas cost of the emitting transaction
+    #[arg(long, value_delimiter = ',', value_parser = ["token-metadata", "usd-price", "tx"])]
+    enrich: Vec<String>,
+
+    /// Coingecko API key for --enrich usd-price (demo key, or a pro key
+    /// with a pro-api URL in the config)
+    #[arg(long)]
+    coingecko_api_key: Option<String>,
+
+    /// Don't look up the on-chain timestamp of each event's block
+    #[arg(long)]
+    no_block_timestamps: bool,
+
+    /// Don't guess the event of logs no ABI decoded from the bundled
+    /// well-known signatures
+    #[arg(long, conflicts_with = "openchain")]
+    no_signature_guesses: bool,
+
+    /// Also look up the topic0 of logs no ABI decoded in the openchain.xyz
+    /// signature database
+    #[arg(long)]
+    openchain: bool,
+
+    /// JSON file of names by address (e.g. "Binance 14"); events whose
+    /// contract or address arguments are listed get their labels
+    #[arg(long)]
+    labels: Option<String>,
+
+    /// Add the ENS names of the contract and of address arguments, looked
+    /// up on Ethereum mainnet
+    #[arg(long)]
+    resolve_ens: bool,
+
+    /// Mainnet RPC endpoint for --resolve-ens (default: the endpoint of
+    /// a listened chain with ID 1, then ETHEREUM_RPC_URL)
+    #[arg(long)]
+    ens_rpc_url: Option<String>,
+
+    /// Decode the events of EIP-1967 and similar proxies with their
+    /// implementation's ABI, fetched from Sourcify (and Etherscan with
+    /// --etherscan-api-key)
+    #[arg(long)]
+    resolve_proxies: bool,
+
+    /// Etherscan API key for --resolve-proxies, for implementations
+    /// verified only on Etherscan
+    #[arg(long)]
+    etherscan_api_key: Option<String>,
+
+    /// Longest a single enrichment lookup may take per event, in
+    /// milliseconds (default: 500)
+    #[arg(long)]
+    enrich_timeout_ms: Option<u64>,
+
+    /// Longest all enrichment lookups together may take per event, in
+    /// milliseconds; events are then delivered with partial_enrichment
+    /// (default: 1000)
+    #[arg(long)]
+    enrich_budget_ms: Option<u64>,
+
+    /// Flag events whose data size or transaction gas usage is an outlier
+    /// for their event type with an anomaly field
+    #[arg(long)]
+    flag_anomalies: bool,
+
+    /// Modified z-score above which --flag-anomalies flags a value (default: 3.5)
+    #[arg(long, requires = "flag_anomalies")]
+    anomaly_threshold: Option<f64>,
+
+    /// Record every JSON-RPC request and response into DIR/rpc.jsonl, with
+    /// API keys masked, for debugging provider issues
+    #[arg(long, value_name = "DIR", global = true)]
+    capture_rpc: Option<PathBuf>,
+
+    /// Answer JSON-RPC requests from a capture in DIR instead of the
+    /// endpoints, to re-run deterministically
+    #[arg(long, value_name = "DIR", global = true, conflicts_with = "capture_rpc")]
+    replay_rpc: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Listen to contracts and deliver their events; the default when no
+    /// subcommand is given
+    Listen(Box<ListenArgs>),
+    /// Fetch the events of past blocks from --start-block to --to-block,
+    /// deliver them like `listen`, and exit
+    Backfill(Box<BackfillArgs>),
+    /// Deliver the events of an export to the configured outputs again,
+    /// paced like they were detected, without connecting to any chain
+    #[command(mut_arg("contract", |arg| arg.required_unless_present("input")))]
+    Replay(Box<ReplayArgs>),
+    /// Fetch contract ABIs
+    Abi(AbiArgs),
+    /// Print where a listener is: asks a running listener through its
+    /// control socket, or reads the checkpoint a stopped one saved
+    Status(StatusArgs),
+    /// Compare two NDJSON exports and report missing, extra, and changed events
+    Diff(DiffArgs),
+    /// Verify the hash chain of an NDJSON export written with --hash-chain
+    Verify {
+        /// Export to verify (JSON Lines file or archive directory)
+        file: String,
+    },
+    /// Print events from an export or archive as JSON Lines
+    Export(ExportArgs),
+    /// Scan recent blocks of a contract and report which events it emits
+    Discover(DiscoverArgs),
+    /// Report which events declared in an ABI were observed in a range of
+    /// blocks and which never fired
+    Coverage(CoverageArgs),
+    /// Read events stored with --sqlite as JSON Lines or CSV
+    #[cfg(feature = "sqlite")]
+    Query(QueryArgs),
+    /// Replace this binary with a release from GitHub
+    SelfUpdate(SelfUpdateArgs),
+    /// Manage the local registry of protocol templates
+    Template(TemplateArgs),
+    /// Print the topic0 of event signatures and the selector of function
+    /// signatures, after normalizing them
+    Hash(HashArgs),
+    /// Decode a raw log given as --topics and --data, or JSON logs read from
+    /// stdin, and print the event
+    Decode(DecodeArgs),
+    /// Check a config file
+    Config(ConfigArgs),
+    /// Pause, resume, or query a listener running with --control-socket
+    Control(ControlArgs),
+    /// Register the event source of the Windows event log sink
+    #[cfg(windows)]
+    Eventlog(EventlogArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct BackfillArgs {
+    /// Last block to fetch (default: the chain head when the backfill starts)
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    #[command(flatten)]
+    listen: ListenArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReplayArgs {
+    /// Events to replay: a JSON Lines export (.jsonl or .jsonl.gz), an
+    /// archive directory, or a SQLite database
+    #[arg(long)]
+    input: String,
+
+    /// 1x for the original timing, 10x for ten times faster, or max to
+    /// replay without pauses
+    #[arg(long, default_value = "1x")]
+    speed: String,
+
+    #[command(flatten)]
+    listen: ListenArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct AbiArgs {
+    #[command(subcommand)]
+    command: AbiCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum AbiCommand {
+    /// Download the verified ABI of a contract from Sourcify, and from
+    /// Etherscan with --etherscan-api-key, for use with --abi
+    Fetch {
+        /// Contract address
+        address: Address,
+
+        /// Chain of the contract (default: 1)
+        #[arg(long, default_value_t = 1)]
+        chain_id: u64,
+
+        /// Etherscan API key, for contracts verified only on Etherscan
+        #[arg(long)]
+        etherscan_api_key: Option<String>,
+
+        /// Write the ABI to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct StatusArgs {
+    /// Control socket of the running listener (its --control-socket)
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// State file of the stopped listener (default: listener-state.json)
+    #[arg(long)]
+    state_file: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ControlArgs {
+    #[command(subcommand)]
+    command: ControlCommand,
+
+    /// Control socket of the running listener
+    #[arg(long, global = true, value_name = "PATH")]
+    socket: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+enum ControlCommand {
+    /// Stop polling, keeping the block cursors, until resumed
+    Pause,
+    /// Poll again from where the listener paused
+    Resume,
+    /// Print whether the listener is paused and where each contract is
+    Status,
+}
+
+#[cfg(windows)]
+#[derive(clap::Args, Debug)]
+struct EventlogArgs {
+    #[command(subcommand)]
+    command: EventlogCommand,
+
+    /// Event source (default: listener)
+    #[arg(long, global = true)]
+    source: Option<String>,
+}
+
+#[cfg(windows)]
+#[derive(Subcommand, Debug)]
+enum EventlogCommand {
+    /// Register the source in the Application log; run from an elevated prompt
+    Register {
+        /// Message file of the source (default: the .NET Framework's
+        /// EventLogMessages.dll)
+        #[arg(long)]
+        message_file: Option<String>,
+    },
+    /// Remove the registration of the source
+    Unregister,
+}
+
+#[derive(clap::Args, Debug)]
+struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Run the example events under each contract's `tests` against its
+    /// filters; fails when one doesn't match as expected
+    Test {
+        /// Config file (TOML, YAML, or JSON)
+        file: PathBuf,
+
+        /// Profile of the config file to apply, e.g. prod
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct HashArgs {
+    /// Signatures, e.g. Transfer(address,address,uint256) or
+    /// "event Transfer(address indexed from, address indexed to, uint value)"
+    #[arg(required = true)]
+    signatures: Vec<String>,
+
+    /// Print JSON Lines instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct DecodeArgs {
+    /// JSON ABI file declaring the event; without it the signature is
+    /// guessed from topic0
+    #[arg(long)]
+    abi: Option<String>,
+
+    /// The log's topics, topic0 first; without them JSON logs are read from
+    /// stdin
+    #[arg(long, num_args = 1.., value_delimiter = ',')]
+    topics: Vec<String>,
+
+    /// The log's data (default: empty)
+    #[arg(long, requires = "topics")]
+    data: Option<String>,
+
+    /// Look topic0 up on openchain.xyz when guessing the signature
+    #[arg(long)]
+    openchain: bool,
+
+    /// Chain the log is from, for amounts in its native token
+    #[arg(long)]
+    chain_id: Option<u64>,
+
+    /// Print each event as JSON instead of text
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct TemplateArgs {
+    #[command(subcommand)]
+    command: TemplateCommand,
+
+    /// Template directory holding the registry (default: templates)
+    #[arg(long, global = true)]
+    template_dir: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum TemplateCommand {
+    /// Download a template and its ABIs into the registry, pinned by checksum;
+    /// reference it in a config as name@version
+    Add {
+        /// URL of the template's TOML file
+        url: String,
+
+        /// Expected SHA-256 of the template file (hex)
+        #[arg(long)]
+        sha256: Option<String>,
+
+        /// Ethereum address that must have signed the template (EIP-191)
+        #[arg(long)]
+        signer: Option<Address>,
+
+        /// URL of the signature (default: the template URL with .sig appended)
+        #[arg(long, requires = "signer")]
+        signature_url: Option<String>,
+    },
+    /// List the templates in the registry
+    List,
+    /// Remove a template version from the registry
+    Remove {
+        /// Template as name@version
+        template: String,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct SelfUpdateArgs {
+    /// Only report whether a newer release is available
+    #[arg(long)]
+    check: bool,
+
+    /// Install this version instead of the latest (e.g. 0.3.0)
+    #[arg(long)]
+    version: Option<String>,
+
+    /// Install even if the release isn't newer than this binary
+    #[arg(long)]
+    force: bool,
+
+    /// Install a release that has no checksum for this target's archive,
+    /// without verifying the download
+    #[arg(long)]
+    allow_unverified: bool,
+
+    /// GitHub repository to update from, as owner/name (default: the one
+    /// this binary was built from)
+    #[arg(long)]
+    repo: Option<String>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(clap::Args, Debug)]
+struct QueryArgs {
+    /// SQLite database written with --sqlite
+    #[arg(long)]
+    sqlite: String,
+
+    /// Event name (e.g. Transfer), full signature, or topic0 hash
+    #[arg(short, long)]
+    event: Option<String>,
+
+    /// Only events emitted by this contract
+    #[arg(short, long)]
+    contract: Option<String>,
+
+    /// Only events at or after this block
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Only events at or before this block
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Maximum number of events to print
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// Output format: json (JSON Lines) or csv
+    #[arg(long, default_value = "json")]
+    format: String,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiscoverArgs {
+    /// Contract address to scan
+    #[arg(short, long)]
+    contract: String,
+
+    /// Chain ID (RPC URLs are read from the chain's environment variable)
+    #[arg(long)]
+    chain_id: Option<u64>,
+
+    /// RPC endpoint URL (overrides chain-id); repeat or comma-separate for failover
+    #[arg(short, long, value_delimiter = ',')]
+    rpc_url: Vec<String>,
+
+    /// Number of blocks to scan, ending at --to-block (default: 1000)
+    #[arg(long, default_value_t = 1000)]
+    blocks: u64,
+
+    /// First block to scan (overrides --blocks)
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Last block to scan (default: latest)
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// JSON ABI file used to name the topics found
+    #[arg(long)]
+    abi: Option<String>,
+
+    /// Print the statistics as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct CoverageArgs {
+    /// JSON ABI file declaring the events to cover
+    #[arg(long)]
+    abi: String,
+
+    /// Contract address to scan (with --export, only its events count)
+    #[arg(short, long, required_unless_present = "export")]
+    contract: Option<String>,
+
+    /// Count the events of an export (JSON Lines file or archive directory)
+    /// instead of scanning the chain
+    #[arg(long, conflicts_with_all = ["chain_id", "rpc_url"])]
+    export: Option<String>,
+
+    /// Chain ID (RPC URLs are read from the chain's environment variable)
+    #[arg(long)]
+    chain_id: Option<u64>,
+
+    /// RPC endpoint URL (overrides chain-id); repeat or comma-separate for failover
+    #[arg(short, long, value_delimiter = ',')]
+    rpc_url: Vec<String>,
+
+    /// Number of blocks to scan, ending at --to-block (default: 1000)
+    #[arg(long, default_value_t = 1000)]
+    blocks: u64,
+
+    /// First block to scan (overrides --blocks)
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Last block to scan (default: latest)
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Fail when a declared event never fired
+    #[arg(long)]
+    strict: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    /// Archive directory (--file-mode archive) or JSON Lines file
+    source: String,
+
+    /// Only export events at or after this block
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Only export events at or before this block
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Only export events with this signature or topic0 hash
+    #[arg(short, long)]
+    event: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffArgs {
+    /// Reference export (JSON Lines file or archive directory, as written by --output-file)
+    left: String,
+
+    /// Export to compare against the reference
+    right: String,
+
+    /// Only compare events at or after this block
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Only compare events at or before this block
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Print the report as JSON instead of a summary
+    #[arg(long)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Load .env file if exists
+    dotenv::dotenv().ok();
+
+    let args = Args::parse();
+    let _telemetry = init_logging(&args)?;
+
+    // `listen` and `backfill` run the listener below; the other subcommands return
+    let (mut args, backfill, to_block) = match args.command {
+        None => (args.listen, false, None),
+        Some(Command::Listen(listen)) => (*listen, false, None),
+        Some(Command::Backfill(backfill)) => {
+            let BackfillArgs { to_block, listen } = *backfill;
+            (listen, true, to_block)
+        }
+        Some(Command::Replay(ref replay_args)) => return run_replay(replay_args).await,
+        Some(Command::Abi(AbiArgs {
+            command: AbiCommand::Fetch {
+                address,
+                chain_id,
+                ref etherscan_api_key,
+                ref output,
+            },
+        })) => return run_abi_fetch(address, chain_id, etherscan_api_key.clone(), output.as_deref()).await,
+        Some(Command::Status(ref status_args)) => return run_status(status_args).await,
+        Some(Command::Diff(ref diff_args)) => return run_diff(diff_args),
+        Some(Command::Verify { ref file }) => return run_verify(file),
+        Some(Command::Export(ref export_args)) => return run_export(export_args),
+        Some(Command::Discover(ref discover_args)) => return run_discover(discover_args).await,
+        Some(Command::Coverage(ref coverage_args)) => return run_coverage(coverage_args).await,
+        #[cfg(feature = "sqlite")]
+        Some(Command::Query(ref query_args)) => return run_query(query_args),
+        Some(Command::SelfUpdate(ref update_args)) => return run_self_update(update_args).await,
+        Some(Command::Template(ref template_args)) => return run_template(template_args).await,
+        Some(Command::Hash(ref hash_args)) => return run_hash(hash_args),
+        Some(Command::Decode(ref decode_args)) => return run_decode(decode_args).await,
+        Some(Command::Control(ref control_args)) => return run_control(control_args).await,
+        #[cfg(windows)]
+        Some(Command::Eventlog(ref eventlog_args)) => return run_eventlog(eventlog_args),
+        Some(Command::Config(ConfigArgs {
+            command: ConfigCommand::Test { ref file, ref profile },
+        })) => return run_config_test(file, profile.as_deref()).await,
+    };
+
+    // Dev mode: a local chain, listened to like any other; stopped on exit
+    let _anvil = match args.anvil {
+        true => Some(start_anvil(&mut args).await?),
+        false => None,
+    };
+
+    if let Some(ref dir) = args.capture_rpc {
+        transport::capture_to(dir)?;
+        info!("Capturing RPC traffic to {}", dir.join(transport::CAPTURE_FILE).display());
+    }
+    if let Some(ref dir) = args.replay_rpc {
+        let count = transport::replay_from(dir)?;
+        info!("Replaying {} captured RPC responses from {}", count, dir.display());
+    }
+
+    let (mut config, templates) = load_config(&args).await?;
+
+    if config.chains.iter().all(|chain| chain.contracts.is_empty()) {
+        anyhow::bail!("No contracts to listen to. Pass --contract or --template, or add contracts to the config file");
+    }
+
+    if args.explain_filter {
+        return print_filter_explanations(&config);
+    }
+
+    // Events of the snapshot's last block that are already loaded
+    let bootstrapped = match args.bootstrap {
+        Some(ref path) => bootstrap(path, &mut config).await?,
+        None => HashSet::new(),
+    };
+
+    if backfill {
+        for chain in &config.chains {
+            let Some(start_block) = chain.start_block else {
+                anyhow::bail!("A backfill needs a start block: pass --start-block or set start_block on every chain");
+            };
+            if to_block.is_some_and(|to_block| to_block < start_block) {
+                anyhow::bail!("--to-block is before the start block {}", start_block);
+            }
+        }
+    }
+
+    let mut run = RunMetadata::new(&config)?;
+    let poll_interval = Duration::from_millis(config.poll_interval_ms.unwrap_or(1000));
+    let retry = RetryPolicy::with_max_retries(config.max_retries.unwrap_or(5));
+
+    info!("Starting Smart Contract Event Listener");
+    if let Some(ref profile) = args.profile {
+        info!("Profile: {}", profile);
+    }
+    for template in &templates {
+        let name = match template.version {
+            Some(ref version) => format!("{}@{}", template.name, version),
+            None => template.name.clone(),
+        };
+        let chain = template.chain_id.map_or("default chain".to_string(), |id| format!("chain {}", id));
+        match template.description {
+            Some(ref description) => info!(
+                "Template: {} ({}), {} contract(s) on {}",
+                name, description, template.contracts, chain
+            ),
+            None => info!("Template: {}, {} contract(s) on {}", name, template.contracts, chain),
+        }
+    }
+    print_config(&config)?;
+    info!("Run: {} (v{}, config {})", run.run_id, run.listener_version, run.config_hash);
+
+    // In the background, so a slow or unreachable GitHub never delays startup
+    if config.check_updates {
+        tokio::spawn(async {
+            match tokio::time::timeout(Duration::from_secs(30), update::check_for_update()).await {
+                Ok(Ok(Some(notice))) => warn!("{}", notice),
+                Ok(Ok(None)) => {}
+                Ok(Err(e)) => warn!("Update check failed: {}", e),
+                Err(_) => warn!("Update check timed out"),
+            }
+        });
+    }
+
+    if let Some(ref addr) = config.metrics_addr {
+        let addr: SocketAddr = addr.parse().with_context(|| format!("Invalid metrics address: {}", addr))?;
+        prometheus::serve(addr)?;
+        info!("Metrics: http://{}/metrics", addr);
+    }
+    if let Some(ref addr) = config.health_addr {
+        let addr: SocketAddr = addr.parse().with_context(|| format!("Invalid health address: {}", addr))?;
+        health::serve(addr, config.health_max_lag.unwrap_or(health::DEFAULT_MAX_LAG))?;
+        info!("Health: http://{}/healthz, http://{}/readyz", addr, addr);
+    }
+
+    let metrics = SupervisorMetrics::new();
+    let mut sinks = sinks::from_config(&config.sinks).await?;
+    for sink in &mut sinks {
+        sink.start_run(&run).await?;
+    }
+    let sinks = spawn_sink_workers(sinks, &config, Some(&run), &metrics)?;
+
+    let (tx, rx) = mpsc::channel(config.pipeline.queue_size.unwrap_or(pipeline::DEFAULT_QUEUE_SIZE).max(1));
+    let mut listeners = ListenerSet::new(tx, poll_interval).metrics(metrics.clone());
+    listeners = listeners.on_idle(|latest_block| debug!("Listening at block {}", latest_block));
+    estimate_backfills(&config, retry, args.yes, to_block).await?;
+    connect_chains(&mut listeners, &config, retry).await?;
+    // Anvil chains start over every time, so their cursors are worthless
+    if !args.anvil {
+        listeners.resume(take_checkpoint(&config)?);
+    }
+    if backfill {
+        listeners.end_at(backfill_ends(&config, &listeners, to_block).await?);
+    }
+
+    // With coordination, only the contracts leased to this process are listened to
+    #[cfg(feature = "postgres")]
+    let mut coordinator = match config.coordination {
+        Some(ref coordination) => Some(start_coordination(coordination, &config, &mut listeners).await?),
+        None => None,
+    };
+    #[cfg(feature = "postgres")]
+    let mut renewal = coordinator.as_ref().map(|coordinator| {
+        let every = coordinator.renew_interval();
+        tokio::time::interval_at(tokio::time::Instant::now() + every, every)
+    });
+    #[cfg(not(feature = "postgres"))]
+    if config.coordination.is_some() {
+        anyhow::bail!("Coordination configured but the listener was built without the `postgres` feature");
+    }
+
+    listeners.sync(&config)?;
+
+    // Reload the config file when it changes; the watcher must stay alive
+    let mut config_watch = match args.config {
+        Some(ref path) => Some(watch_config(path)?),
+        None => None,
+    };
+
+    let mut enrichment = Arc::new(build_enrichment(&config, &listeners)?);
+    let webhooks = if config.has_webhooks() {
+        Some(WebhookQueue::open(&config.sinks.webhook)?)
+    } else {
+        None
+    };
+
+    let hash_chain = if config.hash_chain {
+        let state_path = config.state_file.as_deref().unwrap_or("listener-state.json");
+        Some(HashChain::new(StateFile::open(state_path)?))
+    } else {
+        None
+    };
+    let dedup = match config.dedup {
+        Some(ref dedup) => {
+            let dedup = Dedup::open(dedup, config.state_file.as_deref().unwrap_or("listener-state.json"))?;
+            info!("Deduplicating against {} delivered event(s) in {}", dedup.len(), dedup.path().display());
+            Some(dedup)
+        }
+        None => None,
+    };
+
+    // Enrichment and delivery run in their own tasks, behind bounded queues
+    let outputs = Outputs {
+        config: config.clone(),
+        output_file: open_output_file(&config.sinks)?,
+        template: load_output_template(&config.sinks)?,
+        siem: SiemFormat::from_config(&config.sinks)?,
+        webhooks,
+        sinks,
+        router: Router::new(&config.sinks)?,
+        dedup,
+        hash_chain,
+    };
+    let enrich = Enrich {
+        run: run.clone(),
+        humanize: config.sinks.humanize,
+        include_raw: config.sinks.include_raw,
+        enrichment: enrichment.clone(),
+    };
+    let mut pipeline = Pipeline::spawn(rx, enrich, outputs, bootstrapped, &config.pipeline);
+
+    let mut control = Control::start(config.control_socket.as_deref().map(Path::new))?;
+    if let Some(ref path) = config.control_socket {
+        info!("Control socket: {}", path);
+    }
+    let mut paused_since = None;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let config_changed = async {
+            match config_watch {
+                Some((_, ref mut changes)) => changes.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let renewal_due = async {
+            #[cfg(feature = "postgres")]
+            if let Some(ref mut renewal) = renewal {
+                renewal.tick().await;
+                return;
+            }
+            std::future::pending::<()>().await
+        };
+
+        // Backfill listeners stop at their end block; once all have, the run is over
+        let backfill_check = async {
+            match backfill {
+                true => tokio::time::sleep(Duration::from_secs(1)).await,
+                false => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            error = pipeline.failed() => return Err(error),
+            () = &mut shutdown => {
+                info!("Shutting down");
+                listeners.stop();
+                tokio::spawn(async {
+                    shutdown_signal().await;
+                    warn!("Exiting without finishing the shutdown");
+                    std::process::exit(130);
+                });
+                break;
+            }
+            Some(request) = control.recv() => {
+                match request.command {
+                    control::Command::Pause if listeners.set_paused(true) => {
+                        health::set_paused(true);
+                        paused_since = Some(std::time::Instant::now());
+                        info!("Paused polling; the listeners keep their block cursors");
+                    }
+                    control::Command::Resume if listeners.set_paused(false) => {
+                        health::set_paused(false);
+                        let paused = paused_since.take().map_or(0, |since| since.elapsed().as_secs());
+                        info!("Resumed polling after {}s", paused);
+                    }
+                    _ => {}
+                }
+                request.reply(&Status {
+                    state: if listeners.is_paused() { "paused" } else { "running" },
+                    paused_secs: paused_since.map(|since| since.elapsed().as_secs()),
+                    run_id: run.run_id.clone(),
+                    version: run.listener_version.clone(),
+                    started_at: run.started_at.clone(),
+                    listeners: listeners.len(),
+                    cursors: cursor_list(&listeners.cursors()),
+                });
+            }
+            () = backfill_check => {
+                if listeners.finished() {
+                    info!("Backfill finished");
+                    break;
+                }
+            }
+            () = renewal_due => {
+                #[cfg(feature = "postgres")]
+                if let Some(ref mut coordinator) = coordinator {
+                    if let Err(e) = renew_leases(coordinator, &mut listeners, &config).await {
+                        warn!("Failed to apply coordination leases: {:#}", e);
+                    }
+                }
+            }
+            Some(()) = config_changed => {
+                // Editors often write a file in several steps; let them settle
+                tokio::time::sleep(Duration::from_millis(250)).await;
+                if let Some((_, ref mut changes)) = config_watch {
+                    while changes.try_recv().is_ok() {}
+                }
+
+                let connected_chains = listeners.providers().len();
+                match reload_config(&args, &mut listeners, retry).await {
+                    Ok(new_config) => {
+                        run.config_hash = config_hash(&new_config)?;
+                        // Delivery waits while the outputs are replaced
+                        let mut outputs = pipeline.outputs().lock().await;
+                        if new_config.sinks != config.sinks || new_config.supervision.sinks != config.supervision.sinks {
+                            outputs.flush().await;
+                            let new_sinks = sinks::from_config(&new_config.sinks)
+                                .await
+                                .and_then(|new_sinks| spawn_sink_workers(new_sinks, &new_config, None, &metrics));
+                            match new_sinks {
+                                Ok(new_sinks) => outputs.sinks = new_sinks,
+                                Err(e) => warn!("Failed to reconnect sinks, keeping previous ones: {:#}", e),
+                            }
+                            match open_output_file(&new_config.sinks) {
+                                Ok(file) => outputs.output_file = file,
+                                Err(e) => warn!("Failed to open output file, keeping previous one: {:#}", e),
+                            }
+                            match load_output_template(&new_config.sinks) {
+                                Ok(template) => outputs.template = template,
+                                Err(e) => warn!("Failed to load output template, keeping previous one: {:#}", e),
+                            }
+                            match SiemFormat::from_config(&new_config.sinks) {
+                                Ok(siem) => outputs.siem = siem,
+                                Err(e) => warn!("Invalid siem_events, keeping previous ones: {:#}", e),
+                            }
+                            match Router::new(&new_config.sinks) {
+                                Ok(router) => outputs.router = router,
+                                Err(e) => warn!("Invalid routes, keeping previous ones: {:#}", e),
+                            }
+                        }
+                        for sink in &outputs.sinks {
+                            sink.start_run(&run).await;
+                        }
+                        match outputs.webhooks {
+                            Some(ref queue) => {
+                                if let Err(e) = queue.reconfigure(&new_config.sinks.webhook) {
+                                    warn!("Invalid webhook settings, keeping previous ones: {:#}", e);
+                                }
+                            }
+                            None if new_config.has_webhooks() => match WebhookQueue::open(&new_config.sinks.webhook) {
+                                Ok(queue) => outputs.webhooks = Some(queue),
+                                Err(e) => warn!("Failed to open webhook queue: {:#}", e),
+                            },
+                            None => {}
+                        }
+                        // Anomaly statistics start over, and newly connected chains get gas lookups
+                        if new_config.enrichment != config.enrichment
+                            || new_config.anomaly != config.anomaly
+                            || erc20_contracts(&new_config) != erc20_contracts(&config)
+                            || listeners.providers().len() != connected_chains
+                        {
+                            match build_enrichment(&new_config, &listeners) {
+                                Ok(new_enrichment) => enrichment = Arc::new(new_enrichment),
+                                Err(e) => warn!("Invalid enrichment settings, keeping previous ones: {:#}", e),
+                            }
+                        }
+                        pipeline.set_enrich(Enrich {
+                            run: run.clone(),
+                            humanize: new_config.sinks.humanize,
+                            include_raw: new_config.sinks.include_raw,
+                            enrichment: enrichment.clone(),
+                        });
+                        outputs.config = new_config.clone();
+                        drop(outputs);
+                        if new_config.metrics_addr != config.metrics_addr {
+                            warn!("Metrics address changed; restart the listener to apply it");
+                        }
+                        if new_config.health_addr != config.health_addr
+                            || new_config.health_max_lag != config.health_max_lag
+                        {
+                            warn!("Health endpoint settings changed; restart the listener to apply them");
+                        }
+                        if new_config.control_socket != config.control_socket {
+                            warn!("Control socket changed; restart the listener to apply it");
+                        }
+                        if new_config.coordination != config.coordination {
+                            warn!("Coordination settings changed; restart the listener to apply them");
+                        }
+                        if new_config.pipeline != config.pipeline {
+                            warn!("Pipeline settings changed; restart the listener to apply them");
+                        }
+                        #[cfg(feature = "postgres")]
+                        if let Some(ref mut coordinator) = coordinator {
+                            // Newly configured contracts are claimed by the next renewal
+                            if let Err(e) = coordinator.set_contracts(&new_config) {
+                                warn!("Coordination: {:#}", e);
+                            }
+                        }
+                        config = new_config;
+                    }
+                    Err(e) => warn!("Config reload failed, keeping previous config: {:#}", e),
+                }
+            }
+        }
+    }
+
+    // The events already received go through every stage; see them out
+    // before the cursors are saved
+    let mut outputs = pipeline.finish().await?;
+    outputs.flush().await;
+    if let Some(ref mut file) = outputs.output_file {
+        if let Err(e) = file.sync() {
+            warn!("{:#}", e);
+        }
+    }
+    if let Some(queue) = outputs.webhooks {
+        queue.close().await;
+    }
+    let cursors = listeners.cursors();
+    // A backfill's cursors aren't where listening should carry on from
+    if !backfill && !args.anvil {
+        save_checkpoint(&config, &cursors)?;
+    }
+    #[cfg(feature = "postgres")]
+    if let Some(ref mut coordinator) = coordinator {
+        if let Err(e) = coordinator.release_all(&cursors).await {
+            warn!("Failed to release coordination leases: {:#}", e);
+        }
+    }
+    print_crashes(&metrics);
+    Ok(())
+}
+
+/// Load the snapshot at `path` into the database sinks and start every chain
+/// without a start block at the snapshot's last block. That block is scanned
+/// again in case the snapshot ended partway through it; returns the events of
+/// it the snapshot already had, to be skipped.
+async fn bootstrap(path: &str, config: &mut Config) -> Result<HashSet<(String, u64)>> {
+    let snapshot = Snapshot::load(path)?;
+    let kinds: Vec<SinkKind> = SinkKind::configured(&config.sinks)?
+        .into_iter()
+        .filter(|kind| kind.is_database())
+        .collect();
+    if kinds.is_empty() {
+        anyhow::bail!("--bootstrap needs a database sink (--sqlite or --postgres-url) to load the snapshot into");
+    }
+
+    for kind in kinds {
+        let mut sink = kind.connect(&config.sinks).await?;
+        snapshot
+            .load_into(sink.as_mut())
+            .await
+            .with_context(|| format!("Failed to load snapshot into {}", sink.name()))?;
+        info!("Bootstrapped {} with {} events from {}", sink.name(), snapshot.len(), path);
+    }
+
+    let end_blocks = snapshot.end_blocks();
+    let mut resumed = HashSet::new();
+    for chain in &mut config.chains {
+        let (_, chain_name) = resolve_chain(chain)?;
+        match (chain.start_block, end_blocks.get(&chain.chain_id)) {
+            (Some(block), _) => info!("{} starts at block {} (configured)", chain_name, block),
+            (None, Some(&block)) => {
+                info!("{} resumes at block {}", chain_name, block);
+                chain.start_block = Some(block);
+                resumed.insert(chain.chain_id);
+            }
+            (None, None) => warn!("Snapshot has no events for {}; it starts at the chain head", chain_name),
+        }
+    }
+
+    // Continue the snapshot's hash chain rather than starting a new one
+    if let (true, Some(hash)) = (config.hash_chain, snapshot.last_hash()) {
+        let mut state = StateFile::open(config.state_file.as_deref().unwrap_or("listener-state.json"))?;
+        if state.state.hash_chain_head.is_none() {
+            state.state.hash_chain_head = Some(hash.to_string());
+            state.save()?;
+        }
+    }
+
+    Ok(snapshot.end_block_events(&resumed))
+}
+
+/// Start every sink connected by [`sinks::from_config`] in its own
+/// supervised task. `run` is the run the sinks have already been told about.
+fn spawn_sink_workers(
+    sinks: Vec<Box<dyn Sink>>,
+    config: &Config,
+    run: Option<&RunMetadata>,
+    metrics: &SupervisorMetrics,
+) -> Result<Vec<SinkWorker>> {
+    let kinds = SinkKind::configured(&config.sinks)?;
+    let workers = kinds
+        .into_iter()
+        .zip(sinks)
+        .map(|(kind, sink)| {
+            SinkWorker::spawn(
+                kind,
+                sink,
+                config.sinks.clone(),
+                run.cloned(),
+                config.supervision.sinks,
+                metrics.clone(),
+            )
+        })
+        .collect();
+    Ok(workers)
+}
+
+/// The enrichers configured in `config`, looking up on the chains
+/// `listeners` is connected to
+fn build_enrichment(config: &Config, listeners: &ListenerSet) -> Result<Enrichment> {
+    let mut enrichment = Enrichment::new(&config.enrichment);
+    let tokens = Arc::new(TokenCache::default());
+    // First, so implementation ABIs win over guesses
+    if let Some(ref proxies) = config.enrichment.proxies {
+        enrichment.add(Box::new(ProxyResolver::new(proxies, listeners.providers())?));
+    }
+    // Then guesses, so the other enrichers see the guessed arguments
+    if config.enrichment.guess_signatures.unwrap_or(true) || config.enrichment.openchain {
+        enrichment.add(Box::new(SignatureGuesser::new(config.enrichment.openchain)?));
+    }
+    // Labels are local and never run out of time, and win over ENS names
+    // in humanized values
+    if let Some(ref path) = config.enrichment.labels {
+        enrichment.add(Box::new(Labels::load(Path::new(path))?));
+    }
+    if config.enrichment.block_timestamps.unwrap_or(true) {
+        enrichment.add(Box::new(BlockTimestamps::new(listeners.providers())));
+    }
+    if config.enrichment.transaction {
+        enrichment.add(Box::new(TransactionEnricher::new(listeners.providers())));
+    }
+    if let Some(ref anomaly) = config.anomaly {
+        enrichment.add(Box::new(AnomalyDetector::new(anomaly, listeners.providers())));
+    }
+    let erc20 = erc20_contracts(config);
+    if config.enrichment.token_metadata {
+        // Formats the ERC-20 presets' amounts too
+        enrichment.add(Box::new(TokenMetadataEnricher::new(listeners.providers(), tokens.clone())));
+    } else if !erc20.is_empty() {
+        enrichment.add(Box::new(TokenAmounts::new(erc20, listeners.providers(), tokens.clone())));
+    }
+    // After the amounts are formatted, to add their value to them
+    if let Some(ref price) = config.enrichment.price {
+        let enricher = PriceEnricher::new(price, listeners.providers(), tokens).context("Invalid price settings")?;
+        enrichment.add(Box::new(enricher));
+    }
+    if let Some(ref ens) = config.enrichment.ens {
+        let provider = match ens.rpc_url {
+            Some(ref url) => Arc::new(ProviderManager::new(std::slice::from_ref(url))?),
+            None => match listeners.providers().remove(&Some(1)) {
+                Some(provider) => provider,
+                None => {
+                    let urls = read_rpc_urls_env("ETHEREUM_RPC_URL")
+                        .context("ENS names need a mainnet RPC endpoint: set --ens-rpc-url or ETHEREUM_RPC_URL")?;
+                    Arc::new(ProviderManager::new(&urls)?)
+                }
+            },
+        };
+        let cache_for = ens.cache_secs.map(Duration::from_secs).unwrap_or(ens::DEFAULT_CACHE);
+        enrichment.add(Box::new(EnsResolver::new(provider, cache_for)));
+    }
+    Ok(enrichment)
+}
+
+/// The JSON Lines output file, unless there is none or `output_file` is
+/// written by a sink
+fn open_output_file(sinks: &SinkConfig) -> Result<Option<RotatingFile>> {
+    let Some(ref path) = sinks.output_file else {
+        return Ok(None);
+    };
+    if sinks.is_archive() || sinks.is_parquet() {
+        if sinks.rotation.is_some() {
+            anyhow::bail!("File rotation only applies to JSON Lines output files");
+        }
+        return Ok(None);
+    }
+    Ok(Some(RotatingFile::open(path, sinks.rotation.as_ref())?))
+}
+
+/// The template of the `template` output format
+fn load_output_template(sinks: &SinkConfig) -> Result<Option<EventTemplate>> {
+    match (sinks.output_template.as_deref(), sinks.is_template()) {
+        (Some(path), true) => Ok(Some(EventTemplate::load(path)?)),
+        (None, true) => anyhow::bail!("The template output format needs a template file (--output-template)"),
+        (Some(_), false) => anyhow::bail!("An output template only applies to the template output format"),
+        (None, false) => Ok(None),
+    }
+}
+
+/// Resolves on Ctrl-C (SIGINT) or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+}
+
+/// The cursors checkpointed by the previous run, removed from the state file
+/// so a run that doesn't shut down cleanly can't resume from them again.
+/// Chains with a configured start block start there instead.
+fn take_checkpoint(config: &Config) -> Result<HashMap<ContractKey, u64>> {
+    let path = config.state_file.as_deref().unwrap_or("listener-state.json");
+    let mut state = StateFile::open(path)?;
+    if state.state.cursors.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let cursors = std::mem::take(&mut state.state.cursors);
+    state.save()?;
+
+    let configured: HashSet<Option<u64>> = config
+        .chains
+        .iter()
+        .filter(|chain| chain.start_block.is_some())
+        .map(|chain| chain.chain_id)
+        .collect();
+    Ok(cursors
+        .into_iter()
+        .filter(|cursor| !configured.contains(&cursor.chain_id))
+        .map(|cursor| {
+            let contract = cursor
+                .contract
+                .map_or("listeners without a contract".to_string(), |address| format!("{:?}", address));
+            info!("Resuming {} at block {} (checkpoint in {})", contract, cursor.block, path);
+            ((cursor.chain_id, cursor.contract), cursor.block)
+        })
+        .collect())
+}
+
+/// `cursors` by chain and contract
+fn cursor_list(cursors: &HashMap<ContractKey, u64>) -> Vec<Cursor> {
+    let mut list: Vec<Cursor> = cursors
+        .iter()
+        .map(|(&(chain_id, contract), &block)| Cursor {
+            chain_id,
+            contract,
+            block,
+        })
+        .collect();
+    list.sort_by_key(|cursor| (cursor.chain_id, cursor.contract));
+    list
+}
+
+/// Write where each contract resumes to the state file
+fn save_checkpoint(config: &Config, cursors: &HashMap<ContractKey, u64>) -> Result<()> {
+    if cursors.is_empty() {
+        return Ok(());
+    }
+    let path = config.state_file.as_deref().unwrap_or("listener-state.json");
+    let mut state = StateFile::open(path)?;
+    state.state.cursors = cursor_list(cursors);
+    state.save()?;
+    info!("Saved the cursors of {} contract(s) to {}", cursors.len(), path);
+    Ok(())
+}
+
+/// Stands in for the OTLP exporters when built without them
+#[cfg(not(feature = "otel"))]
+struct Telemetry;
+
+/// Write diagnostics to stderr at `--log-level` as text or JSON lines, so
+/// stdout only carries events and command output. Other crates' logs are
+/// limited to warnings unless RUST_LOG says otherwise. When the OTEL_*
+/// environment asks for it, spans and metrics are exported too, until the
+/// returned exporters are dropped.
+fn init_logging(args: &Args) -> Result<Option<Telemetry>> {
+    let filter = match (args.log_level.as_deref(), std::env::var("RUST_LOG")) {
+        (None, Ok(directives)) if !directives.is_empty() => {
+            EnvFilter::try_new(&directives).with_context(|| format!("Invalid RUST_LOG: {}", directives))?
+        }
+        (level, _) => EnvFilter::new(format!("warn,listener={}", level.unwrap_or("info"))),
+    };
+    let logger = tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let logger = match args.log_format.as_str() {
+        "json" => logger.json().boxed(),
+        _ => logger.boxed(),
+    };
+    // Spans are only exported; the lines logged don't list them
+    let registry = tracing_subscriber::registry().with(logger.with_filter(filter.and(filter_fn(|meta| !meta.is_span()))));
+
+    #[cfg(feature = "otel")]
+    if telemetry::configured() {
+        let telemetry = Telemetry::init()?;
+        let spans = tracing_subscriber::filter::Targets::new().with_target("listener", tracing::Level::INFO);
+        registry.with(telemetry.layer().with_filter(spans)).init();
+        return Ok(Some(telemetry));
+    }
+    registry.init();
+    #[cfg(not(feature = "otel"))]
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+        warn!("OTEL_EXPORTER_OTLP_ENDPOINT is set, but this build has no OTLP export (feature otel)");
+    }
+    Ok(None)
+}
+
+/// Summarize the tasks that crashed during the run
+fn print_crashes(metrics: &SupervisorMetrics) {
+    let crashed: Vec<_> = metrics.snapshot().into_iter().filter(|(_, task)| task.crashes() > 0).collect();
+    if crashed.is_empty() {
+        return;
+    }
+
+    warn!("Crashed tasks:");
+    for (name, task) in crashed {
+        warn!(
+            "{}: {} panic(s), {} failure(s), {} restart(s){}",
+            name,
+            task.panics,
+            task.failures,
+            task.restarts,
+            if task.gave_up { ", stopped" } else { "" }
+        );
+    }
+}
+
+/// Print the chains, endpoints, contracts, and event filters being watched
+fn print_config(config: &Config) -> Result<()> {
+    if let Some(ref postgres) = config.sinks.postgres {
+        info!("PostgreSQL: table {}", postgres.table.as_deref().unwrap_or("events"));
+    }
+    for chain in &config.chains {
+        let (rpc_urls, chain_name) = resolve_chain(chain)?;
+
+        info!("Chain: {}", chain_name);
+        for (i, url) in rpc_urls.iter().enumerate() {
+            let role = if i == 0 { "RPC" } else { "Fallback" };
+            info!("{}: {}", role, mask_api_key(url));
+        }
+        for url in &chain.archive_rpc_urls {
+            info!("Archive: {}", mask_api_key(url));
+        }
+        if let Some(ref firehose) = chain.firehose {
+            info!("Firehose: {}", firehose.endpoint);
+        }
+        for contract in &chain.contracts {
+            match contract.name {
+                Some(ref name) => info!("Contract: {} ({})", contract.label(), name),
+                None => info!("Contract: {}", contract.label()),
+            }
+            if !contract.allow_addresses.is_empty() {
+                info!("Only: {}", contract.allow_addresses.join(", "));
+            }
+            if !contract.deny_addresses.is_empty() {
+                info!("Except: {}", contract.deny_addresses.join(", "));
+            }
+            if let Some(preset) = contract.preset {
+                info!("Preset: {}", preset);
+            }
+            if contract.events.is_empty() {
+                info!("Listening to: ALL events");
+            }
+            for event in &contract.events {
+                info!("Event: {}", event.signature());
+            }
+            for (i, values) in [&contract.topic1, &contract.topic2, &contract.topic3].into_iter().enumerate() {
+                if !values.is_empty() {
+                    info!("Topic {}: {}", i + 1, values.join(" or "));
+                }
+            }
+            if let Some(ref filter) = contract.filter {
+                info!("Where: {}", filter);
+            }
+            if let Some(ref factory) = contract.factory {
+                info!(
+                    "Factory: new contracts from argument {}, listening to {}",
+                    factory.child_argument,
+                    signatures_label(&factory.events)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `--explain-filter`: what each listener asks the node for and what it
+/// checks itself
+fn print_filter_explanations(config: &Config) -> Result<()> {
+    for chain in &config.chains {
+        let chain_name = chain
+            .name
+            .as_deref()
+            .or_else(|| chain.chain_id.and_then(chain_info).map(|info| info.name))
+            .unwrap_or("Custom");
+        for explanation in explain_chain(chain)? {
+            println!(" {} {}", chain_name, explanation.contract);
+            println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+            println!(" Sent to the node (eth_getLogs, fromBlock/toBlock set per range):");
+            for line in serde_json::to_string_pretty(&explanation.node_filter)?.lines() {
+                println!("   {}", line);
+            }
+            for (signature, topic0) in &explanation.topics {
+                println!(" topic0 {:?} = {}", topic0, signature);
+            }
+            if explanation.local.is_empty() {
+                println!(" Applied locally: nothing, every log the node returns is emitted");
+            } else {
+                println!(" Applied locally:");
+                for (i, step) in explanation.local.iter().enumerate() {
+                    println!("   {}. {}", i + 1, step);
+                }
+            }
+            println!();
+        }
+    }
+    Ok(())
+}
+
+/// Join the processes coordinating through `coordination` and assign
+/// `listeners` the contracts leased to this one
+#[cfg(feature = "postgres")]
+async fn start_coordination(
+    coordination: &CoordinationConfig,
+    config: &Config,
+    listeners: &mut ListenerSet,
+) -> Result<Coordinator> {
+    let mut coordinator = Coordinator::connect(coordination).await?;
+    coordinator.set_contracts(config)?;
+    coordinator
+        .heartbeat(&Default::default())
+        .await
+        .context("Failed to claim coordination leases")?;
+    listeners.assign(coordinator.assignment());
+
+    info!(
+        "Coordinating as {}: listening to {} of {} contract(s)",
+        coordinator.node_id(),
+        coordinator.assignment().len(),
+        coordinator.len()
+    );
+    Ok(coordinator)
+}
+
+/// Renew this process's leases and start or stop the listeners of the
+/// contracts it claimed or released. Leases that can't be renewed in time
+/// are given up, stopping their listeners.
+#[cfg(feature = "postgres")]
+async fn renew_leases(coordinator: &mut Coordinator, listeners: &mut ListenerSet, config: &Config) -> Result<()> {
+    let cursors = listeners.cursors();
+    let renewed = tokio::time::timeout(coordinator.renew_interval(), coordinator.heartbeat(&cursors))
+        .await
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+
+    let changed = match renewed {
+        Ok(rebalance) => {
+            if rebalance.lost > 0 {
+                warn!(
+                    "Coordination: {} lease(s) expired and were taken over by another process",
+                    rebalance.lost
+                );
+            }
+            if rebalance.claimed > 0 || rebalance.released > 0 {
+                info!(
+                    "Coordination: {} contract(s) claimed, {} released; listening to {} of {}",
+                    rebalance.claimed,
+                    rebalance.released,
+                    coordinator.assignment().len(),
+                    coordinator.len()
+                );
+            }
+            !rebalance.is_empty()
+        }
+        Err(e) => {
+            warn!("Failed to renew coordination leases: {:#}", e);
+            let expired = coordinator.expire();
+            if expired {
+                warn!("Coordination: leases are about to expire, stopping all listeners until they're renewed");
+            }
+            expired
+        }
+    };
+
+    if changed {
+        listeners.assign(coordinator.assignment());
+        listeners.sync(config)?;
+    }
+    Ok(())
+}
+
+/// Estimate the backfill of every chain that starts more than one range
+/// behind its head, or before `to_block` when given, and ask before starting
+/// when more events than `backfill.confirm_above` are expected in all.
+/// Without a terminal to ask on, a large backfill needs `assume_yes`.
+async fn estimate_backfills(config: &Config, retry: RetryPolicy, assume_yes: bool, to_block: Option<u64>) -> Result<()> {
+    let samples = config.backfill.samples.unwrap_or(DEFAULT_SAMPLES);
+    if samples == 0 {
+        return Ok(());
+    }
+
+    let mut events = 0;
+    for chain in &config.chains {
+        let Some(start_block) = chain.start_block else {
+            continue;
+        };
+        let (rpc_urls, chain_name) = resolve_chain(chain)?;
+        let provider = ProviderManager::new(&rpc_urls)
+            .context("Failed to connect to RPC endpoint")?
+            .with_retry_policy(retry);
+        let head = provider.get_block_number().await?;
+        let head = to_block.map_or(head, |to_block| to_block.min(head));
+        let max_block_range = chain
+            .max_block_range
+            .or(config.max_block_range)
+            .unwrap_or(DEFAULT_MAX_BLOCK_RANGE)
+            .max(1);
+        if head < start_block.saturating_add(max_block_range) {
+            continue;
+        }
+
+        info!("Estimating the backfill of {} from block {}...", chain_name, start_block);
+        let targets = BackfillTarget::from_chain(chain)?;
+        let estimated = estimate_backfill(
+            &provider,
+            chain.chain_id,
+            &chain_name,
+            &targets,
+            start_block,
+            head,
+            max_block_range,
+            samples,
+        )
+        .await;
+        let estimate = match estimated {
+            Ok(estimate) => estimate,
+            Err(e) => {
+                warn!("Failed to estimate the backfill of {}: {:#}", chain_name, e);
+                continue;
+            }
+        };
+
+        let parallelism = estimate
+            .listeners
+            .min(config.catch_up_concurrency.unwrap_or(usize::MAX))
+            .min(chain.catch_up_parallelism.unwrap_or(usize::MAX));
+        let exact = estimate.sampled_blocks == estimate.blocks();
+        info!(
+            "Blocks:    {}..{} ({} blocks, {} sampled)",
+            estimate.from_block,
+            estimate.to_block,
+            estimate.blocks(),
+            if exact { "all".to_string() } else { estimate.sampled_blocks.to_string() }
+        );
+        info!(
+            "Events:    {}{} ({} in the sampled blocks)",
+            if exact { "" } else { "~" },
+            estimate.events,
+            estimate.sampled_events
+        );
+        info!(
+            "RPC calls: ~{} ({} eth_getLogs by {} listener(s))",
+            estimate.rpc_calls(),
+            estimate.get_logs_calls,
+            estimate.listeners
+        );
+        info!(
+            "Duration:  {} ({}ms per eth_getLogs, {} at once)",
+            match estimate.duration(parallelism).as_secs() {
+                0 => "under a second".to_string(),
+                seconds => format!("~{}", format_duration(seconds)),
+            },
+            estimate.call_latency.as_millis(),
+            parallelism
+        );
+        info!("Storage:   ~{} as JSON Lines", format_size(estimate.bytes));
+        events += estimate.events;
+    }
+
+    let confirm_above = config.backfill.confirm_above.unwrap_or(DEFAULT_CONFIRM_ABOVE);
+    if events <= confirm_above || assume_yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!(
+            "The backfill is expected to yield ~{} events, more than the confirmation threshold of {}; pass --yes to start it",
+            events,
+            confirm_above
+        );
+    }
+
+    // On stderr, so the prompt can't end up in piped output
+    eprint!("The backfill is expected to yield ~{} events. Start it? [y/N] ", events);
+    std::io::Write::flush(&mut std::io::stderr())?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+        anyhow::bail!("Backfill cancelled");
+    }
+    Ok(())
+}
+
+/// Connect to every chain in `config` that `listeners` doesn't know yet
+async fn connect_chains(listeners: &mut ListenerSet, config: &Config, retry: RetryPolicy) -> Result<()> {
+    for chain in &config.chains {
+        if listeners.has_chain(chain.chain_id) {
+            continue;
+        }
+        let (rpc_urls, chain_name) = resolve_chain(chain)?;
+
+        // Connect to the network
+        let provider = ProviderManager::new(&rpc_urls)
+            .context("Failed to connect to RPC endpoint")?
+            .with_retry_policy(retry);
+        if rpc_urls.len() > 1 {
+            info!("Checking RPC endpoints...");
+            provider.health_check().await?;
+        }
+
+        // Determine starting block
+        let from_block = if let Some(block) = chain.start_block {
+            block
+        } else {
+            provider.get_block_number().await?
+        };
+
+        info!("Starting from block: {} ({})", from_block, chain_name);
+
+        let archive = if chain.archive_rpc_urls.is_empty() {
+            None
+        } else {
+            let archive = ProviderManager::new(&chain.archive_rpc_urls)
+                .context("Failed to connect to archive RPC endpoint")?
+                .with_retry_policy(retry);
+            Some(Arc::new(archive))
+        };
+
+        listeners.add_chain(
+            chain.chain_id,
+            Arc::new(provider),
+            archive,
+            chain_name,
+            chain.native_symbol.clone(),
+            Some(from_block),
+        );
+        if let Some(ref firehose) = chain.firehose {
+            #[cfg(feature = "firehose")]
+            listeners.set_log_source(chain.chain_id, Arc::new(listener::firehose::FirehoseSource::new(firehose)?));
+            #[cfg(not(feature = "firehose"))]
+            anyhow::bail!(
+                "Firehose endpoint {} configured but the listener was built without the `firehose` feature",
+                firehose.endpoint
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Re-read the config file and apply added/removed chains, contracts, and
+/// events. Running listeners keep their block cursor; new ones start at the
+/// chain head.
+async fn reload_config(args: &ListenArgs, listeners: &mut ListenerSet, retry: RetryPolicy) -> Result<Config> {
+    let (config, _) = load_config(args).await?;
+
+    connect_chains(listeners, &config, retry).await?;
+    let summary = listeners.sync(&config)?;
+
+    info!(
+        "Config reloaded: {} listener(s) added, {} removed, {} running",
+        summary.added,
+        summary.removed,
+        listeners.len()
+    );
+    Ok(config)
+}
+
+/// The config file (or an empty config) with the referenced templates
+/// expanded and the CLI flags applied on top
+async fn load_config(args: &ListenArgs) -> Result<(Config, Vec<AppliedTemplate>)> {
+    let mut config = match args.config {
+        Some(ref path) => Config::load(path, args.profile.as_deref())?,
+        None => Config::default(),
+    };
+
+    // Expanded before the flags are merged so --start-block covers their chains too
+    if args.template_dir.is_some() {
+        config.template_dir = args.template_dir.clone();
+    }
+    config.templates.extend(args.template.iter().cloned().map(TemplateRef::Source));
+    let templates = apply_templates(&mut config).await?;
+
+    merge_cli_args(&mut config, args);
+    config.expand_presets();
+    for (given, canonical) in config.normalize_signatures()? {
+        warn!("Event signature {} is hashed in its canonical form {}", given, canonical);
+    }
+    Ok((config, templates))
+}
+
+/// Parse a `KEY=VALUE` header given on the command line
+fn parse_header(header: &str) -> std::result::Result<(String, String), String> {
+    match header.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {}", header)),
+    }
+}
+
+/// Parse an `EVENT=SEVERITY` alert mapping given on the command line
+fn parse_alert(alert: &str) -> std::result::Result<(String, String), String> {
+    let (event, severity) = match alert.rsplit_once('=') {
+        Some((event, severity)) => (event.trim(), severity.trim()),
+        None => (alert.trim(), "critical"),
+    };
+    if event.is_empty() {
+        return Err(format!("expected EVENT=SEVERITY, got {}", alert));
+    }
+    Ok((event.to_string(), severity.to_string()))
+}
+
+/// Apply CLI flags on top of the config file: global flags override config
+/// values and `--contract` (or `--event` alone, for any contract) adds a
+/// contract on the chain selected by `--chain-id`/`--rpc-url`
+fn merge_cli_args(config: &mut Config, args: &ListenArgs) {
+    if args.poll_interval_ms.is_some() {
+        config.poll_interval_ms = args.poll_interval_ms;
+    }
+    if args.max_retries.is_some() {
+        config.max_retries = args.max_retries;
+    }
+    if args.max_block_range.is_some() {
+        config.max_block_range = args.max_block_range;
+    }
+    if args.catch_up_concurrency.is_some() {
+        config.catch_up_concurrency = args.catch_up_concurrency;
+    }
+    if let Some(size) = args.queue_size {
+        config.pipeline.queue_size = Some(size as usize);
+    }
+    if let Some(concurrency) = args.enrich_concurrency {
+        config.pipeline.enrich_concurrency = Some(concurrency as usize);
+    }
+    if args.backfill_samples.is_some() {
+        config.backfill.samples = args.backfill_samples;
+    }
+    if args.confirm_backfill_above.is_some() {
+        config.backfill.confirm_above = args.confirm_backfill_above;
+    }
+    if args.output_format.is_some() {
+        config.sinks.output_format = args.output_format.clone();
+    }
+    if args.output_template.is_some() {
+        config.sinks.output_template = args.output_template.clone();
+        config.sinks.output_format.get_or_insert_with(|| "template".to_string());
+    }
+    if args.output_file.is_some() {
+        config.sinks.output_file = args.output_file.clone();
+    }
+    if args.humanize {
+        config.sinks.humanize = true;
+    }
+    if args.include_raw {
+        config.sinks.include_raw = true;
+    }
+    if args.file_mode.is_some() {
+        config.sinks.file_mode = args.file_mode.clone();
+    }
+    if args.rotate_size.is_some() || args.rotate_daily || args.rotate_compress || args.rotate_keep.is_some() {
+        let rotation = config.sinks.rotation.get_or_insert_with(RotationConfig::default);
+        if args.rotate_size.is_some() {
+            rotation.max_size = args.rotate_size.clone();
+        }
+        rotation.daily |= args.rotate_daily;
+        rotation.compress |= args.rotate_compress;
+        if args.rotate_keep.is_some() {
+            rotation.keep = args.rotate_keep;
+        }
+    }
+    if args.webhook_url.is_some() {
+        config.sinks.webhook_url = args.webhook_url.clone();
+    }
+    if args.webhook_queue_dir.is_some() {
+        config.sinks.webhook.queue_dir = args.webhook_queue_dir.clone();
+    }
+    if args.webhook_max_attempts.is_some() {
+        config.sinks.webhook.max_attempts = args.webhook_max_attempts;
+    }
+    if args.webhook_dead_letter.is_some() {
+        config.sinks.webhook.dead_letter_file = args.webhook_dead_letter.clone();
+    }
+    if args.webhook_secret.is_some() {
+        config.sinks.webhook.secret = args.webhook_secret.clone();
+    }
+    config.sinks.webhook.headers.extend(args.webhook_headers.iter().cloned());
+    if args.webhook_batch_size.is_some() {
+        config.sinks.webhook.batch_size = args.webhook_batch_size;
+    }
+    if args.webhook_batch_ms.is_some() {
+        config.sinks.webhook.batch_ms = args.webhook_batch_ms;
+    }
+    if args.webhook_concurrency.is_some() {
+        config.sinks.webhook.concurrency = args.webhook_concurrency;
+    }
+    if args.webhook_client_cert.is_some() || args.webhook_ca_cert.is_some() {
+        let tls = config.sinks.webhook.tls.get_or_insert_with(Default::default);
+        if args.webhook_client_cert.is_some() {
+            tls.client_cert = args.webhook_client_cert.clone();
+            tls.client_key = args.webhook_client_key.clone();
+        }
+        if args.webhook_ca_cert.is_some() {
+            tls.ca_cert = args.webhook_ca_cert.clone();
+        }
+    }
+    if let Some(ref url) = args.slack_webhook {
+        config.sinks.slack.get_or_insert_with(Default::default).webhook_url = url.clone();
+    }
+    if let Some(ref url) = args.discord_webhook {
+        config.sinks.discord.get_or_insert_with(Default::default).webhook_url = url.clone();
+    }
+    if let Some(ref token) = args.telegram_token {
+        config.sinks.telegram.get_or_insert_with(Default::default).token = token.clone();
+    }
+    if let Some(ref mut telegram) = config.sinks.telegram {
+        if let Some(ref chat_id) = args.telegram_chat_id {
+            telegram.chat_id = chat_id.clone();
+        }
+        if args.telegram_template.is_some() {
+            telegram.template = args.telegram_template.clone();
+        }
+        if args.telegram_rate_limit.is_some() {
+            telegram.rate_limit = args.telegram_rate_limit;
+        }
+    }
+    if let Some(ref host) = args.smtp_host {
+        config.sinks.smtp.get_or_insert_with(Default::default).host = host.clone();
+    }
+    if let Some(ref mut smtp) = config.sinks.smtp {
+        if args.smtp_port.is_some() {
+            smtp.port = args.smtp_port;
+        }
+        if args.smtp_tls.is_some() {
+            smtp.tls = args.smtp_tls.clone();
+        }
+        if args.smtp_username.is_some() {
+            smtp.username = args.smtp_username.clone();
+        }
+        if args.smtp_password.is_some() {
+            smtp.password = args.smtp_password.clone();
+        }
+        if let Some(ref from) = args.smtp_from {
+            smtp.from = from.clone();
+        }
+        if !args.smtp_to.is_empty() {
+            smtp.to = args.smtp_to.clone();
+        }
+        if args.smtp_subject.is_some() {
+            smtp.subject = args.smtp_subject.clone();
+        }
+        if args.smtp_digest_minutes.is_some() {
+            smtp.digest_minutes = args.smtp_digest_minutes;
+        }
+    }
+    if let Some(ref key) = args.pagerduty_routing_key {
+        config.sinks.pagerduty.get_or_insert_with(Default::default).key = key.clone();
+    }
+    if let Some(ref key) = args.opsgenie_api_key {
+        config.sinks.opsgenie.get_or_insert_with(Default::default).key = key.clone();
+    }
+    for incidents in [&mut config.sinks.pagerduty, &mut config.sinks.opsgenie].into_iter().flatten() {
+        incidents.events.extend(args.alert_on.iter().cloned());
+    }
+    if args.journald || args.journald_identifier.is_some() {
+        config.sinks.journald.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut journald) = config.sinks.journald {
+        if args.journald_identifier.is_some() {
+            journald.identifier = args.journald_identifier.clone();
+        }
+        journald.events.extend(args.alert_on.iter().cloned());
+    }
+    if args.eventlog || args.eventlog_source.is_some() {
+        config.sinks.eventlog.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut eventlog) = config.sinks.eventlog {
+        if args.eventlog_source.is_some() {
+            eventlog.source = args.eventlog_source.clone();
+        }
+        eventlog.events.extend(args.alert_on.iter().cloned());
+    }
+    if let Some(ref address) = args.syslog_address {
+        config.sinks.syslog.get_or_insert_with(Default::default).address = address.clone();
+    }
+    if let Some(ref mut syslog) = config.sinks.syslog {
+        if args.syslog_facility.is_some() {
+            syslog.facility = args.syslog_facility.clone();
+        }
+        if args.syslog_format.is_some() {
+            syslog.format = args.syslog_format.clone();
+        }
+        syslog.events.extend(args.alert_on.iter().cloned());
+    }
+    config.sinks.siem_events.extend(args.alert_on.iter().cloned());
+    if let Some(ref url) = args.postgres_url {
+        config.sinks.postgres.get_or_insert_with(Default::default).url = url.clone();
+    }
+    if let Some(ref mut postgres) = config.sinks.postgres {
+        if args.postgres_table.is_some() {
+            postgres.table = args.postgres_table.clone();
+        }
+        if args.postgres_batch_size.is_some() {
+            postgres.batch_size = args.postgres_batch_size;
+        }
+        if args.postgres_notify.is_some() {
+            postgres.notify_channel = args.postgres_notify.clone();
+        }
+    }
+    if let Some(ref brokers) = args.kafka_brokers {
+        config.sinks.kafka.get_or_insert_with(Default::default).brokers = brokers.clone();
+    }
+    if let Some(ref mut kafka) = config.sinks.kafka {
+        if let Some(ref topic) = args.kafka_topic {
+            kafka.topic = topic.clone();
+        }
+        if args.kafka_transactional_id.is_some() {
+            kafka.transactional_id = args.kafka_transactional_id.clone();
+        }
+    }
+    if let Some(ref url) = args.nats_url {
+        config.sinks.nats.get_or_insert_with(Default::default).url = url.clone();
+    }
+    if let Some(ref mut nats) = config.sinks.nats {
+        if let Some(ref subject) = args.nats_subject {
+            nats.subject = subject.clone();
+        }
+        if args.nats_stream.is_some() {
+            nats.stream = args.nats_stream.clone();
+        }
+    }
+    if let Some(ref url) = args.redis_url {
+        config.sinks.redis.get_or_insert_with(Default::default).url = url.clone();
+    }
+    if let Some(ref mut redis) = config.sinks.redis {
+        if args.redis_stream.is_some() {
+            redis.stream = args.redis_stream.clone();
+        }
+        if args.redis_channel.is_some() {
+            redis.channel = args.redis_channel.clone();
+        }
+    }
+    if let Some(ref broker) = args.mqtt_broker {
+        config.sinks.mqtt.get_or_insert_with(Default::default).broker = broker.clone();
+    }
+    if let Some(ref mut mqtt) = config.sinks.mqtt {
+        if let Some(ref topic) = args.mqtt_topic {
+            mqtt.topic = topic.clone();
+        }
+        if args.mqtt_qos.is_some() {
+            mqtt.qos = args.mqtt_qos;
+        }
+    }
+    if let Some(ref topic) = args.pubsub_topic {
+        config.sinks.pubsub.get_or_insert_with(Default::default).topic = topic.clone();
+    }
+    if let Some(ref mut pubsub) = config.sinks.pubsub {
+        if args.pubsub_project.is_some() {
+            pubsub.project = args.pubsub_project.clone();
+        }
+        if args.pubsub_create_topic {
+            pubsub.create_topic = true;
+        }
+    }
+    if let Some(ref name) = args.eventhubs_name {
+        config.sinks.eventhubs.get_or_insert_with(Default::default).name = Some(name.clone());
+    }
+    if let Some(ref mut eventhubs) = config.sinks.eventhubs {
+        if args.eventhubs_partition_key.is_some() {
+            eventhubs.partition_key = args.eventhubs_partition_key.clone();
+        }
+    }
+    if let Some(ref url) = args.object_storage_url {
+        config.sinks.object_storage.get_or_insert_with(Default::default).url = url.clone();
+    }
+    if let Some(ref mut object_storage) = config.sinks.object_storage {
+        if args.object_storage_batch_size.is_some() {
+            object_storage.batch_size = args.object_storage_batch_size;
+        }
+        if args.object_storage_interval.is_some() {
+            object_storage.batch_interval_secs = args.object_storage_interval;
+        }
+    }
+    if args.sqlite.is_some() {
+        config.sinks.sqlite = args.sqlite.clone();
+    }
+    if args.rollups {
+        config.sinks.rollups = true;
+    }
+    if args.hash_chain {
+        config.hash_chain = true;
+    }
+    if args.dedup || args.dedup_window.is_some() {
+        config.dedup.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut dedup) = config.dedup {
+        if args.dedup_window.is_some() {
+            dedup.window = args.dedup_window;
+        }
+    }
+    if args.check_updates {
+        config.check_updates = true;
+    }
+    if args.metrics_addr.is_some() {
+        config.metrics_addr = args.metrics_addr.clone();
+    }
+    if args.health_addr.is_some() {
+        config.health_addr = args.health_addr.clone();
+    }
+    if args.health_max_lag.is_some() {
+        config.health_max_lag = args.health_max_lag;
+    }
+    if args.control_socket.is_some() {
+        config.control_socket = args.control_socket.clone();
+    }
+    if args.state_file.is_some() {
+        config.state_file = args.state_file.clone();
+    }
+    if let Some(ref url) = args.coordination_url {
+        config.coordination.get_or_insert_with(Default::default).url = url.clone();
+    }
+    if let Some(ref mut coordination) = config.coordination {
+        if args.node_id.is_some() {
+            coordination.node_id = args.node_id.clone();
+        }
+        if args.lease_secs.is_some() {
+            coordination.lease_secs = args.lease_secs;
+        }
+    }
+    if args.enrich.iter().any(|enrichment| enrichment == "token-metadata") {
+        config.enrichment.token_metadata = true;
+    }
+    if args.enrich.iter().any(|enrichment| enrichment == "tx") {
+        config.enrichment.transaction = true;
+    }
+    if args.enrich.iter().any(|enrichment| enrichment == "usd-price") {
+        config.enrichment.price.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut price) = config.enrichment.price {
+        if args.coingecko_api_key.is_some() {
+            price.coingecko_api_key = args.coingecko_api_key.clone();
+        }
+    }
+    if args.no_block_timestamps {
+        config.enrichment.block_timestamps = Some(false);
+    }
+    if args.no_signature_guesses {
+        config.enrichment.guess_signatures = Some(false);
+    }
+    if args.openchain {
+        config.enrichment.openchain = true;
+    }
+    if args.labels.is_some() {
+        config.enrichment.labels = args.labels.clone();
+    }
+    if args.resolve_ens {
+        config.enrichment.ens.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut ens) = config.enrichment.ens {
+        if args.ens_rpc_url.is_some() {
+            ens.rpc_url = args.ens_rpc_url.clone();
+        }
+    }
+    if args.resolve_proxies {
+        config.enrichment.proxies.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut proxies) = config.enrichment.proxies {
+        if args.etherscan_api_key.is_some() {
+            proxies.etherscan_api_key = args.etherscan_api_key.clone();
+        }
+    }
+    if args.enrich_timeout_ms.is_some() {
+        config.enrichment.timeout_ms = args.enrich_timeout_ms;
+    }
+    if args.enrich_budget_ms.is_some() {
+        config.enrichment.budget_ms = args.enrich_budget_ms;
+    }
+    if args.flag_anomalies {
+        config.anomaly.get_or_insert_with(Default::default);
+    }
+    if let Some(ref mut anomaly) = config.anomaly {
+        if args.anomaly_threshold.is_some() {
+            anomaly.threshold = args.anomaly_threshold;
+        }
+    }
 
-    /// Chain ID (e.g., 1=Ethereum, 137=Polygon, 42161=Arbitrum, 8453=Base, 56=BSC)
-    #[arg(long)]
-    chain_id: Option<u64>,
+    let topics = [&args.topic1, &args.topic2, &args.topic3];
+    if args.contract.is_some() || !args.event.is_empty() || args.preset.is_some() || topics.iter().any(|t| !t.is_empty()) {
+        let chain = config.chain_mut(args.chain_id);
+        if !args.rpc_url.is_empty() {
+            chain.rpc_urls = args.rpc_url.clone();
+        }
+        if !args.archive_rpc_url.is_empty() {
+            chain.archive_rpc_urls = args.archive_rpc_url.clone();
+        }
+        if let Some(ref endpoint) = args.firehose_endpoint {
+            chain.firehose = Some(FirehoseConfig {
+                endpoint: endpoint.clone(),
+                api_key: args.firehose_api_key.clone(),
+                token: None,
+            });
+        }
+        chain.contracts.push(ContractConfig {
+            address: args.contract.clone(),
+            events: args.event.iter().cloned().map(EventConfig::Signature).collect(),
+            allow_addresses: args.allow_address.clone(),
+            deny_addresses: args.deny_address.clone(),
+            factory: args.child_argument.filter(|_| args.factory).map(|child_argument| FactoryConfig {
+                child_argument,
+                events: args.child_event.clone(),
+                abi: args.child_abi.clone(),
+            }),
+            abi: args.abi.clone(),
+            preset: args.preset,
+            topic1: args.topic1.clone(),
+            topic2: args.topic2.clone(),
+            topic3: args.topic3.clone(),
+            filter: args.filter.clone(),
+            ..ContractConfig::default()
+        });
+    }
 
-    /// RPC endpoint URL (optional, overrides chain-id)
-    #[arg(short, long)]
-    rpc_url: Option<String>,
+    if args.start_block.is_some() {
+        for chain in &mut config.chains {
+            chain.start_block = args.start_block;
+        }
+    }
+}
 
-    /// Event signature to filter (optional, e.g., "Transfer(address,address,uint256)")
-    /// If not provided, will listen to all events
-    #[arg(short, long)]
-    event: Option<String>,
+/// Get RPC URLs and display name for a chain: priority is explicit
+/// `rpc_urls` > chain ID env > RPC_URLS/RPC_URL env
+fn resolve_chain(chain: &ChainConfig) -> Result<(Vec<String>, String)> {
+    let (rpc_urls, default_name) = if !chain.rpc_urls.is_empty() {
+        let name = chain
+            .chain_id
+            .and_then(chain_info)
+            .map_or("Custom", |info| info.name);
+        (chain.rpc_urls.clone(), name.to_string())
+    } else if let Some(chain_id) = chain.chain_id {
+        get_rpc_urls_from_chain_id(chain_id)?
+    } else if let Some(urls) = read_rpc_urls_env("RPC_URL") {
+        (urls, "Custom".to_string())
+    } else {
+        anyhow::bail!("Must provide --chain-id, --rpc-url, or set RPC_URL environment variable");
+    };
 
-    /// Start block number (optional, defaults to latest)
-    #[arg(short, long)]
-    start_block: Option<u64>,
+    Ok((rpc_urls, chain.name.clone().unwrap_or(default_name)))
+}
 
-    /// Poll interval in milliseconds (default: 1000ms = 1 second)
-    #[arg(short, long, default_value = "1000")]
-    poll_interval_ms: u64,
+/// `diff` subcommand: exits with status 1 when the exports differ
+fn run_diff(args: &DiffArgs) -> Result<()> {
+    let query = ArchiveQuery {
+        from_block: args.from_block,
+        to_block: args.to_block,
+        topic0: None,
+    };
+    let left = read_events_range(&args.left, &query)?;
+    let right = read_events_range(&args.right, &query)?;
+    let (left_count, right_count) = (left.len(), right.len());
 
-    /// Output format: pretty, json, or compact
-    #[arg(long, default_value = "pretty")]
-    output_format: String,
+    let report = diff_events(left, right);
 
-    /// Output file path (optional, writes to stdout if not provided)
-    #[arg(long)]
-    output_file: Option<String>,
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!(" Comparing {} ({} events) with {} ({} events)", args.left, left_count, args.right, right_count);
+        println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+        println!(" Missing from {}: {}", args.right, report.missing.len());
+        for event in &report.missing {
+            println!("   - Block {} | Tx {} | Log {}", event.block_number, event.transaction_hash, event.log_index);
+        }
+        println!(" Extra in {}: {}", args.right, report.extra.len());
+        for event in &report.extra {
+            println!("   + Block {} | Tx {} | Log {}", event.block_number, event.transaction_hash, event.log_index);
+        }
+        println!(" Changed: {}", report.changed.len());
+        for change in &report.changed {
+            println!(
+                "   ~ Block {} | Tx {} | Log {} ({})",
+                change.left.block_number,
+                change.left.transaction_hash,
+                change.left.log_index,
+                change.fields.join(", ")
+            );
+        }
+    }
 
-    /// Webhook URL to POST events to (optional)
-    #[arg(long)]
-    webhook_url: Option<String>,
+    if !report.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-/// Structured event data for JSON output and integrations
-#[derive(Debug, Serialize, Deserialize, Clone)]
-struct EventData {
-    timestamp: String,
-    chain_id: Option<u64>,
-    chain_name: String,
-    block_number: u64,
-    transaction_hash: String,
-    log_index: u64,
-    contract_address: String,
-    topics: Vec<String>,
-    data: String,
-    event_signature: Option<String>,
+/// `export` subcommand
+fn run_export(args: &ExportArgs) -> Result<()> {
+    let topic0 = match args.event {
+        Some(ref event) if event.starts_with("0x") => Some(event.to_lowercase()),
+        Some(ref event) => Some(format!("{:?}", compute_event_topic(&normalize_signature(event)?))),
+        None => None,
+    };
+    let query = ArchiveQuery {
+        from_block: args.from_block,
+        to_block: args.to_block,
+        topic0,
+    };
+
+    for event in read_events_range(&args.source, &query)? {
+        print_json(&event)?;
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Load .env file if exists
-    dotenv::dotenv().ok();
+/// `query` subcommand
+#[cfg(feature = "sqlite")]
+fn run_query(args: &QueryArgs) -> Result<()> {
+    use listener::output::write_csv;
+    use listener::sinks::sqlite::{query_events, EventQuery};
 
-    let args = Args::parse();
+    let query = EventQuery {
+        from_block: args.from_block,
+        to_block: args.to_block,
+        event: args.event.clone(),
+        contract: args.contract.clone(),
+        limit: args.limit,
+    };
+    let events = query_events(&args.sqlite, &query)?;
 
-    // Get RPC URL: priority is --rpc-url > --chain-id > RPC_URL env
-    let (rpc_url, chain_name) = if let Some(url) = args.rpc_url {
-        (url, "Custom".to_string())
-    } else if let Some(chain_id) = args.chain_id {
-        get_rpc_url_from_chain_id(chain_id)?
-    } else if let Ok(url) = std::env::var("RPC_URL") {
-        (url, "Custom".to_string())
-    } else {
-        anyhow::bail!("Must provide --chain-id, --rpc-url, or set RPC_URL environment variable");
+    match args.format.as_str() {
+        "json" => {
+            for event in &events {
+                print_json(event)?;
+            }
+        }
+        "csv" => write_csv(std::io::stdout().lock(), &events)?,
+        other => anyhow::bail!("Unknown query format: {} (expected json or csv)", other),
+    }
+    Ok(())
+}
+
+/// `self-update` subcommand
+async fn run_self_update(args: &SelfUpdateArgs) -> Result<()> {
+    let releases = Releases::new(args.repo.as_deref())?;
+    let current = update::current_version();
+    let release = match args.version {
+        Some(ref version) => releases.tagged(version).await?,
+        None => releases.latest().await?,
+    };
+    let version = release
+        .version()
+        .with_context(|| format!("Release tag {} isn't a version", release.tag_name))?;
+
+    if version <= current && !args.force {
+        println!("✅ listener v{} is up to date (latest release: {})", current, release.tag_name);
+        return Ok(());
+    }
+    if args.check {
+        println!("⬆️  listener {} is available (running v{}): {}", release.tag_name, current, release.html_url);
+        for fix in release.fixes() {
+            println!("    {}", fix);
+        }
+        return Ok(());
+    }
+
+    println!(" Installing listener {} for {}...", release.tag_name, update::TARGET);
+    let path = releases.install(&release, args.allow_unverified).await?;
+    println!("✅ Updated {} from v{} to {}", path.display(), current, release.tag_name);
+    Ok(())
+}
+
+/// `template` subcommand
+async fn run_template(args: &TemplateArgs) -> Result<()> {
+    let mut registry = Registry::open(args.template_dir.as_deref().unwrap_or(DEFAULT_TEMPLATE_DIR))?;
+
+    match args.command {
+        TemplateCommand::Add {
+            ref url,
+            ref sha256,
+            signer,
+            ref signature_url,
+        } => {
+            let pin = Pin {
+                sha256: sha256.clone(),
+                signer,
+                signature_url: signature_url.clone(),
+            };
+            let entry = registry.add(url, &pin).await?;
+            println!("✅ Added template {}@{}", entry.name, entry.version);
+            for (file, checksum) in &entry.files {
+                println!(" {}  sha256 {}", file, checksum);
+            }
+            match entry.signer {
+                Some(ref signer) => println!(" Signed by {}", signer),
+                None => println!(" Not signature-checked; pass --signer to require the publisher's signature"),
+            }
+        }
+        TemplateCommand::List => {
+            if registry.entries().is_empty() {
+                println!(" No templates in {}", registry.path().display());
+            }
+            for entry in registry.entries() {
+                println!(
+                    " {}@{}  {}{}",
+                    entry.name,
+                    entry.version,
+                    entry.source,
+                    entry.signer.as_deref().map(|signer| format!(" (signed by {})", signer)).unwrap_or_default()
+                );
+            }
+        }
+        TemplateCommand::Remove { ref template } => {
+            let (name, version) = template
+                .split_once('@')
+                .with_context(|| format!("Expected name@version, got {}", template))?;
+            let entry = registry.remove(name, version)?;
+            println!(" Removed template {}@{}", entry.name, entry.version);
+        }
+    }
+    Ok(())
+}
+
+/// `discover` subcommand
+async fn run_discover(args: &DiscoverArgs) -> Result<()> {
+    let contract: Address = args.contract.parse()
+        .with_context(|| format!("Invalid contract address: {}", args.contract))?;
+    let chain = ChainConfig {
+        chain_id: args.chain_id,
+        rpc_urls: args.rpc_url.clone(),
+        ..ChainConfig::default()
+    };
+    let (rpc_urls, chain_name) = resolve_chain(&chain)?;
+    let provider = ProviderManager::new(&rpc_urls).context("Failed to connect to RPC endpoint")?;
+    let decoder = args.abi.as_ref().map(AbiDecoder::load).transpose()?;
+
+    let to_block = match args.to_block {
+        Some(block) => block,
+        None => provider.get_block_number().await?,
     };
+    let from_block = args
+        .from_block
+        .unwrap_or_else(|| to_block.saturating_sub(args.blocks.saturating_sub(1)));
+
+    if !args.json {
+        println!(" Scanning {} on {}, blocks {}..{}", args.contract, chain_name, from_block, to_block);
+    }
+    let mut stats = discover_topics(&provider, contract, from_block, to_block, DEFAULT_CHUNK_SIZE, |block| {
+        if !args.json {
+            print!("\r Scanning... (Block: {}/{}) ", block, to_block);
+            std::io::Write::flush(&mut std::io::stdout()).ok();
+        }
+    })
+    .await?;
 
-    println!(" Starting Smart Contract Event Listener");
+    for entry in &mut stats {
+        entry.signature = entry.topic0.and_then(|topic0| {
+            decoder
+                .as_ref()
+                .and_then(|d| d.event_signature(&topic0))
+                .or_else(|| guess_signature(&topic0).map(String::from))
+        });
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let total: usize = stats.iter().map(|s| s.count).sum();
+    println!("\r Found {} log(s) with {} distinct topic0 value(s)          ", total, stats.len());
     println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
-    println!("  Chain: {}", chain_name);
-    println!(" Contract: {}", args.contract);
-    println!(" RPC: {}", mask_api_key(&rpc_url));
-    
-    if let Some(ref event_sig) = args.event {
-        println!(" Event: {}", event_sig);
-    } else {
-        println!(" Listening to: ALL events");
+    for entry in &stats {
+        let share = entry.count as f64 * 100.0 / total as f64;
+        let topics: Vec<String> = entry.topic_counts.iter().map(|n| n.to_string()).collect();
+        println!(
+            " {:>8}  {:>5.1}%  {}",
+            entry.count,
+            share,
+            entry.signature.as_deref().unwrap_or("(unknown signature)")
+        );
+        match entry.topic0 {
+            Some(topic0) => println!("                   topic0 {:?}", topic0),
+            None => println!("                   anonymous (no topics)"),
+        }
+        println!(
+            "                   topics {} | blocks {}..{}",
+            topics.join("/"),
+            entry.first_block,
+            entry.last_block
+        );
+    }
+
+    if let Some(signature) = stats.iter().find_map(|s| s.signature.as_deref()) {
+        println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+        println!(" Filter on an event with: --event \"{}\"", signature);
     }
-    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━\n");
+    Ok(())
+}
 
-    // Connect to the network
-    let provider = Provider::<Http>::try_from(rpc_url.as_str())
-        .context("Failed to connect to RPC endpoint")?;
-    let provider = Arc::new(provider);
+/// `coverage` subcommand
+async fn run_coverage(args: &CoverageArgs) -> Result<()> {
+    let decoder = AbiDecoder::load(&args.abi)?;
+    let contract: Option<Address> = args
+        .contract
+        .as_ref()
+        .map(|contract| contract.parse().with_context(|| format!("Invalid contract address: {}", contract)))
+        .transpose()?;
+
+    let (stats, range) = match args.export {
+        Some(ref export) => {
+            let query = ArchiveQuery {
+                from_block: args.from_block,
+                to_block: args.to_block,
+                topic0: None,
+            };
+            let mut events = read_events_range(export, &query)?;
+            if let Some(contract) = contract {
+                events.retain(|event| event.contract_address.parse::<Address>().ok() == Some(contract));
+            }
+            let first = events.iter().map(|event| event.block_number).min();
+            let last = events.iter().map(|event| event.block_number).max();
+            let range = match (args.from_block.or(first), args.to_block.or(last)) {
+                (Some(from), Some(to)) => format!("{}, blocks {}..{}", export, from, to),
+                _ => export.clone(),
+            };
+            (topic_stats(&events), range)
+        }
+        None => {
+            let contract = contract.context("A contract address is required to scan the chain")?;
+            let chain = ChainConfig {
+                chain_id: args.chain_id,
+                rpc_urls: args.rpc_url.clone(),
+                ..ChainConfig::default()
+            };
+            let (rpc_urls, chain_name) = resolve_chain(&chain)?;
+            let provider = ProviderManager::new(&rpc_urls).context("Failed to connect to RPC endpoint")?;
+            let to_block = match args.to_block {
+                Some(block) => block,
+                None => provider.get_block_number().await?,
+            };
+            let from_block = args
+                .from_block
+                .unwrap_or_else(|| to_block.saturating_sub(args.blocks.saturating_sub(1)));
 
-    // Parse contract address
-    let contract_address: Address = args.contract.parse()
-        .context("Invalid contract address")?;
+            let stats = discover_topics(&provider, contract, from_block, to_block, DEFAULT_CHUNK_SIZE, |block| {
+                if !args.json {
+                    print!("\r Scanning... (Block: {}/{}) ", block, to_block);
+                    std::io::Write::flush(&mut std::io::stdout()).ok();
+                }
+            })
+            .await?;
+            if !args.json {
+                print!("\r");
+            }
+            (stats, format!("{:?} on {}, blocks {}..{}", contract, chain_name, from_block, to_block))
+        }
+    };
 
-    // Determine starting block
-    let from_block = if let Some(block) = args.start_block {
-        block
+    let report = CoverageReport::new(&decoder, &stats);
+    let never_fired: Vec<&str> = report.never_fired().map(|event| event.signature.as_str()).collect();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
     } else {
-        provider.get_block_number().await?.as_u64()
-    };
+        println!(" Coverage of {} declared event(s) in {}", report.events.len(), range);
+        println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+        for event in &report.events {
+            let blocks = match (event.first_block, event.last_block) {
+                (Some(first), Some(last)) => format!("  blocks {}..{}", first, last),
+                _ => String::new(),
+            };
+            let mark = if event.count > 0 { "✓" } else { "✗" };
+            println!(" {} {:>8}  {}{}", mark, event.count, event.signature, blocks);
+        }
+        println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+        println!(
+            " {}/{} declared event(s) observed",
+            report.events.len() - never_fired.len(),
+            report.events.len()
+        );
+        if !never_fired.is_empty() {
+            println!(" Never fired: {}", never_fired.join(", "));
+        }
+        if report.undeclared_logs > 0 {
+            println!(
+                " {} log(s) of {} event(s) not declared in the ABI",
+                report.undeclared_logs, report.undeclared_topics
+            );
+        }
+    }
 
-    println!(" Starting from block: {}\n", from_block);
+    if args.strict && !never_fired.is_empty() {
+        anyhow::bail!("{} declared event(s) never fired", never_fired.len());
+    }
+    Ok(())
+}
 
-    // Create event filter
-    let mut current_block = from_block;
-    let poll_interval = std::time::Duration::from_millis(args.poll_interval_ms);
+/// `config test` subcommand
+async fn run_config_test(file: &Path, profile: Option<&str>) -> Result<()> {
+    let mut config = Config::load(file, profile)?;
+    apply_templates(&mut config).await?;
+    config.expand_presets();
+    config.normalize_signatures()?;
 
-    loop {
-        // Get the latest block number
-        let latest_block = provider.get_block_number().await?.as_u64();
-
-        if latest_block > current_block {
-            // Create filter for the new blocks
-            let filter = Filter::new()
-                .address(contract_address)
-                .from_block(current_block)
-                .to_block(latest_block);
-
-            // Apply event topic filter if specified
-            let filter = if let Some(ref event_sig) = args.event {
-                let _topic = compute_event_topic(event_sig);
-                filter.event(event_sig)
-            } else {
-                filter
+    let (mut passed, mut failed) = (0, 0);
+    for chain in &config.chains {
+        let chain_name = match chain.name {
+            Some(ref name) => name.clone(),
+            None => chain.chain_id.and_then(chain_info).map_or("Custom".to_string(), |info| info.name.to_string()),
+        };
+        for contract in chain.contracts.iter().filter(|contract| !contract.tests.is_empty()) {
+            let decoder = match contract.abi {
+                Some(ref abi) => Some(AbiDecoder::load(abi)?),
+                None => contract.preset.map(Preset::decoder),
             };
+            let label = contract.name.as_deref().unwrap_or(contract.label());
+            println!(" {} {}", chain_name, label);
 
-            // Get logs
-            match provider.get_logs(&filter).await {
-                Ok(logs) => {
-                    for log in &logs {
-                        let event_data = log_to_event_data(
-                            log,
-                            args.chain_id,
-                            &chain_name,
-                            &contract_address,
-                            args.event.as_deref(),
-                        );
-                        
-                        // Output based on format
-                        match args.output_format.as_str() {
-                            "json" => print_json(&event_data)?,
-                            "compact" => print_compact(&event_data),
-                            _ => print_pretty(&event_data),
-                        }
-                        
-                        // Write to file if specified
-                        if let Some(ref file_path) = args.output_file {
-                            write_to_file(file_path, &event_data)?;
-                        }
-                        
-                        // Send to webhook if specified
-                        if let Some(ref webhook) = args.webhook_url {
-                            send_webhook(webhook, &event_data).await?;
+            for (i, test) in contract.tests.iter().enumerate() {
+                let name = test.name.clone().unwrap_or_else(|| format!("test {}", i + 1));
+                let outcome = run_test(chain.chain_id, contract, decoder.as_ref(), test);
+                match outcome {
+                    Ok(verdict) if verdict.matched == test.should_match => {
+                        passed += 1;
+                        let result = if verdict.matched { "matches" } else { "doesn't match" };
+                        println!("   ✓ {}: {}", name, result);
+                    }
+                    Ok(verdict) => {
+                        failed += 1;
+                        match verdict.reason {
+                            Some(reason) => println!("   ✗ {}: expected a match, but {}", name, reason),
+                            None => println!("   ✗ {}: expected no match, but it matches", name),
                         }
                     }
-                    
-                    if logs.is_empty() && args.output_format == "pretty" {
-                        print!("\r Listening... (Block: {}) ", latest_block);
-                        std::io::Write::flush(&mut std::io::stdout()).ok();
+                    Err(e) => {
+                        failed += 1;
+                        println!("   ✗ {}: {:#}", name, e);
                     }
                 }
-                Err(e) => {
-                    eprintln!(" Error fetching logs: {}", e);
-                }
             }
+        }
+    }
+
+    if passed + failed == 0 {
+        println!(" No tests in {}", file.display());
+        return Ok(());
+    }
+    println!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
+    println!(" {} passed, {} failed", passed, failed);
+    if failed > 0 {
+        anyhow::bail!("{} rule test(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// `hash` subcommand. Signatures starting with `event` only get a topic0,
+/// those starting with `function` only a selector, the others both.
+fn run_hash(args: &HashArgs) -> Result<()> {
+    for input in &args.signatures {
+        let signature = normalize_signature(input)?;
+        let trimmed = input.trim_start();
+        let topic0 = (!trimmed.starts_with("function ")).then(|| format!("{:?}", compute_event_topic(&signature)));
+        let selector = (!trimmed.starts_with("event "))
+            .then(|| format!("0x{}", ethers::utils::hex::encode(compute_selector(&signature))));
+
+        if args.json {
+            let hashes = serde_json::json!({
+                "signature": signature,
+                "topic0": topic0,
+                "selector": selector,
+            });
+            println!("{}", hashes);
+        } else {
+            println!(" {}", signature);
+            if let Some(topic0) = topic0 {
+                println!("   topic0:   {}", topic0);
+            }
+            if let Some(selector) = selector {
+                println!("   selector: {}", selector);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Start Anvil for `--anvil`, point `args` at its test contract from the
+/// first block, and emit test events in the background
+async fn start_anvil(args: &mut ListenArgs) -> Result<Anvil> {
+    let anvil = Anvil::start().await?;
+    info!(
+        "Anvil: {} (chain {}), test contract {:?} emitting {}",
+        anvil.url, anvil.chain_id, anvil.contract, TEST_EVENT
+    );
+    args.rpc_url = vec![anvil.url.clone()];
+    args.contract = Some(format!("{:?}", anvil.contract));
+    args.chain_id = Some(anvil.chain_id);
+    args.start_block = args.start_block.or(Some(0));
+    anvil.emit_every(Duration::from_secs(args.anvil_interval.unwrap_or(2)));
+    Ok(anvil)
+}
+
+/// `replay` subcommand: the events of `input`, in order, handed to the
+/// outputs of the config like the listener would, as part of a new run.
+/// They keep the time they were originally detected at.
+async fn run_replay(args: &ReplayArgs) -> Result<()> {
+    let speed = match args.speed.trim() {
+        "max" => None,
+        speed => match speed.trim_end_matches(['x', 'X']).parse::<f64>() {
+            Ok(speed) if speed > 0.0 && speed.is_finite() => Some(speed),
+            _ => anyhow::bail!("Invalid replay speed {:?} (expected e.g. 1x, 10x, 0.5x, or max)", args.speed),
+        },
+    };
+    let events = read_events_file(&args.input)?;
+    let (config, _) = load_config(&args.listen).await?;
+
+    let run = RunMetadata::new(&config)?;
+    info!("Replaying {} event(s) from {} at {}", events.len(), args.input, args.speed);
+    info!("Run: {} (v{}, config {})", run.run_id, run.listener_version, run.config_hash);
+
+    let metrics = SupervisorMetrics::new();
+    let mut sinks = sinks::from_config(&config.sinks).await?;
+    for sink in &mut sinks {
+        sink.start_run(&run).await?;
+    }
+    let mut outputs = Outputs {
+        sinks: spawn_sink_workers(sinks, &config, Some(&run), &metrics)?,
+        output_file: open_output_file(&config.sinks)?,
+        template: load_output_template(&config.sinks)?,
+        siem: SiemFormat::from_config(&config.sinks)?,
+        webhooks: match config.has_webhooks() {
+            true => Some(WebhookQueue::open(&config.sinks.webhook)?),
+            false => None,
+        },
+        router: Router::new(&config.sinks)?,
+        dedup: None,
+        hash_chain: None,
+        config: config.clone(),
+    };
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let total = events.len();
+    let mut replayed = 0;
+    let mut previous: Option<chrono::DateTime<chrono::FixedOffset>> = None;
+    for mut event in events {
+        // The gap to the previous event, at the replay's speed
+        let detected = chrono::DateTime::parse_from_rfc3339(&event.timestamp).ok();
+        let gap = match (speed, previous, detected) {
+            (Some(speed), Some(previous), Some(detected)) => {
+                (detected - previous).to_std().unwrap_or_default().div_f64(speed)
+            }
+            _ => Duration::ZERO,
+        };
+        previous = detected.or(previous);
+        tokio::select! {
+            () = tokio::time::sleep(gap) => {}
+            () = &mut shutdown => {
+                info!("Stopping the replay");
+                break;
+            }
+        }
 
-            current_block = latest_block + 1;
+        event.run = Some(run.clone());
+        if !config.sinks.humanize {
+            event.humanized = None;
+        }
+        if !config.sinks.include_raw {
+            event.raw = None;
         }
+        outputs.deliver(&mut event).await?;
+        replayed += 1;
+    }
 
-        tokio::time::sleep(poll_interval).await;
+    outputs.flush().await;
+    if let Some(ref mut file) = outputs.output_file {
+        file.sync()?;
+    }
+    if let Some(queue) = outputs.webhooks {
+        queue.close().await;
     }
+    info!("Replayed {} of {} event(s)", replayed, total);
+    print_crashes(&metrics);
+    Ok(())
 }
 
-fn get_rpc_url_from_chain_id(chain_id: u64) -> Result<(String, String)> {
-    let (env_var, chain_name) = match chain_id {
-        1 => ("ETHEREUM_RPC_URL", "Ethereum Mainnet"),
-        137 => ("POLYGON_RPC_URL", "Polygon"),
-        42161 => ("ARBITRUM_RPC_URL", "Arbitrum One"),
-        8453 => ("BASE_RPC_URL", "Base"),
-        56 => ("BSC_RPC_URL", "Binance Smart Chain"),
-        10 => ("OPTIMISM_RPC_URL", "Optimism"),
-        43114 => ("AVALANCHE_RPC_URL", "Avalanche C-Chain"),
-        250 => ("FANTOM_RPC_URL", "Fantom"),
-        // Testnets
-        11155111 => ("SEPOLIA_RPC_URL", "Sepolia Testnet"),
-        80001 => ("MUMBAI_RPC_URL", "Mumbai Testnet"),
-        _ => anyhow::bail!("Unsupported chain ID: {}. Add it to your .env file with CHAIN_{}_RPC_URL", chain_id, chain_id),
+/// `decode` subcommand: decode each log with the ABI, else as a guessed
+/// signature, and print it. Fails when some log couldn't be decoded.
+async fn run_decode(args: &DecodeArgs) -> Result<()> {
+    let logs = match args.topics.is_empty() {
+        false => vec![Log {
+            topics: args.topics.iter().map(|topic| parse_topic(topic)).collect::<Result<_>>()?,
+            data: parse_hex_data(args.data.as_deref().unwrap_or_default())?,
+            ..Default::default()
+        }],
+        true => {
+            if std::io::stdin().is_terminal() {
+                anyhow::bail!("Pass the log as --topics and --data, or as JSON on stdin");
+            }
+            let mut input = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+            read_json_logs(&input)?
+        }
     };
+    if logs.is_empty() {
+        anyhow::bail!("No logs on stdin");
+    }
 
-    let rpc_url = std::env::var(env_var)
-        .with_context(|| format!("Environment variable {} not found. Add it to your .env file", env_var))?;
+    let decoder = args.abi.as_ref().map(AbiDecoder::load).transpose()?;
+    let guesser = SignatureGuesser::new(args.openchain)?;
+    let chain_name = args.chain_id.and_then(chain_info).map_or("Unknown", |chain| chain.name);
+    let mut undecoded = 0;
+    for log in &logs {
+        let mut event = log_to_event_data(log, args.chain_id, chain_name, &log.address, None);
+        event.raw = None;
+        match decoder.as_ref().and_then(|d| d.decode(log, native_symbol(args.chain_id))) {
+            Some(Ok(decoded)) => {
+                event.event_signature = decoder.as_ref().and_then(|d| d.event_signature(&log.topics[0]));
+                event.event_name = Some(decoded.name);
+                event.decoded = Some(decoded.params);
+                if !decoded.humanized.is_empty() {
+                    event.humanized = Some(decoded.humanized);
+                }
+                event.decode_error = decoded.error;
+            }
+            Some(Err(e)) => event.decode_error = Some(e),
+            None => guesser.enrich(&mut event).await?,
+        }
+        if event.decoded.is_none() || event.decode_error.is_some() {
+            undecoded += 1;
+        }
+
+        if args.json {
+            println!("{}", serde_json::to_string(&event)?);
+            continue;
+        }
+        match (&event.event_signature, &event.guessed_signature, &event.decode_error) {
+            (_, _, Some(error)) => println!(" ⚠️  Could not decode as {}: {}", error.event, error.reason),
+            (Some(signature), _, None) => println!(" {}", signature),
+            (None, Some(signature), None) => println!(" {} (guessed from topic0)", signature),
+            (None, None, None) => println!(
+                " Unknown event {} (not in the ABI or the known signatures)",
+                event.topics.first().map_or("without topics", String::as_str)
+            ),
+        }
+        for (name, value) in event.decoded.iter().flatten() {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            match event.humanized.as_ref().and_then(|h| h.get(name)).and_then(|h| h.as_str()) {
+                Some(human) => println!("   {}: {} ({})", name, value, human),
+                None => println!("   {}: {}", name, value),
+            }
+        }
+    }
 
-    Ok((rpc_url, chain_name.to_string()))
+    if undecoded > 0 {
+        anyhow::bail!("{} of {} log(s) couldn't be decoded", undecoded, logs.len());
+    }
+    Ok(())
 }
 
-fn mask_api_key(url: &str) -> String {
-    // Mask API keys in URLs for privacy
-    if let Some(pos) = url.rfind('/') {
-        if pos + 1 < url.len() {
-            let (base, key) = url.split_at(pos + 1);
-            if key.len() > 8 {
-                return format!("{}{}...{}", base, &key[..4], &key[key.len()-4..]);
+/// The logs in `input`: JSON log objects as returned by `eth_getLogs`,
+/// block explorers, or the listener's own output, alone, in arrays, in the
+/// `logs` of a receipt or the `result` of an RPC response, or as JSON Lines
+fn read_json_logs(input: &str) -> Result<Vec<Log>> {
+    fn collect(value: serde_json::Value, logs: &mut Vec<Log>) -> Result<()> {
+        match value {
+            serde_json::Value::Array(values) => {
+                for value in values {
+                    collect(value, logs)?;
+                }
+            }
+            serde_json::Value::Object(mut object) => {
+                if let Some(inner) = object.remove("logs").or_else(|| object.remove("result")) {
+                    return collect(inner, logs);
+                }
+                logs.push(json_log(&object)?);
             }
+            other => anyhow::bail!("Expected a JSON log, got {}", other),
         }
+        Ok(())
     }
-    url.to_string()
+
+    let mut logs = Vec::new();
+    for value in serde_json::Deserializer::from_str(input).into_iter::<serde_json::Value>() {
+        collect(value.context("Invalid JSON on stdin")?, &mut logs)?;
+    }
+    Ok(logs)
+}
+
+/// A log from its JSON fields, in RPC (`blockNumber`) or listener
+/// (`block_number`) naming. Only `topics` is required.
+fn json_log(object: &serde_json::Map<String, serde_json::Value>) -> Result<Log> {
+    let field = |rpc: &str, listener: &str| object.get(rpc).or_else(|| object.get(listener));
+    let number = |rpc: &str, listener: &str| match field(rpc, listener)? {
+        serde_json::Value::Number(n) => n.as_u64(),
+        serde_json::Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    };
+
+    let topics = object
+        .get("topics")
+        .and_then(|topics| topics.as_array())
+        .context("JSON log without a topics array")?
+        .iter()
+        .map(|topic| parse_topic(topic.as_str().unwrap_or_default()))
+        .collect::<Result<_>>()?;
+    let address = match field("address", "contract_address").and_then(|address| address.as_str()) {
+        Some(address) => address.parse().with_context(|| format!("Invalid address in JSON log: {}", address))?,
+        None => Address::zero(),
+    };
+    Ok(Log {
+        address,
+        topics,
+        data: parse_hex_data(object.get("data").and_then(|data| data.as_str()).unwrap_or_default())?,
+        block_number: number("blockNumber", "block_number").map(Into::into),
+        transaction_hash: field("transactionHash", "transaction_hash")
+            .and_then(|hash| hash.as_str())
+            .and_then(|hash| hash.parse().ok()),
+        log_index: number("logIndex", "log_index").map(Into::into),
+        ..Default::default()
+    })
 }
 
-fn compute_event_topic(event_sig: &str) -> H256 {
-    use ethers::utils::keccak256;
-    let hash = keccak256(event_sig.as_bytes());
-    H256::from_slice(&hash)
+fn parse_hex_data(data: &str) -> Result<ethers::types::Bytes> {
+    let bytes = hex::decode(data.trim().trim_start_matches("0x"))
+        .map_err(|e| anyhow::anyhow!("Invalid log data {}: {}", data, e))?;
+    Ok(bytes.into())
 }
 
-fn log_to_event_data(
-    log: &Log,
-    chain_id: Option<u64>,
-    chain_name: &str,
-    contract_address: &Address,
-    event_signature: Option<&str>,
-) -> EventData {
-    EventData {
-        timestamp: Local::now().to_rfc3339(),
-        chain_id,
-        chain_name: chain_name.to_string(),
-        block_number: log.block_number.map(|n| n.as_u64()).unwrap_or(0),
-        transaction_hash: log
-            .transaction_hash
-            .map(|h| format!("{:?}", h))
-            .unwrap_or_default(),
-        log_index: log.log_index.map(|n| n.as_u64()).unwrap_or(0),
-        contract_address: format!("{:?}", contract_address),
-        topics: log.topics.iter().map(|t| format!("{:?}", t)).collect(),
-        data: hex::encode(&log.data),
-        event_signature: event_signature.map(String::from),
-    }
-}
-
-fn print_json(event: &EventData) -> Result<()> {
-    println!("{}", serde_json::to_string(event)?);
+/// The last block the `backfill` subcommand fetches on each connected
+/// chain: `to_block`, or else the chain head now
+async fn backfill_ends(config: &Config, listeners: &ListenerSet, to_block: Option<u64>) -> Result<HashMap<Option<u64>, u64>> {
+    let providers = listeners.providers();
+    let mut ends = HashMap::new();
+    for chain in &config.chains {
+        let Some(provider) = providers.get(&chain.chain_id) else {
+            continue;
+        };
+        let end = match to_block {
+            Some(block) => block,
+            None => provider.get_block_number().await.context("Failed to fetch the chain head")?,
+        };
+        let (_, chain_name) = resolve_chain(chain)?;
+        info!(
+            "Backfilling {} from block {} to {}",
+            chain_name,
+            chain.start_block.unwrap_or_default(),
+            end
+        );
+        ends.insert(chain.chain_id, end);
+    }
+    Ok(ends)
+}
+
+/// `abi fetch` subcommand
+async fn run_abi_fetch(
+    address: Address,
+    chain_id: u64,
+    etherscan_api_key: Option<String>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let sources = ProxyConfig {
+        etherscan_api_key: etherscan_api_key.or_else(|| std::env::var("ETHERSCAN_API_KEY").ok()),
+        ..Default::default()
+    };
+    let Some(abi) = fetch_abi(&sources, chain_id, address).await? else {
+        let hint = match sources.etherscan_api_key {
+            Some(_) => "",
+            None => "; pass --etherscan-api-key to look on Etherscan too",
+        };
+        anyhow::bail!("No verified ABI found for {:?} on chain {}{}", address, chain_id, hint);
+    };
+
+    let json = serde_json::to_string_pretty(&abi)?;
+    match output {
+        Some(path) => {
+            std::fs::write(path, json + "\n").with_context(|| format!("Failed to write {}", path.display()))?;
+            eprintln!(
+                "✅ Wrote the ABI of {:?} ({} events, {} functions) to {}",
+                address,
+                abi.events().count(),
+                abi.functions().count(),
+                path.display()
+            );
+        }
+        None => println!("{}", json),
+    }
     Ok(())
 }
 
-fn print_compact(event: &EventData) {
-    println!(
-        "[{}] Block {} | Tx {} | Contract {} | Topics: {}",
-        event.timestamp,
-        event.block_number,
-        &event.transaction_hash[..10],
-        &event.contract_address[..10],
-        event.topics.len()
-    );
+/// `status` subcommand: the running listener's status, or the cursors the
+/// stopped one checkpointed, as JSON
+async fn run_status(args: &StatusArgs) -> Result<()> {
+    if let Some(ref socket) = args.socket {
+        let response = control::send(socket, control::Command::Status).await?;
+        println!("{}", response);
+        return Ok(());
+    }
+
+    let path = args.state_file.as_deref().unwrap_or("listener-state.json");
+    if !Path::new(path).exists() {
+        anyhow::bail!("No state file at {}; pass --socket to ask a running listener", path);
+    }
+    let state = StateFile::open(path)?.state;
+    let status = serde_json::json!({
+        "state": "stopped",
+        "state_file": path,
+        "cursors": state.cursors,
+        "hash_chain_head": state.hash_chain_head,
+    });
+    println!("{}", serde_json::to_string(&status)?);
+    Ok(())
 }
 
-fn print_pretty(event: &EventData) {
-    println!("\n╔════════════════════════════════════════════════════════════");
-    println!("║ Event Detected!");
-    println!("║ Time: {}", event.timestamp);
-    println!("║ Chain: {} (ID: {})", event.chain_name, event.chain_id.unwrap_or(0));
-    println!("║ Block: {}", event.block_number);
-    println!("║ Transaction: {}", event.transaction_hash);
-    println!("║ Log Index: {}", event.log_index);
-    println!("║ Contract: {}", event.contract_address);
-    
-    if let Some(ref sig) = event.event_signature {
-        println!("║ Event: {}", sig);
-    }
-    
-    println!("╠════════════════════════════════════════════════════════════");
-    
-    if !event.topics.is_empty() {
-        println!("║ Topics:");
-        for (i, topic) in event.topics.iter().enumerate() {
-            println!("║   [{}] {}", i, topic);
-        }
-    }
-    
-    if !event.data.is_empty() {
-        println!("║ Data: {}", event.data);
-    }
-    
-    println!("╚════════════════════════════════════════════════════════════\n");
-}
-
-fn write_to_file(file_path: &str, event: &EventData) -> Result<()> {
-    use std::fs::OpenOptions;
-    use std::io::Write;
-    
-    let json = serde_json::to_string(event)?;
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file_path)?;
-    
-    writeln!(file, "{}", json)?;
+/// `control` subcommand: prints the listener's status as JSON after the command
+async fn run_control(args: &ControlArgs) -> Result<()> {
+    let Some(ref socket) = args.socket else {
+        anyhow::bail!("--socket is required: the --control-socket of the running listener");
+    };
+    let command = match args.command {
+        ControlCommand::Pause => control::Command::Pause,
+        ControlCommand::Resume => control::Command::Resume,
+        ControlCommand::Status => control::Command::Status,
+    };
+    let response = control::send(socket, command).await?;
+    if let Some(error) = serde_json::from_str::<serde_json::Value>(&response)
+        .ok()
+        .and_then(|value| value.get("error").and_then(|error| error.as_str()).map(str::to_string))
+    {
+        anyhow::bail!("{}", error);
+    }
+    println!("{}", response);
     Ok(())
 }
 
-async fn send_webhook(url: &str, event: &EventData) -> Result<()> {
-    let client = reqwest::Client::new();
-    let response = client
-        .post(url)
-        .json(event)
-        .send()
-        .await?;
-    
-    if !response.status().is_success() {
-        eprintln!("⚠️  Webhook failed: {}", response.status());
+/// `eventlog` subcommand
+#[cfg(windows)]
+fn run_eventlog(args: &EventlogArgs) -> Result<()> {
+    use listener::sinks::eventlog;
+
+    let source = args.source.as_deref().unwrap_or(eventlog::DEFAULT_SOURCE);
+    match args.command {
+        EventlogCommand::Register { ref message_file } => {
+            eventlog::register(Some(source), message_file.as_deref())?;
+            println!("Registered the event source {}", source);
+        }
+        EventlogCommand::Unregister => match eventlog::unregister(Some(source))? {
+            true => println!("Removed the event source {}", source),
+            false => println!("The event source {} isn't registered", source),
+        },
     }
-    
     Ok(())
 }
+
+/// `verify` subcommand: exits with status 1 when the chain is broken
+fn run_verify(file: &str) -> Result<()> {
+    let records = read_record_lines(file)?;
+
+    match verify_chain(&records)? {
+        None => {
+            println!(" ✓ Hash chain intact ({} records)", records.len());
+            Ok(())
+        }
+        Some((index, reason)) => {
+            eprintln!(" ✗ Hash chain broken at record {}: {}", index + 1, reason);
+            std::process::exit(1);
+        }
+    }
+}
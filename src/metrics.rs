@@ -0,0 +1,320 @@
+//! Prometheus metrics (`--metrics-addr`), served as text at `/metrics`.
+//!
+//! The counters and gauges are process-wide and only kept once [`serve`]
+//! has started the endpoint, or OTLP export has started; until then
+//! recording them does nothing.
+//!
+//! - `listener_events_total{chain, contract, event}`: events delivered to
+//!   the outputs
+//! - `listener_events_deduplicated_total{chain}`: events dropped because
+//!   they were already delivered (`--dedup`)
+//! - `listener_block_lag{chain, contract}`: blocks between the chain head
+//!   and the last block a listener scanned
+//! - `listener_chain_head{chain}`: latest block seen on each chain
+//! - `listener_rpc_errors_total{endpoint}`: failed RPC requests, before
+//!   failover and retries
+//! - `listener_webhook_failures_total`: failed webhook delivery attempts
+//! - `listener_webhook_dead_letters_total`: events whose webhook delivery
+//!   was given up on
+//! - `listener_webhook_rate_limited_total{receiver}`: webhook responses
+//!   that asked for a pause with `Retry-After`
+//! - `listener_webhook_backoff_seconds{receiver}`: how much longer a
+//!   webhook receiver's deliveries are paused
+//! - `listener_delivery_latency_seconds`: time from an event's detection to
+//!   its hand-off to the outputs, enrichment included
+//! - `listener_pipeline_queued{stage}`: events waiting in front of a
+//!   processing stage (see [`crate::pipeline`])
+
+use anyhow::{Context, Result};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::event::EventData;
+
+/// Upper bounds of the delivery latency buckets, in seconds
+pub const LATENCY_BUCKETS: [f64; 12] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+#[derive(Default)]
+struct Metrics {
+    events: Mutex<BTreeMap<(String, String, String), u64>>,
+    deduplicated: Mutex<BTreeMap<String, u64>>,
+    block_lag: Mutex<BTreeMap<(String, String), u64>>,
+    chain_head: Mutex<BTreeMap<String, u64>>,
+    rpc_errors: Mutex<BTreeMap<String, u64>>,
+    webhook_failures: Mutex<u64>,
+    webhook_dead_letters: Mutex<u64>,
+    webhook_rate_limited: Mutex<BTreeMap<String, u64>>,
+    /// When each receiver's pause ends
+    webhook_paused_until: Mutex<BTreeMap<String, Instant>>,
+    pipeline_queued: Mutex<BTreeMap<String, u64>>,
+    latency: Mutex<Histogram>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations per bucket of [`LATENCY_BUCKETS`], not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+/// The current value of each counter and gauge
+#[derive(Default)]
+pub struct Samples {
+    pub events: BTreeMap<(String, String, String), u64>,
+    pub deduplicated: BTreeMap<String, u64>,
+    pub block_lag: BTreeMap<(String, String), u64>,
+    pub chain_head: BTreeMap<String, u64>,
+    pub rpc_errors: BTreeMap<String, u64>,
+    pub webhook_failures: u64,
+    pub webhook_dead_letters: u64,
+    pub webhook_rate_limited: BTreeMap<String, u64>,
+    /// Seconds left of each receiver's pause, 0 once it's over
+    pub webhook_backoff: BTreeMap<String, f64>,
+    pub pipeline_queued: BTreeMap<String, u64>,
+}
+
+/// Start keeping metrics
+pub fn enable() {
+    METRICS.get_or_init(Metrics::default);
+}
+
+/// Start keeping metrics and serve them on `addr` in the background
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let server = Server::try_bind(&addr).with_context(|| format!("Failed to bind metrics endpoint to {}", addr))?;
+    enable();
+
+    let service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    let server = server.serve(service);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("Metrics endpoint stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Body::from(render())),
+        _ => Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("Not found\n")),
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// Count `event` as delivered and observe its latency since detection
+pub fn event_delivered(event: &EventData) {
+    let Some(metrics) = METRICS.get() else {
+        return;
+    };
+    let name = event.event_name.clone().unwrap_or_else(|| "unknown".to_string());
+    let key = (event.chain_name.clone(), event.contract_address.to_lowercase(), name);
+    *lock(&metrics.events).entry(key).or_default() += 1;
+
+    let Ok(detected) = chrono::DateTime::parse_from_rfc3339(&event.timestamp) else {
+        return;
+    };
+    let seconds = (chrono::Utc::now() - detected.with_timezone(&chrono::Utc)).num_microseconds().unwrap_or(0) as f64 / 1e6;
+    let mut latency = lock(&metrics.latency);
+    if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+        latency.buckets[bucket] += 1;
+    }
+    latency.count += 1;
+    latency.sum += seconds.max(0.0);
+    #[cfg(feature = "otel")]
+    crate::telemetry::delivery_latency(seconds.max(0.0));
+}
+
+/// Count an event of `chain` dropped as already delivered
+pub fn event_deduplicated(chain: &str) {
+    if let Some(metrics) = METRICS.get() {
+        *lock(&metrics.deduplicated).entry(chain.to_string()).or_default() += 1;
+    }
+}
+
+/// Record the chain head and how far behind it the listener of `contract`
+/// on `chain` has scanned
+pub fn block_lag(chain: &str, contract: &str, latest_block: u64, lag: u64) {
+    let Some(metrics) = METRICS.get() else {
+        return;
+    };
+    lock(&metrics.block_lag).insert((chain.to_string(), contract.to_string()), lag);
+    let mut heads = lock(&metrics.chain_head);
+    let head = heads.entry(chain.to_string()).or_default();
+    *head = (*head).max(latest_block);
+}
+
+/// Count a failed request to `endpoint`, which must not contain credentials
+pub fn rpc_error(endpoint: &str) {
+    if let Some(metrics) = METRICS.get() {
+        *lock(&metrics.rpc_errors).entry(endpoint.to_string()).or_default() += 1;
+    }
+}
+
+/// Count a failed webhook delivery attempt
+pub fn webhook_failure() {
+    if let Some(metrics) = METRICS.get() {
+        *lock(&metrics.webhook_failures) += 1;
+    }
+}
+
+/// Count `events` whose webhook delivery was given up on
+pub fn webhook_dead_letters(events: usize) {
+    if let Some(metrics) = METRICS.get() {
+        *lock(&metrics.webhook_dead_letters) += events as u64;
+    }
+}
+
+/// Count a response of `receiver` asking for a pause, which lasts `until`
+pub fn webhook_rate_limited(receiver: &str, until: Instant) {
+    if let Some(metrics) = METRICS.get() {
+        *lock(&metrics.webhook_rate_limited).entry(receiver.to_string()).or_default() += 1;
+        lock(&metrics.webhook_paused_until).insert(receiver.to_string(), until);
+    }
+}
+
+/// Record that `queued` events wait in front of the pipeline's `stage`
+pub fn pipeline_queued(stage: &str, queued: usize) {
+    if let Some(metrics) = METRICS.get() {
+        let mut stages = lock(&metrics.pipeline_queued);
+        match stages.get_mut(stage) {
+            Some(gauge) => *gauge = queued as u64,
+            None => {
+                stages.insert(stage.to_string(), queued as u64);
+            }
+        }
+    }
+}
+
+/// The counters and gauges kept so far; empty when they aren't kept
+pub fn samples() -> Samples {
+    let Some(metrics) = METRICS.get() else {
+        return Samples::default();
+    };
+    Samples {
+        events: lock(&metrics.events).clone(),
+        deduplicated: lock(&metrics.deduplicated).clone(),
+        block_lag: lock(&metrics.block_lag).clone(),
+        chain_head: lock(&metrics.chain_head).clone(),
+        rpc_errors: lock(&metrics.rpc_errors).clone(),
+        webhook_failures: *lock(&metrics.webhook_failures),
+        webhook_dead_letters: *lock(&metrics.webhook_dead_letters),
+        webhook_rate_limited: lock(&metrics.webhook_rate_limited).clone(),
+        webhook_backoff: webhook_backoff(metrics),
+        pipeline_queued: lock(&metrics.pipeline_queued).clone(),
+    }
+}
+
+fn webhook_backoff(metrics: &Metrics) -> BTreeMap<String, f64> {
+    let now = Instant::now();
+    lock(&metrics.webhook_paused_until)
+        .iter()
+        .map(|(receiver, until)| (receiver.clone(), until.saturating_duration_since(now).as_secs_f64()))
+        .collect()
+}
+
+/// Every metric in the Prometheus text format
+fn render() -> String {
+    let Some(metrics) = METRICS.get() else {
+        return String::new();
+    };
+    let mut out = String::new();
+
+    header(&mut out, "listener_events_total", "counter", "Events delivered to the outputs");
+    for ((chain, contract, event), count) in lock(&metrics.events).iter() {
+        let labels = labels(&[("chain", chain), ("contract", contract), ("event", event)]);
+        let _ = writeln!(out, "listener_events_total{} {}", labels, count);
+    }
+
+    header(&mut out, "listener_events_deduplicated_total", "counter", "Events dropped as already delivered");
+    for (chain, count) in lock(&metrics.deduplicated).iter() {
+        let _ = writeln!(out, "listener_events_deduplicated_total{} {}", labels(&[("chain", chain)]), count);
+    }
+
+    header(&mut out, "listener_block_lag", "gauge", "Blocks between the chain head and the last scanned block");
+    for ((chain, contract), lag) in lock(&metrics.block_lag).iter() {
+        let _ = writeln!(out, "listener_block_lag{} {}", labels(&[("chain", chain), ("contract", contract)]), lag);
+    }
+
+    header(&mut out, "listener_chain_head", "gauge", "Latest block seen on the chain");
+    for (chain, block) in lock(&metrics.chain_head).iter() {
+        let _ = writeln!(out, "listener_chain_head{} {}", labels(&[("chain", chain)]), block);
+    }
+
+    header(&mut out, "listener_rpc_errors_total", "counter", "Failed RPC requests, before failover and retries");
+    for (endpoint, count) in lock(&metrics.rpc_errors).iter() {
+        let _ = writeln!(out, "listener_rpc_errors_total{} {}", labels(&[("endpoint", endpoint)]), count);
+    }
+
+    header(&mut out, "listener_webhook_failures_total", "counter", "Failed webhook delivery attempts");
+    let _ = writeln!(out, "listener_webhook_failures_total {}", lock(&metrics.webhook_failures));
+    header(&mut out, "listener_webhook_dead_letters_total", "counter", "Events whose webhook delivery was given up on");
+    let _ = writeln!(out, "listener_webhook_dead_letters_total {}", lock(&metrics.webhook_dead_letters));
+    header(
+        &mut out,
+        "listener_webhook_rate_limited_total",
+        "counter",
+        "Webhook responses that asked for a pause with Retry-After",
+    );
+    for (receiver, count) in lock(&metrics.webhook_rate_limited).iter() {
+        let _ = writeln!(out, "listener_webhook_rate_limited_total{} {}", labels(&[("receiver", receiver)]), count);
+    }
+    header(&mut out, "listener_webhook_backoff_seconds", "gauge", "Seconds left of a webhook receiver's pause");
+    for (receiver, seconds) in webhook_backoff(metrics) {
+        let _ = writeln!(out, "listener_webhook_backoff_seconds{} {:.3}", labels(&[("receiver", &receiver)]), seconds);
+    }
+
+    header(&mut out, "listener_pipeline_queued", "gauge", "Events waiting in front of a processing stage");
+    for (stage, queued) in lock(&metrics.pipeline_queued).iter() {
+        let _ = writeln!(out, "listener_pipeline_queued{} {}", labels(&[("stage", stage)]), queued);
+    }
+
+    header(
+        &mut out,
+        "listener_delivery_latency_seconds",
+        "histogram",
+        "Time from detecting an event to handing it to the outputs",
+    );
+    let latency = lock(&metrics.latency);
+    let mut cumulative = 0;
+    for (bound, count) in LATENCY_BUCKETS.iter().zip(latency.buckets) {
+        cumulative += count;
+        let _ = writeln!(out, "listener_delivery_latency_seconds_bucket{{le=\"{}\"}} {}", bound, cumulative);
+    }
+    let _ = writeln!(out, "listener_delivery_latency_seconds_bucket{{le=\"+Inf\"}} {}", latency.count);
+    let _ = writeln!(out, "listener_delivery_latency_seconds_sum {}", latency.sum);
+    let _ = writeln!(out, "listener_delivery_latency_seconds_count {}", latency.count);
+    out
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// `{name="value",...}` with the values escaped
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
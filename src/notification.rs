@@ -0,0 +1,166 @@
+//! Human-readable rendering of events for notification channels such as
+//! Slack and Discord, independent of any channel's message format.
+
+use serde_json::Value;
+
+use crate::chains::explorer_url;
+use crate::event::EventData;
+
+/// An event as a notification: a title, the decoded arguments, and links
+pub struct Notification {
+    /// Event name and chain, e.g. `Transfer on Polygon`
+    pub title: String,
+    /// Event name, e.g. `Transfer`
+    pub event: String,
+    pub chain: String,
+    /// Argument names and values; humanized values follow the raw ones
+    pub fields: Vec<(String, String)>,
+    pub contract: String,
+    /// The contract's label, else its ENS name
+    pub contract_name: Option<String>,
+    pub transaction_hash: String,
+    /// Sender and recipient of the transaction (`--enrich tx`)
+    pub tx_from: Option<String>,
+    pub tx_to: Option<String>,
+    pub block_number: u64,
+    pub log_index: u64,
+    pub timestamp: String,
+    /// Explorer links, when the chain's explorer is known
+    pub transaction_url: Option<String>,
+    pub block_url: Option<String>,
+    pub contract_url: Option<String>,
+}
+
+impl Notification {
+    pub fn from_event(event: &EventData) -> Self {
+        let name = event
+            .event_name
+            .clone()
+            .or_else(|| event.event_signature.as_deref().map(event_name))
+            .unwrap_or_else(|| "Unknown event".to_string());
+
+        let mut fields = Vec::new();
+        match event.decoded {
+            Some(ref decoded) => {
+                for (arg, value) in decoded {
+                    let mut value = match value {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    let humanized = event.humanized.as_ref().and_then(|h| h.get(arg)).and_then(|h| h.as_str());
+                    if let Some(humanized) = humanized {
+                        value = format!("{} ({})", value, humanized);
+                    }
+                    fields.push((arg.clone(), value));
+                }
+            }
+            None => {
+                if let Some(ref signature) = event.event_signature {
+                    fields.push(("signature".to_string(), signature.clone()));
+                }
+                for (i, topic) in event.topics.iter().enumerate().skip(1) {
+                    fields.push((format!("topic{}", i), topic.clone()));
+                }
+                if !event.data.is_empty() {
+                    fields.push(("data".to_string(), format!("0x{}", event.data)));
+                }
+            }
+        }
+        if let Some(ref error) = event.decode_error {
+            fields.push(("decode error".to_string(), error.reason.clone()));
+        }
+
+        let explorer = explorer_url(event.chain_id);
+        Self {
+            title: format!("{} on {}", name, event.chain_name),
+            event: name,
+            chain: event.chain_name.clone(),
+            fields,
+            contract: event.contract_address.clone(),
+            contract_name: event.address_name(&event.contract_address).map(str::to_string),
+            transaction_hash: event.transaction_hash.clone(),
+            tx_from: event.tx.as_ref().map(|tx| tx.from.clone()),
+            tx_to: event.tx.as_ref().and_then(|tx| tx.to.clone()),
+            block_number: event.block_number,
+            log_index: event.log_index,
+            timestamp: event.timestamp.clone(),
+            transaction_url: explorer.map(|url| format!("{}/tx/{}", url, event.transaction_hash)),
+            block_url: explorer.map(|url| format!("{}/block/{}", url, event.block_number)),
+            contract_url: explorer.map(|url| format!("{}/address/{}", url, event.contract_address)),
+        }
+    }
+
+    /// `template` with the placeholders `{event}`, `{chain}`, `{title}`,
+    /// `{contract}`, `{contract_name}`, `{tx}`, `{tx_from}`, `{tx_to}`, `{block}`, `{log_index}`,
+    /// `{timestamp}`, `{tx_url}`, `{block_url}`, and `{contract_url}` replaced by the
+    /// notification's values, `{args.NAME}` by the argument `NAME`, and
+    /// `{args}` by every argument on a line of its own. Values are passed
+    /// through `escape`; unknown placeholders are left as they are.
+    pub fn render(&self, template: &str, escape: impl Fn(&str) -> String) -> String {
+        let mut out = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            let placeholder = &rest[start + 1..];
+            let value = placeholder.find('}').and_then(|end| Some((self.value(&placeholder[..end])?, end)));
+            match value {
+                Some((value, end)) => {
+                    out.push_str(&escape(&value));
+                    rest = &placeholder[end + 1..];
+                }
+                None => {
+                    out.push('{');
+                    rest = placeholder;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn value(&self, placeholder: &str) -> Option<String> {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+        let value = match placeholder.trim() {
+            "event" => self.event.clone(),
+            "chain" => self.chain.clone(),
+            "title" => self.title.clone(),
+            "contract" => self.contract.clone(),
+            "contract_name" => optional(&self.contract_name),
+            "tx" => self.transaction_hash.clone(),
+            "tx_from" => optional(&self.tx_from),
+            "tx_to" => optional(&self.tx_to),
+            "block" => self.block_number.to_string(),
+            "log_index" => self.log_index.to_string(),
+            "timestamp" => self.timestamp.clone(),
+            "tx_url" => optional(&self.transaction_url),
+            "block_url" => optional(&self.block_url),
+            "contract_url" => optional(&self.contract_url),
+            "args" => self
+                .fields
+                .iter()
+                .map(|(name, value)| format!("{}: {}", name, value))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            other => {
+                let arg = other.strip_prefix("args.")?;
+                self.fields.iter().find(|(name, _)| name == arg)?.1.clone()
+            }
+        };
+        Some(value)
+    }
+}
+
+/// `Transfer` for `Transfer(address,address,uint256)`
+fn event_name(signature: &str) -> String {
+    signature.split('(').next().unwrap_or(signature).to_string()
+}
+
+/// `value` cut to at most `max` characters, ending in `…` if cut
+pub fn truncate(value: &str, max: usize) -> String {
+    if value.chars().count() <= max {
+        return value.to_string();
+    }
+    let mut cut: String = value.chars().take(max.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
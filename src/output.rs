@@ -0,0 +1,474 @@
+use anyhow::Result;
+use ethers::types::U256;
+
+use crate::archive::{ArchiveQuery, ArchiveReader};
+use crate::chains::native_symbol;
+use crate::event::EventData;
+use crate::humanize::format_token_amount;
+
+pub fn print_json(event: &EventData) -> Result<()> {
+    println!("{}", serde_json::to_string(event)?);
+    Ok(())
+}
+
+pub fn print_compact(event: &EventData) {
+    let contract = match event.address_name(&event.contract_address) {
+        Some(name) => format!("{} ({})", short(&event.contract_address), name),
+        None => short(&event.contract_address).to_string(),
+    };
+    let mut line = format!(
+        "[{}] Block {} | Tx {} | Contract {} | Topics: {}",
+        event.timestamp,
+        event.block_number,
+        short(&event.transaction_hash),
+        contract,
+        event.topics.len()
+    );
+    if let Some(ref humanized) = event.humanized {
+        for (name, value) in humanized {
+            line.push_str(&format!(" | {}: {}", name, value.as_str().unwrap_or_default()));
+        }
+    }
+    if let Some(ref error) = event.decode_error {
+        line.push_str(&format!(" | Decode error: {}", error.reason));
+    }
+    if event.partial_enrichment {
+        line.push_str(" | Partial enrichment");
+    }
+    if let Some(ref anomaly) = event.anomaly {
+        let mut measures = Vec::new();
+        if anomaly.data_size.is_some() {
+            measures.push("data size");
+        }
+        if anomaly.gas_used.is_some() {
+            measures.push("gas used");
+        }
+        line.push_str(&format!(" | Anomaly: {}", measures.join(", ")));
+    }
+    println!("{}", line);
+}
+
+pub fn print_logfmt(event: &EventData) {
+    println!("{}", logfmt(event));
+}
+
+/// `event` as one line of logfmt (`key=value` pairs), for log agents
+/// shipping to Loki, Datadog, and the like. Keys match the JSON fields;
+/// topics become `topic0`..`topic3`, and decoded arguments, readable values,
+/// token metadata, transaction context, proxy implementation, USD values,
+/// ENS names, and labels are flattened as `decoded.<name>`,
+/// `humanized.<name>`, `token.<field>`, `tx.<field>`, `proxy.<field>`,
+/// `usd.<name>`, `ens.<address>`, and `labels.<address>`.
+pub fn logfmt(event: &EventData) -> String {
+    let mut pairs: Vec<(String, String)> = vec![("timestamp".into(), event.timestamp.clone())];
+    if let Some(chain_id) = event.chain_id {
+        pairs.push(("chain_id".into(), chain_id.to_string()));
+    }
+    pairs.extend([
+        ("chain_name".into(), event.chain_name.clone()),
+        ("block_number".into(), event.block_number.to_string()),
+    ]);
+    if let Some(ref block_timestamp) = event.block_timestamp {
+        pairs.push(("block_timestamp".into(), block_timestamp.clone()));
+    }
+    pairs.extend([
+        ("transaction_hash".into(), event.transaction_hash.clone()),
+        ("log_index".into(), event.log_index.to_string()),
+        ("contract_address".into(), event.contract_address.clone()),
+    ]);
+    let optional = [
+        ("event_name", &event.event_name),
+        ("event_signature", &event.event_signature),
+        ("guessed_signature", &event.guessed_signature),
+    ];
+    for (key, value) in optional {
+        if let Some(ref value) = value {
+            pairs.push((key.into(), value.clone()));
+        }
+    }
+    for (i, topic) in event.topics.iter().enumerate() {
+        pairs.push((format!("topic{}", i), topic.clone()));
+    }
+    pairs.push(("data".into(), event.data.clone()));
+
+    for (prefix, map) in [("decoded", &event.decoded), ("humanized", &event.humanized)] {
+        for (name, value) in map.iter().flatten() {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            pairs.push((format!("{}.{}", prefix, name), value));
+        }
+    }
+    if let Some(ref token) = event.token {
+        let fields = [
+            ("symbol", token.symbol.clone()),
+            ("name", token.name.clone()),
+            ("decimals", token.decimals.map(|decimals| decimals.to_string())),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                pairs.push((format!("token.{}", field), value));
+            }
+        }
+    }
+    if let Some(ref tx) = event.tx {
+        let fields = [
+            ("from", Some(tx.from.clone())),
+            ("to", tx.to.clone()),
+            ("value", Some(tx.value.clone())),
+            ("gas_used", tx.gas_used.map(|gas| gas.to_string())),
+            ("effective_gas_price", tx.effective_gas_price.clone()),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                pairs.push((format!("tx.{}", field), value));
+            }
+        }
+    }
+    if let Some(ref proxy) = event.proxy {
+        let fields = [
+            ("kind", serde_json::to_value(proxy.kind).ok().and_then(|kind| kind.as_str().map(String::from))),
+            ("implementation", Some(proxy.implementation.clone())),
+            ("beacon", proxy.beacon.clone()),
+            ("verified", Some(proxy.verified.to_string())),
+            ("upgraded", proxy.upgraded.then(|| "true".to_string())),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                pairs.push((format!("proxy.{}", field), value));
+            }
+        }
+    }
+    for (name, value) in event.usd.iter().flatten() {
+        pairs.push((format!("usd.{}", name), value.to_string()));
+    }
+    for (prefix, names) in [("ens", &event.ens), ("labels", &event.labels)] {
+        for (address, name) in names.iter().flatten() {
+            if let Some(name) = name.as_str() {
+                pairs.push((format!("{}.{}", prefix, address), name.to_string()));
+            }
+        }
+    }
+    if let Some(ref error) = event.decode_error {
+        pairs.push(("decode_error".into(), error.reason.clone()));
+    }
+    if event.partial_enrichment {
+        pairs.push(("partial_enrichment".into(), "true".into()));
+    }
+    if let Some(ref anomaly) = event.anomaly {
+        let mut measures = Vec::new();
+        if anomaly.data_size.is_some() {
+            measures.push("data_size");
+        }
+        if anomaly.gas_used.is_some() {
+            measures.push("gas_used");
+        }
+        pairs.push(("anomaly".into(), measures.join(",")));
+    }
+    if let Some(ref run) = event.run {
+        pairs.push(("run_id".into(), run.run_id.clone()));
+    }
+    if let Some(ref hash) = event.hash {
+        pairs.push(("hash".into(), hash.clone()));
+    }
+
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", logfmt_key(key), logfmt_value(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A key with the characters logfmt doesn't allow in keys replaced by `_`
+fn logfmt_key(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_graphic() && c != '=' && c != '"' { c } else { '_' })
+        .collect()
+}
+
+/// A value, quoted when empty or containing spaces, `=`, or quotes
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty() && !value.chars().any(|c| c.is_whitespace() || c == '=' || c == '"' || c.is_control()) {
+        return value.to_string();
+    }
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The first 10 characters of a hash or address, e.g. `0x12345678`
+fn short(hex: &str) -> &str {
+    hex.get(..10).unwrap_or(hex)
+}
+
+pub fn print_pretty(event: &EventData) {
+    println!("\n╔════════════════════════════════════════════════════════════");
+    println!("║ Event Detected!");
+    println!("║ Time: {}", event.timestamp);
+    println!("║ Chain: {} (ID: {})", event.chain_name, event.chain_id.unwrap_or(0));
+    match event.block_timestamp {
+        Some(ref time) => println!("║ Block: {} ({})", event.block_number, time),
+        None => println!("║ Block: {}", event.block_number),
+    }
+    println!("║ Transaction: {}", event.transaction_hash);
+    println!("║ Log Index: {}", event.log_index);
+    match event.address_name(&event.contract_address) {
+        Some(name) => println!("║ Contract: {} ({})", event.contract_address, name),
+        None => println!("║ Contract: {}", event.contract_address),
+    }
+    if let Some(ref proxy) = event.proxy {
+        let unverified = if proxy.verified { "" } else { ", unverified" };
+        match (proxy.upgraded, &proxy.beacon) {
+            (true, Some(beacon)) => println!("║ 🔄 Upgraded: {} proxy now follows beacon {}", proxy.kind, beacon),
+            (true, None) => println!("║ 🔄 Upgraded: {} proxy now delegates to {}", proxy.kind, proxy.implementation),
+            (false, _) => println!("║ Proxy: {} of {}{}", proxy.kind, proxy.implementation, unverified),
+        }
+    }
+    
+    if let Some(ref sig) = event.event_signature {
+        println!("║ Event: {}", sig);
+    } else if let Some(ref sig) = event.guessed_signature {
+        println!("║ Event: {} (guessed from topic0)", sig);
+    }
+
+    if let Some(ref token) = event.token {
+        let mut line = token.name.clone().unwrap_or_default();
+        if let Some(ref symbol) = token.symbol {
+            line = if line.is_empty() { symbol.clone() } else { format!("{} ({})", line, symbol) };
+        }
+        if let Some(decimals) = token.decimals {
+            line.push_str(&format!(", {} decimals", decimals));
+        }
+        println!("║ Token: {}", line);
+    }
+
+    if let Some(ref tx) = event.tx {
+        let name = |address: &str| match event.address_name(address) {
+            Some(name) => format!("{} ({})", address, name),
+            None => address.to_string(),
+        };
+        let to = tx.to.as_deref().map(name).unwrap_or_else(|| "contract creation".to_string());
+        println!("║ From: {} → {}", name(&tx.from), to);
+        let value = U256::from_dec_str(&tx.value).unwrap_or_default();
+        if !value.is_zero() {
+            let symbol = native_symbol(event.chain_id);
+            let value = format_token_amount(value, 18, symbol).unwrap_or_else(|| format!("{} wei", tx.value));
+            println!("║ Value: {}", value);
+        }
+        let price = tx.effective_gas_price.as_deref().and_then(|price| U256::from_dec_str(price).ok());
+        if let (Some(gas), Some(price)) = (tx.gas_used, price) {
+            let price = format_token_amount(price, 9, "gwei").unwrap_or_default();
+            println!("║ Gas: {} at {}", gas, price);
+        }
+    }
+
+    if let Some(ref run) = event.run {
+        println!("║ Run: {} (v{})", run.run_id, run.listener_version);
+    }
+    
+    println!("╠════════════════════════════════════════════════════════════");
+    
+    if !event.topics.is_empty() {
+        println!("║ Topics:");
+        for (i, topic) in event.topics.iter().enumerate() {
+            println!("║   [{}] {}", i, topic);
+        }
+    }
+    
+    if !event.data.is_empty() {
+        println!("║ Data: {}", event.data);
+    }
+
+    if let Some(ref decoded) = event.decoded {
+        println!("╠════════════════════════════════════════════════════════════");
+        println!("║ {}", event.event_name.as_deref().unwrap_or("Decoded"));
+        for (name, value) in decoded {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            match event.humanized.as_ref().and_then(|h| h.get(name)).and_then(|h| h.as_str()) {
+                Some(human) => println!("║   {}: {} ({})", name, value, human),
+                None => println!("║   {}: {}", name, value),
+            }
+        }
+    }
+
+    if let Some(ref error) = event.decode_error {
+        println!("╠════════════════════════════════════════════════════════════");
+        println!("║ ⚠️  Could not decode as {}: {}", error.event, error.reason);
+    }
+    if event.partial_enrichment {
+        println!("║ ⏱️  Enrichment incomplete: a lookup timed out or failed");
+    }
+    if let Some(ref anomaly) = event.anomaly {
+        for (measure, outlier) in [("Data size", &anomaly.data_size), ("Gas used", &anomaly.gas_used)] {
+            if let Some(outlier) = outlier {
+                let score = match outlier.score {
+                    Some(score) => format!("score {}", score),
+                    None => "all recent events equal".to_string(),
+                };
+                println!(
+                    "║ 🔺 Anomaly: {} {} (median {}, {})",
+                    measure, outlier.value, outlier.median, score
+                );
+            }
+        }
+    }
+    
+    println!("╚════════════════════════════════════════════════════════════\n");
+}
+
+pub fn write_to_file(file_path: &str, event: &EventData) -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    
+    let json = serde_json::to_string(event)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?;
+    
+    writeln!(file, "{}", json)?;
+    Ok(())
+}
+
+/// Read events written by [`write_to_file`] (JSON Lines, gzip-compressed if
+/// the name ends in `.gz`), skipping blank lines. A directory is read as an [`archive`](crate::archive) and a SQLite
+/// database as written by the SQLite sink.
+pub fn read_events_file(file_path: &str) -> Result<Vec<EventData>> {
+    read_events_range(file_path, &ArchiveQuery::default())
+}
+
+/// Read the events of an export matching `query`. Archives only decompress
+/// the frames whose index entries can match.
+pub fn read_events_range(file_path: &str, query: &ArchiveQuery) -> Result<Vec<EventData>> {
+    use anyhow::Context;
+    use std::io::BufRead;
+
+    if std::path::Path::new(file_path).is_dir() {
+        return ArchiveReader::open(file_path)?.read(query);
+    }
+    #[cfg(feature = "sqlite")]
+    if crate::sinks::sqlite::is_sqlite_file(file_path) {
+        let query = crate::sinks::sqlite::EventQuery {
+            from_block: query.from_block,
+            to_block: query.to_block,
+            event: query.topic0.clone(),
+            ..Default::default()
+        };
+        return crate::sinks::sqlite::query_events(file_path, &query);
+    }
+
+    let mut events = Vec::new();
+    for (i, line) in open_lines(file_path)?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid event record", file_path, i + 1))?;
+        if query.matches(&event) {
+            events.push(event);
+        }
+    }
+
+    Ok(events)
+}
+
+/// The records of an export as JSON lines, for checking their hash chain:
+/// the lines exactly as stored in a JSON Lines file, and the events of an
+/// archive or SQLite database as written by [`write_to_file`]
+pub fn read_record_lines(file_path: &str) -> Result<Vec<String>> {
+    use std::io::BufRead;
+
+    #[cfg(feature = "sqlite")]
+    let is_sqlite = crate::sinks::sqlite::is_sqlite_file(file_path);
+    #[cfg(not(feature = "sqlite"))]
+    let is_sqlite = false;
+    if std::path::Path::new(file_path).is_dir() || is_sqlite {
+        return read_events_file(file_path)?
+            .iter()
+            .map(|event| Ok(serde_json::to_string(event)?))
+            .collect();
+    }
+
+    let mut lines = Vec::new();
+    for line in open_lines(file_path)?.lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// A JSON Lines file, decompressed if its name ends in `.gz` (rotated files
+/// may be gzip-compressed)
+fn open_lines(file_path: &str) -> Result<Box<dyn std::io::BufRead>> {
+    use anyhow::Context;
+
+    let file = std::fs::File::open(file_path)
+        .with_context(|| format!("Failed to open {}", file_path))?;
+    Ok(if file_path.ends_with(".gz") {
+        Box::new(std::io::BufReader::new(flate2::read::GzDecoder::new(file)))
+    } else {
+        Box::new(std::io::BufReader::new(file))
+    })
+}
+
+/// Write events as CSV with a header row. Topics are space-separated and
+/// decoded arguments are a JSON object.
+pub fn write_csv(writer: impl std::io::Write, events: &[EventData]) -> Result<()> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record([
+        "timestamp",
+        "chain_id",
+        "chain_name",
+        "block_number",
+        "transaction_hash",
+        "log_index",
+        "contract_address",
+        "event_name",
+        "event_signature",
+        "topics",
+        "data",
+        "decoded",
+    ])?;
+
+    for event in events {
+        let decoded = match event.decoded {
+            Some(ref decoded) => serde_json::to_string(decoded)?,
+            None => String::new(),
+        };
+        csv.write_record([
+            event.timestamp.as_str(),
+            &event.chain_id.map(|id| id.to_string()).unwrap_or_default(),
+            &event.chain_name,
+            &event.block_number.to_string(),
+            &event.transaction_hash,
+            &event.log_index.to_string(),
+            &event.contract_address,
+            event.event_name.as_deref().unwrap_or_default(),
+            event.event_signature.as_deref().unwrap_or_default(),
+            &event.topics.join(" "),
+            &event.data,
+            &decoded,
+        ])?;
+    }
+
+    csv.flush()?;
+    Ok(())
+}
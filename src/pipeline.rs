@@ -0,0 +1,334 @@
+//! The stages events go through between the listeners and the outputs, each
+//! in its own task and connected by bounded channels:
+//!
+//! 1. fetch and decode: a task per contract ([`crate::runtime`])
+//! 2. enrich: up to `enrich_concurrency` events at once, passed on in the
+//!    order they were received
+//! 3. deliver: the dedup window, the hash chain, stdout, the output file,
+//!    the webhook queue, and the queues of the sink tasks
+//!
+//! A stage that falls behind fills the channel in front of it, and only
+//! then holds up the stages before it, so memory stays bounded during
+//! bursts and a slow sink or enrichment lookup doesn't stop log fetching
+//! until the queues are full.
+
+use anyhow::Result;
+use futures::stream::FuturesOrdered;
+use futures::StreamExt;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::config::{Config, PipelineConfig};
+use crate::dedup::Dedup;
+use crate::enrich::Enrichment;
+use crate::event::EventData;
+use crate::hashchain::HashChain;
+use crate::metrics;
+use crate::output::{print_compact, print_json, print_logfmt, print_pretty};
+use crate::render::EventTemplate;
+use crate::rotate::RotatingFile;
+use crate::routing::{Route, Router};
+use crate::run::RunMetadata;
+use crate::siem::SiemFormat;
+use crate::sinks::SinkWorker;
+use crate::webhook::WebhookQueue;
+
+/// Events queued in front of each stage by default
+pub const DEFAULT_QUEUE_SIZE: usize = 1024;
+
+/// Events enriched at once by default
+pub const DEFAULT_ENRICH_CONCURRENCY: usize = 8;
+
+/// How often the dedup window and hash chain head are saved while events
+/// are delivered
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+type Enriching = Pin<Box<dyn Future<Output = EventData> + Send>>;
+
+/// What the enrich stage does to each event; replaced on config reload
+pub struct Enrich {
+    /// Stamped on every event
+    pub run: RunMetadata,
+    /// Keep the events' humanized values
+    pub humanize: bool,
+    /// Keep the events' raw logs
+    pub include_raw: bool,
+    pub enrichment: Arc<Enrichment>,
+}
+
+impl Enrich {
+    async fn apply(&self, event: &mut EventData) {
+        event.run = Some(self.run.clone());
+        if !self.humanize {
+            event.humanized = None;
+        }
+        if !self.include_raw {
+            event.raw = None;
+        }
+        self.enrichment.apply(event).await;
+    }
+}
+
+/// Where the deliver stage hands events to. Locked while the outputs are
+/// reconfigured, which holds up delivery until they are.
+pub struct Outputs {
+    /// Output format and webhook routes
+    pub config: Config,
+    pub output_file: Option<RotatingFile>,
+    /// Template of the `template` output format
+    pub template: Option<EventTemplate>,
+    /// The `cef` or `leef` output format
+    pub siem: Option<SiemFormat>,
+    pub webhooks: Option<WebhookQueue>,
+    pub sinks: Vec<SinkWorker>,
+    /// Which of the webhooks and sinks each event goes to
+    pub router: Router,
+    /// Events already delivered, which aren't delivered again
+    pub dedup: Option<Dedup>,
+    pub hash_chain: Option<HashChain>,
+}
+
+impl Outputs {
+    /// Link `event` into the hash chain, if kept, and hand it to every
+    /// output: stdout, the output file, and the webhook and sinks of its
+    /// route. Events in the dedup window are dropped.
+    pub async fn deliver(&mut self, event: &mut EventData) -> Result<()> {
+        if let Some(ref mut dedup) = self.dedup {
+            if !dedup.check(event) {
+                debug!(
+                    "Dropping {} of block {} log {}: already delivered",
+                    event.transaction_hash, event.block_number, event.log_index
+                );
+                metrics::event_deduplicated(&event.chain_name);
+                return Ok(());
+            }
+        }
+
+        // Link the record to the previous one before any output sees it
+        if let Some(ref mut chain) = self.hash_chain {
+            chain.append(event)?;
+        }
+
+        // With a template, or as CEF or LEEF, stdout and the file get the rendered text
+        let rendered = match (&self.template, &self.siem) {
+            (Some(template), _) => Some(template.render(event).unwrap_or_else(|e| {
+                warn!("{:#}; writing the event as JSON", e);
+                serde_json::to_string(event).unwrap_or_default()
+            })),
+            (None, Some(siem)) => Some(siem.format(event)),
+            (None, None) => None,
+        };
+
+        // Output based on format; Parquet goes to its sink, with a line per event here
+        match self.config.sinks.output_format.as_deref().unwrap_or("pretty") {
+            "json" => print_json(event)?,
+            "compact" | "parquet" => print_compact(event),
+            "logfmt" => print_logfmt(event),
+            "template" | "cef" | "leef" => println!("{}", rendered.as_deref().unwrap_or_default()),
+            _ => print_pretty(event),
+        }
+
+        // Write to file if specified; archives and Parquet are written by their sinks
+        if let Some(ref mut file) = self.output_file {
+            match rendered {
+                Some(ref text) => file.write_text(text)?,
+                None => file.write(event)?,
+            }
+        }
+
+        // Queue for the webhook if specified; deliveries are retried in the background
+        let route = self.router.route(event);
+        if let (Some(ref mut queue), Some(webhook)) = (&mut self.webhooks, self.config.webhook_for(event)) {
+            if route.is_none_or(Route::includes_webhook) {
+                queue.enqueue(webhook, event)?;
+            }
+        }
+
+        // Each sink runs in its own task; a failing or crashing sink doesn't stop the listener
+        for sink in &self.sinks {
+            if route.is_none_or(|route| route.includes(sink.kind())) {
+                sink.send(event).await;
+            }
+        }
+        metrics::event_delivered(event);
+        Ok(())
+    }
+
+    /// Wait until the sinks have written every event handed to them
+    pub async fn flush(&self) {
+        for sink in &self.sinks {
+            sink.flush().await;
+        }
+    }
+
+    /// Save the dedup window and hash chain head, if kept, with the events
+    /// delivered so far
+    pub fn save_state(&mut self) -> Result<()> {
+        if let Some(ref mut dedup) = self.dedup {
+            dedup.save()?;
+        }
+        if let Some(ref mut chain) = self.hash_chain {
+            chain.save()?;
+        }
+        Ok(())
+    }
+}
+
+/// The enrich and deliver stages, taking events from the listeners'
+/// channel
+pub struct Pipeline {
+    outputs: Arc<Mutex<Outputs>>,
+    enrich: watch::Sender<Arc<Enrich>>,
+    stop: Option<oneshot::Sender<()>>,
+    enrich_task: JoinHandle<()>,
+    deliver_task: Option<JoinHandle<Result<()>>>,
+}
+
+impl Pipeline {
+    /// Start the stages on the events of `input`. Events whose transaction
+    /// hash and log index are in `skip` are dropped, once each.
+    pub fn spawn(
+        input: mpsc::Receiver<EventData>,
+        enrich: Enrich,
+        outputs: Outputs,
+        skip: HashSet<(String, u64)>,
+        config: &PipelineConfig,
+    ) -> Self {
+        let queue_size = config.queue_size.unwrap_or(DEFAULT_QUEUE_SIZE).max(1);
+        let concurrency = config.enrich_concurrency.unwrap_or(DEFAULT_ENRICH_CONCURRENCY).max(1);
+        let (enriched_tx, enriched_rx) = mpsc::channel(queue_size);
+        let (enrich, enrich_rx) = watch::channel(Arc::new(enrich));
+        let (stop, stopped) = oneshot::channel();
+        let outputs = Arc::new(Mutex::new(outputs));
+
+        let enrich_task = tokio::spawn(enrich_stage(input, enrich_rx, enriched_tx, concurrency, skip, stopped));
+        let deliver_task = tokio::spawn(deliver_stage(enriched_rx, outputs.clone()));
+        Self {
+            outputs,
+            enrich,
+            stop: Some(stop),
+            enrich_task,
+            deliver_task: Some(deliver_task),
+        }
+    }
+
+    /// The outputs, to be locked while they're replaced
+    pub fn outputs(&self) -> &Mutex<Outputs> {
+        &self.outputs
+    }
+
+    /// Enrich the events received from now on with `enrich`
+    pub fn set_enrich(&self, enrich: Enrich) {
+        self.enrich.send_replace(Arc::new(enrich));
+    }
+
+    /// Resolves with the error that stopped delivery, if it does
+    pub async fn failed(&mut self) -> anyhow::Error {
+        let Some(ref mut task) = self.deliver_task else {
+            return std::future::pending().await;
+        };
+        let result = task.await;
+        self.deliver_task = None;
+        match result {
+            Ok(Err(e)) => e,
+            Ok(Ok(())) => anyhow::anyhow!("Event delivery stopped"),
+            Err(e) => anyhow::anyhow!("Event delivery crashed: {}", e),
+        }
+    }
+
+    /// Take no more events than those already received, see them through
+    /// every stage, and hand back the outputs
+    pub async fn finish(mut self) -> Result<Outputs> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        let _ = self.enrich_task.await;
+        if let Some(task) = self.deliver_task.take() {
+            task.await??;
+        }
+        match Arc::try_unwrap(self.outputs) {
+            Ok(outputs) => Ok(outputs.into_inner()),
+            Err(_) => anyhow::bail!("Outputs still in use after the pipeline finished"),
+        }
+    }
+}
+
+/// Enrich up to `concurrency` events of `input` at once and pass them on in
+/// order. Once `stop` fires, only the events already queued are taken.
+async fn enrich_stage(
+    mut input: mpsc::Receiver<EventData>,
+    enrich: watch::Receiver<Arc<Enrich>>,
+    output: mpsc::Sender<EventData>,
+    concurrency: usize,
+    mut skip: HashSet<(String, u64)>,
+    mut stop: oneshot::Receiver<()>,
+) {
+    let mut in_flight: FuturesOrdered<Enriching> = FuturesOrdered::new();
+    let mut stopping = false;
+    let mut drained = false;
+
+    loop {
+        let next = async {
+            match stopping {
+                true => input.try_recv().ok(),
+                false => input.recv().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            Some(event) = in_flight.next() => {
+                if output.send(event).await.is_err() {
+                    return;
+                }
+            }
+            event = next, if !drained && in_flight.len() < concurrency => {
+                let Some(mut event) = event else {
+                    drained = true;
+                    continue;
+                };
+                metrics::pipeline_queued("enrich", input.len());
+                if skip.remove(&(event.transaction_hash.clone(), event.log_index)) {
+                    continue;
+                }
+                let enrich = enrich.borrow().clone();
+                in_flight.push_back(Box::pin(async move {
+                    enrich.apply(&mut event).await;
+                    event
+                }));
+            }
+            _ = &mut stop, if !stopping => stopping = true,
+            else => return,
+        }
+    }
+}
+
+/// Hand every event of `input` to the outputs, in order, saving the dedup
+/// window and hash chain head every second while events arrive and once
+/// they stop
+async fn deliver_stage(mut input: mpsc::Receiver<EventData>, outputs: Arc<Mutex<Outputs>>) -> Result<()> {
+    let mut save = tokio::time::interval(STATE_SAVE_INTERVAL);
+    save.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            event = input.recv() => {
+                let Some(mut event) = event else {
+                    break;
+                };
+                metrics::pipeline_queued("deliver", input.len());
+                outputs.lock().await.deliver(&mut event).await?;
+            }
+            _ = save.tick() => {
+                if let Err(e) = outputs.lock().await.save_state() {
+                    warn!("{:#}", e);
+                }
+            }
+        }
+    }
+    outputs.lock().await.save_state()
+}
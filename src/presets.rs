@@ -0,0 +1,166 @@
+//! Token standard presets: the standard events of ERC-20, ERC-721, and
+//! ERC-1155 contracts, decoded without an ABI file.
+//!
+//! ERC-20 `Transfer` and ERC-721 `Transfer` share their signature and so
+//! their topic0; they differ only in whether the third argument is indexed.
+//! ERC-20 amounts are formatted with the token's `decimals()` and
+//! `symbol()`, looked up once per contract by [`TokenAmounts`] (or by the
+//! token metadata enrichment, when enabled).
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::abi::{parse_abi, EventExt};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::abi::AbiDecoder;
+use crate::config::Config;
+use crate::enrich::Enricher;
+use crate::event::{ContractFilter, EventData};
+use crate::provider::ProviderManager;
+use crate::token::{format_amounts, TokenCache};
+
+const ERC20_EVENTS: &[&str] = &[
+    "event Transfer(address indexed from, address indexed to, uint256 value)",
+    "event Approval(address indexed owner, address indexed spender, uint256 value)",
+];
+
+const ERC721_EVENTS: &[&str] = &[
+    "event Transfer(address indexed from, address indexed to, uint256 indexed tokenId)",
+    "event Approval(address indexed owner, address indexed approved, uint256 indexed tokenId)",
+    "event ApprovalForAll(address indexed owner, address indexed operator, bool approved)",
+];
+
+const ERC1155_EVENTS: &[&str] = &[
+    "event TransferSingle(address indexed operator, address indexed from, address indexed to, uint256 id, uint256 value)",
+    "event TransferBatch(address indexed operator, address indexed from, address indexed to, uint256[] ids, uint256[] values)",
+    "event ApprovalForAll(address indexed account, address indexed operator, bool approved)",
+    "event URI(string value, uint256 indexed id)",
+];
+
+/// A token standard whose events a contract is listened to for
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Erc20,
+    Erc721,
+    Erc1155,
+}
+
+impl Preset {
+    fn declarations(self) -> &'static [&'static str] {
+        match self {
+            Preset::Erc20 => ERC20_EVENTS,
+            Preset::Erc721 => ERC721_EVENTS,
+            Preset::Erc1155 => ERC1155_EVENTS,
+        }
+    }
+
+    /// The standard's events, as canonical signatures
+    pub fn signatures(self) -> Vec<String> {
+        let abi = parse_abi(self.declarations()).expect("preset events are valid");
+        abi.events().map(|event| event.abi_signature()).collect()
+    }
+
+    /// A decoder for the standard's events. Arguments aren't humanized by
+    /// name: a `value` is an amount of the token, not of the native token.
+    pub fn decoder(self) -> AbiDecoder {
+        let abi = parse_abi(self.declarations()).expect("preset events are valid");
+        AbiDecoder::from_abi(&abi).without_unit_guesses()
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Preset::Erc20 => "erc20",
+            Preset::Erc721 => "erc721",
+            Preset::Erc1155 => "erc1155",
+        })
+    }
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace(['-', '_'], "").as_str() {
+            "erc20" => Ok(Preset::Erc20),
+            "erc721" => Ok(Preset::Erc721),
+            "erc1155" => Ok(Preset::Erc1155),
+            _ => Err(format!("unknown preset `{}` (expected erc20, erc721, or erc1155)", s)),
+        }
+    }
+}
+
+/// The contracts listened to with the ERC-20 preset, by chain ID
+pub fn erc20_contracts(config: &Config) -> Vec<(Option<u64>, ContractFilter)> {
+    config
+        .chains
+        .iter()
+        .flat_map(|chain| chain.contracts.iter().map(move |contract| (chain.chain_id, contract)))
+        .filter(|(_, contract)| contract.preset == Some(Preset::Erc20))
+        .filter_map(|(chain_id, contract)| Some((chain_id, contract.contract_filter().ok()?)))
+        .collect()
+}
+
+/// Formats the `value` of ERC-20 events into `humanized`, e.g. `1,500.25
+/// USDC`, with the emitting token's decimals and symbol
+pub struct TokenAmounts {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    /// Contracts listened to with the ERC-20 preset, by chain ID
+    contracts: Vec<(Option<u64>, ContractFilter)>,
+    cache: Arc<TokenCache>,
+}
+
+impl TokenAmounts {
+    /// Tokens are looked up through `providers`, by chain ID, and kept in
+    /// `cache`
+    pub fn new(
+        contracts: Vec<(Option<u64>, ContractFilter)>,
+        providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+        cache: Arc<TokenCache>,
+    ) -> Self {
+        Self {
+            providers,
+            contracts,
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl Enricher for TokenAmounts {
+    fn name(&self) -> &str {
+        "Token amount"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        // ERC-721 transfers have the tokenId as a fourth topic
+        if event.topics.len() != 3 || event.decoded.is_none() {
+            return Ok(());
+        }
+        let address: Address = event.contract_address.parse()?;
+        if !self
+            .contracts
+            .iter()
+            .any(|(chain_id, filter)| *chain_id == event.chain_id && filter.matches(&address))
+        {
+            return Ok(());
+        }
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+
+        if let Some(token) = self.cache.get(provider, event.chain_id, address).await? {
+            if let Some(decimals) = token.decimals {
+                format_amounts(event, &["value"], decimals, token.symbol.as_deref().unwrap_or_default());
+            }
+        }
+        Ok(())
+    }
+}
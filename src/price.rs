@@ -0,0 +1,265 @@
+//! USD price enrichment: decoded token amounts get an approximate USD value,
+//! priced from a Chainlink feed where one is configured for the token and
+//! from the Coingecko API otherwise.
+//!
+//! Prices are cached per token for a while, including the absence of one,
+//! so a busy token costs one lookup per cache period. Coingecko requests are
+//! spaced out to stay under the API's rate limit; an event whose price would
+//! need a request sooner is delivered without it and marked
+//! `partial_enrichment`. The values are approximate: a price may be a few
+//! minutes old and feeds or Coingecko may lag the market.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::abi::ParamType;
+use ethers::types::{Address, Bytes, I256, U256};
+use ethers::utils::format_units;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+use crate::chains::chain_info;
+use crate::config::PriceConfig;
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::humanize::format_usd;
+use crate::provider::ProviderManager;
+use crate::token::{format_amounts, TokenCache, AMOUNT_ARGUMENTS};
+
+/// How long a price is reused (default for `cache_secs`)
+pub const DEFAULT_CACHE: Duration = Duration::from_secs(300);
+
+/// Least time between Coingecko requests, within the free tier's limit
+/// (default for `min_interval_ms`)
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_millis(2000);
+
+pub const DEFAULT_COINGECKO_URL: &str = "https://api.coingecko.com/api/v3";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Selector of `latestRoundData()` on a Chainlink aggregator
+const LATEST_ROUND_DATA: [u8; 4] = [0xfe, 0xaf, 0x96, 0x8c];
+
+/// Selector of `decimals()`
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+/// Identifies a token: (chain ID, contract)
+type TokenKey = (Option<u64>, Address);
+
+/// The Coingecko API and when it was last asked
+struct Coingecko {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    min_interval: Duration,
+    last_request: Mutex<Option<Instant>>,
+}
+
+/// Adds the approximate USD value of decoded token amounts to events
+pub struct PriceEnricher {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    tokens: Arc<TokenCache>,
+    /// Chainlink USD feed by token address
+    feeds: HashMap<Address, Address>,
+    coingecko: Option<Coingecko>,
+    cache_for: Duration,
+    /// Price in USD and when it was looked up; `None` for unpriced tokens
+    prices: Mutex<HashMap<TokenKey, (Instant, Option<f64>)>>,
+}
+
+impl PriceEnricher {
+    /// Tokens are looked up through `providers`, by chain ID, and their
+    /// decimals kept in `tokens`
+    pub fn new(
+        config: &PriceConfig,
+        providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+        tokens: Arc<TokenCache>,
+    ) -> Result<Self> {
+        let parse = |address: &String| -> Result<Address> {
+            address.parse().with_context(|| format!("Invalid address in chainlink_feeds: {}", address))
+        };
+        let feeds = config
+            .chainlink_feeds
+            .iter()
+            .map(|(token, feed)| Ok((parse(token)?, parse(feed)?)))
+            .collect::<Result<_>>()?;
+
+        let coingecko = match config.coingecko.unwrap_or(true) {
+            true => Some(Coingecko {
+                client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+                url: config
+                    .coingecko_url
+                    .as_deref()
+                    .unwrap_or(DEFAULT_COINGECKO_URL)
+                    .trim_end_matches('/')
+                    .to_string(),
+                api_key: config.coingecko_api_key.clone(),
+                min_interval: config.min_interval_ms.map(Duration::from_millis).unwrap_or(DEFAULT_MIN_INTERVAL),
+                last_request: Mutex::new(None),
+            }),
+            false => None,
+        };
+
+        Ok(Self {
+            providers,
+            tokens,
+            feeds,
+            coingecko,
+            cache_for: config.cache_secs.map(Duration::from_secs).unwrap_or(DEFAULT_CACHE),
+            prices: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The USD price of one whole token, from the cache, its Chainlink
+    /// feed, or Coingecko
+    async fn price(&self, provider: &ProviderManager, chain_id: Option<u64>, token: Address) -> Result<Option<f64>> {
+        if let Some((looked_up, price)) = lock(&self.prices).get(&(chain_id, token)) {
+            if looked_up.elapsed() < self.cache_for {
+                return Ok(*price);
+            }
+        }
+
+        let price = match (self.feeds.get(&token), &self.coingecko) {
+            (Some(feed), _) => chainlink_price(provider, *feed).await?,
+            (None, Some(coingecko)) => match chain_id.and_then(chain_info).and_then(|chain| chain.coingecko_platform) {
+                Some(platform) => coingecko.price(platform, token).await?,
+                None => None,
+            },
+            (None, None) => None,
+        };
+        lock(&self.prices).insert((chain_id, token), (Instant::now(), price));
+        Ok(price)
+    }
+}
+
+#[async_trait]
+impl Enricher for PriceEnricher {
+    fn name(&self) -> &str {
+        "USD price"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+        let Some(ref decoded) = event.decoded else {
+            return Ok(());
+        };
+        let amounts: Vec<(&str, U256)> = AMOUNT_ARGUMENTS
+            .iter()
+            .filter_map(|name| Some((*name, U256::from_dec_str(decoded.get(*name)?.as_str()?).ok()?)))
+            .collect();
+        if amounts.is_empty() {
+            return Ok(());
+        }
+
+        let address: Address = event.contract_address.parse()?;
+        let Some(token) = self.tokens.get(provider, event.chain_id, address).await? else {
+            return Ok(());
+        };
+        let Some(decimals) = token.decimals else {
+            return Ok(());
+        };
+        let Some(price) = self.price(provider, event.chain_id, address).await? else {
+            return Ok(());
+        };
+
+        let values: Vec<(&str, f64)> = amounts
+            .into_iter()
+            .filter_map(|(name, amount)| {
+                let amount: f64 = format_units(amount, decimals as u32).ok()?.parse().ok()?;
+                Some((name, amount * price))
+            })
+            .collect();
+
+        format_amounts(event, &AMOUNT_ARGUMENTS, decimals, token.symbol.as_deref().unwrap_or_default());
+        let humanized = event.humanized.get_or_insert_with(Default::default);
+        for &(name, usd) in &values {
+            if let Some(serde_json::Value::String(amount)) = humanized.get_mut(name) {
+                *amount = format!("{} (~{})", amount, format_usd(usd));
+            }
+        }
+        let usd = event.usd.get_or_insert_with(Default::default);
+        for (name, value) in values {
+            // Cents are as precise as an approximate price gets
+            usd.insert(name.to_string(), ((value * 100.0).round() / 100.0).into());
+        }
+        Ok(())
+    }
+}
+
+impl Coingecko {
+    /// The USD price of `token` on `platform`, `None` when Coingecko
+    /// doesn't list it
+    async fn price(&self, platform: &str, token: Address) -> Result<Option<f64>> {
+        {
+            let mut last_request = lock(&self.last_request);
+            if let Some(last) = *last_request {
+                let wait = self.min_interval.saturating_sub(last.elapsed());
+                if !wait.is_zero() {
+                    anyhow::bail!("Coingecko rate limit, next request in {}ms", wait.as_millis());
+                }
+            }
+            *last_request = Some(Instant::now());
+        }
+
+        let address = format!("{:?}", token);
+        let mut request = self
+            .client
+            .get(format!("{}/simple/token_price/{}", self.url, platform))
+            .query(&[("contract_addresses", address.as_str()), ("vs_currencies", "usd")]);
+        if let Some(ref key) = self.api_key {
+            let header = if self.url.contains("pro-api") { "x-cg-pro-api-key" } else { "x-cg-demo-api-key" };
+            request = request.header(header, key);
+        }
+        let response = request.send().await.context("Coingecko request failed")?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Coingecko answered {}", status);
+        }
+
+        let prices: serde_json::Value = response.json().await.context("Invalid Coingecko response")?;
+        Ok(prices.get(&address).and_then(|price| price.get("usd")).and_then(|usd| usd.as_f64()))
+    }
+}
+
+/// The latest answer of the Chainlink aggregator at `feed`, `None` when it
+/// isn't positive
+async fn chainlink_price(provider: &ProviderManager, feed: Address) -> Result<Option<f64>> {
+    let call = |selector: [u8; 4]| provider.call_contract(feed, Bytes::from(selector.to_vec()));
+    let round = call(LATEST_ROUND_DATA)
+        .await?
+        .with_context(|| format!("Chainlink feed {:?} reverted", feed))?;
+    let decimals = call(DECIMALS)
+        .await?
+        .with_context(|| format!("Chainlink feed {:?} reverted", feed))?;
+
+    let round = ethers::abi::decode(
+        &[
+            ParamType::Uint(80),
+            ParamType::Int(256),
+            ParamType::Uint(256),
+            ParamType::Uint(256),
+            ParamType::Uint(80),
+        ],
+        &round,
+    )
+    .with_context(|| format!("Invalid latestRoundData() of Chainlink feed {:?}", feed))?;
+    let decimals = ethers::abi::decode(&[ParamType::Uint(8)], &decimals)
+        .with_context(|| format!("Invalid decimals() of Chainlink feed {:?}", feed))?;
+
+    let (Some(answer), Some(decimals)) = (round[1].clone().into_int(), decimals[0].clone().into_uint()) else {
+        return Ok(None);
+    };
+    let answer = I256::from_raw(answer);
+    if answer <= I256::zero() {
+        return Ok(None);
+    }
+    let price: f64 = format_units(answer.into_raw(), decimals.low_u32())?.parse()?;
+    Ok(Some(price))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,298 @@
+use anyhow::{Context, Result};
+use ethers::prelude::*;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::{info, warn};
+
+use crate::metrics;
+use crate::retry::RetryPolicy;
+use crate::transport::{is_not_captured, sanitize_url, RpcTransport};
+
+/// A single RPC endpoint managed by the [`ProviderManager`]
+struct Endpoint {
+    url: String,
+    provider: Provider<RpcTransport>,
+}
+
+/// Manages a set of RPC endpoints and fails over between them.
+///
+/// Calls are always sent to the active endpoint. When it errors (timeouts,
+/// rate limits, bad gateways, ...) the manager rotates to the next endpoint
+/// and retries there, so a single flaky provider doesn't stall the listener.
+/// Once every endpoint has failed, the call is retried with exponential
+/// backoff according to the [`RetryPolicy`]. Requests for history the
+/// endpoints have pruned ([`is_pruned`]), log ranges they refuse as too
+/// large ([`is_range_too_large`]), and requests missing from a replayed
+/// capture, fail without backing off.
+pub struct ProviderManager {
+    endpoints: Vec<Endpoint>,
+    active: AtomicUsize,
+    retry: RetryPolicy,
+}
+
+impl ProviderManager {
+    pub fn new(urls: &[String]) -> Result<Self> {
+        if urls.is_empty() {
+            anyhow::bail!("At least one RPC endpoint is required");
+        }
+
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let provider = RpcTransport::new(url)
+                    .map(Provider::new)
+                    .with_context(|| format!("Invalid RPC endpoint: {}", mask_api_key(url)))?;
+                Ok(Endpoint {
+                    url: url.clone(),
+                    provider,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            endpoints,
+            active: AtomicUsize::new(0),
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Probe every endpoint and make the first healthy one active.
+    /// Fails only if no endpoint answers.
+    pub async fn health_check(&self) -> Result<()> {
+        let mut first_healthy = None;
+
+        for (i, endpoint) in self.endpoints.iter().enumerate() {
+            match endpoint.provider.get_block_number().await {
+                Ok(block) => {
+                    info!("RPC endpoint {} healthy at block {}", mask_api_key(&endpoint.url), block);
+                    first_healthy.get_or_insert(i);
+                }
+                Err(e) => {
+                    warn!("RPC endpoint {} unavailable: {}", mask_api_key(&endpoint.url), e);
+                }
+            }
+        }
+
+        let index = first_healthy.context("No healthy RPC endpoint available")?;
+        self.active.store(index, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub async fn get_block_number(&self) -> Result<u64> {
+        self.call(|provider| async move { provider.get_block_number().await })
+            .await
+            .map(|n| n.as_u64())
+    }
+
+    pub async fn get_logs(&self, filter: &Filter) -> Result<Vec<Log>> {
+        self.call(|provider| async move { provider.get_logs(filter).await })
+            .await
+    }
+
+    pub async fn get_transaction_receipt(&self, hash: H256) -> Result<Option<TransactionReceipt>> {
+        self.call(|provider| async move { provider.get_transaction_receipt(hash).await })
+            .await
+    }
+
+    pub async fn get_transaction(&self, hash: H256) -> Result<Option<Transaction>> {
+        self.call(|provider| async move { provider.get_transaction(hash).await })
+            .await
+    }
+
+    /// Unix timestamp of block `number`, `None` when the node doesn't have
+    /// the block (yet)
+    pub async fn get_block_timestamp(&self, number: u64) -> Result<Option<u64>> {
+        self.call(|provider| async move { provider.get_block(number).await })
+            .await
+            .map(|block| block.map(|block| block.timestamp.as_u64()))
+    }
+
+    /// Storage slot `slot` of the contract at `address`, at the latest block
+    pub async fn get_storage_at(&self, address: Address, slot: H256) -> Result<H256> {
+        self.call(|provider| async move { provider.get_storage_at(address, slot, None).await })
+            .await
+    }
+
+    /// The primary ENS name of `address`, verified to resolve back to it;
+    /// `None` when it has none. Only meaningful on Ethereum mainnet.
+    pub async fn lookup_address(&self, address: Address) -> Result<Option<String>> {
+        self.call(|provider| async move {
+            match provider.lookup_address(address).await {
+                Ok(name) if !name.is_empty() => Ok(Some(name)),
+                Ok(_) => Ok(None),
+                Err(e) if is_unresolved(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+    }
+
+    /// `eth_call` of `data` on the contract at `to`, at the latest block.
+    /// `None` when the call reverts, which is the contract's answer rather
+    /// than an endpoint failure, so it's neither retried nor failed over.
+    pub async fn call_contract(&self, to: Address, data: Bytes) -> Result<Option<Bytes>> {
+        let tx: TypedTransaction = TransactionRequest::new().to(to).data(data).into();
+        let tx = &tx;
+        self.call(|provider| async move {
+            match provider.call(tx, None).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if is_revert(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+        .await
+    }
+
+    /// Run `op` against the active endpoint, rotating through the others on
+    /// error, and back off before trying the whole set again.
+    async fn call<'a, T, F, Fut>(&'a self, op: F) -> Result<T>
+    where
+        F: Fn(&'a Provider<RpcTransport>) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let mut attempt = 0;
+
+        loop {
+            match self.try_endpoints(&op).await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.retry.max_retries && !is_pruned(&e) && !is_range_too_large(&e) && !is_not_captured(&e) => {
+                    let delay = self.retry.delay(attempt);
+                    attempt += 1;
+                    warn!(
+                        "RPC error: {} (retry {}/{} in {}ms)",
+                        e,
+                        attempt,
+                        self.retry.max_retries,
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Try `op` on each endpoint at most once, starting with the active one
+    async fn try_endpoints<'a, T, F, Fut>(&'a self, op: &F) -> Result<T, ProviderError>
+    where
+        F: Fn(&'a Provider<RpcTransport>) -> Fut,
+        Fut: Future<Output = Result<T, ProviderError>>,
+    {
+        let mut last_error = None;
+
+        for _ in 0..self.endpoints.len() {
+            let index = self.active.load(Ordering::Relaxed);
+            match op(&self.endpoints[index].provider).await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    metrics::rpc_error(&sanitize_url(&self.endpoints[index].url));
+                    self.failover(index, &e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.expect("at least one endpoint"))
+    }
+
+    fn failover(&self, from: usize, error: &ProviderError) {
+        if self.endpoints.len() < 2 {
+            return;
+        }
+
+        let to = (from + 1) % self.endpoints.len();
+        // Another caller may already have rotated away from this endpoint
+        if self
+            .active
+            .compare_exchange(from, to, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let reason = if is_rate_limited(error) {
+            "rate limited".to_string()
+        } else {
+            error.to_string()
+        };
+        warn!(
+            "RPC failover: {} -> {} ({})",
+            mask_api_key(&self.endpoints[from].url),
+            mask_api_key(&self.endpoints[to].url),
+            reason
+        );
+    }
+}
+
+/// Best-effort detection of provider rate limiting (HTTP 429 or the
+/// JSON-RPC "limit exceeded" codes used by Infura/Alchemy)
+pub fn is_rate_limited(error: &ProviderError) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("429")
+        || message.contains("rate limit")
+        || message.contains("too many requests")
+        || message.contains("-32005")
+}
+
+/// Best-effort detection of a node refusing a request for blocks or state
+/// older than it retains (Geth, Erigon, Nethermind, Reth, and hosted
+/// providers word this differently)
+pub fn is_pruned(error: &ProviderError) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("missing trie node")
+        || message.contains("pruned")
+        || message.contains("pruning")
+        || message.contains("history expired")
+        || message.contains("history unavailable")
+        || message.contains("old data not available")
+}
+
+/// Best-effort detection of a node refusing an `eth_getLogs` range as too
+/// many blocks or too many results; every provider sets its own limits and
+/// words them differently
+pub fn is_range_too_large(error: &ProviderError) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("more than 10000 results")
+        || message.contains("limited to a 10,000")
+        || message.contains("block range too large")
+        || message.contains("block range is too large")
+        || message.contains("block range is too wide")
+        || message.contains("range is too large")
+        || message.contains("exceed maximum block range")
+        || message.contains("exceeds max block range")
+        || message.contains("log response size exceeded")
+        || message.contains("query timeout exceeded")
+        || message.contains("too many results")
+}
+
+/// Whether an ENS lookup failed because the name or address has no record,
+/// or the record doesn't resolve back
+fn is_unresolved(error: &ProviderError) -> bool {
+    matches!(error, ProviderError::EnsError(_) | ProviderError::EnsNotOwned(_)) || is_revert(error)
+}
+
+/// Best-effort detection of an `eth_call` reverting, as opposed to the
+/// endpoint failing
+pub fn is_revert(error: &ProviderError) -> bool {
+    let message = error.to_string().to_lowercase();
+    message.contains("revert") || message.contains("invalid opcode") || message.contains("out of gas")
+}
+
+pub fn mask_api_key(url: &str) -> String {
+    // Mask API keys in URLs for privacy
+    if let Some(pos) = url.rfind('/') {
+        if pos + 1 < url.len() {
+            let (base, key) = url.split_at(pos + 1);
+            if key.len() > 8 {
+                return format!("{}{}...{}", base, &key[..4], &key[key.len()-4..]);
+            }
+        }
+    }
+    url.to_string()
+}
@@ -0,0 +1,441 @@
+//! Proxy resolution: events of upgradeable contracts are emitted by the
+//! proxy but declared in its implementation's ABI, so the implementation is
+//! read from the proxy's storage and its verified ABI decodes what the
+//! listener's ABI didn't.
+//!
+//! The implementation is read from the EIP-1967 slot (transparent and UUPS
+//! proxies), through the EIP-1967 beacon slot (beacon proxies), and from the
+//! EIP-1822 and legacy OpenZeppelin slots. Its ABI is fetched from Sourcify
+//! and optionally Etherscan. Both are looked up once per contract in a
+//! background task that outlives the enrichment time limits, so a slow
+//! explorer costs the first events of a proxy their decoding rather than
+//! every event.
+//!
+//! The proxy's `Upgraded(address)` and `BeaconUpgraded(address)` events are
+//! flagged, reported on stderr, and make the next event of the proxy look
+//! the implementation up again. The implementation is the current one, so
+//! events from before an upgrade are decoded with the ABI in use now.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::abi::Abi;
+use ethers::types::{Address, Bytes, Log, H256, U256};
+use ethers::utils::keccak256;
+use futures::future::{BoxFuture, FutureExt, Shared};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+use crate::abi::AbiDecoder;
+use crate::chains::native_symbol;
+use crate::config::ProxyConfig;
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::provider::ProviderManager;
+
+pub const DEFAULT_SOURCIFY_URL: &str = "https://sourcify.dev/server";
+
+pub const DEFAULT_ETHERSCAN_URL: &str = "https://api.etherscan.io/v2/api";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Contracts whose resolution is kept
+const CACHE_SIZE: usize = 10_000;
+
+/// Selector of `implementation()`, which beacons answer
+const IMPLEMENTATION_SELECTOR: [u8; 4] = [0x5c, 0x60, 0xda, 0x1b];
+
+/// How a proxy stores its implementation
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProxyKind {
+    /// EIP-1967 implementation slot: transparent and UUPS proxies
+    Eip1967,
+    /// EIP-1967 beacon slot; the beacon holds the implementation
+    Beacon,
+    /// EIP-1822 (the original UUPS) `PROXIABLE` slot
+    Eip1822,
+    /// OpenZeppelin's slot from before EIP-1967 (zos-lib)
+    Zeppelinos,
+}
+
+impl fmt::Display for ProxyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProxyKind::Eip1967 => "EIP-1967",
+            ProxyKind::Beacon => "EIP-1967 beacon",
+            ProxyKind::Eip1822 => "EIP-1822",
+            ProxyKind::Zeppelinos => "OpenZeppelin legacy",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The implementation behind the proxy that emitted an event
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProxyInfo {
+    pub kind: ProxyKind,
+    /// Contract the proxy delegates to; on an `Upgraded` event, the one it
+    /// was upgraded to
+    pub implementation: String,
+    /// Beacon the implementation is read from, for beacon proxies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beacon: Option<String>,
+    /// Whether the implementation's ABI was found; without it only the
+    /// listener's ABI decodes the proxy's events
+    pub verified: bool,
+    /// Set on the proxy's `Upgraded` and `BeaconUpgraded` events
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub upgraded: bool,
+}
+
+/// A resolved proxy, with a decoder when the implementation is verified
+struct Resolved {
+    info: ProxyInfo,
+    decoder: Option<AbiDecoder>,
+}
+
+/// Outcome of resolving a contract: `None` when it isn't a proxy, and the
+/// error message when the lookup failed
+type Lookup = Shared<BoxFuture<'static, Result<Option<Arc<Resolved>>, String>>>;
+
+/// Identifies a contract: (chain ID, address)
+type ContractKey = (Option<u64>, Address);
+
+#[derive(Default)]
+struct Cache {
+    lookups: HashMap<ContractKey, Lookup>,
+    /// Contracts in the order they were added, oldest first
+    order: VecDeque<ContractKey>,
+}
+
+/// Where implementation ABIs are fetched from
+struct AbiSources {
+    client: reqwest::Client,
+    sourcify: Option<String>,
+    /// (URL, API key)
+    etherscan: Option<(String, String)>,
+}
+
+/// Adds the [`ProxyInfo`] of proxy contracts to their events, and decodes
+/// events no ABI decoded with the implementation's ABI
+pub struct ProxyResolver {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    sources: Arc<AbiSources>,
+    cache: Mutex<Cache>,
+}
+
+impl ProxyResolver {
+    /// Proxy storage is read through `providers`, by chain ID
+    pub fn new(config: &ProxyConfig, providers: HashMap<Option<u64>, Arc<ProviderManager>>) -> Result<Self> {
+        Ok(Self {
+            providers,
+            sources: Arc::new(AbiSources::new(config)?),
+            cache: Mutex::new(Cache::default()),
+        })
+    }
+
+    /// The resolution of `key`, started in the background on first use
+    fn lookup(&self, provider: &Arc<ProviderManager>, key: ContractKey) -> Lookup {
+        let mut cache = lock(&self.cache);
+        if let Some(lookup) = cache.lookups.get(&key) {
+            return lookup.clone();
+        }
+
+        let task = tokio::spawn(resolve(provider.clone(), self.sources.clone(), key));
+        let lookup = task
+            .map(|joined| match joined {
+                Ok(Ok(resolved)) => Ok(resolved.map(Arc::new)),
+                Ok(Err(e)) => Err(format!("{:#}", e)),
+                Err(e) => Err(e.to_string()),
+            })
+            .boxed()
+            .shared();
+        cache.lookups.insert(key, lookup.clone());
+        cache.order.push_back(key);
+        if cache.order.len() > CACHE_SIZE {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.lookups.remove(&oldest);
+            }
+        }
+        lookup
+    }
+
+    /// Resolve `contract` again on its next event, along with the proxies
+    /// whose beacon it is
+    fn forget(&self, key: ContractKey) {
+        let mut cache = lock(&self.cache);
+        cache.lookups.retain(|&(chain_id, _), lookup| {
+            let behind_beacon = matches!(
+                lookup.peek(),
+                Some(Ok(Some(resolved))) if chain_id == key.0 && resolved.info.beacon == Some(format!("{:?}", key.1))
+            );
+            !behind_beacon
+        });
+        cache.lookups.remove(&key);
+        let Cache { lookups, order } = &mut *cache;
+        order.retain(|key| lookups.contains_key(key));
+    }
+
+    /// Drop a failed lookup so the next event tries again
+    fn forget_failure(&self, key: ContractKey) {
+        let mut cache = lock(&self.cache);
+        if matches!(cache.lookups.get(&key).and_then(|lookup| lookup.peek()), Some(Err(_))) {
+            cache.lookups.remove(&key);
+            cache.order.retain(|k| *k != key);
+        }
+    }
+}
+
+#[async_trait]
+impl Enricher for ProxyResolver {
+    fn name(&self) -> &str {
+        "Proxy"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+        let contract: Address = event.contract_address.parse()?;
+        let key = (event.chain_id, contract);
+
+        let upgrade = upgrade(event);
+        if let Some((ref signature, target)) = upgrade {
+            info!(
+                "{} upgraded on {}: {} {:?} in block {}",
+                event.contract_address, event.chain_name, signature, target, event.block_number
+            );
+            self.forget(key);
+        }
+
+        let resolved = match self.lookup(provider, key).await {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                self.forget_failure(key);
+                anyhow::bail!(e);
+            }
+        };
+        let Some(resolved) = resolved else {
+            return Ok(());
+        };
+
+        let mut info = resolved.info.clone();
+        if let Some((signature, target)) = upgrade {
+            info.upgraded = true;
+            match signature {
+                "BeaconUpgraded" => info.beacon = Some(format!("{:?}", target)),
+                _ => info.implementation = format!("{:?}", target),
+            }
+        }
+        event.proxy = Some(info);
+
+        if event.event_name.is_some() || event.decode_error.is_some() {
+            return Ok(());
+        }
+        let Some(ref decoder) = resolved.decoder else {
+            return Ok(());
+        };
+        let log = Log {
+            topics: event.topics.iter().map(|topic| topic.parse()).collect::<Result<_, _>>()?,
+            data: Bytes::from(hex::decode(event.data.trim_start_matches("0x"))?),
+            ..Default::default()
+        };
+        match decoder.decode(&log, native_symbol(event.chain_id)) {
+            Some(Ok(decoded)) => {
+                if event.event_signature.is_none() {
+                    event.event_signature = decoder.event_signature(&log.topics[0]);
+                }
+                event.event_name = Some(decoded.name);
+                event.decoded = Some(decoded.params);
+                if !decoded.humanized.is_empty() {
+                    event.humanized = Some(decoded.humanized);
+                }
+                event.decode_error = decoded.error;
+            }
+            Some(Err(e)) => event.decode_error = Some(e),
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// The event name and new implementation or beacon, when `event` is a
+/// proxy's `Upgraded(address)` or `BeaconUpgraded(address)`
+fn upgrade(event: &EventData) -> Option<(&'static str, Address)> {
+    let [topic0, target] = event.topics.as_slice() else {
+        return None;
+    };
+    let topic0: H256 = topic0.parse().ok()?;
+    let name = ["Upgraded", "BeaconUpgraded"]
+        .into_iter()
+        .find(|name| H256(keccak256(format!("{}(address)", name))) == topic0)?;
+    address_in(target.parse().ok()?).map(|address| (name, address))
+}
+
+/// Look up the implementation of the contract `key` and its ABI
+async fn resolve(provider: Arc<ProviderManager>, sources: Arc<AbiSources>, key: ContractKey) -> Result<Option<Resolved>> {
+    let (chain_id, contract) = key;
+    let Some((kind, implementation, beacon)) = implementation(&provider, contract).await? else {
+        return Ok(None);
+    };
+    let abi = match chain_id {
+        Some(chain_id) => sources.fetch(chain_id, implementation).await?,
+        None => None,
+    };
+    let info = ProxyInfo {
+        kind,
+        implementation: format!("{:?}", implementation),
+        beacon: beacon.map(|beacon| format!("{:?}", beacon)),
+        verified: abi.is_some(),
+        upgraded: false,
+    };
+    Ok(Some(Resolved {
+        info,
+        decoder: abi.as_ref().map(AbiDecoder::from_abi),
+    }))
+}
+
+/// The kind, implementation, and beacon of the proxy at `contract`, or
+/// `None` when it isn't one
+async fn implementation(provider: &ProviderManager, contract: Address) -> Result<Option<(ProxyKind, Address, Option<Address>)>> {
+    let slot = eip1967_slot("eip1967.proxy.implementation");
+    if let Some(implementation) = address_in(provider.get_storage_at(contract, slot).await?) {
+        return Ok(Some((ProxyKind::Eip1967, implementation, None)));
+    }
+
+    let slot = eip1967_slot("eip1967.proxy.beacon");
+    if let Some(beacon) = address_in(provider.get_storage_at(contract, slot).await?) {
+        let answer = provider.call_contract(beacon, Bytes::from(IMPLEMENTATION_SELECTOR.to_vec())).await?;
+        let implementation = answer
+            .filter(|answer| answer.len() == 32)
+            .and_then(|answer| address_in(H256::from_slice(&answer)))
+            .with_context(|| format!("Beacon {:?} has no implementation()", beacon))?;
+        return Ok(Some((ProxyKind::Beacon, implementation, Some(beacon))));
+    }
+
+    let slots = [
+        (ProxyKind::Eip1822, H256(keccak256("PROXIABLE"))),
+        (ProxyKind::Zeppelinos, H256(keccak256("org.zeppelinos.proxy.implementation"))),
+    ];
+    for (kind, slot) in slots {
+        if let Some(implementation) = address_in(provider.get_storage_at(contract, slot).await?) {
+            return Ok(Some((kind, implementation, None)));
+        }
+    }
+    Ok(None)
+}
+
+/// The EIP-1967 slot of `name`: its hash minus one, which no Solidity
+/// mapping or array can land on
+fn eip1967_slot(name: &str) -> H256 {
+    let slot = U256::from_big_endian(&keccak256(name)) - 1;
+    let mut bytes = [0u8; 32];
+    slot.to_big_endian(&mut bytes);
+    H256(bytes)
+}
+
+/// The address stored in `word`, unless it's empty or not an address
+fn address_in(word: H256) -> Option<Address> {
+    let (padding, address) = word.as_bytes().split_at(12);
+    (padding.iter().all(|&b| b == 0) && address.iter().any(|&b| b != 0)).then(|| Address::from_slice(address))
+}
+
+/// The verified ABI of `address` on `chain_id` from the sources in
+/// `config`, `None` when none of them has it
+pub async fn fetch_abi(config: &ProxyConfig, chain_id: u64, address: Address) -> Result<Option<Abi>> {
+    AbiSources::new(config)?.fetch(chain_id, address).await
+}
+
+impl AbiSources {
+    fn new(config: &ProxyConfig) -> Result<Self> {
+        let sourcify = match config.sourcify.unwrap_or(true) {
+            true => Some(config.sourcify_url.clone().unwrap_or_else(|| DEFAULT_SOURCIFY_URL.to_string())),
+            false => None,
+        };
+        let etherscan = config.etherscan_api_key.clone().map(|key| {
+            let url = config.etherscan_url.clone().unwrap_or_else(|| DEFAULT_ETHERSCAN_URL.to_string());
+            (url, key)
+        });
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            sourcify,
+            etherscan,
+        })
+    }
+
+    /// The verified ABI of `address`, from the first source that has it
+    async fn fetch(&self, chain_id: u64, address: Address) -> Result<Option<Abi>> {
+        if let Some(ref url) = self.sourcify {
+            if let Some(abi) = self.sourcify(url, chain_id, address).await? {
+                return Ok(Some(abi));
+            }
+        }
+        if let Some((ref url, ref key)) = self.etherscan {
+            return self.etherscan(url, key, chain_id, address).await;
+        }
+        Ok(None)
+    }
+
+    async fn sourcify(&self, url: &str, chain_id: u64, address: Address) -> Result<Option<Abi>> {
+        let response = self
+            .client
+            .get(format!("{}/v2/contract/{}/{:?}", url.trim_end_matches('/'), chain_id, address))
+            .query(&[("fields", "abi")])
+            .send()
+            .await
+            .context("Sourcify request failed")?;
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            anyhow::bail!("Sourcify answered {}", status);
+        }
+
+        let body: serde_json::Value = response.json().await.context("Invalid Sourcify response")?;
+        let abi = body.get("abi").cloned().context("Sourcify response has no ABI")?;
+        Ok(Some(serde_json::from_value(abi).context("Invalid ABI from Sourcify")?))
+    }
+
+    async fn etherscan(&self, url: &str, key: &str, chain_id: u64, address: Address) -> Result<Option<Abi>> {
+        let chain_id = chain_id.to_string();
+        let address = format!("{:?}", address);
+        let response = self
+            .client
+            .get(url)
+            .query(&[
+                ("chainid", chain_id.as_str()),
+                ("module", "contract"),
+                ("action", "getabi"),
+                ("address", address.as_str()),
+                ("apikey", key),
+            ])
+            .send()
+            .await
+            // reqwest's message has the request URL, API key included
+            .map_err(|e| e.without_url())
+            .context("Etherscan request failed")?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Etherscan answered {}", status);
+        }
+
+        let body: serde_json::Value = response.json().await.context("Invalid Etherscan response")?;
+        let result = body.get("result").and_then(|result| result.as_str()).unwrap_or_default();
+        if body.get("status").and_then(|status| status.as_str()) != Some("1") {
+            if result.to_lowercase().contains("not verified") {
+                return Ok(None);
+            }
+            anyhow::bail!("Etherscan answered: {}", result);
+        }
+        Ok(Some(serde_json::from_str(result).context("Invalid ABI from Etherscan")?))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,98 @@
+//! Event templates (`--output-template`): text rendered per event with
+//! [MiniJinja](https://docs.rs/minijinja), a Jinja2 dialect, for stdout, the
+//! output file, and the chat sinks.
+//!
+//! A template sees the event's JSON fields (`event_name`, `chain_name`,
+//! `block_number`, `transaction_hash`, `decoded`, `humanized`, `token`,
+//! `tx`, ...) and a few conveniences:
+//!
+//! - `title`: event name and chain, e.g. `Transfer on Polygon`
+//! - `args`: the decoded arguments by name, empty when not decoded
+//! - `fields`: `name` and readable `value` of each argument, humanized
+//!   values included
+//! - `contract_name`: the contract's label, else its ENS name
+//! - `tx_url`, `block_url`, `contract_url`: explorer links, when the
+//!   chain's explorer is known
+//!
+//! and the `short` filter, which abbreviates hashes and addresses to
+//! `0x1234…abcd`. Missing fields, including nested ones such as
+//! `token.symbol` of an event without token metadata, render as nothing.
+//! Templates whose file name ends in `.html` escape the values they insert.
+
+use anyhow::{Context, Result};
+use minijinja::{Environment, UndefinedBehavior};
+use serde_json::{json, Value};
+use std::path::Path;
+
+use crate::event::EventData;
+use crate::notification::Notification;
+
+/// A template file loaded and parsed
+pub struct EventTemplate {
+    env: Environment<'static>,
+    name: String,
+}
+
+impl EventTemplate {
+    /// Load and parse the template at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        let name = path.file_name().map_or("template".into(), |name| name.to_string_lossy().into_owned());
+        Self::parse(name, source).with_context(|| format!("Invalid template {}", path.display()))
+    }
+
+    /// Parse `source`; `name` picks the escaping, by its extension
+    pub fn parse(name: String, source: String) -> Result<Self> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Chainable);
+        env.set_trim_blocks(true);
+        env.set_lstrip_blocks(true);
+        env.add_filter("short", short);
+        env.add_template_owned(name.clone(), source).map_err(|e| anyhow::anyhow!("{:#}", e))?;
+        Ok(Self { env, name })
+    }
+
+    /// `event` rendered, without trailing newlines
+    pub fn render(&self, event: &EventData) -> Result<String> {
+        let template = self.env.get_template(&self.name)?;
+        let text = template
+            .render(context(event)?)
+            .map_err(|e| anyhow::anyhow!("Failed to render template {}: {:#}", self.name, e))?;
+        Ok(text.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// The values a template sees for `event`
+fn context(event: &EventData) -> Result<Value> {
+    let notification = Notification::from_event(event);
+    let mut context = serde_json::to_value(event)?;
+    let fields: Vec<Value> = notification
+        .fields
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+    let extra = json!({
+        "title": notification.title,
+        "args": event.decoded.clone().unwrap_or_default(),
+        "fields": fields,
+        "contract_name": notification.contract_name,
+        "tx_url": notification.transaction_url,
+        "block_url": notification.block_url,
+        "contract_url": notification.contract_url,
+    });
+    // Missing values stay undefined, which renders as nothing rather than `none`
+    if let (Value::Object(context), Value::Object(extra)) = (&mut context, extra) {
+        context.extend(extra.into_iter().filter(|(_, value)| !value.is_null()));
+    }
+    Ok(context)
+}
+
+/// `0x1234…abcd` for long hex strings; other values as they are
+fn short(value: String) -> String {
+    match value.strip_prefix("0x") {
+        Some(hex) if hex.len() > 12 && hex.is_ascii() => format!("0x{}…{}", &hex[..4], &hex[hex.len() - 4..]),
+        _ => value,
+    }
+}
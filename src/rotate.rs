@@ -0,0 +1,256 @@
+//! JSON Lines output file with size- and time-based rotation.
+//!
+//! The active file keeps its configured name. When it's rolled over it's
+//! renamed to `<stem>.<YYYYMMDD-HHMMSS>.<extension>` (the local time of the
+//! rotation), optionally gzip-compressed in the background, and the oldest
+//! rotated files beyond the configured number are deleted.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::config::RotationConfig;
+use crate::event::EventData;
+
+/// Appends events as JSON Lines to a file, rolling it over as configured
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    /// Bytes in the active file
+    size: u64,
+    /// Local date the active file was last written on
+    day: NaiveDate,
+    max_size: Option<u64>,
+    daily: bool,
+    compress: bool,
+    keep: Option<usize>,
+}
+
+impl RotatingFile {
+    /// Open `path` for appending. Without `rotation` the file grows unbounded.
+    pub fn open(path: impl AsRef<Path>, rotation: Option<&RotationConfig>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if rotation.is_some_and(|rotation| rotation.max_size.is_none() && !rotation.daily) {
+            anyhow::bail!("File rotation needs a size (--rotate-size) or daily rotation (--rotate-daily)");
+        }
+        let rotation = rotation.cloned().unwrap_or_default();
+        let max_size = rotation
+            .max_size
+            .as_deref()
+            .map(parse_size)
+            .transpose()
+            .context("Invalid rotation size")?;
+
+        let file = open_append(&path)?;
+        let metadata = file.metadata()?;
+        // An existing file counts as written on its last modification, so a
+        // file from yesterday is rolled over on the first write today
+        let day = metadata
+            .modified()
+            .map(|modified| DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+
+        Ok(Self {
+            path,
+            file,
+            size: metadata.len(),
+            day,
+            max_size,
+            daily: rotation.daily,
+            compress: rotation.compress,
+            keep: rotation.keep,
+        })
+    }
+
+    /// Append `event` as one line, rolling the file over first if it's due
+    pub fn write(&mut self, event: &EventData) -> Result<()> {
+        self.write_line(serde_json::to_vec(event)?)
+    }
+
+    /// Append `text`, e.g. an event rendered with a template, and a newline
+    pub fn write_text(&mut self, text: &str) -> Result<()> {
+        self.write_line(text.as_bytes().to_vec())
+    }
+
+    fn write_line(&mut self, mut line: Vec<u8>) -> Result<()> {
+        line.push(b'\n');
+
+        let now = Local::now();
+        let too_big = self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + line.len() as u64 > max);
+        let new_day = self.daily && now.date_naive() != self.day;
+        if too_big || new_day {
+            self.rotate(now)?;
+        }
+
+        self.file
+            .write_all(&line)
+            .with_context(|| format!("Failed to write to {}", self.path.display()))?;
+        self.size += line.len() as u64;
+        self.day = now.date_naive();
+        Ok(())
+    }
+
+    /// Make sure the lines written so far are on disk
+    pub fn sync(&mut self) -> Result<()> {
+        self.file
+            .sync_data()
+            .with_context(|| format!("Failed to sync {}", self.path.display()))
+    }
+
+    /// Rename the active file and start a new one. Compression and deleting
+    /// old files happen on a background thread so events aren't held up.
+    fn rotate(&mut self, now: DateTime<Local>) -> Result<()> {
+        let rotated = rotated_path(&self.path, now);
+        std::fs::rename(&self.path, &rotated)
+            .with_context(|| format!("Failed to rotate {} to {}", self.path.display(), rotated.display()))?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+
+        let (path, compress, keep) = (self.path.clone(), self.compress, self.keep);
+        std::thread::spawn(move || {
+            if compress {
+                if let Err(e) = gzip(&rotated) {
+                    warn!("Failed to compress {}: {:#}", rotated.display(), e);
+                }
+            }
+            if let Some(keep) = keep {
+                if let Err(e) = remove_old(&path, keep) {
+                    warn!("Failed to delete old rotated files of {}: {:#}", path.display(), e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+/// `events.jsonl` rotated at `now`: `events.20240501-000000.jsonl`, with a
+/// counter added if that name is taken
+fn rotated_path(path: &Path, now: DateTime<Local>) -> PathBuf {
+    let (stem, extension) = split_name(path);
+    let stamp = now.format("%Y%m%d-%H%M%S").to_string();
+
+    let mut n = 0;
+    loop {
+        let stamp = if n == 0 { stamp.clone() } else { format!("{}-{}", stamp, n) };
+        let name = match &extension {
+            Some(extension) => format!("{}.{}.{}", stem, stamp, extension),
+            None => format!("{}.{}", stem, stamp),
+        };
+        let candidate = path.with_file_name(name);
+        let compressed = PathBuf::from(format!("{}.gz", candidate.display()));
+        if !candidate.exists() && !compressed.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+fn split_name(path: &Path) -> (String, Option<String>) {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let extension = path.extension().map(|e| e.to_string_lossy().into_owned());
+    (stem, extension)
+}
+
+/// Compress `path` to `path.gz` and remove it. The archive is written under a
+/// temporary name first so a partial one is never left behind as `.gz`.
+fn gzip(path: &Path) -> Result<()> {
+    let target = PathBuf::from(format!("{}.gz", path.display()));
+    let partial = PathBuf::from(format!("{}.gz.partial", path.display()));
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::rename(&partial, &target)?;
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Delete all but the `keep` newest rotated files of `path`
+fn remove_old(path: &Path, keep: usize) -> Result<()> {
+    let (stem, extension) = split_name(path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    // Names without `.gz`: a file still being compressed exists under both
+    let mut rotated = BTreeSet::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        let name = name.strip_suffix(".gz").unwrap_or(&name);
+        let Some(rest) = name.strip_prefix(&format!("{}.", stem)) else {
+            continue;
+        };
+        let stamp = match extension {
+            Some(ref extension) => rest.strip_suffix(&format!(".{}", extension)),
+            None => Some(rest),
+        };
+        if stamp.is_some_and(is_rotation_stamp) {
+            rotated.insert(name.to_string());
+        }
+    }
+
+    // Timestamps sort chronologically
+    let excess = rotated.len().saturating_sub(keep);
+    for name in rotated.iter().take(excess) {
+        for candidate in [dir.join(name), dir.join(format!("{}.gz", name))] {
+            match std::fs::remove_file(&candidate) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to delete {}", candidate.display())),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `stamp` is a rotation time, `YYYYMMDD-HHMMSS` optionally
+/// followed by `-N`
+fn is_rotation_stamp(stamp: &str) -> bool {
+    let (time, counter) = match stamp.get(15..) {
+        Some(rest) => (&stamp[..15], rest),
+        None => return false,
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    time.as_bytes()[8] == b'-'
+        && digits(&time[..8])
+        && digits(&time[9..])
+        && (counter.is_empty() || counter.strip_prefix('-').is_some_and(digits))
+}
+
+/// Parse a size such as `1048576`, `500K`, `100M`, or `1G` (powers of 1024;
+/// `KB`/`KiB` and friends are accepted too)
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: u64 = number.parse().with_context(|| format!("Invalid size: {}", size))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => anyhow::bail!("Invalid size unit in {} (expected K, M, or G)", size),
+    };
+    number
+        .checked_mul(multiplier)
+        .filter(|&bytes| bytes > 0)
+        .with_context(|| format!("Invalid size: {}", size))
+}
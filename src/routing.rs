@@ -0,0 +1,119 @@
+//! Routing of events to sinks (`[[sinks.routes]]`).
+//!
+//! Each route matches events by signature, name, or topic0 hash, and
+//! optionally by a filter expression (see [`crate::filter`]), and names the
+//! sinks they go to. An event takes the first route it matches and goes to
+//! that route's sinks only; events that match no route go to every sink.
+//! A route without events or a filter matches every event, so as the last
+//! route it decides where everything else goes.
+//!
+//! stdout and the output file get every event regardless.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+
+use crate::config::{RouteConfig, SinkConfig};
+use crate::event::EventData;
+use crate::filter::EventFilter;
+use crate::sinks::SinkKind;
+
+/// Name routes use for the webhooks
+pub const WEBHOOK: &str = "webhook";
+
+/// The routes of a sink config
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+pub struct Route {
+    /// Signatures and names as given, hashes lowercase
+    events: HashSet<String>,
+    filter: Option<EventFilter>,
+    sinks: Vec<SinkKind>,
+    webhook: bool,
+}
+
+impl Router {
+    /// Parse the routes of `config`; they may only name sinks it enables
+    pub fn new(config: &SinkConfig) -> Result<Self> {
+        let configured = SinkKind::configured(config)?;
+        let routes = config
+            .routes
+            .iter()
+            .enumerate()
+            .map(|(i, route)| Route::parse(route, &configured).with_context(|| format!("Invalid route {}", i + 1)))
+            .collect::<Result<_>>()?;
+        Ok(Self { routes })
+    }
+
+    /// The route `event` takes; `None` sends it to every sink
+    pub fn route(&self, event: &EventData) -> Option<&Route> {
+        self.routes.iter().find(|route| route.matches(event))
+    }
+}
+
+impl Route {
+    fn parse(config: &RouteConfig, configured: &[SinkKind]) -> Result<Self> {
+        let mut sinks = Vec::new();
+        let mut webhook = false;
+        for name in &config.sinks {
+            let name = name.trim().to_lowercase();
+            if name == WEBHOOK {
+                webhook = true;
+                continue;
+            }
+            let kind = SinkKind::ALL.into_iter().find(|kind| kind.config_name() == name).with_context(|| {
+                let known: Vec<&str> = SinkKind::ALL.iter().map(|kind| kind.config_name()).collect();
+                format!("Unknown sink: {} (expected {} or {})", name, known.join(", "), WEBHOOK)
+            })?;
+            if !configured.contains(&kind) {
+                anyhow::bail!("The {} sink isn't configured", name);
+            }
+            sinks.push(kind);
+        }
+
+        let filter = match config.filter {
+            Some(ref filter) => Some(EventFilter::parse(filter).with_context(|| format!("Invalid filter: {}", filter))?),
+            None => None,
+        };
+        let events = config
+            .events
+            .iter()
+            .map(|event| match event.trim() {
+                // Hashes compare case-insensitively
+                hash if hash.starts_with("0x") => hash.to_lowercase(),
+                other => other.to_string(),
+            })
+            .collect();
+        Ok(Self {
+            events,
+            filter,
+            sinks,
+            webhook,
+        })
+    }
+
+    fn matches(&self, event: &EventData) -> bool {
+        let selected = self.events.is_empty()
+            || [
+                event.event_signature.as_deref(),
+                event.event_name.as_deref(),
+                event.topics.first().map(|topic| topic.to_lowercase()).as_deref(),
+            ]
+            .into_iter()
+            .flatten()
+            .any(|key| self.events.contains(key));
+        selected && self.filter.as_ref().is_none_or(|filter| filter.matches(event))
+    }
+
+    /// Whether the route's events go to the sink of `kind`
+    pub fn includes(&self, kind: SinkKind) -> bool {
+        self.sinks.contains(&kind)
+    }
+
+    /// Whether the route's events go to their webhook
+    pub fn includes_webhook(&self) -> bool {
+        self.webhook
+    }
+}
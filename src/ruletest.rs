@@ -0,0 +1,155 @@
+//! Rule tests: example events embedded in a contract's config with whether
+//! its filters should let them through, run by `config test` so filter
+//! changes can be checked before they're deployed.
+//!
+//! An example is written like an event of the JSON output, and only the
+//! fields the filters look at are needed; the others get placeholders. The
+//! contract address defaults to the contract's own. With `topics` and
+//! `data` but no `decoded` arguments, the example is decoded with the
+//! contract's ABI or preset, as the listener would decode the log.
+
+use anyhow::{Context, Result};
+use ethers::types::{Address, Bytes, Log, H256};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::abi::AbiDecoder;
+use crate::chains::native_symbol;
+use crate::config::ContractConfig;
+use crate::event::{compute_event_topic, parse_topic, EventData};
+
+/// An example event and whether the contract's filters should match it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RuleTest {
+    /// Shown in the results (default: the test's position)
+    pub name: Option<String>,
+    /// Event fields, as in the JSON output
+    pub event: Map<String, Value>,
+    pub should_match: bool,
+}
+
+/// Whether an example matched, and why not
+#[derive(Debug, Clone, PartialEq)]
+pub struct Verdict {
+    pub matched: bool,
+    /// The first filter the example didn't pass
+    pub reason: Option<String>,
+}
+
+impl Verdict {
+    fn matched() -> Self {
+        Self {
+            matched: true,
+            reason: None,
+        }
+    }
+
+    fn rejected(reason: String) -> Self {
+        Self {
+            matched: false,
+            reason: Some(reason),
+        }
+    }
+}
+
+/// Run `test` against the filters of `contract` on chain `chain_id`:
+/// contract address, events, topic filters, and `where` expression, in the
+/// order the listener applies them. `decoder` is the contract's ABI or
+/// preset decoder.
+pub fn run_test(
+    chain_id: Option<u64>,
+    contract: &ContractConfig,
+    decoder: Option<&AbiDecoder>,
+    test: &RuleTest,
+) -> Result<Verdict> {
+    let mut event = example_event(chain_id, contract, &test.event)?;
+
+    let address: Address = event
+        .contract_address
+        .parse()
+        .with_context(|| format!("Invalid contract_address in example: {}", event.contract_address))?;
+    if !contract.contract_filter()?.matches(&address) {
+        return Ok(Verdict::rejected(format!("contract {:?} isn't listened to", address)));
+    }
+
+    let topics = event
+        .topics
+        .iter()
+        .map(|topic| parse_topic(topic))
+        .collect::<Result<Vec<H256>>>()
+        .context("Invalid topics in example")?;
+    let topic0 = match (topics.first(), &event.event_signature) {
+        (Some(topic0), _) => Some(*topic0),
+        (None, Some(signature)) => Some(compute_event_topic(signature)),
+        (None, None) => None,
+    };
+    if !contract.events.is_empty() {
+        let Some(topic0) = topic0 else {
+            anyhow::bail!("The example needs topics or an event_signature to check against the contract's events");
+        };
+        match contract.events.iter().find(|e| compute_event_topic(e.signature()) == topic0) {
+            Some(matching) => {
+                event.event_signature.get_or_insert_with(|| matching.signature().to_string());
+            }
+            None => return Ok(Verdict::rejected(format!("topic0 {:?} isn't one of the contract's events", topic0))),
+        }
+    }
+
+    for (i, accepted) in contract.topic_filter()?.iter().enumerate() {
+        if accepted.is_empty() {
+            continue;
+        }
+        match topics.get(i + 1) {
+            Some(topic) if accepted.contains(topic) => {}
+            Some(topic) => return Ok(Verdict::rejected(format!("topic{} {:?} isn't in the topic{} filter", i + 1, topic, i + 1))),
+            None => return Ok(Verdict::rejected(format!("the topic{} filter needs a topic{}", i + 1, i + 1))),
+        }
+    }
+
+    if event.decoded.is_none() && !topics.is_empty() {
+        if let Some(decoder) = decoder {
+            let log = Log {
+                address,
+                topics,
+                data: Bytes::from(hex::decode(event.data.trim_start_matches("0x")).context("Invalid data in example")?),
+                ..Default::default()
+            };
+            match decoder.decode(&log, native_symbol(chain_id)) {
+                Some(Ok(decoded)) => {
+                    event.event_name = Some(decoded.name);
+                    event.decoded = Some(decoded.params);
+                }
+                Some(Err(e)) => anyhow::bail!("The example doesn't decode as {}: {}", e.event, e.reason),
+                None => {}
+            }
+        }
+    }
+
+    if let Some(filter) = contract.event_filter()? {
+        if !filter.matches(&event) {
+            return Ok(Verdict::rejected(format!("`{}` is false", filter.source())));
+        }
+    }
+    Ok(Verdict::matched())
+}
+
+/// `fields` as an event, with placeholders for the fields left out
+fn example_event(chain_id: Option<u64>, contract: &ContractConfig, fields: &Map<String, Value>) -> Result<EventData> {
+    let mut event = json!({
+        "timestamp": "",
+        "chain_id": chain_id,
+        "chain_name": "",
+        "block_number": 0,
+        "transaction_hash": format!("{:?}", H256::zero()),
+        "log_index": 0,
+        "contract_address": contract.address.clone().unwrap_or_else(|| format!("{:?}", Address::zero())),
+        "topics": [],
+        "data": "",
+    });
+    let object = event.as_object_mut().expect("object literal");
+    for (key, value) in fields {
+        object.insert(key.clone(), value.clone());
+    }
+    serde_json::from_value(event).context("Invalid example event")
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+/// Identifies the listener build, configuration, and process that produced
+/// a record, so exported data can be traced back to the run that emitted it
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RunMetadata {
+    pub run_id: String,
+    pub listener_version: String,
+    pub config_hash: String,
+    pub started_at: String,
+}
+
+impl RunMetadata {
+    /// Start a new run for `config`. The config hash is the keccak256 of its
+    /// JSON serialization, truncated to 16 hex characters.
+    pub fn new(config: &impl Serialize) -> Result<Self> {
+        Ok(Self {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            listener_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash: config_hash(config)?,
+            started_at: Local::now().to_rfc3339(),
+        })
+    }
+}
+
+pub fn config_hash(config: &impl Serialize) -> Result<String> {
+    let json = serde_json::to_vec(config)?;
+    let hash = ethers::utils::keccak256(json);
+    Ok(hex::encode(&hash[..8]))
+}
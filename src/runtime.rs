@@ -0,0 +1,498 @@
+use anyhow::{Context, Result};
+use ethers::prelude::*;
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::abi::AbiDecoder;
+use crate::catchup::CatchUpScheduler;
+use crate::config::{Config, ContractConfig};
+use crate::control::PauseSwitch;
+use crate::event::{ContractFilter, EventData, TopicFilter};
+use crate::factory::{Children, Factory};
+use crate::filter::EventFilter;
+use crate::listener::{signatures_label, EventListener, LogSource};
+use crate::provider::ProviderManager;
+use crate::supervisor::{supervise, RestartPolicy, SupervisorMetrics};
+
+/// Identifies one running listener: (chain ID, contracts, event signatures,
+/// indexed argument filter, `where` expression, factory settings). A
+/// contract's events are fetched by one listener, with no signatures
+/// meaning all events.
+pub type ListenerKey = (Option<u64>, ContractFilter, Vec<String>, TopicFilter, Option<String>, Option<Factory>);
+
+type IdleCallback = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Identifies one contract on one chain; `None` stands for the chain's
+/// listeners without a contract address
+pub type ContractKey = (Option<u64>, Option<Address>);
+
+/// ABI decoders of each listener's contract and of the contracts its
+/// factory created
+type DecoderMap = HashMap<ListenerKey, (Option<Arc<AbiDecoder>>, Option<Arc<AbiDecoder>>)>;
+
+/// Block a listener resumes from after a restart; `None` means the chain head
+type Resume = Arc<Mutex<Option<u64>>>;
+
+/// A connected chain that listeners can be attached to
+struct ChainHandle {
+    provider: Arc<ProviderManager>,
+    /// Used for ranges `provider` has pruned
+    archive: Option<Arc<ProviderManager>>,
+    /// Serves logs instead of `provider` and `archive`
+    log_source: Option<Arc<dyn LogSource>>,
+    name: String,
+    native_symbol: Option<String>,
+    /// Block newly attached listeners start from; `None` means the chain head
+    start_block: Option<u64>,
+}
+
+/// A running listener task and where it would resume
+struct Task {
+    handle: JoinHandle<()>,
+    resume: Resume,
+    /// Contracts created by the listener's factory so far
+    children: Option<Children>,
+}
+
+/// Listeners started and stopped by [`ListenerSet::sync`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SyncSummary {
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// The set of running listeners, all feeding the same channel.
+///
+/// [`ListenerSet::sync`] reconciles the running set with a [`Config`]:
+/// listeners that are still configured keep running with their block cursor,
+/// removed ones are stopped, and new ones are started.
+///
+/// Every listener is a supervised task: when it panics (for instance while
+/// decoding a log) it is restarted under the config's listener
+/// [`RestartPolicy`], resuming from the last block it reached. Events of
+/// that block may be emitted again.
+///
+/// With an [`assign`](Self::assign)ment, only the assigned contracts are
+/// listened to, as when contracts are partitioned between processes.
+pub struct ListenerSet {
+    tx: mpsc::Sender<EventData>,
+    chains: HashMap<Option<u64>, ChainHandle>,
+    tasks: HashMap<ListenerKey, Task>,
+    /// Contracts this set may listen to, with the block each starts at
+    assignment: Option<HashMap<ContractKey, Option<u64>>>,
+    /// Blocks contracts resume from when the next sync starts them
+    checkpoint: HashMap<ContractKey, u64>,
+    /// Last block listeners fetch on each chain, for a backfill
+    end_blocks: HashMap<Option<u64>, u64>,
+    poll_interval: Duration,
+    on_idle: Option<IdleCallback>,
+    metrics: SupervisorMetrics,
+    catch_up: CatchUpScheduler,
+    pause: PauseSwitch,
+}
+
+impl ListenerSet {
+    pub fn new(tx: mpsc::Sender<EventData>, poll_interval: Duration) -> Self {
+        Self {
+            tx,
+            chains: HashMap::new(),
+            tasks: HashMap::new(),
+            assignment: None,
+            checkpoint: HashMap::new(),
+            end_blocks: HashMap::new(),
+            poll_interval,
+            on_idle: None,
+            metrics: SupervisorMetrics::default(),
+            catch_up: CatchUpScheduler::new(),
+            pause: PauseSwitch::default(),
+        }
+    }
+
+    /// Count listener crashes and restarts in `metrics`
+    pub fn metrics(mut self, metrics: SupervisorMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Called with the latest block number whenever a listener's poll finds no events
+    pub fn on_idle(mut self, callback: impl Fn(u64) + Send + Sync + 'static) -> Self {
+        self.on_idle = Some(Arc::new(callback));
+        self
+    }
+
+    /// The provider of every connected chain, by chain ID
+    pub fn providers(&self) -> HashMap<Option<u64>, Arc<ProviderManager>> {
+        self.chains
+            .iter()
+            .map(|(chain_id, chain)| (*chain_id, chain.provider.clone()))
+            .collect()
+    }
+
+    pub fn has_chain(&self, chain_id: Option<u64>) -> bool {
+        self.chains.contains_key(&chain_id)
+    }
+
+    /// Register a connected chain. Listeners attached to it by the next
+    /// [`sync`](Self::sync) start at `start_block`, later ones at the chain head.
+    /// `archive` serves ranges `provider` has pruned, and `native_symbol`
+    /// overrides the registry's symbol for `chain_id`.
+    pub fn add_chain(
+        &mut self,
+        chain_id: Option<u64>,
+        provider: Arc<ProviderManager>,
+        archive: Option<Arc<ProviderManager>>,
+        name: String,
+        native_symbol: Option<String>,
+        start_block: Option<u64>,
+    ) {
+        self.chains.insert(
+            chain_id,
+            ChainHandle {
+                provider,
+                archive,
+                log_source: None,
+                name,
+                native_symbol,
+                start_block,
+            },
+        );
+    }
+
+    /// Read the logs of the listeners on `chain_id` from `source` rather
+    /// than the chain's providers. Listeners already running keep theirs.
+    pub fn set_log_source(&mut self, chain_id: Option<u64>, source: Arc<dyn LogSource>) {
+        if let Some(chain) = self.chains.get_mut(&chain_id) {
+            chain.log_source = Some(source);
+        }
+    }
+
+    /// Only listen to the contracts in `contracts`, each starting at its
+    /// block when one is given, and otherwise where new listeners start.
+    /// Applied by the next [`sync`](Self::sync).
+    pub fn assign(&mut self, contracts: HashMap<ContractKey, Option<u64>>) {
+        self.assignment = Some(contracts);
+    }
+
+    /// Start the contracts in `cursors` at their block rather than where new
+    /// listeners start, unless assigned one. Applied by the next
+    /// [`sync`](Self::sync) only.
+    pub fn resume(&mut self, cursors: HashMap<ContractKey, u64>) {
+        self.checkpoint = cursors;
+    }
+
+    /// Stop the listeners of each chain in `blocks` after its block, as for
+    /// a backfill. Applies to listeners started from then on.
+    pub fn end_at(&mut self, blocks: HashMap<Option<u64>, u64>) {
+        self.end_blocks = blocks;
+    }
+
+    /// Whether there are listeners and all of them have stopped, having
+    /// reached their end block or given up
+    pub fn finished(&self) -> bool {
+        !self.tasks.is_empty() && self.tasks.values().all(|task| task.handle.is_finished())
+    }
+
+    /// Pause or resume polling of every listener, running or started later;
+    /// returns whether that changed anything
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.pause.set(paused)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Stop every listener, keeping their cursors for [`cursors`](Self::cursors).
+    /// Events they already sent stay in the channel.
+    pub fn stop(&mut self) {
+        for task in self.tasks.values() {
+            task.handle.abort();
+        }
+    }
+
+    /// The block each running contract would resume from: the earliest
+    /// among its listeners. Contracts whose listeners haven't reached a
+    /// block yet are left out.
+    pub fn cursors(&self) -> HashMap<ContractKey, u64> {
+        let mut cursors: HashMap<ContractKey, u64> = HashMap::new();
+        for ((chain_id, contract, ..), task) in &self.tasks {
+            if let Some(block) = *lock(&task.resume) {
+                let cursor = cursors.entry((*chain_id, contract.address())).or_insert(block);
+                *cursor = (*cursor).min(block);
+            }
+        }
+        cursors
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Start listeners for every contract in `config` that isn't running yet
+    /// and stop those no longer configured or assigned. A contract whose
+    /// events or filters changed is restarted from its listener's cursor.
+    /// All chains in `config` must have been added with
+    /// [`add_chain`](Self::add_chain).
+    pub fn sync(&mut self, config: &Config) -> Result<SyncSummary> {
+        let mut desired = listener_keys(config)?;
+        if let Some(ref assignment) = self.assignment {
+            desired.retain(|(chain_id, contract, ..)| assignment.contains_key(&(*chain_id, contract.address())));
+        }
+        let decoders = load_decoders(config)?;
+        let mut summary = SyncSummary::default();
+
+        self.catch_up.set_limit(config.catch_up_concurrency);
+        for chain in &config.chains {
+            self.catch_up
+                .set_chain(chain.chain_id, chain.catch_up_priority.unwrap_or(0), chain.catch_up_parallelism);
+        }
+
+        // A contract whose events or filters changed gets a new listener,
+        // which picks up where the old one was
+        let contracts: HashSet<ContractKey> = desired.iter().map(|key| (key.0, key.1.address())).collect();
+        let mut replaced: HashMap<ContractKey, u64> = HashMap::new();
+        let mut children: HashMap<ContractKey, Children> = HashMap::new();
+        self.tasks.retain(|key, task| {
+            let keep = desired.contains(key);
+            if !keep {
+                task.handle.abort();
+                summary.removed += 1;
+                let contract = (key.0, key.1.address());
+                if let (true, Some(block)) = (contracts.contains(&contract), *lock(&task.resume)) {
+                    let cursor = replaced.entry(contract).or_insert(block);
+                    *cursor = (*cursor).min(block);
+                }
+                if let Some(ref created) = task.children {
+                    children.insert(contract, created.clone());
+                }
+            }
+            keep
+        });
+
+        for key in desired {
+            if !self.tasks.contains_key(&key) {
+                let decoders = decoders.get(&key).cloned().unwrap_or_default();
+                let max_block_range = config
+                    .chains
+                    .iter()
+                    .find(|chain| chain.chain_id == key.0)
+                    .and_then(|chain| chain.max_block_range)
+                    .or(config.max_block_range);
+                let contract = (key.0, key.1.address());
+                let resume_from = replaced.get(&contract).copied();
+                let children = key.5.as_ref().map(|_| children.get(&contract).cloned().unwrap_or_default());
+                let task = self.spawn(
+                    &key,
+                    decoders,
+                    max_block_range,
+                    resume_from,
+                    children,
+                    config.supervision.listeners,
+                )?;
+                self.tasks.insert(key, task);
+                summary.added += 1;
+            }
+        }
+
+        for chain in self.chains.values_mut() {
+            chain.start_block = None;
+        }
+        self.checkpoint.clear();
+
+        Ok(summary)
+    }
+
+    fn spawn(
+        &self,
+        key: &ListenerKey,
+        (decoder, child_decoder): (Option<Arc<AbiDecoder>>, Option<Arc<AbiDecoder>>),
+        max_block_range: Option<u64>,
+        resume_from: Option<u64>,
+        children: Option<Children>,
+        policy: RestartPolicy,
+    ) -> Result<Task> {
+        let (chain_id, ref contract, ref event_signatures, ref topic_filter, ref filter, ref factory) = *key;
+        let chain = self
+            .chains
+            .get(&chain_id)
+            .with_context(|| format!("Chain {:?} is not connected", chain_id))?;
+        let start_block = resume_from
+            .or_else(|| {
+                self.assignment
+                    .as_ref()
+                    .and_then(|assignment| assignment.get(&(chain_id, contract.address())).copied().flatten())
+            })
+            .or_else(|| self.checkpoint.get(&(chain_id, contract.address())).copied())
+            .or(chain.start_block);
+
+        let name = format!("Listener {} {} ({})", chain.name, contract, signatures_label(event_signatures));
+        let contract = contract.clone();
+        let provider = chain.provider.clone();
+        let archive = chain.archive.clone();
+        let log_source = chain.log_source.clone();
+        let chain_name = chain.name.clone();
+        let native_symbol = chain.native_symbol.clone();
+        let event_signatures = event_signatures.clone();
+        let topic_filter = topic_filter.clone();
+        let filter = filter.as_deref().map(EventFilter::parse).transpose()?;
+        let factory = factory.clone().zip(children.clone());
+        let poll_interval = self.poll_interval;
+        let catch_up = self.catch_up.clone();
+        let pause = self.pause.clone();
+        let end_block = self.end_blocks.get(&chain_id).copied();
+        let on_idle = self.on_idle.clone();
+        let tx = self.tx.clone();
+        let resume: Resume = Arc::new(Mutex::new(start_block));
+        let task_resume = resume.clone();
+
+        let task = supervise(name, policy, self.metrics.clone(), move |_| {
+            let mut builder = EventListener::builder()
+                .provider(provider.clone())
+                .contract_filter(contract.clone())
+                .chain_name(chain_name.clone())
+                .poll_interval(poll_interval)
+                .event_signatures(event_signatures.clone())
+                .topic_filter(topic_filter.clone())
+                .catch_up_scheduler(catch_up.clone())
+                .pause_switch(pause.clone());
+            if let Some(chain_id) = chain_id {
+                builder = builder.chain_id(chain_id);
+            }
+            if let Some(ref archive) = archive {
+                builder = builder.archive_provider(archive.clone());
+            }
+            if let Some(ref source) = log_source {
+                builder = builder.log_source(source.clone());
+            }
+            if let Some(ref symbol) = native_symbol {
+                builder = builder.native_symbol(symbol.clone());
+            }
+            if let Some(block) = *lock(&resume) {
+                builder = builder.start_block(block);
+            }
+            if let Some(block) = end_block {
+                builder = builder.end_block(block);
+            }
+            if let Some(blocks) = max_block_range {
+                builder = builder.max_block_range(blocks);
+            }
+            if let Some(ref decoder) = decoder {
+                builder = builder.decoder(decoder.clone());
+            }
+            if let Some(ref filter) = filter {
+                builder = builder.filter(filter.clone());
+            }
+            if let Some((ref factory, ref children)) = factory {
+                builder = builder.factory(factory.clone(), children.clone());
+            }
+            if let Some(ref decoder) = child_decoder {
+                builder = builder.child_decoder(decoder.clone());
+            }
+            let idle_resume = resume.clone();
+            let on_idle = on_idle.clone();
+            builder = builder.on_idle(move |block| {
+                *lock(&idle_resume) = Some(block + 1);
+                if let Some(ref on_idle) = on_idle {
+                    on_idle(block);
+                }
+            });
+
+            let events = builder.build();
+            let resume = resume.clone();
+            let tx = tx.clone();
+            async move {
+                let mut events = events?;
+                while let Some(event) = events.next().await {
+                    *lock(&resume) = Some(event.block_number);
+                    if tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(())
+            }
+        });
+        Ok(Task {
+            handle: task,
+            resume: task_resume,
+            children,
+        })
+    }
+}
+
+impl Drop for ListenerSet {
+    fn drop(&mut self) {
+        for task in self.tasks.values() {
+            task.handle.abort();
+        }
+    }
+}
+
+/// One key per configured contract, covering all of its event filters.
+/// Changed events, topic filter, or `where` expression make a new key, so
+/// the listener is restarted with them.
+fn listener_keys(config: &Config) -> Result<HashSet<ListenerKey>> {
+    let mut keys = HashSet::new();
+
+    for chain in &config.chains {
+        for contract in &chain.contracts {
+            keys.insert(listener_key(chain.chain_id, contract)?);
+        }
+    }
+
+    Ok(keys)
+}
+
+fn listener_key(chain_id: Option<u64>, contract: &ContractConfig) -> Result<ListenerKey> {
+    let contract_filter = contract.contract_filter()?;
+    let topic_filter = contract
+        .topic_filter()
+        .with_context(|| format!("Invalid topic filter of {}", contract.label()))?;
+    contract
+        .event_filter()
+        .with_context(|| format!("Invalid filter of {}", contract.label()))?;
+
+    // Sorted so reordering the events doesn't restart the listener
+    let mut signatures: Vec<String> = contract.events.iter().map(|e| e.signature().to_string()).collect();
+    signatures.sort();
+    signatures.dedup();
+    let factory = contract
+        .factory()
+        .with_context(|| format!("Invalid factory settings of {}", contract.label()))?;
+    Ok((chain_id, contract_filter, signatures, topic_filter, contract.filter.clone(), factory))
+}
+
+/// ABI decoders for every contract in `config` that has an `abi` file or a
+/// preset, or a factory with an ABI for the contracts it creates
+fn load_decoders(config: &Config) -> Result<DecoderMap> {
+    let mut decoders = HashMap::new();
+
+    for chain in &config.chains {
+        for contract in &chain.contracts {
+            let child_abi = contract.factory.as_ref().and_then(|factory| factory.abi.as_ref());
+            if contract.abi.is_none() && contract.preset.is_none() && child_abi.is_none() {
+                continue;
+            }
+            let load = |path: &String| AbiDecoder::load(path).map(Arc::new);
+            // An ABI file takes precedence over the preset's events
+            let decoder = match contract.abi {
+                Some(ref path) => Some(load(path)?),
+                None => contract.preset.map(|preset| Arc::new(preset.decoder())),
+            };
+            decoders.insert(listener_key(chain.chain_id, contract)?, (decoder, child_abi.map(load).transpose()?));
+        }
+    }
+
+    Ok(decoders)
+}
+
+fn lock(resume: &Mutex<Option<u64>>) -> std::sync::MutexGuard<'_, Option<u64>> {
+    resume.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,227 @@
+//! CEF (ArcSight) and LEEF (QRadar) lines, for the `cef` and `leef` output
+//! formats and the syslog sink, so a SIEM ingests events without a custom
+//! parser.
+//!
+//! Both name the device `listener`/`listener` with the crate version, use
+//! the event signature (else topic0) as the event class and the severity
+//! configured for the event, and map its fields to the format's standard
+//! keys where one fits: the detection time to `rt`/`devTime`, the chain to
+//! `cat`, the transaction's sender to `suser`/`usrName`, and the explorer
+//! link to `request`/`url`. The rest go in custom keys, labeled
+//! `cs1Label`.. in CEF.
+
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::config::SinkConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+use crate::sinks::incident::{Severities, Severity};
+
+/// Device vendor and product of the lines
+pub const DEVICE: &str = "listener";
+
+/// CEF or LEEF, with the severities events are reported at
+pub struct SiemFormat {
+    kind: SiemKind,
+    severities: Severities,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SiemKind {
+    Cef,
+    Leef,
+}
+
+impl SiemFormat {
+    /// `format` (`cef` or `leef`) with a severity by event signature, name,
+    /// or topic0 hash; `None` for other formats
+    pub fn parse(format: &str, events: &BTreeMap<String, String>) -> Result<Option<Self>> {
+        let kind = match format {
+            "cef" => SiemKind::Cef,
+            "leef" => SiemKind::Leef,
+            _ => return Ok(None),
+        };
+        Ok(Some(Self {
+            kind,
+            severities: Severities::parse(events)?,
+        }))
+    }
+
+    /// The format of stdout and `output_file`, when it's `cef` or `leef`
+    pub fn from_config(sinks: &SinkConfig) -> Result<Option<Self>> {
+        match sinks.output_format.as_deref() {
+            Some(format) => Self::parse(format, &sinks.siem_events),
+            None => Ok(None),
+        }
+    }
+
+    /// `event` as one line
+    pub fn format(&self, event: &EventData) -> String {
+        let notification = Notification::from_event(event);
+        let severity = self.severities.get(event, &notification.event);
+        match self.kind {
+            SiemKind::Cef => cef(event, &notification, severity),
+            SiemKind::Leef => leef(event, &notification, severity),
+        }
+    }
+}
+
+/// Event class: the signature, else topic0, else the event name
+fn class_id(event: &EventData, notification: &Notification) -> String {
+    event
+        .event_signature
+        .clone()
+        .or_else(|| event.topics.first().cloned())
+        .unwrap_or_else(|| notification.event.clone())
+}
+
+/// `title: name=value ...`, as in the syslog messages
+fn summary(notification: &Notification) -> String {
+    let args = notification
+        .fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match args.is_empty() {
+        true => notification.title.clone(),
+        false => format!("{}: {}", notification.title, args),
+    }
+}
+
+/// Milliseconds since the epoch of an RFC 3339 timestamp
+fn millis(timestamp: &str) -> Option<String> {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .ok()
+        .map(|time| time.timestamp_millis().to_string())
+}
+
+/// `CEF:0|listener|listener|VERSION|CLASS|NAME|SEVERITY|key=value ...`
+fn cef(event: &EventData, notification: &Notification, severity: Option<Severity>) -> String {
+    let header = [
+        DEVICE.to_string(),
+        DEVICE.to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+        class_id(event, notification),
+        notification.event.clone(),
+        severity.unwrap_or(Severity::Info).siem_level().to_string(),
+    ];
+    let header: Vec<String> = header.iter().map(|field| cef_header(field)).collect();
+
+    let mut extension = vec![
+        ("rt", millis(&event.timestamp)),
+        ("cat", Some(event.chain_name.clone())),
+        ("act", Some(notification.event.clone())),
+        ("msg", Some(summary(notification))),
+        ("externalId", Some(format!("{}:{}", event.transaction_hash, event.log_index))),
+        ("suser", notification.tx_from.clone()),
+        ("duser", notification.tx_to.clone()),
+        ("request", notification.transaction_url.clone()),
+    ];
+    let custom = [
+        ("cs1", "contractAddress", Some(event.contract_address.to_lowercase())),
+        ("cs2", "contractName", notification.contract_name.clone()),
+        ("cs3", "eventSignature", event.event_signature.clone()),
+        ("cs4", "transactionHash", Some(event.transaction_hash.clone())),
+        ("cs5", "blockHash", event.block_hash.clone()),
+        ("cs6", "topic0", event.topics.first().cloned()),
+        ("cn1", "blockNumber", Some(event.block_number.to_string())),
+        ("cn2", "logIndex", Some(event.log_index.to_string())),
+        ("cn3", "chainId", event.chain_id.map(|id| id.to_string())),
+    ];
+    let mut labels = Vec::new();
+    for (key, label, value) in custom {
+        if value.is_some() {
+            labels.push((key, label));
+            extension.push((key, value));
+        }
+    }
+    if let Some(time) = event.block_timestamp.as_deref().and_then(millis) {
+        labels.push(("deviceCustomDate1", "blockTime"));
+        extension.push(("deviceCustomDate1", Some(time)));
+    }
+
+    let mut pairs: Vec<String> = extension
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{}={}", key, cef_value(&value))))
+        .collect();
+    pairs.extend(labels.into_iter().map(|(key, label)| format!("{}Label={}", key, label)));
+    format!("CEF:0|{}|{}", header.join("|"), pairs.join(" "))
+}
+
+/// `LEEF:2.0|listener|listener|VERSION|CLASS|x09|key=value<TAB>...`; the
+/// decoded arguments are added as `arg_<name>`
+fn leef(event: &EventData, notification: &Notification, severity: Option<Severity>) -> String {
+    let header = [
+        DEVICE.to_string(),
+        DEVICE.to_string(),
+        env!("CARGO_PKG_VERSION").to_string(),
+        class_id(event, notification),
+    ];
+    let header: Vec<String> = header.iter().map(|field| leef_header(field)).collect();
+
+    let mut attributes = vec![
+        ("devTime".to_string(), millis(&event.timestamp)),
+        ("sev".to_string(), Some(severity.unwrap_or(Severity::Info).siem_level().to_string())),
+        ("cat".to_string(), Some(event.chain_name.clone())),
+        ("usrName".to_string(), notification.tx_from.clone()),
+        ("resource".to_string(), Some(event.contract_address.to_lowercase())),
+        ("url".to_string(), notification.transaction_url.clone()),
+        ("msg".to_string(), Some(summary(notification))),
+        ("eventName".to_string(), Some(notification.event.clone())),
+        ("eventSignature".to_string(), event.event_signature.clone()),
+        ("topic0".to_string(), event.topics.first().cloned()),
+        ("severity".to_string(), severity.map(|severity| severity.as_str().to_string())),
+        ("chainId".to_string(), event.chain_id.map(|id| id.to_string())),
+        ("contractName".to_string(), notification.contract_name.clone()),
+        ("blockNumber".to_string(), Some(event.block_number.to_string())),
+        ("blockHash".to_string(), event.block_hash.clone()),
+        ("blockTime".to_string(), event.block_timestamp.clone()),
+        ("transactionHash".to_string(), Some(event.transaction_hash.clone())),
+        ("logIndex".to_string(), Some(event.log_index.to_string())),
+        ("txTo".to_string(), notification.tx_to.clone()),
+    ];
+    for (name, value) in event.decoded.iter().flatten() {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let name: String = name.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+        attributes.push((format!("arg_{}", name), Some(value)));
+    }
+
+    let attributes: Vec<String> = attributes
+        .into_iter()
+        .filter_map(|(key, value)| value.map(|value| format!("{}={}", key, leef_value(&value))))
+        .collect();
+    format!("LEEF:2.0|{}|x09|{}", header.join("|"), attributes.join("\t"))
+}
+
+/// A CEF header field: `\` and `|` escaped, line breaks as spaces
+fn cef_header(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+}
+
+/// A CEF extension value: `\` and `=` escaped, line breaks as `\n` and `\r`
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// A LEEF header field: `|` escaped, line breaks as spaces
+fn leef_header(field: &str) -> String {
+    field.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// A LEEF attribute value: tabs (the delimiter) and line breaks as spaces
+fn leef_value(value: &str) -> String {
+    value.replace(['\t', '\r', '\n'], " ")
+}
@@ -0,0 +1,276 @@
+use anyhow::{Context, Result};
+use ethers::prelude::*;
+
+use crate::event::compute_event_topic;
+
+/// Widely used event signatures, used to guess what an unknown topic0 is
+pub const KNOWN_EVENTS: &[&str] = &[
+    // ERC-20 / ERC-721
+    "Transfer(address,address,uint256)",
+    "Approval(address,address,uint256)",
+    "ApprovalForAll(address,address,bool)",
+    // ERC-1155
+    "TransferSingle(address,address,address,uint256,uint256)",
+    "TransferBatch(address,address,address,uint256[],uint256[])",
+    "URI(string,uint256)",
+    // WETH
+    "Deposit(address,uint256)",
+    "Withdrawal(address,uint256)",
+    // Ownable / AccessControl / Pausable / proxies
+    "OwnershipTransferred(address,address)",
+    "RoleGranted(bytes32,address,address)",
+    "RoleRevoked(bytes32,address,address)",
+    "RoleAdminChanged(bytes32,bytes32,bytes32)",
+    "Paused(address)",
+    "Unpaused(address)",
+    "Upgraded(address)",
+    "AdminChanged(address,address)",
+    "BeaconUpgraded(address)",
+    "Initialized(uint8)",
+    "Initialized(uint64)",
+    // Uniswap V2
+    "Swap(address,uint256,uint256,uint256,uint256,address)",
+    "Sync(uint112,uint112)",
+    "Mint(address,uint256,uint256)",
+    "Burn(address,uint256,uint256,address)",
+    "PairCreated(address,address,address,uint256)",
+    // Uniswap V3
+    "Swap(address,address,int256,int256,uint160,uint128,int24)",
+    "Mint(address,address,int24,int24,uint128,uint256,uint256)",
+    "Burn(address,int24,int24,uint128,uint256,uint256)",
+    "Collect(address,address,int24,int24,uint128,uint128)",
+    "PoolCreated(address,address,uint24,int24,address)",
+    "IncreaseLiquidity(uint256,uint128,uint256,uint256)",
+    "DecreaseLiquidity(uint256,uint128,uint256,uint256)",
+    // ERC-4626
+    "Deposit(address,address,uint256,uint256)",
+    "Withdraw(address,address,address,uint256,uint256)",
+    // Curve / Balancer
+    "TokenExchange(address,int128,uint256,int128,uint256)",
+    "TokenExchangeUnderlying(address,int128,uint256,int128,uint256)",
+    "Swap(bytes32,address,address,uint256,uint256)",
+    // Aave V3
+    "Supply(address,address,address,uint256,uint16)",
+    "Withdraw(address,address,address,uint256)",
+    "Borrow(address,address,address,uint256,uint8,uint256,uint16)",
+    "Repay(address,address,address,uint256,bool)",
+    "LiquidationCall(address,address,address,uint256,uint256,address,bool)",
+    // Compound
+    "Borrow(address,uint256,uint256,uint256)",
+    "RepayBorrow(address,address,uint256,uint256,uint256)",
+    "Redeem(address,uint256,uint256)",
+    // Chainlink
+    "AnswerUpdated(int256,uint256,uint256)",
+    "NewRound(uint256,address,uint256)",
+    // Safe
+    "ExecutionSuccess(bytes32,uint256)",
+    "ExecutionFailure(bytes32,uint256)",
+    "AddedOwner(address)",
+    "RemovedOwner(address)",
+    "ChangedThreshold(uint256)",
+    "SafeReceived(address,uint256)",
+    // ERC-4337
+    "UserOperationEvent(bytes32,address,address,uint256,bool,uint256,uint256)",
+    // Governance / timelocks
+    "ProposalCreated(uint256,address,address[],uint256[],string[],bytes[],uint256,uint256,string)",
+    "VoteCast(address,uint256,uint8,uint256,string)",
+    "ProposalExecuted(uint256)",
+    "CallScheduled(bytes32,uint256,address,uint256,bytes,bytes32,uint256)",
+    "CallExecuted(bytes32,uint256,address,uint256,bytes)",
+];
+
+/// The known signature whose topic hash is `topic0`, if any
+pub fn guess_signature(topic0: &H256) -> Option<&'static str> {
+    KNOWN_EVENTS
+        .iter()
+        .copied()
+        .find(|signature| compute_event_topic(signature) == *topic0)
+}
+
+/// Keywords that may follow the parameter list of a Solidity declaration
+const TRAILING_KEYWORDS: &[&str] = &[
+    "anonymous", "external", "public", "internal", "private", "view", "pure", "payable", "nonpayable", "virtual",
+    "override", "returns",
+];
+
+/// Words that may follow a parameter's type, besides its name
+const PARAM_KEYWORDS: &[&str] = &["indexed", "memory", "calldata", "storage", "payable"];
+
+/// The canonical form of an event or function signature, as hashed for
+/// topic0 and selectors: `event`/`function` keywords, parameter names,
+/// `indexed` and data locations, and whitespace removed, and type aliases
+/// expanded (`uint` → `uint256`, `int` → `int256`, `byte` → `bytes1`).
+/// Tuples may be written as `(uint a, address b)` or `tuple(...)`.
+///
+/// `event Transfer(address indexed from, address indexed to, uint value)`
+/// becomes `Transfer(address,address,uint256)`.
+pub fn normalize_signature(signature: &str) -> Result<String> {
+    let input = signature.trim().trim_end_matches(';').trim();
+    let input = ["event ", "function "]
+        .iter()
+        .find_map(|keyword| input.strip_prefix(keyword))
+        .unwrap_or(input)
+        .trim();
+
+    let open = input
+        .find('(')
+        .with_context(|| format!("Invalid signature {}: expected Name(type,...)", signature))?;
+    let name = input[..open].trim();
+    let valid_name = name
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c == '$' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit()));
+    if name.is_empty() || !valid_name {
+        anyhow::bail!("Invalid signature {}: {:?} isn't a valid name", signature, name);
+    }
+
+    let close = matching_paren(input, open)
+        .with_context(|| format!("Invalid signature {}: unbalanced parentheses", signature))?;
+    let params = normalize_params(&input[open + 1..close])
+        .map_err(|e| anyhow::anyhow!("Invalid signature {}: {}", signature, e))?;
+
+    let rest = input[close + 1..].trim();
+    let first_word = rest.split(|c: char| c.is_whitespace() || c == '(').next().unwrap_or_default();
+    if !rest.is_empty() && !TRAILING_KEYWORDS.contains(&first_word) {
+        anyhow::bail!("Invalid signature {}: unexpected {:?} after the parameters", signature, rest);
+    }
+
+    Ok(format!("{}({})", name, params))
+}
+
+/// 4-byte selector of a function signature, e.g. `0xa9059cbb` for
+/// `transfer(address,uint256)`
+pub fn compute_selector(signature: &str) -> [u8; 4] {
+    let hash = ethers::utils::keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Index of the `)` closing the `(` at `open`
+fn matching_paren(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices().skip_while(|(i, _)| *i < open) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Canonical, comma-separated types of a parameter list
+fn normalize_params(params: &str) -> std::result::Result<String, String> {
+    if params.trim().is_empty() {
+        return Ok(String::new());
+    }
+
+    // Split on the commas outside of tuples
+    let mut types = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(normalize_param(&params[start..i])?);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    types.push(normalize_param(&params[start..])?);
+    Ok(types.join(","))
+}
+
+/// Canonical type of one parameter, without its name and keywords
+fn normalize_param(param: &str) -> std::result::Result<String, String> {
+    let param = param.trim();
+    if param.is_empty() {
+        return Err("empty parameter".to_string());
+    }
+
+    // A tuple: its components, then any array suffixes, then the name
+    let tuple = match param.strip_prefix("tuple") {
+        Some(rest) if rest.trim_start().starts_with('(') => Some(rest.trim_start()),
+        _ if param.starts_with('(') => Some(param),
+        _ => None,
+    };
+    let (base, rest) = match tuple {
+        Some(tuple) => {
+            let close = matching_paren(tuple, 0).ok_or("unbalanced parentheses")?;
+            (format!("({})", normalize_params(&tuple[1..close])?), &tuple[close + 1..])
+        }
+        None => {
+            let end = param
+                .find(|c: char| c.is_whitespace() || c == '[')
+                .unwrap_or(param.len());
+            (normalize_type(&param[..end])?, &param[end..])
+        }
+    };
+
+    // Array suffixes, possibly spaced out, e.g. `uint [ 3 ]`
+    let mut suffix = String::new();
+    let mut rest = rest.trim_start();
+    while let Some(after) = rest.strip_prefix('[') {
+        let close = after.find(']').ok_or("unclosed [")?;
+        let size = after[..close].trim();
+        if !size.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("invalid array size {:?}", size));
+        }
+        suffix.push_str(&format!("[{}]", size));
+        rest = after[close + 1..].trim_start();
+    }
+
+    // What's left is keywords and at most a name
+    let words: Vec<&str> = rest.split_whitespace().filter(|word| !PARAM_KEYWORDS.contains(word)).collect();
+    if words.len() > 1 {
+        return Err(format!("unexpected {:?} in parameter {:?}", words.join(" "), param));
+    }
+
+    Ok(format!("{}{}", base, suffix))
+}
+
+/// Canonical name of an elementary type
+fn normalize_type(kind: &str) -> std::result::Result<String, String> {
+    let canonical = match kind {
+        "uint" => "uint256".to_string(),
+        "int" => "int256".to_string(),
+        "byte" => "bytes1".to_string(),
+        "fixed" => "fixed128x18".to_string(),
+        "ufixed" => "ufixed128x18".to_string(),
+        other => other.to_string(),
+    };
+
+    let sized = |prefix: &str, valid: fn(u32) -> bool| {
+        canonical
+            .strip_prefix(prefix)
+            .and_then(|size| size.parse::<u32>().ok())
+            .is_some_and(valid)
+    };
+    let valid = matches!(canonical.as_str(), "address" | "bool" | "string" | "bytes" | "function")
+        || sized("uint", |bits| bits % 8 == 0 && (8..=256).contains(&bits))
+        || sized("int", |bits| bits % 8 == 0 && (8..=256).contains(&bits))
+        || sized("bytes", |size| (1..=32).contains(&size))
+        || is_fixed(canonical.strip_prefix("ufixed").or(canonical.strip_prefix("fixed")));
+    if !valid {
+        return Err(format!("unknown type {:?}", kind));
+    }
+    Ok(canonical)
+}
+
+/// Whether `size` is the `MxN` of a fixed-point type: 8 to 256 bits in
+/// steps of 8, with up to 80 decimals
+fn is_fixed(size: Option<&str>) -> bool {
+    let Some((bits, decimals)) = size.and_then(|size| size.split_once('x')) else {
+        return false;
+    };
+    match (bits.parse::<u32>(), decimals.parse::<u32>()) {
+        (Ok(bits), Ok(decimals)) => bits % 8 == 0 && (8..=256).contains(&bits) && decimals <= 80,
+        _ => false,
+    }
+}
@@ -0,0 +1,47 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::path::Path;
+
+use super::Sink;
+use crate::archive::ArchiveWriter;
+use crate::event::EventData;
+
+/// Events per compressed frame; smaller frames are written on every flush
+const FRAME_EVENTS: usize = 1000;
+
+/// File sink in archival mode: buffers events into zstd frames of an
+/// [`archive`](crate::archive) directory
+pub struct ArchiveSink {
+    writer: ArchiveWriter,
+    buffer: Vec<EventData>,
+}
+
+impl ArchiveSink {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            writer: ArchiveWriter::open(dir)?,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for ArchiveSink {
+    fn name(&self) -> &str {
+        "Archive"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        self.buffer.push(event.clone());
+        if self.buffer.len() >= FRAME_EVENTS {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.writer.write_frame(&self.buffer)?;
+        self.buffer.clear();
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::Sink;
+use crate::config::ChatConfig;
+use crate::event::EventData;
+use crate::notification::{truncate, Notification};
+use crate::render::EventTemplate;
+
+/// Events per message: Discord allows 10 embeds, and 10 events keep a Slack
+/// message within its 50 blocks
+const BATCH_SIZE: usize = 10;
+
+/// Events waiting to be posted; the oldest are dropped beyond this
+const MAX_PENDING: usize = 1_000;
+
+/// Longest message text Discord accepts
+const DISCORD_MAX_CONTENT: usize = 2000;
+
+/// Longest message text posted to Slack
+const SLACK_MAX_TEXT: usize = 40_000;
+
+/// Wait after a 429 without a usable `Retry-After`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Slack,
+    Discord,
+}
+
+/// Posts events as formatted messages to a Slack or Discord incoming
+/// webhook: the event name, decoded arguments, and explorer links.
+///
+/// Events are posted from [`tick`](Sink::tick), up to [`BATCH_SIZE`] per
+/// message and one message per tick, which stays within the webhooks' rate
+/// limits; a 429 pauses posting for as long as the response asks. With a
+/// template, each event is rendered with it and the texts are posted as
+/// the message instead of the built-in layout. Messages
+/// that fail for other reasons than a rejected request are posted again
+/// later. Chat channels aren't meant for high volumes: beyond
+/// [`MAX_PENDING`] waiting events the oldest are dropped.
+pub struct ChatSink {
+    platform: Platform,
+    client: reqwest::Client,
+    url: String,
+    template: Option<EventTemplate>,
+    pending: VecDeque<EventData>,
+    /// Set while rate-limited
+    paused_until: Option<Instant>,
+}
+
+impl ChatSink {
+    pub fn connect(platform: Platform, config: &ChatConfig) -> Result<Self> {
+        reqwest::Url::parse(&config.webhook_url)
+            .with_context(|| format!("Invalid {:?} webhook URL", platform))?;
+        Ok(Self {
+            platform,
+            client: crate::tls::configure(reqwest::Client::builder().timeout(TIMEOUT), config.tls.as_ref())
+                .with_context(|| format!("Invalid {:?} TLS settings", platform))?
+                .build()?,
+            url: config.webhook_url.clone(),
+            template: config.template_file.as_deref().map(EventTemplate::load).transpose()?,
+            pending: VecDeque::new(),
+            paused_until: None,
+        })
+    }
+
+    /// Post the oldest pending events as one message. Rejected messages are
+    /// dropped; others stay pending.
+    async fn post_batch(&mut self) -> Result<()> {
+        if self.paused_until.is_some_and(|until| Instant::now() < until) {
+            return Ok(());
+        }
+        self.paused_until = None;
+
+        let notifications: Vec<Notification> = self
+            .pending
+            .iter()
+            .take(BATCH_SIZE)
+            .map(Notification::from_event)
+            .collect();
+        let (payload, len) = match (self.platform, &self.template) {
+            (platform, Some(template)) => {
+                let texts: Vec<String> = self
+                    .pending
+                    .iter()
+                    .zip(&notifications)
+                    .map(|(event, notification)| {
+                        template.render(event).unwrap_or_else(|e| {
+                            warn!("{:#}; posting the event's title", e);
+                            notification.title.clone()
+                        })
+                    })
+                    .collect();
+                text_message(platform, &texts)
+            }
+            (Platform::Slack, None) => (slack_message(&notifications), notifications.len()),
+            (Platform::Discord, None) => discord_message(&notifications),
+        };
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&payload)
+            .send()
+            .await
+            .context("Failed to post message")?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<f64>().ok())
+                .map(Duration::from_secs_f64)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.paused_until = Some(Instant::now() + retry_after);
+            anyhow::bail!("rate limit hit, pausing for {:?}", retry_after);
+        }
+        if status.is_client_error() {
+            self.pending.drain(..len);
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("message of {} event(s) rejected: {} {}", len, status, body.trim());
+        }
+        if !status.is_success() {
+            anyhow::bail!("webhook returned {}, will retry", status);
+        }
+
+        self.pending.drain(..len);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Sink for ChatSink {
+    fn name(&self) -> &str {
+        match self.platform {
+            Platform::Slack => "Slack",
+            Platform::Discord => "Discord",
+        }
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        self.pending.push_back(event.clone());
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            anyhow::bail!("too many events waiting to be posted, dropped {} oldest", dropped);
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            if self.paused_until.is_some_and(|until| Instant::now() < until) {
+                anyhow::bail!("{} event(s) still waiting for the rate limit", self.pending.len());
+            }
+            self.post_batch().await?;
+        }
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.post_batch().await
+    }
+}
+
+/// A plain message of the rendered `texts`, one paragraph each, and how
+/// many of them it holds; as many as fit into the platform's limit, and at
+/// least one, truncated
+fn text_message(platform: Platform, texts: &[String]) -> (Value, usize) {
+    let max = match platform {
+        Platform::Slack => SLACK_MAX_TEXT,
+        Platform::Discord => DISCORD_MAX_CONTENT,
+    };
+    let mut text = String::new();
+    let mut len = 0;
+    for rendered in texts {
+        let size = text.chars().count() + 2 + rendered.chars().count();
+        if len > 0 && size > max {
+            break;
+        }
+        if len > 0 {
+            text.push_str("\n\n");
+        }
+        text.push_str(rendered);
+        len += 1;
+    }
+    let text = truncate(&text, max);
+    match platform {
+        Platform::Slack => (json!({ "text": text }), len),
+        Platform::Discord => (json!({ "content": text }), len),
+    }
+}
+
+/// A Slack message with a section and a context line of links per event
+fn slack_message(notifications: &[Notification]) -> Value {
+    let mut blocks = Vec::new();
+    for (i, notification) in notifications.iter().enumerate() {
+        if i > 0 {
+            blocks.push(json!({ "type": "divider" }));
+        }
+        let mut text = format!("*{}*", slack_escape(&notification.title));
+        for (name, value) in &notification.fields {
+            text.push_str(&format!("\n*{}*: `{}`", slack_escape(name), slack_escape(value)));
+        }
+        blocks.push(json!({
+            "type": "section",
+            // Section text is limited to 3000 characters
+            "text": { "type": "mrkdwn", "text": truncate(&text, 3000) },
+        }));
+
+        let link = |label: String, url: &Option<String>| match url {
+            Some(url) => format!("<{}|{}>", url, label),
+            None => label,
+        };
+        let context = [
+            link(format!("Tx {}", short(&notification.transaction_hash)), &notification.transaction_url),
+            link(format!("Block {}", notification.block_number), &notification.block_url),
+            link(
+                format!("Contract {}", notification.contract_name.as_deref().unwrap_or(short(&notification.contract))),
+                &notification.contract_url,
+            ),
+        ]
+        .join(" · ");
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": context }],
+        }));
+    }
+
+    // Shown in notifications and clients that don't render blocks
+    let text = match notifications {
+        [single] => single.title.clone(),
+        _ => format!("{} events", notifications.len()),
+    };
+    json!({ "text": text, "blocks": blocks })
+}
+
+/// A Discord message with an embed per event, and how many of
+/// `notifications` it holds. Discord limits the text of all embeds of a
+/// message to 6000 characters, so later events may be left for the next one.
+fn discord_message(notifications: &[Notification]) -> (Value, usize) {
+    let mut embeds = Vec::new();
+    let mut total = 0;
+    for notification in notifications {
+        let title = truncate(&notification.title, 256);
+        let footer = format!(
+            "Block {} · Log {} · Tx {}",
+            notification.block_number, notification.log_index, notification.transaction_hash
+        );
+        let mut contract = match notification.contract_url {
+            Some(ref url) => format!("[{}]({})", notification.contract, url),
+            None => notification.contract.clone(),
+        };
+        if let Some(ref name) = notification.contract_name {
+            contract = format!("{} ({})", contract, name);
+        }
+        let mut size = title.chars().count() + footer.chars().count() + "Contract".len() + contract.chars().count();
+
+        // Embeds take at most 25 fields; values are kept short so that a
+        // few events fit into one message
+        let mut fields = Vec::new();
+        for (name, value) in notification.fields.iter().take(24) {
+            let name = truncate(name, 256);
+            let value = format!("`{}`", truncate(value, 300));
+            size += name.chars().count() + value.chars().count();
+            fields.push(json!({ "name": name, "value": value, "inline": false }));
+        }
+        fields.push(json!({ "name": "Contract", "value": contract, "inline": false }));
+
+        if !embeds.is_empty() && total + size > 6000 {
+            break;
+        }
+        total += size;
+
+        let mut embed = json!({
+            "title": title,
+            "fields": fields,
+            "footer": { "text": footer },
+            "timestamp": notification.timestamp,
+        });
+        if let Some(ref url) = notification.transaction_url {
+            embed["url"] = json!(url);
+        }
+        embeds.push(embed);
+    }
+    let count = embeds.len();
+    (json!({ "embeds": embeds }), count)
+}
+
+/// Escape the characters Slack treats as markup
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+fn short(hex: &str) -> &str {
+    hex.get(..10).unwrap_or(hex)
+}
@@ -0,0 +1,177 @@
+//! Typed tables of the database sinks (`[[sinks.tables]]`): the decoded
+//! arguments of one event stored in columns of their own, next to the
+//! generic events table, so they can be indexed and compared in SQL.
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::config::TableConfig;
+use crate::event::EventData;
+use crate::signatures::normalize_signature;
+
+/// Columns of every typed table before the mapped arguments, besides the
+/// `id` key; arguments can't be mapped to these
+pub const EVENT_COLUMNS: [&str; 7] = [
+    "chain_id",
+    "block_number",
+    "transaction_hash",
+    "log_index",
+    "contract_address",
+    "detected_at",
+    "run_id",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    /// Integers of any size, exactly
+    Numeric,
+    Text,
+    /// Addresses, hashes, and byte strings, from their hex
+    Bytea,
+}
+
+/// A decoded argument converted for its column
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnValue {
+    /// Decimal digits with an optional sign
+    Numeric(String),
+    Text(String),
+    Bytea(Vec<u8>),
+    /// The argument is missing or can't be converted
+    Null,
+}
+
+/// The typed table of one event
+#[derive(Debug, Clone)]
+pub struct TypedTable {
+    /// Event name, or a normalized signature when the config names one
+    event: String,
+    pub table: String,
+    /// Each mapped argument, which is also its column's name, and its type
+    pub columns: Vec<(String, ColumnType)>,
+}
+
+impl TypedTable {
+    /// Whether `event` is a decoded event this table is for
+    pub fn matches(&self, event: &EventData) -> bool {
+        if event.decoded.is_none() {
+            return false;
+        }
+        let signature = event.event_signature.as_deref().or(event.guessed_signature.as_deref());
+        if self.event.contains('(') {
+            return signature == Some(self.event.as_str());
+        }
+        event.event_name.as_deref() == Some(self.event.as_str())
+            || signature.is_some_and(|signature| signature.split('(').next() == Some(self.event.as_str()))
+    }
+
+    /// The value of each column for `event`, in the order of [`columns`](Self::columns)
+    pub fn values(&self, event: &EventData) -> Vec<ColumnValue> {
+        self.columns
+            .iter()
+            .map(|(name, kind)| {
+                event
+                    .decoded
+                    .as_ref()
+                    .and_then(|decoded| decoded.get(name))
+                    .map_or(ColumnValue::Null, |value| convert(value, *kind))
+            })
+            .collect()
+    }
+}
+
+/// The typed tables configured, checked and in order
+pub fn typed_tables(tables: &[TableConfig]) -> Result<Vec<TypedTable>> {
+    let mut seen = BTreeMap::new();
+    tables
+        .iter()
+        .map(|config| {
+            if !is_valid_table_name(&config.table) {
+                anyhow::bail!("Invalid table name: {}", config.table);
+            }
+            if let Some(other) = seen.insert(config.table.clone(), config.event.clone()) {
+                anyhow::bail!("Table {} is configured for both {} and {}", config.table, other, config.event);
+            }
+            let event = if config.event.contains('(') {
+                normalize_signature(&config.event)
+                    .with_context(|| format!("Invalid event of table {}: {}", config.table, config.event))?
+            } else {
+                config.event.trim().to_string()
+            };
+            if config.columns.is_empty() {
+                anyhow::bail!("Table {} maps no columns", config.table);
+            }
+            let columns = config
+                .columns
+                .iter()
+                .map(|(name, kind)| {
+                    if !is_identifier(name) {
+                        anyhow::bail!("Invalid column name in table {}: {}", config.table, name);
+                    }
+                    let lowercase = name.to_lowercase();
+                    if lowercase == "id" || EVENT_COLUMNS.contains(&lowercase.as_str()) {
+                        anyhow::bail!("Column {} of table {} is reserved", name, config.table);
+                    }
+                    let kind = match kind.to_lowercase().as_str() {
+                        "numeric" => ColumnType::Numeric,
+                        "text" => ColumnType::Text,
+                        "bytea" => ColumnType::Bytea,
+                        _ => anyhow::bail!(
+                            "Unknown type of column {} in table {}: {} (expected numeric, text, or bytea)",
+                            name,
+                            config.table,
+                            kind
+                        ),
+                    };
+                    Ok((name.clone(), kind))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(TypedTable {
+                event,
+                table: config.table.clone(),
+                columns,
+            })
+        })
+        .collect()
+}
+
+/// `value` for a column of type `kind`. Integers arrive as decimal strings,
+/// addresses and bytes as hex; anything else becomes a NULL numeric or
+/// bytea, or its JSON as text.
+fn convert(value: &Value, kind: ColumnType) -> ColumnValue {
+    match (kind, value) {
+        (ColumnType::Numeric, Value::String(s)) if is_integer(s) => ColumnValue::Numeric(s.clone()),
+        (ColumnType::Numeric, Value::Number(n)) if n.is_i64() || n.is_u64() => ColumnValue::Numeric(n.to_string()),
+        (ColumnType::Numeric, Value::Bool(b)) => ColumnValue::Numeric(u8::from(*b).to_string()),
+        (ColumnType::Text, Value::String(s)) => ColumnValue::Text(s.clone()),
+        (ColumnType::Text, Value::Null) => ColumnValue::Null,
+        (ColumnType::Text, value) => ColumnValue::Text(value.to_string()),
+        (ColumnType::Bytea, Value::String(s)) => match s.strip_prefix("0x").map(hex::decode) {
+            Some(Ok(bytes)) => ColumnValue::Bytea(bytes),
+            _ => ColumnValue::Null,
+        },
+        _ => ColumnValue::Null,
+    }
+}
+
+fn is_integer(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `name` or `schema.name`, each part a plain SQL identifier
+pub(crate) fn is_valid_table_name(table: &str) -> bool {
+    let parts: Vec<&str> = table.split('.').collect();
+    parts.len() <= 2 && parts.iter().all(|part| is_identifier(part))
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `name` quoted, so arguments named like keywords (`from`, `to`) work
+pub fn quote(name: &str) -> String {
+    format!("\"{}\"", name)
+}
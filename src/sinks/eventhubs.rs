@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use super::Sink;
+use crate::config::EventHubsConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+
+/// Partition key of every event unless configured otherwise
+pub const DEFAULT_PARTITION_KEY: &str = "{contract}";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Events per send; a batch must stay under the hub's 1 MB (Basic: 256 KB)
+/// message size, so large ones are cut further by size
+const MAX_BATCH: usize = 500;
+
+const MAX_BATCH_BYTES: usize = 200 * 1024;
+
+/// Events kept while sending fails; the oldest are dropped beyond this
+const MAX_BUFFERED: usize = 100_000;
+
+/// Lifetime of the shared access signatures; renewed when half of it is left
+const TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// Sends every event as JSON to an Azure Event Hub through its HTTPS API,
+/// with a partition key rendered from a template, so events with the same
+/// key land on the same partition in order.
+///
+/// Events are sent in batches on every tick, one partition key per batch.
+/// Events of a batch that fails stay buffered, ahead of later events with
+/// the same key, and are sent again on the next flush.
+pub struct EventHubsSink {
+    client: reqwest::Client,
+    /// `https://<namespace>.servicebus.windows.net/<hub>`
+    hub_url: String,
+    key_name: String,
+    key: String,
+    /// The shared access signature and when it expires (Unix seconds)
+    token: Option<(String, u64)>,
+    partition_key: String,
+    /// Events not sent yet, oldest first
+    buffer: Vec<Message>,
+}
+
+#[derive(Serialize)]
+struct Message {
+    #[serde(rename = "Body")]
+    body: String,
+    #[serde(rename = "BrokerProperties")]
+    properties: BrokerProperties,
+}
+
+#[derive(Serialize)]
+struct BrokerProperties {
+    #[serde(rename = "PartitionKey")]
+    partition_key: String,
+}
+
+impl EventHubsSink {
+    /// Parse the connection string of the namespace or hub; the hub name
+    /// comes from `name`, else the connection string's `EntityPath`
+    pub fn connect(config: &EventHubsConfig) -> Result<Self> {
+        let connection_string = config
+            .connection_string
+            .clone()
+            .or_else(|| std::env::var("EVENTHUBS_CONNECTION_STRING").ok())
+            .context("An Event Hubs connection string is required (connection_string or EVENTHUBS_CONNECTION_STRING)")?;
+        let field = |name: &str| {
+            connection_string.split(';').find_map(|part| {
+                let (key, value) = part.split_once('=')?;
+                key.trim().eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+            })
+        };
+        let endpoint = field("Endpoint").context("No Endpoint in the Event Hubs connection string")?;
+        let host = endpoint
+            .trim_start_matches("sb://")
+            .trim_start_matches("https://")
+            .trim_end_matches('/')
+            .to_string();
+        let key_name = field("SharedAccessKeyName").context("No SharedAccessKeyName in the Event Hubs connection string")?;
+        let key = field("SharedAccessKey").context("No SharedAccessKey in the Event Hubs connection string")?;
+        let hub = config
+            .name
+            .clone()
+            .or_else(|| field("EntityPath"))
+            .context("An event hub name is required (--eventhubs-name or EntityPath in the connection string)")?;
+
+        Ok(Self {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            hub_url: format!("https://{}/{}", host, hub),
+            key_name,
+            key,
+            token: None,
+            partition_key: config
+                .partition_key
+                .clone()
+                .unwrap_or_else(|| DEFAULT_PARTITION_KEY.to_string()),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// A shared access signature for the hub, renewed when half expired
+    fn token(&mut self) -> Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if let Some((ref token, expiry)) = self.token {
+            if expiry > now + TOKEN_LIFETIME.as_secs() / 2 {
+                return Ok(token.clone());
+            }
+        }
+        let expiry = now + TOKEN_LIFETIME.as_secs();
+        let resource = encode(&self.hub_url);
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.key.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+        let signature = STANDARD.encode(mac.finalize().into_bytes());
+        let token = format!(
+            "SharedAccessSignature sr={}&sig={}&se={}&skn={}",
+            resource,
+            encode(&signature),
+            expiry,
+            encode(&self.key_name)
+        );
+        self.token = Some((token.clone(), expiry));
+        Ok(token)
+    }
+
+    /// Send `messages`, which share a partition key, in one request
+    async fn send_batch(&mut self, messages: &[Message]) -> Result<()> {
+        let token = self.token()?;
+        let response = self
+            .client
+            .post(format!("{}/messages?timeout=60&api-version=2014-01", self.hub_url))
+            .header("Authorization", token)
+            .header("Content-Type", "application/vnd.microsoft.servicebus.json")
+            .header("BrokerProperties", serde_json::to_string(&messages[0].properties)?)
+            .json(messages)
+            .send()
+            .await
+            .context("Event Hubs send failed, will retry")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            anyhow::bail!("Event Hubs answered {}, will retry: {}", status, body.trim());
+        }
+        Ok(())
+    }
+}
+
+/// `value` percent-encoded for a shared access signature
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Sink for EventHubsSink {
+    fn name(&self) -> &str {
+        "Event Hubs"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let partition_key = Notification::from_event(event).render(&self.partition_key, |value| value.to_string());
+        self.buffer.push(Message {
+            body: serde_json::to_string(event)?,
+            properties: BrokerProperties { partition_key },
+        });
+        if self.buffer.len() > MAX_BUFFERED {
+            self.buffer.remove(0);
+            warn!("Event Hubs buffer full, dropped the oldest event");
+        }
+
+        if self.buffer.len() >= MAX_BATCH {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Send the buffered events grouped by partition key, each key's in
+    /// order. On failure the events not sent yet are kept.
+    async fn flush(&mut self) -> Result<()> {
+        let mut groups: Vec<Vec<Message>> = Vec::new();
+        for message in std::mem::take(&mut self.buffer) {
+            let key = &message.properties.partition_key;
+            match groups.iter_mut().find(|group| &group[0].properties.partition_key == key) {
+                Some(group) => group.push(message),
+                None => groups.push(vec![message]),
+            }
+        }
+
+        let mut groups = groups.into_iter();
+        while let Some(mut group) = groups.next() {
+            while !group.is_empty() {
+                let mut count = 0;
+                let mut bytes = 0;
+                for message in &group {
+                    let size = message.body.len() + message.properties.partition_key.len() + 64;
+                    if count == MAX_BATCH || (count > 0 && bytes + size > MAX_BATCH_BYTES) {
+                        break;
+                    }
+                    count += 1;
+                    bytes += size;
+                }
+                if let Err(e) = self.send_batch(&group[..count]).await {
+                    self.buffer = group.into_iter().chain(groups.flatten()).collect();
+                    return Err(e);
+                }
+                group.drain(..count);
+            }
+        }
+        Ok(())
+    }
+}
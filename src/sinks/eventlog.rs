@@ -0,0 +1,230 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::ptr;
+use windows_sys::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS, HANDLE};
+use windows_sys::Win32::System::EventLog::{
+    DeregisterEventSource, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE, EVENTLOG_INFORMATION_TYPE,
+    EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+};
+use windows_sys::Win32::System::Registry::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteTreeW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_WRITE, REG_DWORD,
+    REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+};
+
+use super::incident::{Severities, Severity};
+use super::Sink;
+use crate::config::EventLogConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+
+pub const DEFAULT_SOURCE: &str = "listener";
+
+/// Message file of registered sources: the .NET Framework's, whose messages
+/// are just the event's text, for every event ID
+pub const DEFAULT_MESSAGE_FILE: &str = r"%SystemRoot%\Microsoft.NET\Framework64\v4.0.30319\EventLogMessages.dll";
+
+/// Registry key of the Application log's sources
+const SOURCES_KEY: &str = r"SYSTEM\CurrentControlSet\Services\EventLog\Application";
+
+/// Longest string, in UTF-16 units, an event log entry holds
+const MAX_MESSAGE_LEN: usize = 31839;
+
+/// Writes every event to the Application log of the Windows event log, as
+/// entries of a registered source whose text lists the event's fields
+/// (`Event`, `Contract address`, `Transaction hash`, `Arg from`, ...).
+///
+/// The entry type and event ID follow the severity configured for the
+/// event, as for the incident sinks: `critical` is an error with ID 4,
+/// `error` an error with ID 3, `warning` a warning with ID 2, and `info` and
+/// events without a severity information with ID 1.
+pub struct EventLogSink {
+    source: EventSource,
+    severities: Severities,
+}
+
+/// Handle of a registered event source
+struct EventSource(HANDLE);
+
+// The handle can be used from any thread
+unsafe impl Send for EventSource {}
+
+impl Drop for EventSource {
+    fn drop(&mut self) {
+        unsafe {
+            DeregisterEventSource(self.0);
+        }
+    }
+}
+
+impl EventLogSink {
+    pub fn connect(config: &EventLogConfig) -> Result<Self> {
+        let name = source_name(config.source.as_deref())?;
+        let handle = unsafe { RegisterEventSourceW(ptr::null(), wide(name).as_ptr()) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("Failed to open the event source {}", name));
+        }
+        Ok(Self {
+            source: EventSource(handle),
+            severities: Severities::parse(&config.events)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for EventLogSink {
+    fn name(&self) -> &str {
+        "eventlog"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let notification = Notification::from_event(event);
+        let severity = self.severities.get(event, &notification.event);
+        let (kind, id) = entry_type(severity);
+
+        let mut text: Vec<u16> = message(event, &notification, severity).encode_utf16().take(MAX_MESSAGE_LEN).collect();
+        text.push(0);
+        let strings = [text.as_ptr()];
+        let reported = unsafe {
+            ReportEventW(
+                self.source.0,
+                kind,
+                0,
+                id,
+                ptr::null_mut(),
+                strings.len() as u16,
+                0,
+                strings.as_ptr(),
+                ptr::null(),
+            )
+        };
+        if reported == 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to write to the event log");
+        }
+        Ok(())
+    }
+}
+
+/// Register `source` in the Application log with `message_file`; needs an
+/// elevated prompt
+pub fn register(source: Option<&str>, message_file: Option<&str>) -> Result<()> {
+    let name = source_name(source)?;
+    let message_file = message_file.unwrap_or(DEFAULT_MESSAGE_FILE);
+    let path = format!(r"{}\{}", SOURCES_KEY, name);
+    let mut key: HKEY = ptr::null_mut();
+    let status = unsafe {
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            wide(&path).as_ptr(),
+            0,
+            ptr::null(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            ptr::null(),
+            &mut key,
+            ptr::null_mut(),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(status as i32))
+            .with_context(|| format!("Failed to create HKLM\\{} (is the prompt elevated?)", path));
+    }
+
+    let file: Vec<u8> = wide(message_file).iter().flat_map(|unit| unit.to_le_bytes()).collect();
+    let types = (EVENTLOG_ERROR_TYPE | EVENTLOG_WARNING_TYPE | EVENTLOG_INFORMATION_TYPE) as u32;
+    let result = set_value(key, "EventMessageFile", REG_EXPAND_SZ, &file)
+        .and_then(|_| set_value(key, "TypesSupported", REG_DWORD, &types.to_le_bytes()));
+    unsafe {
+        RegCloseKey(key);
+    }
+    result.with_context(|| format!("Failed to register the event source {}", name))
+}
+
+/// Remove the registration of `source`; returns whether it was registered
+pub fn unregister(source: Option<&str>) -> Result<bool> {
+    let name = source_name(source)?;
+    let path = format!(r"{}\{}", SOURCES_KEY, name);
+    match unsafe { RegDeleteTreeW(HKEY_LOCAL_MACHINE, wide(&path).as_ptr()) } {
+        ERROR_SUCCESS => Ok(true),
+        ERROR_FILE_NOT_FOUND => Ok(false),
+        status => Err(std::io::Error::from_raw_os_error(status as i32))
+            .with_context(|| format!("Failed to delete HKLM\\{} (is the prompt elevated?)", path)),
+    }
+}
+
+fn set_value(key: HKEY, name: &str, kind: u32, data: &[u8]) -> Result<()> {
+    let status = unsafe { RegSetValueExW(key, wide(name).as_ptr(), 0, kind, data.as_ptr(), data.len() as u32) };
+    if status != ERROR_SUCCESS {
+        return Err(std::io::Error::from_raw_os_error(status as i32)).with_context(|| format!("Failed to set {}", name));
+    }
+    Ok(())
+}
+
+fn source_name(source: Option<&str>) -> Result<&str> {
+    let name = source.unwrap_or(DEFAULT_SOURCE).trim();
+    if name.is_empty() || name.contains('\\') {
+        anyhow::bail!("Invalid event source: {:?}", name);
+    }
+    Ok(name)
+}
+
+/// `s` as a NUL-terminated UTF-16 string
+fn wide(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(std::iter::once(0)).collect()
+}
+
+/// Entry type and event ID of `severity`
+fn entry_type(severity: Option<Severity>) -> (REPORT_EVENT_TYPE, u32) {
+    match severity {
+        Some(Severity::Critical) => (EVENTLOG_ERROR_TYPE, 4),
+        Some(Severity::Error) => (EVENTLOG_ERROR_TYPE, 3),
+        Some(Severity::Warning) => (EVENTLOG_WARNING_TYPE, 2),
+        Some(Severity::Info) | None => (EVENTLOG_INFORMATION_TYPE, 1),
+    }
+}
+
+/// Text of the entry: the title, then one `Name: value` line per field
+fn message(event: &EventData, notification: &Notification, severity: Option<Severity>) -> String {
+    let mut lines = vec![
+        notification.title.clone(),
+        String::new(),
+        format!("Event: {}", notification.event),
+        format!("Chain: {}", event.chain_name),
+        format!("Contract address: {}", event.contract_address.to_lowercase()),
+        format!("Block number: {}", event.block_number),
+        format!("Transaction hash: {}", event.transaction_hash),
+        format!("Log index: {}", event.log_index),
+        format!("Detected at: {}", event.timestamp),
+    ];
+    let optional = [
+        ("Event signature", event.event_signature.clone()),
+        ("Severity", severity.map(|severity| severity.as_str().to_string())),
+        ("Chain ID", event.chain_id.map(|id| id.to_string())),
+        ("Contract name", notification.contract_name.clone()),
+        ("Block timestamp", event.block_timestamp.clone()),
+        ("Transaction from", notification.tx_from.clone()),
+        ("Transaction to", notification.tx_to.clone()),
+        ("Transaction URL", notification.transaction_url.clone()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            lines.push(format!("{}: {}", name, value));
+        }
+    }
+    for (i, topic) in event.topics.iter().enumerate() {
+        lines.push(format!("Topic {}: {}", i, topic));
+    }
+    if !event.data.is_empty() {
+        lines.push(format!("Data: 0x{}", event.data));
+    }
+    for (name, value) in event.decoded.iter().flatten() {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        lines.push(format!("Arg {}: {}", name, value));
+    }
+    // Event Viewer shows entries with Windows line breaks
+    lines.join("\r\n")
+}
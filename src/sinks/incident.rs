@@ -0,0 +1,352 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::Sink;
+use crate::config::IncidentConfig;
+use crate::event::EventData;
+use crate::notification::{truncate, Notification};
+
+const PAGERDUTY_API_URL: &str = "https://events.pagerduty.com";
+
+const OPSGENIE_API_URL: &str = "https://api.opsgenie.com";
+
+/// Incidents waiting to be opened; the oldest are dropped beyond this
+const MAX_PENDING: usize = 1_000;
+
+/// Wait after a 429 without a usable `Retry-After`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    PagerDuty,
+    Opsgenie,
+}
+
+/// Severity of an alerting event, shared with the journald, event log, and
+/// syslog sinks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Critical,
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    fn parse(severity: &str) -> Result<Self> {
+        match severity.trim().to_lowercase().as_str() {
+            "critical" => Ok(Severity::Critical),
+            "error" => Ok(Severity::Error),
+            "warning" => Ok(Severity::Warning),
+            "info" => Ok(Severity::Info),
+            other => anyhow::bail!("Unknown severity: {} (expected critical, error, warning, or info)", other),
+        }
+    }
+
+    /// PagerDuty's name for the severity
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Critical => "critical",
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+
+    /// syslog severity level (RFC 5424): `critical` is 2 (crit), `error` 3
+    /// (err), `warning` 4, and `info` 6
+    pub fn syslog_level(self) -> u8 {
+        match self {
+            Severity::Critical => 2,
+            Severity::Error => 3,
+            Severity::Warning => 4,
+            Severity::Info => 6,
+        }
+    }
+
+    /// CEF and LEEF severity, from 0 to 10: `critical` is 10, `error` 8,
+    /// `warning` 6, and `info` 3
+    pub fn siem_level(self) -> u8 {
+        match self {
+            Severity::Critical => 10,
+            Severity::Error => 8,
+            Severity::Warning => 6,
+            Severity::Info => 3,
+        }
+    }
+
+    /// Opsgenie priority; P4 is left for alerts of other sources
+    fn priority(self) -> &'static str {
+        match self {
+            Severity::Critical => "P1",
+            Severity::Error => "P2",
+            Severity::Warning => "P3",
+            Severity::Info => "P5",
+        }
+    }
+}
+
+/// Severities configured by event signature, name, or topic0 hash
+pub struct Severities(BTreeMap<String, Severity>);
+
+impl Severities {
+    /// Parse the severity configured for each event
+    pub fn parse(events: &BTreeMap<String, String>) -> Result<Self> {
+        let severities = events
+            .iter()
+            .map(|(event, severity)| {
+                let severity = Severity::parse(severity).with_context(|| format!("Invalid severity for {}", event))?;
+                // Hashes compare case-insensitively
+                let event = if event.starts_with("0x") { event.to_lowercase() } else { event.trim().to_string() };
+                Ok((event, severity))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self(severities))
+    }
+
+    /// Severity configured for `event`, whose name is `name`, by its
+    /// signature, then its name, then its topic0
+    pub fn get(&self, event: &EventData, name: &str) -> Option<Severity> {
+        let topic0 = event.topics.first().map(|topic| topic.to_lowercase());
+        [event.event_signature.clone(), Some(name.to_string()), topic0]
+            .into_iter()
+            .flatten()
+            .find_map(|key| self.0.get(&key).copied())
+    }
+}
+
+/// Opens a PagerDuty incident (through the Events API v2) or an Opsgenie
+/// alert for each event that has a severity configured, so that security
+/// events such as `Paused()` or `Upgraded(address)` page someone. Other
+/// events are ignored.
+///
+/// Incidents are deduplicated by chain, transaction, and log index, so an
+/// event emitted again after a restart doesn't page twice. They're opened
+/// from [`tick`](Sink::tick); a 429 pauses sending for as long as the
+/// response asks, and requests that fail for other reasons than being
+/// rejected are sent again later.
+pub struct IncidentSink {
+    service: Service,
+    client: reqwest::Client,
+    url: String,
+    key: String,
+    severities: Severities,
+    pending: VecDeque<Value>,
+    /// Set while rate-limited
+    paused_until: Option<Instant>,
+}
+
+impl IncidentSink {
+    pub fn connect(service: Service, config: &IncidentConfig) -> Result<Self> {
+        if config.key.trim().is_empty() {
+            anyhow::bail!("{:?} key is empty", service);
+        }
+        if config.events.is_empty() {
+            anyhow::bail!("{:?} sink has no events to open incidents for", service);
+        }
+        let severities = Severities::parse(&config.events)?;
+
+        let url = match service {
+            Service::PagerDuty => {
+                let api_url = config.api_url.as_deref().unwrap_or(PAGERDUTY_API_URL).trim_end_matches('/');
+                format!("{}/v2/enqueue", api_url)
+            }
+            Service::Opsgenie => {
+                let api_url = config.api_url.as_deref().unwrap_or(OPSGENIE_API_URL).trim_end_matches('/');
+                format!("{}/v2/alerts", api_url)
+            }
+        };
+        reqwest::Url::parse(&url).with_context(|| format!("Invalid {:?} API URL", service))?;
+
+        Ok(Self {
+            service,
+            client: crate::tls::configure(reqwest::Client::builder().timeout(TIMEOUT), config.tls.as_ref())
+                .with_context(|| format!("Invalid {:?} TLS settings", service))?
+                .build()?,
+            url,
+            key: config.key.trim().to_string(),
+            severities,
+            pending: VecDeque::new(),
+            paused_until: None,
+        })
+    }
+
+    /// Open the oldest pending incident. Rejected requests are dropped;
+    /// others stay pending.
+    async fn send_next(&mut self) -> Result<()> {
+        if self.paused_until.is_some_and(|until| Instant::now() < until) {
+            return Ok(());
+        }
+        self.paused_until = None;
+        let Some(payload) = self.pending.front() else {
+            return Ok(());
+        };
+
+        let request = self.client.post(&self.url).json(payload);
+        let request = match self.service {
+            Service::PagerDuty => request,
+            Service::Opsgenie => request.header(reqwest::header::AUTHORIZATION, format!("GenieKey {}", self.key)),
+        };
+        let response = request
+            .send()
+            .await
+            .with_context(|| format!("Failed to send {:?} incident", self.service))?;
+
+        let status = response.status();
+        if status.is_success() {
+            self.pending.pop_front();
+            return Ok(());
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<f64>().ok())
+                .map(Duration::from_secs_f64)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.paused_until = Some(Instant::now() + retry_after);
+            anyhow::bail!("rate limit hit, pausing for {:?}", retry_after);
+        }
+        let body = response.text().await.unwrap_or_default();
+        if status.is_client_error() {
+            self.pending.pop_front();
+            anyhow::bail!("incident rejected: {} {}", status, body.trim());
+        }
+        anyhow::bail!("{:?} returned {} {}, will retry", self.service, status, body.trim())
+    }
+}
+
+#[async_trait]
+impl Sink for IncidentSink {
+    fn name(&self) -> &str {
+        match self.service {
+            Service::PagerDuty => "PagerDuty",
+            Service::Opsgenie => "Opsgenie",
+        }
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let notification = Notification::from_event(event);
+        let Some(severity) = self.severities.get(event, &notification.event) else {
+            return Ok(());
+        };
+
+        let dedup_key = format!(
+            "{}:{}:{}",
+            event.chain_id.map_or("custom".to_string(), |id| id.to_string()),
+            event.transaction_hash,
+            event.log_index
+        );
+        let payload = match self.service {
+            Service::PagerDuty => pagerduty_event(&self.key, &dedup_key, severity, &notification),
+            Service::Opsgenie => opsgenie_alert(&dedup_key, severity, &notification),
+        };
+        self.pending.push_back(payload);
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            anyhow::bail!("too many incidents waiting to be sent, dropped {} oldest", dropped);
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            if self.paused_until.is_some_and(|until| Instant::now() < until) {
+                anyhow::bail!("{} incident(s) still waiting for the rate limit", self.pending.len());
+            }
+            self.send_next().await?;
+        }
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        // Incidents are rare; open all that are due rather than one per tick
+        while !self.pending.is_empty() && self.paused_until.is_none_or(|until| Instant::now() >= until) {
+            self.send_next().await?;
+        }
+        Ok(())
+    }
+}
+
+/// A PagerDuty Events API v2 trigger
+fn pagerduty_event(routing_key: &str, dedup_key: &str, severity: Severity, notification: &Notification) -> Value {
+    let mut links = Vec::new();
+    for (text, url) in [
+        ("Transaction", &notification.transaction_url),
+        ("Contract", &notification.contract_url),
+    ] {
+        if let Some(url) = url {
+            links.push(json!({ "href": url, "text": text }));
+        }
+    }
+
+    json!({
+        "routing_key": routing_key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key,
+        "client": "listener",
+        "payload": {
+            "summary": truncate(
+                &format!(
+                    "{} at {}",
+                    notification.title,
+                    notification.contract_name.as_deref().unwrap_or(&notification.contract)
+                ),
+                1024
+            ),
+            "source": notification.contract,
+            "severity": severity.as_str(),
+            "timestamp": notification.timestamp,
+            "component": notification.contract,
+            "group": notification.chain,
+            "class": notification.event,
+            "custom_details": details(notification),
+        },
+        "links": links,
+    })
+}
+
+/// An Opsgenie alert
+fn opsgenie_alert(alias: &str, severity: Severity, notification: &Notification) -> Value {
+    let mut description = notification
+        .fields
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>();
+    if let Some(ref url) = notification.transaction_url {
+        description.push(format!("Transaction: {}", url));
+    }
+
+    json!({
+        "message": truncate(&notification.title, 130),
+        "alias": alias,
+        "description": truncate(&description.join("\n"), 15_000),
+        "priority": severity.priority(),
+        "source": "listener",
+        "entity": notification.contract,
+        "tags": [notification.chain, notification.event],
+        "details": details(notification),
+    })
+}
+
+/// The event's arguments and location as string key/value pairs
+fn details(notification: &Notification) -> Map<String, Value> {
+    let mut details = Map::new();
+    for (name, value) in &notification.fields {
+        details.insert(name.clone(), json!(value));
+    }
+    details.insert("chain".to_string(), json!(notification.chain));
+    details.insert("contract".to_string(), json!(notification.contract));
+    details.insert("transaction".to_string(), json!(notification.transaction_hash));
+    details.insert("block".to_string(), json!(notification.block_number.to_string()));
+    details.insert("log_index".to_string(), json!(notification.log_index.to_string()));
+    details
+}
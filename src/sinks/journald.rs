@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::path::PathBuf;
+use tokio::net::UnixDatagram;
+
+use super::incident::{Severities, Severity};
+use super::Sink;
+use crate::config::JournaldConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+
+const DEFAULT_SOCKET: &str = "/run/systemd/journal/socket";
+
+const DEFAULT_IDENTIFIER: &str = "listener";
+
+/// Entries larger than this are sent without `EVENT_JSON`, to stay within
+/// the datagram size the journal socket accepts
+const MAX_ENTRY_SIZE: usize = 128 * 1024;
+
+/// Longest field name the journal accepts
+const MAX_FIELD_NAME_LEN: usize = 64;
+
+/// Logs every event to the systemd journal through its native protocol,
+/// with the event's fields as structured journal fields (`EVENT_NAME`,
+/// `CONTRACT_ADDRESS`, `TRANSACTION_HASH`, `ARG_FROM`, ...), so events can
+/// be queried with `journalctl EVENT_NAME=Transfer`.
+///
+/// `PRIORITY` follows the severity configured for the event, as for the
+/// incident sinks: `critical` is 2, `error` 3, `warning` 4, and `info` and
+/// events without a severity 6.
+pub struct JournaldSink {
+    socket: UnixDatagram,
+    path: PathBuf,
+    identifier: String,
+    severities: Severities,
+}
+
+impl JournaldSink {
+    pub fn connect(config: &JournaldConfig) -> Result<Self> {
+        let path = PathBuf::from(config.socket.as_deref().unwrap_or(DEFAULT_SOCKET));
+        if !path.exists() {
+            anyhow::bail!("No systemd journal socket at {}", path.display());
+        }
+        let identifier = config.identifier.as_deref().unwrap_or(DEFAULT_IDENTIFIER).trim();
+        if identifier.is_empty() {
+            anyhow::bail!("Journal identifier is empty");
+        }
+        Ok(Self {
+            socket: UnixDatagram::unbound().context("Failed to open a socket for the journal")?,
+            path,
+            identifier: identifier.to_string(),
+            severities: Severities::parse(&config.events)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for JournaldSink {
+    fn name(&self) -> &str {
+        "journald"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let notification = Notification::from_event(event);
+        let severity = self.severities.get(event, &notification.event);
+        let fields = fields(event, &notification, severity, &self.identifier);
+
+        let mut entry = serialize(&fields);
+        if entry.len() > MAX_ENTRY_SIZE {
+            entry = serialize(&fields[..fields.len() - 1]);
+        }
+        self.socket
+            .send_to(&entry, &self.path)
+            .await
+            .with_context(|| format!("Failed to write to the journal at {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// syslog priority of `severity`; events without one are logged at info
+fn priority(severity: Option<Severity>) -> u8 {
+    severity.map_or(Severity::Info.syslog_level(), Severity::syslog_level)
+}
+
+/// The journal fields of `event`; `EVENT_JSON`, the whole event, comes last
+fn fields(event: &EventData, notification: &Notification, severity: Option<Severity>, identifier: &str) -> Vec<(String, String)> {
+    let args = notification
+        .fields
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let message = match args.is_empty() {
+        true => notification.title.clone(),
+        false => format!("{}: {}", notification.title, args),
+    };
+
+    let mut fields = vec![
+        ("MESSAGE".to_string(), message),
+        ("PRIORITY".to_string(), priority(severity).to_string()),
+        ("SYSLOG_IDENTIFIER".to_string(), identifier.to_string()),
+        ("EVENT_NAME".to_string(), notification.event.clone()),
+        ("CHAIN_NAME".to_string(), event.chain_name.clone()),
+        ("CONTRACT_ADDRESS".to_string(), event.contract_address.to_lowercase()),
+        ("BLOCK_NUMBER".to_string(), event.block_number.to_string()),
+        ("TRANSACTION_HASH".to_string(), event.transaction_hash.clone()),
+        ("LOG_INDEX".to_string(), event.log_index.to_string()),
+        ("DETECTED_AT".to_string(), event.timestamp.clone()),
+    ];
+    let optional = [
+        ("EVENT_SIGNATURE", event.event_signature.clone()),
+        ("SEVERITY", severity.map(|severity| severity.as_str().to_string())),
+        ("CHAIN_ID", event.chain_id.map(|id| id.to_string())),
+        ("CONTRACT_NAME", notification.contract_name.clone()),
+        ("BLOCK_TIMESTAMP", event.block_timestamp.clone()),
+        ("TX_FROM", notification.tx_from.clone()),
+        ("TX_TO", notification.tx_to.clone()),
+        ("TRANSACTION_URL", notification.transaction_url.clone()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            fields.push((name.to_string(), value));
+        }
+    }
+    for (i, topic) in event.topics.iter().enumerate() {
+        fields.push((format!("TOPIC{}", i), topic.clone()));
+    }
+    if !event.data.is_empty() {
+        fields.push(("EVENT_DATA".to_string(), format!("0x{}", event.data)));
+    }
+    for (name, value) in event.decoded.iter().flatten() {
+        let value = match value {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        fields.push((field_name(&format!("ARG_{}", name)), value));
+    }
+    fields.push(("EVENT_JSON".to_string(), serde_json::to_string(event).unwrap_or_default()));
+    fields
+}
+
+/// `name` as a journal field name: uppercase letters, digits, and
+/// underscores, at most 64 characters
+fn field_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .take(MAX_FIELD_NAME_LEN)
+        .collect()
+}
+
+/// An entry in the journal's native protocol: `NAME=value` lines, and
+/// values with newlines as the name, their length, and the raw value
+fn serialize(fields: &[(String, String)]) -> Vec<u8> {
+    let mut entry = Vec::new();
+    for (name, value) in fields {
+        entry.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            entry.push(b'=');
+        }
+        entry.extend_from_slice(value.as_bytes());
+        entry.push(b'\n');
+    }
+    entry
+}
@@ -0,0 +1,410 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::FutureExt;
+use rdkafka::consumer::{BaseConsumer, Consumer};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::message::Message;
+use rdkafka::producer::{DeliveryFuture, FutureProducer, FutureRecord, Producer};
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+use super::Sink;
+use crate::config::KafkaConfig;
+use crate::event::EventData;
+
+/// Deliveries awaiting acknowledgement before `send` waits for the oldest
+const MAX_IN_FLIGHT: usize = 10_000;
+
+/// Failed deliveries kept for retry; the oldest are dropped beyond this
+const MAX_RETRY: usize = 10_000;
+
+/// How long setting up, committing, or aborting a transaction may take
+const TRANSACTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long reading back the committed cursors may take at startup
+const CURSOR_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Publishes every event as JSON to a Kafka topic, keyed by contract address
+/// so that a contract's events stay ordered within one partition.
+///
+/// Produces are acknowledged by all in-sync replicas. Deliveries that fail
+/// after librdkafka's own retries are produced again on the next flush.
+///
+/// With a `transactional_id`, events are instead produced in transactions
+/// committed on every flush, together with each contract's cursor: the
+/// position of its last event, kept in a compacted cursor topic. Events at
+/// or before a committed cursor were already delivered and are skipped, so
+/// events replayed after a crash aren't produced twice.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+    /// (key, delivery) for produced messages not acknowledged yet
+    in_flight: VecDeque<(String, DeliveryFuture)>,
+    /// (key, payload) of failed deliveries
+    retry: VecDeque<(String, String)>,
+    transactions: Option<Transactions>,
+}
+
+/// Where a contract's events have been produced up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Position {
+    block: u64,
+    log_index: u64,
+}
+
+/// The transaction state of a transactional sink
+struct Transactions {
+    cursor_topic: String,
+    /// Each contract's cursor as of the last committed transaction
+    committed: HashMap<String, Position>,
+    /// (key, payload) of the events of the current transaction, produced
+    /// again when it's aborted
+    pending: Vec<(String, String)>,
+    /// How many of `pending` were produced in the open transaction
+    produced: usize,
+    /// Where the current transaction moves each contract's cursor
+    cursors: HashMap<String, Position>,
+    /// Whether a transaction was begun and not committed or aborted yet
+    open: bool,
+}
+
+impl KafkaSink {
+    pub async fn connect(config: &KafkaConfig) -> Result<Self> {
+        if config.topic.is_empty() {
+            anyhow::bail!("A Kafka topic is required (--kafka-topic)");
+        }
+
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", &config.brokers)
+            .set("acks", "all")
+            .set("enable.idempotence", "true")
+            .set("queue.buffering.max.messages", "100000")
+            .set("queue.buffering.max.kbytes", "65536")
+            .set("message.timeout.ms", "60000")
+            .set("linger.ms", "5");
+        if let Some(ref id) = config.transactional_id {
+            client.set("transactional.id", id);
+        }
+        // User settings (SASL, TLS, compression, ...) override the defaults
+        for (key, value) in &config.properties {
+            client.set(key, value);
+        }
+        let producer: FutureProducer = client.create().context("Failed to create Kafka producer")?;
+
+        let transactions = match config.transactional_id {
+            Some(_) => {
+                // Fences off earlier producers with the same ID and aborts
+                // the transaction a crashed one left open
+                blocking(&producer, |producer| producer.init_transactions(TRANSACTION_TIMEOUT))
+                    .await?
+                    .context("Failed to initialize Kafka transactions")?;
+                let cursor_topic = config
+                    .cursor_topic
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.cursors", config.topic));
+                let committed = {
+                    let (config, topic) = (config.clone(), cursor_topic.clone());
+                    tokio::task::spawn_blocking(move || read_cursors(&config, &topic)).await??
+                };
+                info!(
+                    "Kafka sink: {} committed cursor(s) in {}",
+                    committed.len(),
+                    cursor_topic
+                );
+                Some(Transactions {
+                    cursor_topic,
+                    committed,
+                    pending: Vec::new(),
+                    produced: 0,
+                    cursors: HashMap::new(),
+                    open: false,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            producer,
+            topic: config.topic.clone(),
+            in_flight: VecDeque::new(),
+            retry: VecDeque::new(),
+            transactions,
+        })
+    }
+
+    /// Hand a message to the producer, waiting for acknowledgements while
+    /// its local queue or our in-flight limit is full
+    async fn produce(&mut self, key: String, payload: String) -> Result<()> {
+        loop {
+            let record = FutureRecord::to(&self.topic).key(&key).payload(&payload);
+            match self.producer.send_result(record) {
+                Ok(delivery) => {
+                    self.in_flight.push_back((key, delivery));
+                    break;
+                }
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) if !self.in_flight.is_empty() => {
+                    self.acknowledge_oldest().await?;
+                }
+                Err((e, _)) => return Err(e).context("Kafka produce failed"),
+            }
+        }
+
+        if self.in_flight.len() > MAX_IN_FLIGHT {
+            self.acknowledge_oldest().await?;
+        }
+        Ok(())
+    }
+
+    async fn acknowledge_oldest(&mut self) -> Result<()> {
+        match self.in_flight.pop_front() {
+            Some((key, delivery)) => self.settle(key, delivery.await),
+            None => Ok(()),
+        }
+    }
+
+    /// Record the outcome of a delivery, keeping failed messages for retry
+    fn settle(
+        &mut self,
+        key: String,
+        result: Result<rdkafka::producer::future_producer::OwnedDeliveryResult, futures::channel::oneshot::Canceled>,
+    ) -> Result<()> {
+        match result {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err((e, message))) => {
+                let payload = message
+                    .payload()
+                    .map(|p| String::from_utf8_lossy(p).into_owned())
+                    .unwrap_or_default();
+                self.retry.push_back((key, payload));
+                if self.retry.len() > MAX_RETRY {
+                    self.retry.pop_front();
+                    warn!("Kafka retry buffer full, dropped the oldest event");
+                }
+                Err(e).context("Kafka delivery failed, will retry")
+            }
+            Err(_) => anyhow::bail!("Kafka producer shut down before acknowledging delivery"),
+        }
+    }
+
+    /// Add an event to the current transaction unless a committed cursor is
+    /// already past it, committing once the transaction is full
+    async fn send_in_transaction(&mut self, event: &EventData, payload: String) -> Result<()> {
+        let transactions = self.transactions.as_mut().expect("transactional sink");
+        let cursor = cursor_key(event);
+        let position = Position {
+            block: event.block_number,
+            log_index: event.log_index,
+        };
+        if transactions.committed.get(&cursor).is_some_and(|committed| position <= *committed) {
+            debug!(
+                "Kafka sink: skipping {}:{}, already committed",
+                event.transaction_hash, event.log_index
+            );
+            return Ok(());
+        }
+        let moved = transactions.cursors.entry(cursor).or_insert(position);
+        *moved = (*moved).max(position);
+        transactions.pending.push((event.contract_address.clone(), payload));
+
+        self.produce_pending()?;
+        if self.transactions.as_ref().is_some_and(|t| t.pending.len() >= MAX_IN_FLIGHT) {
+            self.commit().await?;
+        }
+        Ok(())
+    }
+
+    /// Produce the events of the current transaction that haven't been,
+    /// beginning the transaction if needed
+    fn produce_pending(&mut self) -> Result<()> {
+        let transactions = self.transactions.as_mut().expect("transactional sink");
+        if !transactions.open {
+            self.producer.begin_transaction().context("Failed to begin Kafka transaction")?;
+            transactions.open = true;
+            transactions.produced = 0;
+        }
+        for (key, payload) in &transactions.pending[transactions.produced..] {
+            let record = FutureRecord::to(&self.topic).key(key).payload(payload);
+            // The commit waits for every delivery and fails if one did
+            self.producer
+                .send_result(record)
+                .map_err(|(e, _)| e)
+                .context("Kafka produce failed")?;
+            transactions.produced += 1;
+        }
+        Ok(())
+    }
+
+    /// Commit the current transaction with the cursors it moves. An aborted
+    /// transaction is produced again on the next flush.
+    async fn commit(&mut self) -> Result<()> {
+        if self.transactions.as_ref().is_some_and(|t| t.pending.is_empty() && !t.open) {
+            return Ok(());
+        }
+        self.produce_pending()?;
+        let transactions = self.transactions.as_mut().expect("transactional sink");
+        for (cursor, position) in &transactions.cursors {
+            let payload = serde_json::to_string(position)?;
+            let record = FutureRecord::to(&transactions.cursor_topic).key(cursor).payload(&payload);
+            self.producer
+                .send_result(record)
+                .map_err(|(e, _)| e)
+                .context("Kafka produce of cursor failed")?;
+        }
+
+        let result = blocking(&self.producer, |producer| producer.commit_transaction(TRANSACTION_TIMEOUT)).await?;
+        match result {
+            Ok(()) => {
+                transactions.committed.extend(transactions.cursors.drain());
+                transactions.pending.clear();
+                transactions.open = false;
+                Ok(())
+            }
+            Err(KafkaError::Transaction(ref e)) if e.txn_requires_abort() => {
+                blocking(&self.producer, |producer| producer.abort_transaction(TRANSACTION_TIMEOUT))
+                    .await?
+                    .context("Failed to abort Kafka transaction")?;
+                transactions.open = false;
+                Err(result.unwrap_err()).context("Kafka transaction aborted, will retry")
+            }
+            Err(KafkaError::Transaction(ref e)) if e.is_fatal() => Err(result.unwrap_err())
+                .context("Kafka producer failed fatally, restart the listener to produce again"),
+            Err(e) => Err(e).context("Kafka transaction commit failed, will retry"),
+        }
+    }
+}
+
+/// The cursor topic key of `event`'s contract
+fn cursor_key(event: &EventData) -> String {
+    format!(
+        "{}:{}",
+        event.chain_id.unwrap_or_default(),
+        event.contract_address.to_lowercase()
+    )
+}
+
+/// Run a blocking transaction call on a clone of `producer` without
+/// holding up the runtime
+async fn blocking<T: Send + 'static>(
+    producer: &FutureProducer,
+    call: impl FnOnce(&FutureProducer) -> KafkaResult<T> + Send + 'static,
+) -> Result<KafkaResult<T>> {
+    let producer = producer.clone();
+    tokio::task::spawn_blocking(move || call(&producer))
+        .await
+        .context("Kafka transaction call panicked")
+}
+
+/// The committed cursors in `topic`, read to its end. Connection settings
+/// among the configured properties apply to this consumer too.
+fn read_cursors(config: &KafkaConfig, topic: &str) -> Result<HashMap<String, Position>> {
+    let mut client = ClientConfig::new();
+    client
+        .set("bootstrap.servers", &config.brokers)
+        .set("enable.auto.commit", "false")
+        .set("enable.partition.eof", "true")
+        .set("isolation.level", "read_committed");
+    for (key, value) in &config.properties {
+        if key.starts_with("security.") || key.starts_with("sasl.") || key.starts_with("ssl.") {
+            client.set(key, value);
+        }
+    }
+    let consumer: BaseConsumer = client.create().context("Failed to create Kafka cursor consumer")?;
+
+    let metadata = consumer
+        .fetch_metadata(Some(topic), CURSOR_READ_TIMEOUT)
+        .with_context(|| format!("Failed to fetch metadata of Kafka topic {}", topic))?;
+    // A missing topic has no cursors yet
+    let mut remaining: HashSet<i32> = metadata
+        .topics()
+        .iter()
+        .filter(|t| t.name() == topic && t.error().is_none())
+        .flat_map(|t| t.partitions().iter().map(|partition| partition.id()))
+        .collect();
+    let mut cursors = HashMap::new();
+    if remaining.is_empty() {
+        return Ok(cursors);
+    }
+
+    let mut assignment = TopicPartitionList::new();
+    for partition in &remaining {
+        assignment.add_partition_offset(topic, *partition, Offset::Beginning)?;
+    }
+    consumer.assign(&assignment)?;
+
+    let deadline = Instant::now() + CURSOR_READ_TIMEOUT;
+    while !remaining.is_empty() {
+        let left = deadline
+            .checked_duration_since(Instant::now())
+            .with_context(|| format!("Timed out reading the Kafka cursors in {}", topic))?;
+        match consumer.poll(left) {
+            Some(Ok(message)) => {
+                let Some(key) = message.key() else { continue };
+                let key = String::from_utf8_lossy(key).into_owned();
+                match message.payload().map(serde_json::from_slice::<Position>) {
+                    Some(Ok(position)) => {
+                        cursors.insert(key, position);
+                    }
+                    Some(Err(e)) => warn!("Ignoring Kafka cursor {} in {}: {}", key, topic, e),
+                    None => {
+                        cursors.remove(&key);
+                    }
+                }
+            }
+            Some(Err(KafkaError::PartitionEOF(partition))) => {
+                remaining.remove(&partition);
+            }
+            Some(Err(e)) => return Err(e).with_context(|| format!("Failed to read the Kafka cursors in {}", topic)),
+            None => {}
+        }
+    }
+    Ok(cursors)
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &str {
+        "Kafka"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let payload = serde_json::to_string(event)?;
+        if self.transactions.is_some() {
+            return self.send_in_transaction(event, payload).await;
+        }
+        self.produce(event.contract_address.clone(), payload).await
+    }
+
+    /// Collect acknowledgements that have arrived and re-produce failed
+    /// deliveries. Doesn't wait for outstanding acknowledgements.
+    /// Transactional sinks commit the current transaction instead.
+    async fn flush(&mut self) -> Result<()> {
+        if self.transactions.is_some() {
+            return self.commit().await;
+        }
+        let mut result = Ok(());
+
+        while let Some((_, delivery)) = self.in_flight.front_mut() {
+            let Some(outcome) = delivery.now_or_never() else { break };
+            let (key, _) = self.in_flight.pop_front().expect("front exists");
+            if let Err(e) = self.settle(key, outcome) {
+                result = Err(e);
+            }
+        }
+
+        let retries: Vec<(String, String)> = self.retry.drain(..).collect();
+        let mut retries = retries.into_iter();
+        while let Some((key, payload)) = retries.next() {
+            if let Err(e) = self.produce(key, payload).await {
+                // Keep the rest for the next flush
+                self.retry.extend(retries);
+                return Err(e);
+            }
+        }
+
+        result
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+
+use crate::config::SinkConfig;
+use crate::event::EventData;
+use crate::run::RunMetadata;
+
+pub mod archive;
+pub mod chat;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+pub mod columns;
+#[cfg(windows)]
+pub mod eventlog;
+pub mod incident;
+#[cfg(unix)]
+pub mod journald;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+#[cfg(feature = "eventhubs")]
+pub mod eventhubs;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "object-storage")]
+pub mod object_storage;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(any(feature = "postgres", feature = "sqlite"))]
+pub mod rollups;
+#[cfg(feature = "smtp")]
+pub mod smtp;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod syslog;
+pub mod telegram;
+pub mod worker;
+
+pub use worker::SinkWorker;
+
+/// A destination that receives every emitted event. Sinks may buffer events
+/// in [`send`](Sink::send) and write them out in [`tick`](Sink::tick), which
+/// the listener calls periodically, and [`flush`](Sink::flush).
+#[async_trait]
+pub trait Sink: Send {
+    /// Short name used in log messages
+    fn name(&self) -> &str;
+
+    /// Record the run whose events follow
+    async fn start_run(&mut self, _run: &RunMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()>;
+
+    /// Write out any buffered events
+    async fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called about once a second to write out buffered events that are
+    /// due; sinks that batch over longer periods only write full or old
+    /// batches here. Flushes everything by default.
+    async fn tick(&mut self) -> Result<()> {
+        self.flush().await
+    }
+}
+
+/// One of the sinks a [`SinkConfig`] can enable
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Archive,
+    Postgres,
+    Kafka,
+    Nats,
+    Redis,
+    Mqtt,
+    PubSub,
+    EventHubs,
+    ObjectStorage,
+    Sqlite,
+    Parquet,
+    Slack,
+    Discord,
+    Telegram,
+    Smtp,
+    PagerDuty,
+    Opsgenie,
+    Journald,
+    EventLog,
+    Syslog,
+}
+
+impl SinkKind {
+    pub const ALL: [SinkKind; 20] = [
+        SinkKind::Archive,
+        SinkKind::Postgres,
+        SinkKind::Kafka,
+        SinkKind::Nats,
+        SinkKind::Redis,
+        SinkKind::Mqtt,
+        SinkKind::PubSub,
+        SinkKind::EventHubs,
+        SinkKind::ObjectStorage,
+        SinkKind::Sqlite,
+        SinkKind::Parquet,
+        SinkKind::Slack,
+        SinkKind::Discord,
+        SinkKind::Telegram,
+        SinkKind::Smtp,
+        SinkKind::PagerDuty,
+        SinkKind::Opsgenie,
+        SinkKind::Journald,
+        SinkKind::EventLog,
+        SinkKind::Syslog,
+    ];
+
+    /// Name of the sink's section under `[sinks]`, or its file mode
+    pub fn config_name(self) -> &'static str {
+        match self {
+            SinkKind::Archive => "archive",
+            SinkKind::Postgres => "postgres",
+            SinkKind::Kafka => "kafka",
+            SinkKind::Nats => "nats",
+            SinkKind::Redis => "redis",
+            SinkKind::Mqtt => "mqtt",
+            SinkKind::PubSub => "pubsub",
+            SinkKind::EventHubs => "eventhubs",
+            SinkKind::ObjectStorage => "object_storage",
+            SinkKind::Sqlite => "sqlite",
+            SinkKind::Parquet => "parquet",
+            SinkKind::Slack => "slack",
+            SinkKind::Discord => "discord",
+            SinkKind::Telegram => "telegram",
+            SinkKind::Smtp => "smtp",
+            SinkKind::PagerDuty => "pagerduty",
+            SinkKind::Opsgenie => "opsgenie",
+            SinkKind::Journald => "journald",
+            SinkKind::EventLog => "eventlog",
+            SinkKind::Syslog => "syslog",
+        }
+    }
+
+    /// The sinks enabled in `config`, in the order events are handed to them
+    pub fn configured(config: &SinkConfig) -> Result<Vec<SinkKind>> {
+        let mut kinds = Vec::new();
+
+        match config.file_mode.as_deref() {
+            None | Some("jsonl") => {}
+            Some("archive") => kinds.push(SinkKind::Archive),
+            Some(other) => anyhow::bail!("Unknown file mode: {} (expected jsonl or archive)", other),
+        }
+        if config.is_parquet() {
+            if config.is_archive() {
+                anyhow::bail!("Parquet output can't be combined with the archive file mode");
+            }
+            kinds.push(SinkKind::Parquet);
+        }
+        if config.postgres.is_some() {
+            kinds.push(SinkKind::Postgres);
+        }
+        if config.kafka.is_some() {
+            kinds.push(SinkKind::Kafka);
+        }
+        if config.nats.is_some() {
+            kinds.push(SinkKind::Nats);
+        }
+        if config.redis.is_some() {
+            kinds.push(SinkKind::Redis);
+        }
+        if config.mqtt.is_some() {
+            kinds.push(SinkKind::Mqtt);
+        }
+        if config.pubsub.is_some() {
+            kinds.push(SinkKind::PubSub);
+        }
+        if config.eventhubs.is_some() {
+            kinds.push(SinkKind::EventHubs);
+        }
+        if config.object_storage.is_some() {
+            kinds.push(SinkKind::ObjectStorage);
+        }
+        if config.sqlite.is_some() {
+            kinds.push(SinkKind::Sqlite);
+        }
+        if config.slack.is_some() {
+            kinds.push(SinkKind::Slack);
+        }
+        if config.discord.is_some() {
+            kinds.push(SinkKind::Discord);
+        }
+        if config.telegram.is_some() {
+            kinds.push(SinkKind::Telegram);
+        }
+        if config.smtp.is_some() {
+            kinds.push(SinkKind::Smtp);
+        }
+        if config.pagerduty.is_some() {
+            kinds.push(SinkKind::PagerDuty);
+        }
+        if config.opsgenie.is_some() {
+            kinds.push(SinkKind::Opsgenie);
+        }
+        if config.journald.is_some() {
+            kinds.push(SinkKind::Journald);
+        }
+        if config.eventlog.is_some() {
+            kinds.push(SinkKind::EventLog);
+        }
+        if config.syslog.is_some() {
+            kinds.push(SinkKind::Syslog);
+        }
+
+        Ok(kinds)
+    }
+
+    /// Whether this sink stores events in a database that can be
+    /// bootstrapped from a snapshot
+    pub fn is_database(self) -> bool {
+        matches!(self, SinkKind::Postgres | SinkKind::Sqlite)
+    }
+
+    /// Connect this sink as configured in `config`
+    pub async fn connect(self, config: &SinkConfig) -> Result<Box<dyn Sink>> {
+        match self {
+            SinkKind::Archive => {
+                let dir = config.output_file.as_deref()
+                    .context("Archive file mode needs an output directory (--output-file)")?;
+                Ok(Box::new(archive::ArchiveSink::open(dir)?))
+            }
+            SinkKind::Postgres => {
+                let postgres = config.postgres.as_ref().context("No PostgreSQL sink configured")?;
+                #[cfg(feature = "postgres")]
+                return Ok(Box::new(postgres::PostgresSink::connect(postgres, &config.tables, config.rollups).await?));
+                #[cfg(not(feature = "postgres"))]
+                {
+                    let _ = postgres;
+                    anyhow::bail!("PostgreSQL sink configured but the listener was built without the `postgres` feature");
+                }
+            }
+            SinkKind::Kafka => {
+                let kafka = config.kafka.as_ref().context("No Kafka sink configured")?;
+                #[cfg(feature = "kafka")]
+                return Ok(Box::new(kafka::KafkaSink::connect(kafka).await?));
+                #[cfg(not(feature = "kafka"))]
+                {
+                    let _ = kafka;
+                    anyhow::bail!("Kafka sink configured but the listener was built without the `kafka` feature");
+                }
+            }
+            SinkKind::Nats => {
+                let nats = config.nats.as_ref().context("No NATS sink configured")?;
+                #[cfg(feature = "nats")]
+                return Ok(Box::new(nats::NatsSink::connect(nats).await?));
+                #[cfg(not(feature = "nats"))]
+                {
+                    let _ = nats;
+                    anyhow::bail!("NATS sink configured but the listener was built without the `nats` feature");
+                }
+            }
+            SinkKind::Redis => {
+                let redis = config.redis.as_ref().context("No Redis sink configured")?;
+                #[cfg(feature = "redis")]
+                return Ok(Box::new(self::redis::RedisSink::connect(redis).await?));
+                #[cfg(not(feature = "redis"))]
+                {
+                    let _ = redis;
+                    anyhow::bail!("Redis sink configured but the listener was built without the `redis` feature");
+                }
+            }
+            SinkKind::Mqtt => {
+                let mqtt = config.mqtt.as_ref().context("No MQTT sink configured")?;
+                #[cfg(feature = "mqtt")]
+                return Ok(Box::new(mqtt::MqttSink::connect(mqtt).await?));
+                #[cfg(not(feature = "mqtt"))]
+                {
+                    let _ = mqtt;
+                    anyhow::bail!("MQTT sink configured but the listener was built without the `mqtt` feature");
+                }
+            }
+            SinkKind::PubSub => {
+                let pubsub = config.pubsub.as_ref().context("No Pub/Sub sink configured")?;
+                #[cfg(feature = "pubsub")]
+                return Ok(Box::new(pubsub::PubSubSink::connect(pubsub).await?));
+                #[cfg(not(feature = "pubsub"))]
+                {
+                    let _ = pubsub;
+                    anyhow::bail!("Pub/Sub sink configured but the listener was built without the `pubsub` feature");
+                }
+            }
+            SinkKind::EventHubs => {
+                let eventhubs = config.eventhubs.as_ref().context("No Event Hubs sink configured")?;
+                #[cfg(feature = "eventhubs")]
+                return Ok(Box::new(eventhubs::EventHubsSink::connect(eventhubs)?));
+                #[cfg(not(feature = "eventhubs"))]
+                {
+                    let _ = eventhubs;
+                    anyhow::bail!("Event Hubs sink configured but the listener was built without the `eventhubs` feature");
+                }
+            }
+            SinkKind::ObjectStorage => {
+                let object_storage = config.object_storage.as_ref().context("No object storage sink configured")?;
+                #[cfg(feature = "object-storage")]
+                return Ok(Box::new(object_storage::ObjectStorageSink::connect(object_storage)?));
+                #[cfg(not(feature = "object-storage"))]
+                {
+                    let _ = object_storage;
+                    anyhow::bail!("Object storage sink configured but the listener was built without the `object-storage` feature");
+                }
+            }
+            SinkKind::Sqlite => {
+                let path = config.sqlite.as_ref().context("No SQLite sink configured")?;
+                #[cfg(feature = "sqlite")]
+                return Ok(Box::new(sqlite::SqliteSink::open(path, &config.tables, config.rollups)?));
+                #[cfg(not(feature = "sqlite"))]
+                {
+                    let _ = path;
+                    anyhow::bail!("SQLite sink configured but the listener was built without the `sqlite` feature");
+                }
+            }
+            SinkKind::Slack => {
+                let slack = config.slack.as_ref().context("No Slack sink configured")?;
+                Ok(Box::new(chat::ChatSink::connect(chat::Platform::Slack, slack)?))
+            }
+            SinkKind::Discord => {
+                let discord = config.discord.as_ref().context("No Discord sink configured")?;
+                Ok(Box::new(chat::ChatSink::connect(chat::Platform::Discord, discord)?))
+            }
+            SinkKind::Telegram => {
+                let telegram = config.telegram.as_ref().context("No Telegram sink configured")?;
+                Ok(Box::new(telegram::TelegramSink::connect(telegram)?))
+            }
+            SinkKind::Smtp => {
+                let smtp = config.smtp.as_ref().context("No SMTP sink configured")?;
+                #[cfg(feature = "smtp")]
+                return Ok(Box::new(smtp::SmtpSink::connect(smtp)?));
+                #[cfg(not(feature = "smtp"))]
+                {
+                    let _ = smtp;
+                    anyhow::bail!("SMTP sink configured but the listener was built without the `smtp` feature");
+                }
+            }
+            SinkKind::PagerDuty => {
+                let pagerduty = config.pagerduty.as_ref().context("No PagerDuty sink configured")?;
+                Ok(Box::new(incident::IncidentSink::connect(incident::Service::PagerDuty, pagerduty)?))
+            }
+            SinkKind::Opsgenie => {
+                let opsgenie = config.opsgenie.as_ref().context("No Opsgenie sink configured")?;
+                Ok(Box::new(incident::IncidentSink::connect(incident::Service::Opsgenie, opsgenie)?))
+            }
+            SinkKind::Journald => {
+                let journald = config.journald.as_ref().context("No journald sink configured")?;
+                #[cfg(unix)]
+                return Ok(Box::new(journald::JournaldSink::connect(journald)?));
+                #[cfg(not(unix))]
+                {
+                    let _ = journald;
+                    anyhow::bail!("The journald sink is only available on Linux");
+                }
+            }
+            SinkKind::EventLog => {
+                let eventlog = config.eventlog.as_ref().context("No event log sink configured")?;
+                #[cfg(windows)]
+                return Ok(Box::new(eventlog::EventLogSink::connect(eventlog)?));
+                #[cfg(not(windows))]
+                {
+                    let _ = eventlog;
+                    anyhow::bail!("The event log sink is only available on Windows");
+                }
+            }
+            SinkKind::Syslog => {
+                let syslog = config.syslog.as_ref().context("No syslog sink configured")?;
+                Ok(Box::new(syslog::SyslogSink::connect(syslog).await?))
+            }
+            SinkKind::Parquet => {
+                let dir = config.output_file.as_deref()
+                    .context("Parquet output needs an output directory (--output-file)")?;
+                #[cfg(feature = "parquet")]
+                return Ok(Box::new(self::parquet::ParquetSink::open(dir)?));
+                #[cfg(not(feature = "parquet"))]
+                {
+                    let _ = dir;
+                    anyhow::bail!("Parquet output configured but the listener was built without the `parquet` feature");
+                }
+            }
+        }
+    }
+}
+
+/// Connect every sink configured in `config`
+pub async fn from_config(config: &SinkConfig) -> Result<Vec<Box<dyn Sink>>> {
+    let mut sinks = Vec::new();
+    for kind in SinkKind::configured(config)? {
+        sinks.push(kind.connect(config).await?);
+    }
+    Ok(sinks)
+}
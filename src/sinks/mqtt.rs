@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, TlsConfiguration, Transport};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+use super::Sink;
+use crate::config::MqttConfig;
+use crate::event::EventData;
+
+/// Publishes queued in the client before `send` waits
+const QUEUE_SIZE: usize = 100;
+
+/// Largest event accepted by the client; the MQTT default of 10 KiB is too
+/// small for events with decoded arguments
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// Publishes every event as JSON to an MQTT topic.
+///
+/// The connection is driven by a background task that reconnects when the
+/// broker goes away. With QoS 1 or 2, publishes that weren't acknowledged
+/// yet are sent again after reconnecting.
+pub struct MqttSink {
+    client: AsyncClient,
+    topic: String,
+    qos: QoS,
+    connection: JoinHandle<()>,
+}
+
+impl MqttSink {
+    pub async fn connect(config: &MqttConfig) -> Result<Self> {
+        if config.topic.is_empty() {
+            anyhow::bail!("An MQTT topic is required (--mqtt-topic)");
+        }
+        let qos = match config.qos.unwrap_or(1) {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            other => anyhow::bail!("Invalid MQTT QoS: {} (expected 0, 1, or 2)", other),
+        };
+
+        let (tls, address) = match config.broker.split_once("://") {
+            Some(("mqtt" | "tcp", address)) => (false, address),
+            Some(("mqtts" | "ssl", address)) => (true, address),
+            Some((scheme, _)) => anyhow::bail!("Unsupported MQTT scheme: {} (expected mqtt or mqtts)", scheme),
+            None => (false, config.broker.as_str()),
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().with_context(|| format!("Invalid MQTT port: {}", port))?),
+            None => (address, if tls { 8883 } else { 1883 }),
+        };
+
+        let client_id = config
+            .client_id
+            .clone()
+            .unwrap_or_else(|| format!("listener-{}", &uuid::Uuid::new_v4().simple().to_string()[..8]));
+        let mut options = MqttOptions::new(client_id, host, port);
+        options
+            .set_keep_alive(Duration::from_secs(30))
+            .set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
+        if let Some(ref username) = config.username {
+            options.set_credentials(username, config.password.as_deref().unwrap_or_default());
+        }
+        if tls {
+            options.set_transport(Transport::Tls(TlsConfiguration::Rustls(Arc::new(
+                crate::tls::rustls_client_config(None)?,
+            ))));
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, QUEUE_SIZE);
+
+        // Wait for the broker to accept the connection so that a wrong
+        // address or credentials fail at startup
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => break,
+                Ok(_) => {}
+                Err(e) => return Err(e).with_context(|| format!("Failed to connect to MQTT broker {}", config.broker)),
+            }
+        }
+
+        let connection = tokio::spawn(async move {
+            let mut connected = true;
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) if !connected => {
+                        connected = true;
+                        info!("MQTT connection restored");
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        if connected {
+                            warn!("MQTT connection lost, reconnecting: {}", e);
+                            connected = false;
+                        }
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            client,
+            topic: config.topic.clone(),
+            qos,
+            connection,
+        })
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+#[async_trait]
+impl Sink for MqttSink {
+    fn name(&self) -> &str {
+        "MQTT"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let payload = serde_json::to_vec(event)?;
+        self.client
+            .publish(&self.topic, self.qos, false, payload)
+            .await
+            .context("MQTT publish failed")
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use async_nats::jetstream::{self, context::Publish, stream};
+use async_trait::async_trait;
+use futures::FutureExt;
+use std::collections::VecDeque;
+use std::future::{Future, IntoFuture};
+use std::pin::Pin;
+use tracing::warn;
+
+use super::Sink;
+use crate::config::NatsConfig;
+use crate::event::EventData;
+
+/// Publishes awaiting a JetStream acknowledgement before `send` waits for the oldest
+const MAX_IN_FLIGHT: usize = 10_000;
+
+/// Failed publishes kept for retry; the oldest are dropped beyond this
+const MAX_RETRY: usize = 10_000;
+
+type AckFuture = Pin<Box<dyn Future<Output = Result<jetstream::publish::PublishAck, jetstream::context::PublishError>> + Send>>;
+
+/// A message that can be published again if its acknowledgement fails
+#[derive(Clone)]
+struct Pending {
+    /// `transaction_hash:log_index`, used by JetStream to drop duplicates
+    id: String,
+    payload: String,
+}
+
+/// Publishes every event as JSON to a NATS subject.
+///
+/// With a stream configured, messages go through JetStream: each publish is
+/// acknowledged by the server and failed ones are published again on the
+/// next flush (at-least-once). The message ID lets the stream drop
+/// duplicates within its duplicate window. Without a stream, events are
+/// published with core NATS (at-most-once).
+pub struct NatsSink {
+    client: async_nats::Client,
+    jetstream: Option<jetstream::Context>,
+    subject: String,
+    in_flight: VecDeque<(Pending, AckFuture)>,
+    retry: VecDeque<Pending>,
+}
+
+impl NatsSink {
+    pub async fn connect(config: &NatsConfig) -> Result<Self> {
+        if config.subject.is_empty() {
+            anyhow::bail!("A NATS subject is required (--nats-subject)");
+        }
+
+        let client = async_nats::connect(&config.url)
+            .await
+            .with_context(|| format!("Failed to connect to NATS at {}", config.url))?;
+
+        let jetstream = match config.stream {
+            Some(ref name) => {
+                let context = jetstream::new(client.clone());
+                context
+                    .get_or_create_stream(stream::Config {
+                        name: name.clone(),
+                        subjects: vec![config.subject.clone()],
+                        ..Default::default()
+                    })
+                    .await
+                    .with_context(|| format!("Failed to open JetStream stream {}", name))?;
+                Some(context)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            client,
+            jetstream,
+            subject: config.subject.clone(),
+            in_flight: VecDeque::new(),
+            retry: VecDeque::new(),
+        })
+    }
+
+    async fn publish(&mut self, message: Pending) -> Result<()> {
+        let Some(ref jetstream) = self.jetstream else {
+            return self
+                .client
+                .publish(self.subject.clone(), message.payload.into())
+                .await
+                .context("NATS publish failed");
+        };
+
+        let publish = Publish::build()
+            .payload(message.payload.clone().into())
+            .message_id(&message.id);
+        match jetstream.send_publish(self.subject.clone(), publish).await {
+            Ok(ack) => self.in_flight.push_back((message, ack.into_future())),
+            Err(e) => {
+                self.keep_for_retry(message);
+                return Err(e).context("JetStream publish failed, will retry");
+            }
+        }
+
+        if self.in_flight.len() > MAX_IN_FLIGHT {
+            if let Some((message, ack)) = self.in_flight.pop_front() {
+                self.settle(message, ack.await)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn settle(
+        &mut self,
+        message: Pending,
+        result: Result<jetstream::publish::PublishAck, jetstream::context::PublishError>,
+    ) -> Result<()> {
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                self.keep_for_retry(message);
+                Err(e).context("JetStream acknowledgement failed, will retry")
+            }
+        }
+    }
+
+    fn keep_for_retry(&mut self, message: Pending) {
+        self.retry.push_back(message);
+        if self.retry.len() > MAX_RETRY {
+            self.retry.pop_front();
+            warn!("NATS retry buffer full, dropped the oldest event");
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &str {
+        "NATS"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let message = Pending {
+            id: format!("{}:{}", event.transaction_hash, event.log_index),
+            payload: serde_json::to_string(event)?,
+        };
+        self.publish(message).await
+    }
+
+    /// Collect acknowledgements that have arrived and publish failed
+    /// messages again. Doesn't wait for outstanding acknowledgements.
+    async fn flush(&mut self) -> Result<()> {
+        if self.jetstream.is_none() {
+            return self.client.flush().await.context("NATS flush failed");
+        }
+
+        let mut result = Ok(());
+        while let Some((_, ack)) = self.in_flight.front_mut() {
+            let Some(outcome) = ack.now_or_never() else { break };
+            let (message, _) = self.in_flight.pop_front().expect("front exists");
+            if let Err(e) = self.settle(message, outcome) {
+                result = Err(e);
+            }
+        }
+
+        let retries: Vec<Pending> = self.retry.drain(..).collect();
+        let mut retries = retries.into_iter();
+        while let Some(message) = retries.next() {
+            if let Err(e) = self.publish(message).await {
+                // Still unreachable: keep the rest for the next flush
+                self.retry.extend(retries);
+                return Err(e);
+            }
+        }
+
+        result
+    }
+}
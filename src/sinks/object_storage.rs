@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey};
+use object_store::gcp::{GoogleCloudStorageBuilder, GoogleConfigKey};
+use object_store::path::Path;
+use object_store::{Attribute, Attributes, ObjectStore, PutOptions};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::Sink;
+use crate::config::ObjectStorageConfig;
+use crate::event::EventData;
+
+const DEFAULT_BATCH_SIZE: usize = 10_000;
+
+const DEFAULT_BATCH_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Events kept per batch while uploads fail; the oldest are dropped beyond this
+const MAX_BUFFERED: usize = 100_000;
+
+/// Uploads events to S3 or Google Cloud Storage as gzip-compressed JSON
+/// Lines objects, one batch per chain and contract.
+///
+/// A batch is uploaded once it holds `batch_size` events or its first event
+/// is `batch_interval` old, to
+/// `<prefix>/<chain id>/<contract>/<date>/<first block>-<last block>.json.gz`.
+/// Full batches are only cut between blocks, so the same block range always
+/// maps to the same key. Batches that fail to upload are kept and retried.
+pub struct ObjectStorageSink {
+    store: Box<dyn ObjectStore>,
+    prefix: Path,
+    batch_size: usize,
+    batch_interval: Duration,
+    /// Pending batches by chain and contract
+    batches: BTreeMap<(String, String), Batch>,
+}
+
+struct Batch {
+    events: Vec<EventData>,
+    started: Instant,
+}
+
+impl ObjectStorageSink {
+    /// Set up the store for `s3://bucket/prefix` or `gs://bucket/prefix`.
+    /// Credentials not given in the options are read from the environment.
+    pub fn connect(config: &ObjectStorageConfig) -> Result<Self> {
+        let (scheme, location) = config
+            .url
+            .split_once("://")
+            .with_context(|| format!("Invalid object storage URL: {} (expected s3://bucket or gs://bucket)", config.url))?;
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            anyhow::bail!("Object storage URL has no bucket: {}", config.url);
+        }
+
+        let store: Box<dyn ObjectStore> = match scheme {
+            "s3" | "s3a" => {
+                let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+                for (key, value) in &config.options {
+                    let key: AmazonS3ConfigKey = key.parse().with_context(|| format!("Unknown S3 option: {}", key))?;
+                    builder = builder.with_config(key, value);
+                }
+                Box::new(builder.build().context("Invalid S3 configuration")?)
+            }
+            "gs" => {
+                let mut builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket);
+                for (key, value) in &config.options {
+                    let key: GoogleConfigKey = key.parse().with_context(|| format!("Unknown GCS option: {}", key))?;
+                    builder = builder.with_config(key, value);
+                }
+                Box::new(builder.build().context("Invalid GCS configuration")?)
+            }
+            other => anyhow::bail!("Unsupported object storage scheme: {} (expected s3 or gs)", other),
+        };
+
+        Ok(Self {
+            store,
+            prefix: Path::parse(prefix.trim_end_matches('/')).context("Invalid object storage prefix")?,
+            batch_size: config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            batch_interval: config
+                .batch_interval_secs
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_BATCH_INTERVAL),
+            batches: BTreeMap::new(),
+        })
+    }
+
+    /// Upload and remove the batches selected by `due`. Failed batches stay
+    /// buffered; the first error is returned after trying all of them.
+    async fn upload_where(&mut self, due: impl Fn(&Batch) -> bool) -> Result<()> {
+        let keys: Vec<(String, String)> = self
+            .batches
+            .iter()
+            .filter(|(_, batch)| due(batch))
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        let mut result = Ok(());
+        for key in keys {
+            if let Err(e) = self.upload(&key).await {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    async fn upload(&mut self, key: &(String, String)) -> Result<()> {
+        let Some(batch) = self.batches.get_mut(key) else {
+            return Ok(());
+        };
+        if batch.events.is_empty() {
+            self.batches.remove(key);
+            return Ok(());
+        }
+
+        let path = object_path(&self.prefix, &key.0, &key.1, &batch.events);
+        let body = compress(&batch.events)?;
+        let options = PutOptions {
+            attributes: Attributes::from_iter([(Attribute::ContentType, "application/gzip")]),
+            ..Default::default()
+        };
+
+        match self.store.put_opts(&path, body.into(), options).await {
+            Ok(_) => {
+                self.batches.remove(key);
+                Ok(())
+            }
+            Err(e) => {
+                if batch.events.len() > MAX_BUFFERED {
+                    let dropped = batch.events.len() - MAX_BUFFERED;
+                    batch.events.drain(..dropped);
+                    warn!("Object storage buffer full, dropped {} oldest event(s)", dropped);
+                }
+                Err(e).with_context(|| format!("Upload of {} failed, will retry", path))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for ObjectStorageSink {
+    fn name(&self) -> &str {
+        "Object storage"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let chain = match event.chain_id {
+            Some(id) => id.to_string(),
+            None => event.chain_name.to_lowercase(),
+        };
+        let key = (chain, event.contract_address.to_lowercase());
+
+        // Cut full batches between blocks so a block never spans two objects
+        let full = self.batches.get(&key).is_some_and(|batch| {
+            batch.events.len() >= self.batch_size
+                && batch.events.last().is_some_and(|last| last.block_number != event.block_number)
+        });
+        let uploaded = if full { self.upload(&key).await } else { Ok(()) };
+
+        self.batches
+            .entry(key)
+            .or_insert_with(|| Batch {
+                events: Vec::new(),
+                started: Instant::now(),
+            })
+            .events
+            .push(event.clone());
+        uploaded
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.upload_where(|_| true).await
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        let interval = self.batch_interval;
+        self.upload_where(|batch| batch.started.elapsed() >= interval).await
+    }
+}
+
+/// Key of the object holding `events` of `chain` and `contract`
+fn object_path(prefix: &Path, chain: &str, contract: &str, events: &[EventData]) -> Path {
+    let first = events.iter().map(|e| e.block_number).min().unwrap_or_default();
+    let last = events.iter().map(|e| e.block_number).max().unwrap_or_default();
+    let date = events
+        .first()
+        .and_then(|e| DateTime::parse_from_rfc3339(&e.timestamp).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now)
+        .format("%Y-%m-%d")
+        .to_string();
+
+    prefix
+        .child(chain)
+        .child(contract)
+        .child(date)
+        .child(format!("{:012}-{:012}.json.gz", first, last))
+}
+
+/// `events` as gzip-compressed JSON Lines
+fn compress(events: &[EventData]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for event in events {
+        serde_json::to_writer(&mut encoder, event)?;
+        encoder.write_all(b"\n")?;
+    }
+    Ok(encoder.finish()?)
+}
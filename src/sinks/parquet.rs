@@ -0,0 +1,269 @@
+use anyhow::{Context, Result};
+use arrow_array::builder::{
+    BinaryBuilder, BooleanBuilder, FixedSizeBinaryBuilder, Float64Builder, ListBuilder, MapBuilder, StringBuilder,
+    TimestampMicrosecondBuilder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{Field, Schema};
+use async_trait::async_trait;
+use chrono::DateTime;
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::Sink;
+use crate::event::EventData;
+
+/// Rows per row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+/// Rows per file; a file is written once this many events are buffered
+const FILE_SIZE: usize = 100_000;
+
+/// A file is written at the latest this long after its first event
+const FILE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Writes events into a directory of Parquet files with typed columns.
+///
+/// Events are buffered and written as one file of up to [`FILE_SIZE`] rows,
+/// in row groups of [`ROW_GROUP_SIZE`], at the latest [`FILE_INTERVAL`] after
+/// the first one. Files are named `<first block>-<last block>.parquet` and
+/// only appear once complete, so readers never see a partial file. The
+/// directory can be queried as one table, e.g. with DuckDB's
+/// `read_parquet('events.parquet/*.parquet')`.
+pub struct ParquetSink {
+    dir: PathBuf,
+    buffer: Vec<EventData>,
+    started: Instant,
+}
+
+impl ParquetSink {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        if dir.is_file() {
+            anyhow::bail!(
+                "Parquet output {} is a file; Parquet output is written into a directory",
+                dir.display()
+            );
+        }
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create Parquet directory {}", dir.display()))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            buffer: Vec::new(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Write the buffered events to a new file
+    fn write_file(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let batch = record_batch(&self.buffer)?;
+        let path = self.file_path();
+        let partial = path.with_extension("parquet.partial");
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let file = File::create(&partial).with_context(|| format!("Failed to create {}", partial.display()))?;
+        let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+        writer.write(&batch)?;
+        writer.close()?;
+        std::fs::rename(&partial, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Path of the next file, named after the block range of the buffer
+    fn file_path(&self) -> PathBuf {
+        let first = self.buffer.iter().map(|e| e.block_number).min().unwrap_or_default();
+        let last = self.buffer.iter().map(|e| e.block_number).max().unwrap_or_default();
+        let name = format!("{:012}-{:012}", first, last);
+
+        let mut path = self.dir.join(format!("{}.parquet", name));
+        let mut n = 1;
+        while path.exists() {
+            path = self.dir.join(format!("{}-{}.parquet", name, n));
+            n += 1;
+        }
+        path
+    }
+}
+
+#[async_trait]
+impl Sink for ParquetSink {
+    fn name(&self) -> &str {
+        "Parquet"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        if self.buffer.is_empty() {
+            self.started = Instant::now();
+        }
+        self.buffer.push(event.clone());
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.write_file()
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if self.buffer.len() >= FILE_SIZE || (!self.buffer.is_empty() && self.started.elapsed() >= FILE_INTERVAL) {
+            self.write_file()?;
+        }
+        Ok(())
+    }
+}
+
+/// `events` as Arrow columns. Hashes and addresses are stored as fixed-size
+/// binary; decoded arguments as a map of argument name to value, with
+/// integers as decimal strings since most don't fit 64 bits.
+fn record_batch(events: &[EventData]) -> Result<RecordBatch> {
+    let mut timestamp = TimestampMicrosecondBuilder::with_capacity(events.len()).with_timezone("UTC");
+    let mut chain_id = UInt64Builder::with_capacity(events.len());
+    let mut chain_name = StringBuilder::new();
+    let mut block_number = UInt64Builder::with_capacity(events.len());
+    let mut block_timestamp = TimestampMicrosecondBuilder::with_capacity(events.len()).with_timezone("UTC");
+    let mut transaction_hash = FixedSizeBinaryBuilder::new(32);
+    let mut log_index = UInt64Builder::with_capacity(events.len());
+    let mut contract_address = FixedSizeBinaryBuilder::new(20);
+    let mut topics = ListBuilder::new(FixedSizeBinaryBuilder::new(32));
+    let mut data = BinaryBuilder::new();
+    let mut event_signature = StringBuilder::new();
+    let mut guessed_signature = StringBuilder::new();
+    let mut event_name = StringBuilder::new();
+    let mut decoded = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    let mut decode_error = StringBuilder::new();
+    let mut partial_enrichment = BooleanBuilder::with_capacity(events.len());
+    let mut anomaly = StringBuilder::new();
+    let mut token = StringBuilder::new();
+    let mut tx = StringBuilder::new();
+    let mut proxy = StringBuilder::new();
+    let mut usd = MapBuilder::new(None, StringBuilder::new(), Float64Builder::new());
+    let mut ens = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    let mut labels = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    let mut run_id = StringBuilder::new();
+
+    for event in events {
+        timestamp.append_option(
+            DateTime::parse_from_rfc3339(&event.timestamp)
+                .ok()
+                .map(|t| t.timestamp_micros()),
+        );
+        chain_id.append_option(event.chain_id);
+        chain_name.append_value(&event.chain_name);
+        block_number.append_value(event.block_number);
+        block_timestamp.append_option(
+            event
+                .block_timestamp
+                .as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map(|t| t.timestamp_micros()),
+        );
+        append_hex(&mut transaction_hash, &event.transaction_hash, 32)?;
+        log_index.append_value(event.log_index);
+        append_hex(&mut contract_address, &event.contract_address, 20)?;
+        for topic in &event.topics {
+            append_hex(topics.values(), topic, 32)?;
+        }
+        topics.append(true);
+        data.append_value(hex_bytes(&event.data).unwrap_or_default());
+        event_signature.append_option(event.event_signature.as_deref());
+        guessed_signature.append_option(event.guessed_signature.as_deref());
+        event_name.append_option(event.event_name.as_deref());
+        append_map(&mut decoded, event.decoded.as_ref())?;
+        decode_error.append_option(event.decode_error.as_ref().map(|e| e.reason.as_str()));
+        partial_enrichment.append_value(event.partial_enrichment);
+        anomaly.append_option(event.anomaly.as_ref().map(serde_json::to_string).transpose()?);
+        token.append_option(event.token.as_ref().map(serde_json::to_string).transpose()?);
+        tx.append_option(event.tx.as_ref().map(serde_json::to_string).transpose()?);
+        proxy.append_option(event.proxy.as_ref().map(serde_json::to_string).transpose()?);
+        for (name, value) in event.usd.iter().flatten() {
+            usd.keys().append_value(name);
+            usd.values().append_option(value.as_f64());
+        }
+        usd.append(event.usd.is_some())?;
+        append_map(&mut ens, event.ens.as_ref())?;
+        append_map(&mut labels, event.labels.as_ref())?;
+        run_id.append_option(event.run.as_ref().map(|run| run.run_id.as_str()));
+    }
+
+    let columns: Vec<(&str, ArrayRef, bool)> = vec![
+        ("timestamp", Arc::new(timestamp.finish()), true),
+        ("chain_id", Arc::new(chain_id.finish()), true),
+        ("chain_name", Arc::new(chain_name.finish()), false),
+        ("block_number", Arc::new(block_number.finish()), false),
+        ("block_timestamp", Arc::new(block_timestamp.finish()), true),
+        ("transaction_hash", Arc::new(transaction_hash.finish()), true),
+        ("log_index", Arc::new(log_index.finish()), false),
+        ("contract_address", Arc::new(contract_address.finish()), true),
+        ("topics", Arc::new(topics.finish()), false),
+        ("data", Arc::new(data.finish()), false),
+        ("event_signature", Arc::new(event_signature.finish()), true),
+        ("guessed_signature", Arc::new(guessed_signature.finish()), true),
+        ("event_name", Arc::new(event_name.finish()), true),
+        ("decoded", Arc::new(decoded.finish()), true),
+        ("decode_error", Arc::new(decode_error.finish()), true),
+        ("partial_enrichment", Arc::new(partial_enrichment.finish()), false),
+        ("anomaly", Arc::new(anomaly.finish()), true),
+        ("token", Arc::new(token.finish()), true),
+        ("tx", Arc::new(tx.finish()), true),
+        ("proxy", Arc::new(proxy.finish()), true),
+        ("usd", Arc::new(usd.finish()), true),
+        ("ens", Arc::new(ens.finish()), true),
+        ("labels", Arc::new(labels.finish()), true),
+        ("run_id", Arc::new(run_id.finish()), true),
+    ];
+    let schema = Schema::new(
+        columns
+            .iter()
+            .map(|(name, array, nullable)| Field::new(*name, array.data_type().clone(), *nullable))
+            .collect::<Vec<_>>(),
+    );
+    Ok(RecordBatch::try_new(
+        Arc::new(schema),
+        columns.into_iter().map(|(_, array, _)| array).collect(),
+    )?)
+}
+
+fn hex_bytes(value: &str) -> Option<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x")).ok()
+}
+
+/// Append `value` as `width` bytes, or null if it isn't a hex string of that length
+fn append_hex(builder: &mut FixedSizeBinaryBuilder, value: &str, width: usize) -> Result<()> {
+    match hex_bytes(value).filter(|bytes| bytes.len() == width) {
+        Some(bytes) => builder.append_value(bytes)?,
+        None => builder.append_null(),
+    }
+    Ok(())
+}
+
+/// Append decoded arguments; strings are stored as is, other values as JSON
+fn append_map(builder: &mut MapBuilder<StringBuilder, StringBuilder>, map: Option<&Map<String, Value>>) -> Result<()> {
+    let Some(map) = map else {
+        builder.append(false)?;
+        return Ok(());
+    };
+    for (key, value) in map {
+        builder.keys().append_value(key);
+        match value {
+            Value::String(value) => builder.values().append_value(value),
+            other => builder.values().append_value(other.to_string()),
+        }
+    }
+    builder.append(true)?;
+    Ok(())
+}
@@ -0,0 +1,459 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tokio_postgres::config::SslMode;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{Client, NoTls};
+use tracing::warn;
+
+use super::columns::{quote, typed_tables, ColumnType, ColumnValue, TypedTable, EVENT_COLUMNS};
+use super::rollups::{Rollup, EVENT_COUNTS_TABLE, TRANSFER_VOLUME_TABLE};
+use super::Sink;
+use crate::config::{PostgresConfig, TableConfig};
+use crate::event::EventData;
+use crate::run::RunMetadata;
+
+const DEFAULT_TABLE: &str = "events";
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Longest channel name PostgreSQL accepts, in bytes
+const MAX_CHANNEL_LEN: usize = 63;
+
+/// Events kept while the database is unreachable; the oldest are dropped beyond this
+const MAX_BUFFERED: usize = 10_000;
+
+const COLUMNS: [&str; 13] = [
+    "chain_id",
+    "chain_name",
+    "block_number",
+    "transaction_hash",
+    "log_index",
+    "contract_address",
+    "topics",
+    "data",
+    "event_signature",
+    "event_name",
+    "decoded",
+    "detected_at",
+    "run_id",
+];
+
+/// Upserts events into a PostgreSQL table, keyed on `(transaction_hash, log_index)`
+/// so that re-delivered events overwrite rather than duplicate rows.
+///
+/// The events table and a `runs` table are created if they don't exist.
+/// With a notify channel, each row written is announced on it with its `id`
+/// as the payload, in the same statement. Typed tables get a row for each
+/// of their events, upserted the same way. Rollup tables are updated by the
+/// same statement too, with the rows it inserted.
+pub struct PostgresSink {
+    client: Client,
+    table: String,
+    typed_tables: Vec<TypedTable>,
+    rollups: bool,
+    batch_size: usize,
+    notify_channel: Option<String>,
+    buffer: Vec<EventData>,
+}
+
+impl PostgresSink {
+    /// Connect and create the tables, typed and rollup tables included. TLS
+    /// is used unless the URL sets `sslmode=disable`.
+    pub async fn connect(config: &PostgresConfig, tables: &[TableConfig], rollups: bool) -> Result<Self> {
+        let table = config.table.clone().unwrap_or_else(|| DEFAULT_TABLE.to_string());
+        if !is_valid_table_name(&table) {
+            anyhow::bail!("Invalid PostgreSQL table name: {}", table);
+        }
+        if let Some(ref channel) = config.notify_channel {
+            if channel.is_empty() || channel.len() > MAX_CHANNEL_LEN {
+                anyhow::bail!("Invalid PostgreSQL notify channel: {:?}", channel);
+            }
+        }
+
+        let client = connect(&config.url).await?;
+
+        client
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {table} (
+                    id BIGSERIAL PRIMARY KEY,
+                    chain_id BIGINT,
+                    chain_name TEXT NOT NULL,
+                    block_number BIGINT NOT NULL,
+                    transaction_hash TEXT NOT NULL,
+                    log_index BIGINT NOT NULL,
+                    contract_address TEXT NOT NULL,
+                    topics TEXT[] NOT NULL,
+                    data TEXT NOT NULL,
+                    event_signature TEXT,
+                    event_name TEXT,
+                    decoded JSONB,
+                    detected_at TIMESTAMPTZ NOT NULL,
+                    run_id TEXT,
+                    UNIQUE (transaction_hash, log_index)
+                );
+                CREATE TABLE IF NOT EXISTS runs (
+                    run_id TEXT PRIMARY KEY,
+                    listener_version TEXT NOT NULL,
+                    config_hash TEXT NOT NULL,
+                    started_at TIMESTAMPTZ NOT NULL
+                );"
+            ))
+            .await
+            .with_context(|| format!("Failed to create PostgreSQL table {}", table))?;
+
+        let typed_tables = typed_tables(tables)?;
+        for typed in &typed_tables {
+            create_typed_table(&client, typed).await?;
+        }
+        if rollups {
+            client
+                .batch_execute(&format!(
+                    "CREATE TABLE IF NOT EXISTS {EVENT_COUNTS_TABLE} (
+                        chain_id BIGINT NOT NULL,
+                        contract_address TEXT NOT NULL,
+                        event TEXT NOT NULL,
+                        hour TIMESTAMPTZ NOT NULL,
+                        events BIGINT NOT NULL,
+                        PRIMARY KEY (chain_id, contract_address, event, hour)
+                    );
+                    CREATE TABLE IF NOT EXISTS {TRANSFER_VOLUME_TABLE} (
+                        chain_id BIGINT NOT NULL,
+                        token TEXT NOT NULL,
+                        day DATE NOT NULL,
+                        transfers BIGINT NOT NULL,
+                        volume NUMERIC NOT NULL,
+                        PRIMARY KEY (chain_id, token, day)
+                    );"
+                ))
+                .await
+                .context("Failed to create PostgreSQL rollup tables")?;
+        }
+
+        Ok(Self {
+            client,
+            table,
+            typed_tables,
+            rollups,
+            batch_size: config.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+            notify_channel: config.notify_channel.clone(),
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Upsert `batch` in one statement
+    async fn insert_batch(&self, batch: &[EventData]) -> Result<()> {
+        // A single upsert can't touch the same row twice, so keep only the
+        // latest copy of each (transaction_hash, log_index)
+        let mut latest: HashMap<(&str, u64), &EventData> = HashMap::new();
+        for event in batch {
+            latest.insert((&event.transaction_hash, event.log_index), event);
+        }
+        let mut events: Vec<&EventData> = latest.into_values().collect();
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::with_capacity(events.len() * COLUMNS.len());
+        let mut rows = Vec::with_capacity(events.len());
+        for event in &events {
+            let n = params.len();
+            rows.push(format!(
+                "(${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}, ${}::text::timestamptz, ${})",
+                n + 1, n + 2, n + 3, n + 4, n + 5, n + 6, n + 7, n + 8, n + 9, n + 10, n + 11, n + 12, n + 13
+            ));
+            params.push(Box::new(event.chain_id.map(|id| id as i64)));
+            params.push(Box::new(event.chain_name.clone()));
+            params.push(Box::new(event.block_number as i64));
+            params.push(Box::new(event.transaction_hash.clone()));
+            params.push(Box::new(event.log_index as i64));
+            params.push(Box::new(event.contract_address.clone()));
+            params.push(Box::new(event.topics.clone()));
+            params.push(Box::new(event.data.clone()));
+            params.push(Box::new(event.event_signature.clone()));
+            params.push(Box::new(event.event_name.clone()));
+            params.push(Box::new(event.decoded.clone().map(serde_json::Value::Object)));
+            params.push(Box::new(event.timestamp.clone()));
+            params.push(Box::new(event.run.as_ref().map(|run| run.run_id.clone())));
+        }
+
+        let updates: Vec<String> = COLUMNS
+            .iter()
+            .filter(|c| !matches!(**c, "transaction_hash" | "log_index"))
+            .map(|c| format!("{c} = EXCLUDED.{c}"))
+            .collect();
+        let mut query = format!(
+            "INSERT INTO {} ({}) VALUES {} ON CONFLICT (transaction_hash, log_index) DO UPDATE SET {}",
+            self.table,
+            COLUMNS.join(", "),
+            rows.join(", "),
+            updates.join(", ")
+        );
+        // Rollups and notifications take the rows written from the upsert,
+        // so they only happen if it commits
+        if self.rollups || self.notify_channel.is_some() {
+            let mut steps = vec![format!(
+                "written AS ({} RETURNING id, transaction_hash, log_index, xmax = 0 AS inserted)",
+                query
+            )];
+            if self.rollups {
+                steps.extend(rollup_steps(&events, &mut params));
+            }
+            let select = match self.notify_channel {
+                Some(ref channel) => {
+                    params.push(Box::new(channel.clone()));
+                    format!("SELECT pg_notify(${}, id::text) FROM written", params.len())
+                }
+                None => "SELECT id FROM written".to_string(),
+            };
+            query = format!("WITH {} {}", steps.join(", "), select);
+        }
+
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect();
+        self.client.execute(&query, &params).await?;
+        Ok(())
+    }
+
+    /// Upsert the events of `typed` in `batch` in one statement
+    async fn insert_typed(&self, typed: &TypedTable, batch: &[EventData]) -> Result<()> {
+        let mut latest: HashMap<(&str, u64), &EventData> = HashMap::new();
+        for event in batch.iter().filter(|event| typed.matches(event)) {
+            latest.insert((&event.transaction_hash, event.log_index), event);
+        }
+        if latest.is_empty() {
+            return Ok(());
+        }
+        let mut events: Vec<&EventData> = latest.into_values().collect();
+        events.sort_by_key(|e| (e.block_number, e.log_index));
+
+        let mut columns: Vec<String> = EVENT_COLUMNS.iter().map(|c| c.to_string()).collect();
+        columns.extend(typed.columns.iter().map(|(name, _)| quote(name)));
+
+        let mut params: Vec<Box<dyn ToSql + Sync + Send>> = Vec::with_capacity(events.len() * columns.len());
+        let mut rows = Vec::with_capacity(events.len());
+        for event in events {
+            let n = params.len();
+            let mut placeholders = vec![
+                format!("${}", n + 1),
+                format!("${}", n + 2),
+                format!("${}", n + 3),
+                format!("${}", n + 4),
+                format!("${}", n + 5),
+                format!("${}::text::timestamptz", n + 6),
+                format!("${}", n + 7),
+            ];
+            params.push(Box::new(event.chain_id.map(|id| id as i64)));
+            params.push(Box::new(event.block_number as i64));
+            params.push(Box::new(event.transaction_hash.clone()));
+            params.push(Box::new(event.log_index as i64));
+            params.push(Box::new(event.contract_address.clone()));
+            params.push(Box::new(event.timestamp.clone()));
+            params.push(Box::new(event.run.as_ref().map(|run| run.run_id.clone())));
+            for ((_, kind), value) in typed.columns.iter().zip(typed.values(event)) {
+                let cast = if *kind == ColumnType::Numeric { "::text::numeric" } else { "" };
+                placeholders.push(format!("${}{}", params.len() + 1, cast));
+                params.push(to_sql(*kind, value));
+            }
+            rows.push(format!("({})", placeholders.join(", ")));
+        }
+
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|c| !matches!(c.as_str(), "transaction_hash" | "log_index"))
+            .map(|c| format!("{c} = EXCLUDED.{c}"))
+            .collect();
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES {} ON CONFLICT (transaction_hash, log_index) DO UPDATE SET {}",
+            typed.table,
+            columns.join(", "),
+            rows.join(", "),
+            updates.join(", ")
+        );
+
+        let params: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect();
+        self.client
+            .execute(&query, &params)
+            .await
+            .with_context(|| format!("Failed to write PostgreSQL table {}", typed.table))?;
+        Ok(())
+    }
+}
+
+/// The steps of the upsert's `WITH` that add the rows it inserted, rather
+/// than overwrote, to the rollup tables
+fn rollup_steps(events: &[&EventData], params: &mut Vec<Box<dyn ToSql + Sync + Send>>) -> Vec<String> {
+    let mut rows = Vec::with_capacity(events.len());
+    for event in events {
+        let rollup = Rollup::of(event);
+        let n = params.len();
+        rows.push(format!(
+            "(${}::text, ${}::bigint, ${}::bigint, ${}::text, ${}::text, ${}::text::timestamptz, ${}::text::date, ${}::text::numeric)",
+            n + 1, n + 2, n + 3, n + 4, n + 5, n + 6, n + 7, n + 8
+        ));
+        params.push(Box::new(event.transaction_hash.clone()));
+        params.push(Box::new(event.log_index as i64));
+        params.push(Box::new(rollup.chain_id));
+        params.push(Box::new(rollup.contract));
+        params.push(Box::new(rollup.event));
+        params.push(Box::new(rollup.hour));
+        params.push(Box::new(rollup.day));
+        params.push(Box::new(rollup.transfer.map(|amount| amount.to_string())));
+    }
+    vec![
+        format!(
+            "inserted AS (
+                SELECT r.* FROM (VALUES {}) AS r (transaction_hash, log_index, chain_id, contract_address, event, hour, day, amount)
+                JOIN written USING (transaction_hash, log_index)
+                WHERE written.inserted
+            )",
+            rows.join(", ")
+        ),
+        format!(
+            "counted AS (
+                INSERT INTO {EVENT_COUNTS_TABLE} (chain_id, contract_address, event, hour, events)
+                SELECT chain_id, contract_address, event, hour, count(*) FROM inserted
+                GROUP BY chain_id, contract_address, event, hour
+                ON CONFLICT (chain_id, contract_address, event, hour)
+                DO UPDATE SET events = {EVENT_COUNTS_TABLE}.events + EXCLUDED.events
+            )"
+        ),
+        format!(
+            "summed AS (
+                INSERT INTO {TRANSFER_VOLUME_TABLE} (chain_id, token, day, transfers, volume)
+                SELECT chain_id, contract_address, day, count(*), sum(amount) FROM inserted
+                WHERE amount IS NOT NULL
+                GROUP BY chain_id, contract_address, day
+                ON CONFLICT (chain_id, token, day)
+                DO UPDATE SET transfers = {TRANSFER_VOLUME_TABLE}.transfers + EXCLUDED.transfers,
+                    volume = {TRANSFER_VOLUME_TABLE}.volume + EXCLUDED.volume
+            )"
+        ),
+    ]
+}
+
+/// Create `typed`'s table, and add the columns mapped since it was created
+async fn create_typed_table(client: &Client, typed: &TypedTable) -> Result<()> {
+    let columns: Vec<String> = typed
+        .columns
+        .iter()
+        .map(|(name, kind)| format!("{} {}", quote(name), sql_type(*kind)))
+        .collect();
+    let mut sql = format!(
+        "CREATE TABLE IF NOT EXISTS {} (
+            id BIGSERIAL PRIMARY KEY,
+            chain_id BIGINT,
+            block_number BIGINT NOT NULL,
+            transaction_hash TEXT NOT NULL,
+            log_index BIGINT NOT NULL,
+            contract_address TEXT NOT NULL,
+            detected_at TIMESTAMPTZ NOT NULL,
+            run_id TEXT,
+            {},
+            UNIQUE (transaction_hash, log_index)
+        );",
+        typed.table,
+        columns.join(", ")
+    );
+    for column in &columns {
+        sql.push_str(&format!("ALTER TABLE {} ADD COLUMN IF NOT EXISTS {};", typed.table, column));
+    }
+    client
+        .batch_execute(&sql)
+        .await
+        .with_context(|| format!("Failed to create PostgreSQL table {}", typed.table))
+}
+
+fn sql_type(kind: ColumnType) -> &'static str {
+    match kind {
+        ColumnType::Numeric => "NUMERIC",
+        ColumnType::Text => "TEXT",
+        ColumnType::Bytea => "BYTEA",
+    }
+}
+
+/// `value` as a parameter of a `kind` column; numerics are sent as text
+/// and cast, since they can exceed every integer type
+fn to_sql(kind: ColumnType, value: ColumnValue) -> Box<dyn ToSql + Sync + Send> {
+    match (kind, value) {
+        (_, ColumnValue::Numeric(s) | ColumnValue::Text(s)) => Box::new(Some(s)),
+        (_, ColumnValue::Bytea(bytes)) => Box::new(Some(bytes)),
+        (ColumnType::Bytea, ColumnValue::Null) => Box::new(None::<Vec<u8>>),
+        (_, ColumnValue::Null) => Box::new(None::<String>),
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    fn name(&self) -> &str {
+        "PostgreSQL"
+    }
+
+    async fn start_run(&mut self, run: &RunMetadata) -> Result<()> {
+        self.client
+            .execute(
+                "INSERT INTO runs (run_id, listener_version, config_hash, started_at)
+                 VALUES ($1, $2, $3, $4::text::timestamptz)
+                 ON CONFLICT (run_id) DO UPDATE SET config_hash = EXCLUDED.config_hash",
+                &[&run.run_id, &run.listener_version, &run.config_hash, &run.started_at],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        self.buffer.push(event.clone());
+        if self.buffer.len() > MAX_BUFFERED {
+            let dropped = self.buffer.len() - MAX_BUFFERED;
+            self.buffer.drain(..dropped);
+            warn!("PostgreSQL buffer full, dropped {} oldest event(s)", dropped);
+        }
+
+        if self.buffer.len() >= self.batch_size {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        // Batches that fail stay buffered and are retried on the next flush
+        while !self.buffer.is_empty() {
+            let len = self.buffer.len().min(self.batch_size);
+            self.insert_batch(&self.buffer[..len]).await?;
+            for typed in &self.typed_tables {
+                self.insert_typed(typed, &self.buffer[..len]).await?;
+            }
+            self.buffer.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+/// Connect to the database at `url`. TLS is used unless the URL sets `sslmode=disable`.
+pub(crate) async fn connect(url: &str) -> Result<Client> {
+    let pg_config: tokio_postgres::Config = url.parse()
+        .context("Invalid PostgreSQL connection string")?;
+
+    let client = if pg_config.get_ssl_mode() == SslMode::Disable {
+        let (client, connection) = pg_config.connect(NoTls).await
+            .context("Failed to connect to PostgreSQL")?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("PostgreSQL connection closed: {}", e);
+            }
+        });
+        client
+    } else {
+        let tls = tokio_postgres_rustls::MakeRustlsConnect::new(crate::tls::rustls_client_config(None)?);
+        let (client, connection) = pg_config.connect(tls).await
+            .context("Failed to connect to PostgreSQL")?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                warn!("PostgreSQL connection closed: {}", e);
+            }
+        });
+        client
+    };
+    Ok(client)
+}
+
+pub(crate) use super::columns::is_valid_table_name;
@@ -0,0 +1,325 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+use super::Sink;
+use crate::config::PubSubConfig;
+use crate::event::EventData;
+
+const DEFAULT_ENDPOINT: &str = "https://pubsub.googleapis.com";
+
+const SCOPE: &str = "https://www.googleapis.com/auth/pubsub";
+
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Messages per publish request; the API takes up to 1,000 and 10 MB
+const MAX_BATCH: usize = 1_000;
+
+const MAX_BATCH_BYTES: usize = 9 * 1024 * 1024;
+
+/// Events kept while publishing fails; the oldest are dropped beyond this
+const MAX_BUFFERED: usize = 100_000;
+
+/// Access tokens are renewed this long before they expire
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+/// Publishes every event as JSON to a Google Cloud Pub/Sub topic, with its
+/// contract address as ordering key, so subscriptions with message ordering
+/// receive a contract's events in order.
+///
+/// Events are published in batches on every tick. A batch that fails stays
+/// buffered ahead of later events and is published again on the next
+/// flush, which keeps each ordering key in order.
+pub struct PubSubSink {
+    client: reqwest::Client,
+    /// `<endpoint>/v1/projects/<project>/topics/<topic>`
+    topic_url: String,
+    auth: Auth,
+    /// Messages not published yet, oldest first
+    buffer: VecDeque<Message>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Message {
+    /// The event's JSON, base64-encoded
+    data: String,
+    ordering_key: String,
+    attributes: BTreeMap<&'static str, String>,
+}
+
+/// Where access tokens come from
+enum Auth {
+    /// The emulator takes none
+    None,
+    ServiceAccount {
+        key: ServiceAccountKey,
+        token: Option<Token>,
+    },
+    /// The metadata server of GCE, GKE, or Cloud Run
+    Metadata { token: Option<Token> },
+}
+
+struct Token {
+    value: String,
+    expires: Instant,
+}
+
+#[derive(Deserialize)]
+struct ServiceAccountKey {
+    client_email: String,
+    private_key: String,
+    token_uri: String,
+    project_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl PubSubSink {
+    /// Check the topic, creating it when configured to. Credentials are the
+    /// configured service account key, else `GOOGLE_APPLICATION_CREDENTIALS`,
+    /// else the metadata server; the emulator (`PUBSUB_EMULATOR_HOST`)
+    /// needs none.
+    pub async fn connect(config: &PubSubConfig) -> Result<Self> {
+        if config.topic.is_empty() {
+            anyhow::bail!("A Pub/Sub topic is required (--pubsub-topic)");
+        }
+        let emulator = std::env::var("PUBSUB_EMULATOR_HOST").ok().filter(|host| !host.is_empty());
+        let endpoint = match (&config.endpoint, &emulator) {
+            (Some(endpoint), _) => endpoint.clone(),
+            (None, Some(host)) => format!("http://{}", host),
+            (None, None) => DEFAULT_ENDPOINT.to_string(),
+        };
+        let credentials = config
+            .credentials
+            .clone()
+            .or_else(|| std::env::var("GOOGLE_APPLICATION_CREDENTIALS").ok());
+        let auth = match credentials {
+            _ if emulator.is_some() && config.endpoint.is_none() => Auth::None,
+            Some(path) => {
+                let key = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read Pub/Sub credentials {}", path))?;
+                let key: ServiceAccountKey = serde_json::from_str(&key)
+                    .with_context(|| format!("Invalid service account key {}", path))?;
+                Auth::ServiceAccount { key, token: None }
+            }
+            None => Auth::Metadata { token: None },
+        };
+
+        let project = config
+            .project
+            .clone()
+            .or_else(|| std::env::var("GOOGLE_CLOUD_PROJECT").ok())
+            .or_else(|| match auth {
+                Auth::ServiceAccount { ref key, .. } => key.project_id.clone(),
+                _ => None,
+            })
+            .context("A Pub/Sub project is required (--pubsub-project or GOOGLE_CLOUD_PROJECT)")?;
+
+        let mut sink = Self {
+            client: reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            topic_url: format!(
+                "{}/v1/projects/{}/topics/{}",
+                endpoint.trim_end_matches('/'),
+                project,
+                config.topic
+            ),
+            auth,
+            buffer: VecDeque::new(),
+        };
+        sink.check_topic(config.create_topic).await?;
+        Ok(sink)
+    }
+
+    /// Fail unless the topic exists, creating it first if `create`
+    async fn check_topic(&mut self, create: bool) -> Result<()> {
+        let token = self.auth.token(&self.client).await?;
+        let request = match create {
+            true => self.client.put(&self.topic_url).json(&serde_json::json!({})),
+            false => self.client.get(&self.topic_url),
+        };
+        let response = authorized(request, token.as_deref())
+            .send()
+            .await
+            .context("Pub/Sub request failed")?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::CONFLICT if create => Ok(()),
+            StatusCode::NOT_FOUND => anyhow::bail!(
+                "Pub/Sub topic {} doesn't exist (use --pubsub-create-topic to create it)",
+                self.topic_url.rsplit_once("/v1/").map_or(self.topic_url.as_str(), |(_, path)| path)
+            ),
+            status => anyhow::bail!("Pub/Sub answered {}: {}", status, error_message(response).await),
+        }
+    }
+}
+
+impl Auth {
+    /// A valid access token, fetched again when close to expiring
+    async fn token(&mut self, client: &reqwest::Client) -> Result<Option<String>> {
+        let cached = match self {
+            Auth::None => return Ok(None),
+            Auth::ServiceAccount { token, .. } | Auth::Metadata { token } => token,
+        };
+        if let Some(ref token) = cached {
+            if token.expires > Instant::now() + TOKEN_MARGIN {
+                return Ok(Some(token.value.clone()));
+            }
+        }
+
+        let response = match self {
+            Auth::ServiceAccount { ref key, .. } => {
+                let iat = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let claims = Claims {
+                    iss: &key.client_email,
+                    scope: SCOPE,
+                    aud: &key.token_uri,
+                    iat,
+                    exp: iat + 3600,
+                };
+                let signing_key = EncodingKey::from_rsa_pem(key.private_key.as_bytes())
+                    .context("Invalid private key in the Pub/Sub credentials")?;
+                let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &signing_key)?;
+                client
+                    .post(&key.token_uri)
+                    .form(&[
+                        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                        ("assertion", assertion.as_str()),
+                    ])
+                    .send()
+                    .await
+                    .context("Google token request failed")?
+            }
+            Auth::Metadata { .. } => client
+                .get(METADATA_TOKEN_URL)
+                .header("Metadata-Flavor", "Google")
+                .send()
+                .await
+                .context("No Pub/Sub credentials: set credentials or GOOGLE_APPLICATION_CREDENTIALS, or run on GCP")?,
+            Auth::None => unreachable!(),
+        };
+        if !response.status().is_success() {
+            let status = response.status();
+            anyhow::bail!("Google token request answered {}: {}", status, error_message(response).await);
+        }
+        let token: TokenResponse = response.json().await.context("Invalid Google token response")?;
+
+        let value = token.access_token.clone();
+        if let Auth::ServiceAccount { token: cached, .. } | Auth::Metadata { token: cached } = self {
+            *cached = Some(Token {
+                value: token.access_token,
+                expires: Instant::now() + Duration::from_secs(token.expires_in),
+            });
+        }
+        Ok(Some(value))
+    }
+}
+
+fn authorized(request: reqwest::RequestBuilder, token: Option<&str>) -> reqwest::RequestBuilder {
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+/// The error message of a failed Google API response
+async fn error_message(response: reqwest::Response) -> String {
+    let body = response.text().await.unwrap_or_default();
+    serde_json::from_str::<serde_json::Value>(&body)
+        .ok()
+        .and_then(|body| body["error"]["message"].as_str().map(str::to_string))
+        .unwrap_or(body)
+}
+
+#[async_trait]
+impl Sink for PubSubSink {
+    fn name(&self) -> &str {
+        "Pub/Sub"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let mut attributes = BTreeMap::new();
+        if let Some(chain_id) = event.chain_id {
+            attributes.insert("chain_id", chain_id.to_string());
+        }
+        attributes.insert("contract", event.contract_address.to_lowercase());
+        if let Some(ref name) = event.event_name {
+            attributes.insert("event", name.clone());
+        }
+        self.buffer.push_back(Message {
+            data: STANDARD.encode(serde_json::to_vec(event)?),
+            ordering_key: event.contract_address.to_lowercase(),
+            attributes,
+        });
+        if self.buffer.len() > MAX_BUFFERED {
+            self.buffer.pop_front();
+            warn!("Pub/Sub buffer full, dropped the oldest event");
+        }
+
+        if self.buffer.len() >= MAX_BATCH {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Publish the buffered events, a batch at a time, stopping at the
+    /// first batch that fails
+    async fn flush(&mut self) -> Result<()> {
+        while !self.buffer.is_empty() {
+            let mut count = 0;
+            let mut bytes = 0;
+            for message in &self.buffer {
+                let size = message.data.len() + message.ordering_key.len() + 200;
+                if count == MAX_BATCH || (count > 0 && bytes + size > MAX_BATCH_BYTES) {
+                    break;
+                }
+                count += 1;
+                bytes += size;
+            }
+
+            let token = self.auth.token(&self.client).await?;
+            let messages: Vec<&Message> = self.buffer.range(..count).collect();
+            let request = self
+                .client
+                .post(format!("{}:publish", self.topic_url))
+                .json(&serde_json::json!({ "messages": messages }));
+            let response = authorized(request, token.as_deref())
+                .send()
+                .await
+                .context("Pub/Sub publish failed, will retry")?;
+            if !response.status().is_success() {
+                let status = response.status();
+                anyhow::bail!(
+                    "Pub/Sub publish answered {}, will retry: {}",
+                    status,
+                    error_message(response).await
+                );
+            }
+            self.buffer.drain(..count);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncConnectionConfig;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::warn;
+
+use super::Sink;
+use crate::config::RedisConfig;
+use crate::event::EventData;
+
+/// Commands sent in one pipeline when retrying
+const BATCH_SIZE: usize = 100;
+
+/// Events kept while Redis is unreachable; the oldest are dropped beyond this
+const MAX_RETRY: usize = 10_000;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Publishes every event as JSON to a Redis stream (`XADD`, field `event`),
+/// a pub/sub channel (`PUBLISH`), or both.
+///
+/// Each event is written before `send` returns, so a slow Redis slows the
+/// listener down instead of queueing without limit. Events that fail are
+/// kept and written again, in order, on the next flush.
+pub struct RedisSink {
+    client: redis::Client,
+    /// Dropped after a failed write and reopened by the next one
+    conn: Option<MultiplexedConnection>,
+    stream: Option<String>,
+    channel: Option<String>,
+    max_len: Option<usize>,
+    retry: VecDeque<String>,
+}
+
+impl RedisSink {
+    pub async fn connect(config: &RedisConfig) -> Result<Self> {
+        if config.stream.is_none() && config.channel.is_none() {
+            anyhow::bail!("A Redis stream or channel is required (--redis-stream or --redis-channel)");
+        }
+        if config.url.starts_with("rediss://") {
+            // rustls needs a process-wide crypto provider; keep one set by someone else
+            let _ = rustls::crypto::ring::default_provider().install_default();
+        }
+
+        let client = redis::Client::open(config.url.as_str()).context("Invalid Redis URL")?;
+        let conn = open(&client).await?;
+
+        Ok(Self {
+            client,
+            conn: Some(conn),
+            stream: config.stream.clone(),
+            channel: config.channel.clone(),
+            max_len: config.max_len,
+            retry: VecDeque::new(),
+        })
+    }
+
+    /// Write `payloads` in a single pipeline
+    async fn write(&mut self, payloads: &[String]) -> Result<()> {
+        let mut pipe = redis::pipe();
+        for payload in payloads {
+            if let Some(ref stream) = self.stream {
+                let xadd = pipe.cmd("XADD").arg(stream);
+                if let Some(max_len) = self.max_len {
+                    xadd.arg("MAXLEN").arg("~").arg(max_len);
+                }
+                xadd.arg("*").arg("event").arg(payload).ignore();
+            }
+            if let Some(ref channel) = self.channel {
+                pipe.cmd("PUBLISH").arg(channel).arg(payload).ignore();
+            }
+        }
+
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => open(&self.client).await?,
+        };
+        pipe.query_async::<()>(&mut conn).await?;
+        self.conn = Some(conn);
+        Ok(())
+    }
+
+    fn keep_for_retry(&mut self, payloads: impl IntoIterator<Item = String>) {
+        self.retry.extend(payloads);
+        if self.retry.len() > MAX_RETRY {
+            let dropped = self.retry.len() - MAX_RETRY;
+            self.retry.drain(..dropped);
+            warn!("Redis retry buffer full, dropped {} oldest event(s)", dropped);
+        }
+    }
+}
+
+#[async_trait]
+impl Sink for RedisSink {
+    fn name(&self) -> &str {
+        "Redis"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let payload = serde_json::to_string(event)?;
+        // Don't overtake events still waiting for a retry
+        if !self.retry.is_empty() {
+            self.keep_for_retry([payload]);
+            return Ok(());
+        }
+
+        let payloads = [payload];
+        if let Err(e) = self.write(&payloads).await {
+            self.keep_for_retry(payloads);
+            return Err(e).context("Redis write failed, will retry");
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        while !self.retry.is_empty() {
+            let len = self.retry.len().min(BATCH_SIZE);
+            let batch: Vec<String> = self.retry.iter().take(len).cloned().collect();
+            // Failed batches stay queued for the next flush
+            self.write(&batch).await.context("Redis write failed")?;
+            self.retry.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+async fn open(client: &redis::Client) -> Result<MultiplexedConnection> {
+    let config = AsyncConnectionConfig::new()
+        .set_connection_timeout(TIMEOUT)
+        .set_response_timeout(TIMEOUT);
+    client
+        .get_multiplexed_async_connection_with_config(&config)
+        .await
+        .context("Failed to connect to Redis")
+}
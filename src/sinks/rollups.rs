@@ -0,0 +1,79 @@
+//! Rollup tables of the database sinks (`--rollups`): small aggregates kept
+//! up to date as events are written, so dashboards can read them instead of
+//! scanning the events table.
+//!
+//! - `event_counts_hourly`: events per chain, contract, event, and hour
+//! - `transfer_volume_daily`: ERC-20 transfers and the sum of their amounts
+//!   per chain, token, and day
+//!
+//! Only events written for the first time are counted, so replayed events
+//! don't count twice. Hours and days are UTC, of the block's timestamp when
+//! the event has one and of its detection otherwise.
+
+use chrono::{DateTime, Utc};
+use ethers::types::U256;
+
+use crate::event::EventData;
+
+pub const EVENT_COUNTS_TABLE: &str = "event_counts_hourly";
+pub const TRANSFER_VOLUME_TABLE: &str = "transfer_volume_daily";
+
+/// topic0 of `Transfer(address,address,uint256)`
+const TRANSFER_TOPIC: &str = "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+/// What one event adds to the rollups
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rollup {
+    /// 0 for chains configured without an ID
+    pub chain_id: i64,
+    pub contract: String,
+    /// Event name, else the name in its signature, else its topic0
+    pub event: String,
+    /// Start of the hour, e.g. `2024-05-01T12:00:00Z`
+    pub hour: String,
+    /// e.g. `2024-05-01`
+    pub day: String,
+    /// Amount of an ERC-20 transfer, in the token's base units
+    pub transfer: Option<U256>,
+}
+
+impl Rollup {
+    pub fn of(event: &EventData) -> Self {
+        let name = event.event_name.clone().or_else(|| {
+            event
+                .event_signature
+                .as_deref()
+                .or(event.guessed_signature.as_deref())
+                .and_then(|signature| signature.split('(').next())
+                .map(str::to_string)
+        });
+        let at = event
+            .block_timestamp
+            .as_deref()
+            .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+            .or_else(|| DateTime::parse_from_rfc3339(&event.timestamp).ok())
+            .map_or_else(Utc::now, |at| at.with_timezone(&Utc));
+
+        Self {
+            chain_id: event.chain_id.map_or(0, |id| id as i64),
+            contract: event.contract_address.clone(),
+            event: name
+                .or_else(|| event.topics.first().map(|topic| topic.to_lowercase()))
+                .unwrap_or_else(|| "anonymous".to_string()),
+            hour: at.format("%Y-%m-%dT%H:00:00Z").to_string(),
+            day: at.format("%Y-%m-%d").to_string(),
+            transfer: transfer_amount(event),
+        }
+    }
+}
+
+/// The amount of an ERC-20 `Transfer`, read from the log itself so it
+/// doesn't need an ABI. ERC-721 transfers, whose token ID is indexed too,
+/// have a fourth topic and are left out.
+fn transfer_amount(event: &EventData) -> Option<U256> {
+    if event.topics.len() != 3 || !event.topics[0].eq_ignore_ascii_case(TRANSFER_TOPIC) {
+        return None;
+    }
+    let data = hex::decode(event.data.trim_start_matches("0x")).ok()?;
+    (data.len() == 32).then(|| U256::from_big_endian(&data))
+}
@@ -0,0 +1,217 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::Sink;
+use crate::config::SmtpConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+
+/// Events waiting to be mailed; the oldest are dropped beyond this
+const MAX_PENDING: usize = 10_000;
+
+/// Most events in one digest email; larger digests are split
+const MAX_DIGEST_EVENTS: usize = 1_000;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mails events, each as its own email or collected into a digest every
+/// `digest_minutes`.
+///
+/// Every email has a readable body with the decoded arguments and explorer
+/// links, and the events as a JSON Lines attachment for record keeping.
+/// Emails the server fails to take are sent again on the next tick, or with
+/// the next digest, unless it rejected them permanently.
+pub struct SmtpSink {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    subject: String,
+    digest: Option<Duration>,
+    next_digest: Instant,
+    pending: VecDeque<EventData>,
+}
+
+impl SmtpSink {
+    pub fn connect(config: &SmtpConfig) -> Result<Self> {
+        if config.host.is_empty() {
+            anyhow::bail!("An SMTP host is required (--smtp-host)");
+        }
+        let from: Mailbox = config.from.parse()
+            .with_context(|| format!("Invalid sender address: {}", config.from))?;
+        if config.to.is_empty() {
+            anyhow::bail!("At least one recipient is required (--smtp-to)");
+        }
+        let to = config
+            .to
+            .iter()
+            .map(|to| to.parse().with_context(|| format!("Invalid recipient address: {}", to)))
+            .collect::<Result<Vec<Mailbox>>>()?;
+
+        let mut builder = match config.tls.as_deref().unwrap_or("starttls") {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?,
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+            other => anyhow::bail!("Unknown SMTP TLS mode: {} (expected starttls, tls, or none)", other),
+        };
+        if let Some(port) = config.port {
+            builder = builder.port(port);
+        }
+        if let Some(ref username) = config.username {
+            let password = config
+                .password
+                .clone()
+                .or_else(|| std::env::var("SMTP_PASSWORD").ok())
+                .context("An SMTP password is required with a username (--smtp-password or SMTP_PASSWORD)")?;
+            builder = builder.credentials(Credentials::new(username.clone(), password));
+        }
+
+        let digest = match config.digest_minutes {
+            Some(0) => anyhow::bail!("The SMTP digest interval must be at least a minute"),
+            Some(minutes) => Some(Duration::from_secs(minutes * 60)),
+            None => None,
+        };
+
+        Ok(Self {
+            transport: builder.timeout(Some(TIMEOUT)).build(),
+            from,
+            to,
+            subject: config.subject.clone().unwrap_or_else(|| "{title}".to_string()),
+            digest,
+            next_digest: Instant::now() + digest.unwrap_or_default(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Mail every pending event: one email each, or digests of up to
+    /// [`MAX_DIGEST_EVENTS`]
+    async fn send_pending(&mut self) -> Result<()> {
+        while !self.pending.is_empty() {
+            let len = match self.digest {
+                Some(_) => self.pending.len().min(MAX_DIGEST_EVENTS),
+                None => 1,
+            };
+            let events: Vec<&EventData> = self.pending.iter().take(len).collect();
+            let email = self.email(&events)?;
+
+            match self.transport.send(email).await {
+                Ok(_) => {}
+                Err(e) if e.is_permanent() => {
+                    self.pending.drain(..len);
+                    return Err(e).with_context(|| format!("email of {} event(s) rejected", len));
+                }
+                Err(e) => return Err(e).context("failed to send email, will retry"),
+            }
+            self.pending.drain(..len);
+        }
+        Ok(())
+    }
+
+    fn email(&self, events: &[&EventData]) -> Result<Message> {
+        let notifications: Vec<Notification> = events.iter().map(|event| Notification::from_event(event)).collect();
+        let (subject, mut body) = match notifications.as_slice() {
+            [single] if self.digest.is_none() => (single.render(&self.subject, str::to_string), String::new()),
+            _ => (
+                digest_subject(&notifications),
+                format!(
+                    "{} event(s) from {} to {}\n\n",
+                    events.len(),
+                    events.first().map_or("", |event| event.timestamp.as_str()),
+                    events.last().map_or("", |event| event.timestamp.as_str())
+                ),
+            ),
+        };
+        body.push_str(&notifications.iter().map(render).collect::<Vec<_>>().join("\n\n"));
+
+        let mut records = String::new();
+        for event in events {
+            records.push_str(&serde_json::to_string(event)?);
+            records.push('\n');
+        }
+        let attachment = Attachment::new("events.jsonl".to_string())
+            .body(records, ContentType::parse("application/x-ndjson")?);
+
+        let mut builder = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            builder = builder.to(to.clone());
+        }
+        Ok(builder.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(body))
+                .singlepart(attachment),
+        )?)
+    }
+}
+
+#[async_trait]
+impl Sink for SmtpSink {
+    fn name(&self) -> &str {
+        "SMTP"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        self.pending.push_back(event.clone());
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            anyhow::bail!("too many events waiting to be mailed, dropped {} oldest", dropped);
+        }
+        Ok(())
+    }
+
+    /// Mail everything pending, including a digest that isn't due yet
+    async fn flush(&mut self) -> Result<()> {
+        self.send_pending().await
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if let Some(digest) = self.digest {
+            if Instant::now() < self.next_digest {
+                return Ok(());
+            }
+            self.next_digest = Instant::now() + digest;
+        }
+        self.send_pending().await
+    }
+}
+
+/// `5 events: OwnershipTransferred (3), RoleGranted (2)`
+fn digest_subject(notifications: &[Notification]) -> String {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for notification in notifications {
+        *counts.entry(&notification.event).or_default() += 1;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    let names: Vec<String> = counts.iter().map(|(name, count)| format!("{} ({})", name, count)).collect();
+    format!("{} event(s): {}", notifications.len(), names.join(", "))
+}
+
+fn render(notification: &Notification) -> String {
+    let with_link = |value: &str, url: &Option<String>| match url {
+        Some(url) => format!("{} ({})", value, url),
+        None => value.to_string(),
+    };
+    let mut text = notification.title.clone();
+    for (name, value) in &notification.fields {
+        text.push_str(&format!("\n  {}: {}", name, value));
+    }
+    let mut contract = with_link(&notification.contract, &notification.contract_url);
+    if let Some(ref name) = notification.contract_name {
+        contract = format!("{} [{}]", contract, name);
+    }
+    text.push_str(&format!(
+        "\n  Contract:    {}\n  Transaction: {}\n  Block:       {}, log {}\n  Detected:    {}",
+        contract,
+        with_link(&notification.transaction_hash, &notification.transaction_url),
+        with_link(&notification.block_number.to_string(), &notification.block_url),
+        notification.log_index,
+        notification.timestamp
+    ));
+    text
+}
@@ -0,0 +1,519 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use rusqlite::types::Value;
+use ethers::types::U256;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use std::path::Path;
+
+use super::columns::{quote, typed_tables, ColumnType, ColumnValue, TypedTable, EVENT_COLUMNS};
+use super::rollups::{Rollup, EVENT_COUNTS_TABLE, TRANSFER_VOLUME_TABLE};
+use super::Sink;
+use crate::config::TableConfig;
+use crate::event::{compute_event_topic, EventData};
+use crate::run::RunMetadata;
+use crate::signatures::{normalize_signature, KNOWN_EVENTS};
+
+/// Events written per transaction; smaller batches are written on every flush
+const BATCH_SIZE: usize = 500;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS events (
+        id INTEGER PRIMARY KEY,
+        chain_id INTEGER,
+        chain_name TEXT NOT NULL,
+        block_number INTEGER NOT NULL,
+        transaction_hash TEXT NOT NULL,
+        log_index INTEGER NOT NULL,
+        contract_address TEXT NOT NULL,
+        topic0 TEXT,
+        topics TEXT NOT NULL,
+        data TEXT NOT NULL,
+        event_signature TEXT,
+        event_name TEXT,
+        decoded TEXT,
+        humanized TEXT,
+        decode_error TEXT,
+        partial_enrichment INTEGER NOT NULL DEFAULT 0,
+        anomaly TEXT,
+        token TEXT,
+        usd TEXT,
+        ens TEXT,
+        labels TEXT,
+        block_timestamp TEXT,
+        tx TEXT,
+        guessed_signature TEXT,
+        proxy TEXT,
+        detected_at TEXT NOT NULL,
+        run_id TEXT,
+        config_hash TEXT,
+        prev_hash TEXT,
+        hash TEXT,
+        UNIQUE (transaction_hash, log_index)
+    );
+    CREATE INDEX IF NOT EXISTS events_block_number ON events (block_number);
+    CREATE INDEX IF NOT EXISTS events_topic0 ON events (topic0);
+    CREATE INDEX IF NOT EXISTS events_event_name ON events (event_name);
+    CREATE TABLE IF NOT EXISTS runs (
+        run_id TEXT PRIMARY KEY,
+        listener_version TEXT NOT NULL,
+        config_hash TEXT NOT NULL,
+        started_at TEXT NOT NULL
+    );";
+
+/// Stores events in a local SQLite database, upserting on
+/// `(transaction_hash, log_index)`. Read them back with [`query_events`].
+/// Typed tables get a row for each of their events, and rollup tables are
+/// updated with the events new to the database, in the same transaction.
+pub struct SqliteSink {
+    conn: Connection,
+    /// Each typed table and its upsert
+    typed_tables: Vec<(TypedTable, String)>,
+    rollups: bool,
+    buffer: Vec<EventData>,
+}
+
+impl SqliteSink {
+    /// Open or create the database and its tables, typed and rollup tables
+    /// included
+    pub fn open(path: impl AsRef<Path>, tables: &[TableConfig], rollups: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open SQLite database {}", path.display()))?;
+        // WAL lets `query` read while the listener is writing
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to create tables in {}", path.display()))?;
+        add_missing_columns(&conn)?;
+
+        let mut typed = Vec::new();
+        for table in typed_tables(tables)? {
+            create_typed_table(&conn, &table)?;
+            let upsert = typed_upsert(&table);
+            typed.push((table, upsert));
+        }
+        if rollups {
+            // Volumes are decimal text, since they can exceed every SQLite number type
+            conn.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS {EVENT_COUNTS_TABLE} (
+                    chain_id INTEGER NOT NULL,
+                    contract_address TEXT NOT NULL,
+                    event TEXT NOT NULL,
+                    hour TEXT NOT NULL,
+                    events INTEGER NOT NULL,
+                    PRIMARY KEY (chain_id, contract_address, event, hour)
+                );
+                CREATE TABLE IF NOT EXISTS {TRANSFER_VOLUME_TABLE} (
+                    chain_id INTEGER NOT NULL,
+                    token TEXT NOT NULL,
+                    day TEXT NOT NULL,
+                    transfers INTEGER NOT NULL,
+                    volume TEXT NOT NULL,
+                    PRIMARY KEY (chain_id, token, day)
+                );"
+            ))
+            .with_context(|| format!("Failed to create rollup tables in {}", path.display()))?;
+        }
+
+        Ok(Self {
+            conn,
+            typed_tables: typed,
+            rollups,
+            buffer: Vec::new(),
+        })
+    }
+}
+
+/// Add `event`, which is new to the database, to the rollup tables
+fn add_to_rollups(conn: &Connection, event: &EventData) -> Result<()> {
+    let rollup = Rollup::of(event);
+    conn.prepare_cached(&format!(
+        "INSERT INTO {EVENT_COUNTS_TABLE} (chain_id, contract_address, event, hour, events)
+         VALUES (?1, ?2, ?3, ?4, 1)
+         ON CONFLICT (chain_id, contract_address, event, hour) DO UPDATE SET events = events + 1"
+    ))?
+    .execute(params![rollup.chain_id, rollup.contract, rollup.event, rollup.hour])?;
+
+    if let Some(amount) = rollup.transfer {
+        let volume: Option<String> = conn
+            .prepare_cached(&format!(
+                "SELECT volume FROM {TRANSFER_VOLUME_TABLE} WHERE chain_id = ?1 AND token = ?2 AND day = ?3"
+            ))?
+            .query_row(params![rollup.chain_id, rollup.contract, rollup.day], |row| row.get(0))
+            .optional()?;
+        let volume = volume
+            .and_then(|volume| U256::from_dec_str(&volume).ok())
+            .unwrap_or_default()
+            .saturating_add(amount);
+        conn.prepare_cached(&format!(
+            "INSERT INTO {TRANSFER_VOLUME_TABLE} (chain_id, token, day, transfers, volume)
+             VALUES (?1, ?2, ?3, 1, ?4)
+             ON CONFLICT (chain_id, token, day) DO UPDATE SET transfers = transfers + 1, volume = excluded.volume"
+        ))?
+        .execute(params![rollup.chain_id, rollup.contract, rollup.day, volume.to_string()])?;
+    }
+    Ok(())
+}
+
+/// Create `typed`'s table, and add the columns mapped since it was created.
+/// Numeric columns have no declared type, so that integers too large for
+/// 64 bits are kept exactly, as text, rather than converted to floats.
+fn create_typed_table(conn: &Connection, typed: &TypedTable) -> Result<()> {
+    if typed.table.contains('.') {
+        anyhow::bail!("SQLite tables can't be schema-qualified: {}", typed.table);
+    }
+    let definition = |(name, kind): &(String, ColumnType)| match kind {
+        ColumnType::Numeric => quote(name),
+        ColumnType::Text => format!("{} TEXT", quote(name)),
+        ColumnType::Bytea => format!("{} BLOB", quote(name)),
+    };
+    let columns: Vec<String> = typed.columns.iter().map(definition).collect();
+    conn.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {table} (
+            id INTEGER PRIMARY KEY,
+            chain_id INTEGER,
+            block_number INTEGER NOT NULL,
+            transaction_hash TEXT NOT NULL,
+            log_index INTEGER NOT NULL,
+            contract_address TEXT NOT NULL,
+            detected_at TEXT NOT NULL,
+            run_id TEXT,
+            {columns},
+            UNIQUE (transaction_hash, log_index)
+        );
+        CREATE INDEX IF NOT EXISTS {table}_block_number ON {table} (block_number);",
+        table = typed.table,
+        columns = columns.join(", ")
+    ))
+    .with_context(|| format!("Failed to create table {}", typed.table))?;
+
+    let mut statement = conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", typed.table))?;
+    let existing: Vec<String> = statement.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+    for (column, definition) in typed.columns.iter().zip(&columns) {
+        if !existing.iter().any(|c| c == &column.0) {
+            conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {}", typed.table, definition))?;
+        }
+    }
+    Ok(())
+}
+
+/// The upsert of a row of `typed`, with the fixed columns first
+fn typed_upsert(typed: &TypedTable) -> String {
+    let mut columns: Vec<String> = EVENT_COLUMNS.iter().map(|c| c.to_string()).collect();
+    columns.extend(typed.columns.iter().map(|(name, _)| quote(name)));
+    let placeholders: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let updates: Vec<String> = columns
+        .iter()
+        .filter(|c| !matches!(c.as_str(), "transaction_hash" | "log_index"))
+        .map(|c| format!("{c} = excluded.{c}"))
+        .collect();
+    format!(
+        "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (transaction_hash, log_index) DO UPDATE SET {}",
+        typed.table,
+        columns.join(", "),
+        placeholders.join(", "),
+        updates.join(", ")
+    )
+}
+
+/// The row of `event` in `typed`'s table
+fn typed_row(typed: &TypedTable, event: &EventData) -> Vec<Value> {
+    let mut row = vec![
+        event.chain_id.map_or(Value::Null, |id| Value::Integer(id as i64)),
+        Value::Integer(event.block_number as i64),
+        Value::Text(event.transaction_hash.clone()),
+        Value::Integer(event.log_index as i64),
+        Value::Text(event.contract_address.clone()),
+        Value::Text(event.timestamp.clone()),
+        event.run.as_ref().map_or(Value::Null, |run| Value::Text(run.run_id.clone())),
+    ];
+    row.extend(typed.values(event).into_iter().map(|value| match value {
+        ColumnValue::Numeric(s) => s.parse().map_or(Value::Text(s), Value::Integer),
+        ColumnValue::Text(s) => Value::Text(s),
+        ColumnValue::Bytea(bytes) => Value::Blob(bytes),
+        ColumnValue::Null => Value::Null,
+    }));
+    row
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    fn name(&self) -> &str {
+        "SQLite"
+    }
+
+    async fn start_run(&mut self, run: &RunMetadata) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO runs (run_id, listener_version, config_hash, started_at)
+             VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (run_id) DO UPDATE SET config_hash = excluded.config_hash",
+            params![run.run_id, run.listener_version, run.config_hash, run.started_at],
+        )?;
+        Ok(())
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        self.buffer.push(event.clone());
+        if self.buffer.len() >= BATCH_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO events (chain_id, chain_name, block_number, transaction_hash, log_index,
+                     contract_address, topic0, topics, data, event_signature, event_name, decoded,
+                     humanized, detected_at, run_id, config_hash, prev_hash, hash, decode_error, partial_enrichment,
+                     anomaly, token, usd, ens, labels, block_timestamp, tx, guessed_signature, proxy)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21,
+                     ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29)
+                 ON CONFLICT (transaction_hash, log_index) DO UPDATE SET
+                     chain_id = excluded.chain_id, chain_name = excluded.chain_name,
+                     block_number = excluded.block_number, contract_address = excluded.contract_address,
+                     topic0 = excluded.topic0, topics = excluded.topics, data = excluded.data,
+                     event_signature = excluded.event_signature, event_name = excluded.event_name,
+                     decoded = excluded.decoded, humanized = excluded.humanized, detected_at = excluded.detected_at,
+                     run_id = excluded.run_id, config_hash = excluded.config_hash,
+                     prev_hash = excluded.prev_hash, hash = excluded.hash,
+                     decode_error = excluded.decode_error, partial_enrichment = excluded.partial_enrichment,
+                     anomaly = excluded.anomaly, token = excluded.token, usd = excluded.usd,
+                     ens = excluded.ens, labels = excluded.labels, block_timestamp = excluded.block_timestamp,
+                     tx = excluded.tx, guessed_signature = excluded.guessed_signature, proxy = excluded.proxy",
+            )?;
+            let mut stored = tx.prepare_cached("SELECT 1 FROM events WHERE transaction_hash = ?1 AND log_index = ?2")?;
+            for event in &self.buffer {
+                // Replayed events overwrite their row but aren't counted again
+                let new = self.rollups && !stored.exists(params![event.transaction_hash, event.log_index as i64])?;
+                insert.execute(params![
+                    event.chain_id.map(|id| id as i64),
+                    event.chain_name,
+                    event.block_number as i64,
+                    event.transaction_hash,
+                    event.log_index as i64,
+                    event.contract_address,
+                    event.topics.first().map(|t| t.to_lowercase()),
+                    serde_json::to_string(&event.topics)?,
+                    event.data,
+                    event.event_signature,
+                    event.event_name,
+                    event.decoded.as_ref().map(serde_json::to_string).transpose()?,
+                    event.humanized.as_ref().map(serde_json::to_string).transpose()?,
+                    event.timestamp,
+                    event.run.as_ref().map(|run| &run.run_id),
+                    event.run.as_ref().map(|run| &run.config_hash),
+                    event.prev_hash,
+                    event.hash,
+                    event.decode_error.as_ref().map(serde_json::to_string).transpose()?,
+                    event.partial_enrichment,
+                    event.anomaly.as_ref().map(serde_json::to_string).transpose()?,
+                    event.token.as_ref().map(serde_json::to_string).transpose()?,
+                    event.usd.as_ref().map(serde_json::to_string).transpose()?,
+                    event.ens.as_ref().map(serde_json::to_string).transpose()?,
+                    event.labels.as_ref().map(serde_json::to_string).transpose()?,
+                    event.block_timestamp,
+                    event.tx.as_ref().map(serde_json::to_string).transpose()?,
+                    event.guessed_signature,
+                    event.proxy.as_ref().map(serde_json::to_string).transpose()?,
+                ])?;
+                if new {
+                    add_to_rollups(&tx, event)?;
+                }
+            }
+            for (typed, upsert) in &self.typed_tables {
+                let mut insert = tx.prepare_cached(upsert)?;
+                for event in self.buffer.iter().filter(|event| typed.matches(event)) {
+                    insert
+                        .execute(rusqlite::params_from_iter(typed_row(typed, event)))
+                        .with_context(|| format!("Failed to write table {}", typed.table))?;
+                }
+            }
+        }
+        tx.commit()?;
+
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// Selects events from a SQLite database; `None` fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    /// A topic0 hash, a full signature (`Transfer(address,address,uint256)`),
+    /// or a bare event name (`Transfer`) matched against ABI-decoded names,
+    /// filtered signatures, and well-known signatures
+    pub event: Option<String>,
+    pub contract: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Read events written by [`SqliteSink`], in the order they were emitted
+pub fn query_events(path: impl AsRef<Path>, query: &EventQuery) -> Result<Vec<EventData>> {
+    let path = path.as_ref();
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open SQLite database {}", path.display()))?;
+
+    let mut conditions = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+
+    if let Some(from) = query.from_block {
+        values.push(Value::Integer(from as i64));
+        conditions.push(format!("e.block_number >= ?{}", values.len()));
+    }
+    if let Some(to) = query.to_block {
+        values.push(Value::Integer(to as i64));
+        conditions.push(format!("e.block_number <= ?{}", values.len()));
+    }
+    if let Some(ref contract) = query.contract {
+        values.push(Value::Text(contract.to_lowercase()));
+        conditions.push(format!("lower(e.contract_address) = ?{}", values.len()));
+    }
+    if let Some(ref event) = query.event {
+        if event.starts_with("0x") {
+            values.push(Value::Text(event.to_lowercase()));
+            conditions.push(format!("e.topic0 = ?{}", values.len()));
+        } else if event.contains('(') {
+            values.push(Value::Text(format!("{:?}", compute_event_topic(&normalize_signature(event)?))));
+            conditions.push(format!("e.topic0 = ?{}", values.len()));
+        } else {
+            let mut alternatives = Vec::new();
+            values.push(Value::Text(event.clone()));
+            alternatives.push(format!("e.event_name = ?{}", values.len()));
+            values.push(Value::Text(format!("{}(%", event)));
+            alternatives.push(format!("e.event_signature LIKE ?{}", values.len()));
+            for signature in KNOWN_EVENTS.iter().filter(|s| s.split('(').next() == Some(event.as_str())) {
+                values.push(Value::Text(format!("{:?}", compute_event_topic(signature))));
+                alternatives.push(format!("e.topic0 = ?{}", values.len()));
+            }
+            conditions.push(format!("({})", alternatives.join(" OR ")));
+        }
+    }
+
+    let mut sql = String::from(
+        "SELECT e.chain_id, e.chain_name, e.block_number, e.transaction_hash, e.log_index,
+                e.contract_address, e.topics, e.data, e.event_signature, e.event_name, e.decoded,
+                e.detected_at, e.prev_hash, e.hash, e.run_id, e.config_hash,
+                r.listener_version, r.started_at, e.humanized, e.decode_error, e.partial_enrichment,
+                e.anomaly, e.token, e.usd, e.ens, e.labels, e.block_timestamp, e.tx, e.guessed_signature,
+                e.proxy
+         FROM events e LEFT JOIN runs r ON r.run_id = e.run_id",
+    );
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+    sql.push_str(" ORDER BY e.id");
+    if let Some(limit) = query.limit {
+        sql.push_str(&format!(" LIMIT {}", limit));
+    }
+
+    let mut statement = conn.prepare(&sql)?;
+    let mut rows = statement.query(rusqlite::params_from_iter(values))?;
+
+    let mut events = Vec::new();
+    while let Some(row) = rows.next()? {
+        let topics: String = row.get(6)?;
+        let decoded: Option<String> = row.get(10)?;
+        let run_id: Option<String> = row.get(14)?;
+        let config_hash: Option<String> = row.get(15)?;
+        let listener_version: Option<String> = row.get(16)?;
+        let started_at: Option<String> = row.get(17)?;
+        let humanized: Option<String> = row.get(18)?;
+        let decode_error: Option<String> = row.get(19)?;
+        let anomaly: Option<String> = row.get(21)?;
+        let token: Option<String> = row.get(22)?;
+        let usd: Option<String> = row.get(23)?;
+        let ens: Option<String> = row.get(24)?;
+        let labels: Option<String> = row.get(25)?;
+        let tx: Option<String> = row.get(27)?;
+        let proxy: Option<String> = row.get(29)?;
+
+        let run = match (run_id, config_hash, listener_version, started_at) {
+            (Some(run_id), Some(config_hash), Some(listener_version), Some(started_at)) => Some(RunMetadata {
+                run_id,
+                listener_version,
+                config_hash,
+                started_at,
+            }),
+            _ => None,
+        };
+
+        events.push(EventData {
+            chain_id: row.get::<_, Option<i64>>(0)?.map(|id| id as u64),
+            chain_name: row.get(1)?,
+            block_number: row.get::<_, i64>(2)? as u64,
+            transaction_hash: row.get(3)?,
+            log_index: row.get::<_, i64>(4)? as u64,
+            contract_address: row.get(5)?,
+            topics: serde_json::from_str(&topics)?,
+            data: row.get(7)?,
+            event_signature: row.get(8)?,
+            guessed_signature: row.get(28)?,
+            event_name: row.get(9)?,
+            decoded: decoded.map(|d| serde_json::from_str(&d)).transpose()?,
+            humanized: humanized.map(|h| serde_json::from_str(&h)).transpose()?,
+            decode_error: decode_error.map(|e| serde_json::from_str(&e)).transpose()?,
+            partial_enrichment: row.get(20)?,
+            anomaly: anomaly.map(|a| serde_json::from_str(&a)).transpose()?,
+            token: token.map(|t| serde_json::from_str(&t)).transpose()?,
+            tx: tx.map(|t| serde_json::from_str(&t)).transpose()?,
+            proxy: proxy.map(|p| serde_json::from_str(&p)).transpose()?,
+            usd: usd.map(|u| serde_json::from_str(&u)).transpose()?,
+            ens: ens.map(|e| serde_json::from_str(&e)).transpose()?,
+            labels: labels.map(|l| serde_json::from_str(&l)).transpose()?,
+            raw: None,
+            block_timestamp: row.get(26)?,
+            block_hash: None,
+            timestamp: row.get(11)?,
+            prev_hash: row.get(12)?,
+            hash: row.get(13)?,
+            run,
+        });
+    }
+
+    Ok(events)
+}
+
+/// Bring an `events` table created by an older version up to date
+fn add_missing_columns(conn: &Connection) -> Result<()> {
+    let mut statement = conn.prepare("SELECT name FROM pragma_table_info('events')")?;
+    let columns: Vec<String> = statement.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+
+    for (column, definition) in [
+        ("humanized", "TEXT"),
+        ("decode_error", "TEXT"),
+        ("partial_enrichment", "INTEGER NOT NULL DEFAULT 0"),
+        ("anomaly", "TEXT"),
+        ("token", "TEXT"),
+        ("usd", "TEXT"),
+        ("ens", "TEXT"),
+        ("labels", "TEXT"),
+        ("block_timestamp", "TEXT"),
+        ("tx", "TEXT"),
+        ("guessed_signature", "TEXT"),
+        ("proxy", "TEXT"),
+    ] {
+        if !columns.iter().any(|c| c == column) {
+            conn.execute_batch(&format!("ALTER TABLE events ADD COLUMN {} {}", column, definition))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` is a SQLite database, judged by its header
+pub fn is_sqlite_file(path: impl AsRef<Path>) -> bool {
+    use std::io::Read;
+
+    let mut header = [0u8; 16];
+    std::fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok()
+        && &header == b"SQLite format 3\0"
+}
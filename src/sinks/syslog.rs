@@ -0,0 +1,418 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::VecDeque;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::warn;
+
+use super::incident::{Severities, Severity};
+use super::Sink;
+use crate::config::SyslogConfig;
+use crate::event::EventData;
+use crate::notification::Notification;
+use crate::siem::SiemFormat;
+
+const DEFAULT_APP_NAME: &str = "listener";
+
+/// Private enterprise number of the structured data IDs by default: the one
+/// RFC 5612 reserves for documentation
+const DEFAULT_ENTERPRISE_ID: u32 = 32473;
+
+/// Longest UDP datagram sent; longer messages are cut
+const MAX_DATAGRAM: usize = 65_000;
+
+/// Messages kept for a TCP or TLS collector that can't be reached; older
+/// ones are dropped beyond this
+const MAX_PENDING: usize = 10_000;
+
+/// Longest SD-NAME and MSGID
+const MAX_NAME_LEN: usize = 32;
+
+/// Sends every event to a syslog collector as an RFC 5424 message, over UDP
+/// (RFC 5426), TCP (RFC 6587), or TLS (RFC 5425).
+///
+/// The message is a summary such as `Transfer on Polygon: from=0x...
+/// value=1000`, its MSGID the event name, and the event's fields go in
+/// structured data elements that collectors parse into fields:
+///
+/// ```text
+/// [chain@32473 id="137" name="Polygon"]
+/// [contract@32473 address="0x3c49..." name="USDC"]
+/// [event@32473 name="Transfer" block="51234567" tx="0x..." logIndex="3" ...]
+/// [args@32473 from="0x..." to="0x..." value="1000000"]
+/// ```
+///
+/// The severity follows the one configured for the event, as for the
+/// incident sinks: `critical` is 2, `error` 3, `warning` 4, and `info` and
+/// events without a severity 6. With the `cef` or `leef` format, the
+/// message is instead a [CEF or LEEF](crate::siem) line, without structured
+/// data. Over TCP and TLS, messages that couldn't be written are kept and
+/// written again, on a new connection, on the next event or tick.
+pub struct SyslogSink {
+    transport: Transport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    enterprise_id: u32,
+    severities: Severities,
+    /// CEF or LEEF line sent as the message instead of structured data
+    siem: Option<SiemFormat>,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Stream(Box<Stream>),
+}
+
+/// A TCP or TLS collector and the messages waiting for it
+struct Stream {
+    host: String,
+    port: u16,
+    #[cfg(feature = "rustls")]
+    tls: Option<tokio_rustls::TlsConnector>,
+    octet_counting: bool,
+    connection: Option<Box<dyn AsyncWrite + Unpin + Send>>,
+    pending: VecDeque<Vec<u8>>,
+}
+
+impl SyslogSink {
+    pub async fn connect(config: &SyslogConfig) -> Result<Self> {
+        let (scheme, address) = config.address.split_once("://").unwrap_or(("udp", config.address.as_str()));
+        let default_port = match scheme {
+            "udp" => 514,
+            "tcp" => 601,
+            "tls" => 6514,
+            other => anyhow::bail!("Unknown syslog transport: {} (expected udp, tcp, or tls)", other),
+        };
+        // An IPv6 host is bracketed, so only `]:` ends it
+        let (host, port) = match address.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, rest)) => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => anyhow::bail!("Invalid syslog address: {}", address),
+                },
+                None => anyhow::bail!("Invalid syslog address: {} (missing ])", address),
+            },
+            None => match address.split_once(':') {
+                Some((host, port)) if !port.contains(':') => (host, Some(port)),
+                _ => (address, None),
+            },
+        };
+        let port = match port {
+            Some(port) => port.parse().with_context(|| format!("Invalid syslog port: {}", port))?,
+            None => default_port,
+        };
+        let host = host.to_string();
+        let octet_counting = match config.framing.as_deref() {
+            None | Some("octet-counting") => true,
+            Some("newline") => false,
+            Some(other) => anyhow::bail!("Unknown syslog framing: {} (expected octet-counting or newline)", other),
+        };
+        if config.tls.is_some() && scheme != "tls" {
+            anyhow::bail!("Syslog TLS settings need a tls:// address");
+        }
+        #[cfg(not(feature = "rustls"))]
+        if scheme == "tls" {
+            anyhow::bail!("Syslog over TLS needs the rustls feature");
+        }
+
+        let transport = match scheme {
+            "udp" => {
+                let socket = UdpSocket::bind(if host.contains(':') { "[::]:0" } else { "0.0.0.0:0" })
+                    .await
+                    .context("Failed to open a UDP socket for syslog")?;
+                socket
+                    .connect((host.as_str(), port))
+                    .await
+                    .with_context(|| format!("Failed to resolve syslog collector {}:{}", host, port))?;
+                Transport::Udp(socket)
+            }
+            _ => {
+                let mut stream = Stream {
+                    #[cfg(feature = "rustls")]
+                    tls: match scheme {
+                        "tls" => Some(tokio_rustls::TlsConnector::from(std::sync::Arc::new(
+                            crate::tls::rustls_client_config(config.tls.as_ref())?,
+                        ))),
+                        _ => None,
+                    },
+                    host,
+                    port,
+                    octet_counting,
+                    connection: None,
+                    pending: VecDeque::new(),
+                };
+                stream.connect().await?;
+                Transport::Stream(Box::new(stream))
+            }
+        };
+
+        let siem = match config.format.as_deref() {
+            None | Some("rfc5424") => None,
+            Some(format) => Some(SiemFormat::parse(format, &config.events)?.with_context(|| {
+                format!("Unknown syslog format: {} (expected rfc5424, cef, or leef)", format)
+            })?),
+        };
+
+        let app_name = config.app_name.as_deref().unwrap_or(DEFAULT_APP_NAME);
+        Ok(Self {
+            transport,
+            facility: facility(config.facility.as_deref().unwrap_or("user"))?,
+            hostname: header_field(config.hostname.clone().or_else(hostname).as_deref().unwrap_or("-"), 255),
+            app_name: header_field(app_name, 48),
+            enterprise_id: config.enterprise_id.unwrap_or(DEFAULT_ENTERPRISE_ID),
+            severities: Severities::parse(&config.events)?,
+            siem,
+        })
+    }
+
+    /// `event` as an RFC 5424 message, or a CEF or LEEF line in an RFC
+    /// 5424 header
+    fn message(&self, event: &EventData) -> String {
+        let notification = Notification::from_event(event);
+        let severity = self.severities.get(event, &notification.event);
+        let priority = self.facility * 8 + severity.map_or(Severity::Info.syslog_level(), Severity::syslog_level);
+        let timestamp = chrono::DateTime::parse_from_rfc3339(&event.timestamp)
+            .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+            .unwrap_or_else(|_| "-".to_string());
+
+        let (data, text) = match self.siem {
+            Some(ref siem) => ("-".to_string(), siem.format(event)),
+            None => {
+                let args = notification
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let text = match args.is_empty() {
+                    true => notification.title.clone(),
+                    false => format!("{}: {}", notification.title, args),
+                };
+                (structured_data(event, &notification, severity, self.enterprise_id), text)
+            }
+        };
+
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            priority,
+            timestamp,
+            self.hostname,
+            self.app_name,
+            std::process::id(),
+            header_field(&notification.event, MAX_NAME_LEN),
+            data,
+            text,
+        )
+    }
+}
+
+#[async_trait]
+impl Sink for SyslogSink {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let message = self.message(event);
+        match self.transport {
+            Transport::Udp(ref socket) => {
+                let mut end = message.len().min(MAX_DATAGRAM);
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                socket.send(&message.as_bytes()[..end]).await.context("Failed to send to the syslog collector")?;
+                Ok(())
+            }
+            Transport::Stream(ref mut stream) => {
+                stream.queue(message);
+                stream.write_pending().await
+            }
+        }
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        match self.transport {
+            Transport::Udp(_) => Ok(()),
+            Transport::Stream(ref mut stream) => stream.write_pending().await,
+        }
+    }
+}
+
+impl Stream {
+    async fn connect(&mut self) -> Result<()> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("Failed to connect to syslog collector {}:{}", self.host, self.port))?;
+        tcp.set_nodelay(true)?;
+        #[cfg(feature = "rustls")]
+        if let Some(ref connector) = self.tls {
+            let name = rustls::pki_types::ServerName::try_from(self.host.clone())
+                .with_context(|| format!("Invalid syslog collector name: {}", self.host))?;
+            let tls = connector
+                .connect(name, tcp)
+                .await
+                .with_context(|| format!("TLS handshake with syslog collector {}:{} failed", self.host, self.port))?;
+            self.connection = Some(Box::new(tls));
+            return Ok(());
+        }
+        self.connection = Some(Box::new(tcp));
+        Ok(())
+    }
+
+    /// Frame `message` and queue it, dropping the oldest beyond
+    /// [`MAX_PENDING`]
+    fn queue(&mut self, message: String) {
+        let frame = match self.octet_counting {
+            true => format!("{} {}", message.len(), message),
+            false => format!("{}\n", message.replace('\n', " ")),
+        };
+        self.pending.push_back(frame.into_bytes());
+        if self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+            warn!("syslog sink: collector unreachable, dropping the oldest queued message");
+        }
+    }
+
+    /// Write the queued messages, reconnecting first if the connection was
+    /// lost; those that couldn't be written stay queued
+    async fn write_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        if self.connection.is_none() {
+            self.connect().await?;
+        }
+        let Some(ref mut connection) = self.connection else {
+            return Ok(());
+        };
+        while let Some(frame) = self.pending.front() {
+            let written = async {
+                connection.write_all(frame).await?;
+                connection.flush().await
+            };
+            if let Err(e) = written.await {
+                self.connection = None;
+                return Err(e).with_context(|| {
+                    format!("Failed to write to syslog collector {}:{}; {} message(s) queued", self.host, self.port, self.pending.len())
+                });
+            }
+            self.pending.pop_front();
+        }
+        Ok(())
+    }
+}
+
+/// Code of the facility `name`
+fn facility(name: &str) -> Result<u8> {
+    const FACILITIES: [&str; 16] = [
+        "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron", "authpriv", "ftp", "ntp",
+        "audit", "alert", "clock",
+    ];
+    let name = name.trim().to_lowercase();
+    if let Some(code) = FACILITIES.iter().position(|&facility| facility == name) {
+        return Ok(code as u8);
+    }
+    match name.strip_prefix("local").and_then(|n| n.parse::<u8>().ok()) {
+        Some(n) if n <= 7 => Ok(16 + n),
+        _ => anyhow::bail!("Unknown syslog facility: {} (expected e.g. user, daemon, or local0 to local7)", name),
+    }
+}
+
+/// The host's name, if known
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
+
+/// `value` as a header field or SD-NAME: printable ASCII without spaces,
+/// at most `max` characters, `-` if nothing is left
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+        .take(max)
+        .collect();
+    match field.is_empty() {
+        true => "-".to_string(),
+        false => field,
+    }
+}
+
+/// The SD-ELEMENTs of `event`: `chain`, `contract`, `event`, and the
+/// decoded arguments as `args`
+fn structured_data(event: &EventData, notification: &Notification, severity: Option<Severity>, enterprise_id: u32) -> String {
+    let chain = [
+        ("id", event.chain_id.map(|id| id.to_string())),
+        ("name", Some(event.chain_name.clone())),
+    ];
+    let contract = [
+        ("address", Some(event.contract_address.to_lowercase())),
+        ("name", notification.contract_name.clone()),
+    ];
+    let details = [
+        ("name", Some(notification.event.clone())),
+        ("signature", event.event_signature.clone()),
+        ("topic0", event.topics.first().cloned()),
+        ("severity", severity.map(|severity| severity.as_str().to_string())),
+        ("block", Some(event.block_number.to_string())),
+        ("blockHash", event.block_hash.clone()),
+        ("blockTime", event.block_timestamp.clone()),
+        ("tx", Some(event.transaction_hash.clone())),
+        ("logIndex", Some(event.log_index.to_string())),
+        ("txFrom", notification.tx_from.clone()),
+        ("txTo", notification.tx_to.clone()),
+        ("url", notification.transaction_url.clone()),
+    ];
+    let args: Vec<(String, Option<String>)> = event
+        .decoded
+        .iter()
+        .flatten()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (header_field(name, MAX_NAME_LEN), Some(value))
+        })
+        .collect();
+
+    let mut data = element("chain", enterprise_id, chain.iter().map(|(name, value)| (*name, value)));
+    data += &element("contract", enterprise_id, contract.iter().map(|(name, value)| (*name, value)));
+    data += &element("event", enterprise_id, details.iter().map(|(name, value)| (*name, value)));
+    if !args.is_empty() {
+        data += &element("args", enterprise_id, args.iter().map(|(name, value)| (name.as_str(), value)));
+    }
+    data
+}
+
+/// `[id@enterprise_id name="value" ...]`, without the params that have no
+/// value
+fn element<'a>(id: &str, enterprise_id: u32, params: impl Iterator<Item = (&'a str, &'a Option<String>)>) -> String {
+    let mut element = format!("[{}@{}", id, enterprise_id);
+    for (name, value) in params {
+        if let Some(value) = value {
+            element += &format!(" {}=\"{}\"", name, escape(value));
+        }
+    }
+    element.push(']');
+    element
+}
+
+/// `value` as a PARAM-VALUE: `"`, `\`, and `]` escaped
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,260 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use super::Sink;
+use crate::config::TelegramConfig;
+use crate::event::EventData;
+use crate::notification::{truncate, Notification};
+use crate::render::EventTemplate;
+
+const DEFAULT_API_URL: &str = "https://api.telegram.org";
+
+/// Messages per event name and minute (default for `rate_limit`)
+const DEFAULT_RATE_LIMIT: usize = 10;
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Messages waiting to be sent; the oldest are dropped beyond this
+const MAX_PENDING: usize = 1_000;
+
+/// Wait after a 429 without a usable `retry_after`
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Longest message text Telegram accepts
+const MAX_MESSAGE_LEN: usize = 4096;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends events as messages from a Telegram bot to a chat.
+///
+/// Messages are rendered from a template in Telegram's HTML formatting, or
+/// a template file (see [`crate::render`]), and
+/// sent from [`tick`](Sink::tick), one per tick, which stays within
+/// Telegram's per-chat limits; a 429 pauses sending for as long as the
+/// response asks. Each event name gets at most `rate_limit` messages per
+/// minute, so a burst of one event can't drown out another: events beyond
+/// that are counted, and the count is reported once the event's limit
+/// allows another message.
+pub struct TelegramSink {
+    client: reqwest::Client,
+    /// `sendMessage` endpoint; holds the bot token
+    url: String,
+    chat_id: String,
+    template: Option<String>,
+    template_file: Option<EventTemplate>,
+    rate_limit: usize,
+    pending: VecDeque<String>,
+    /// Times of the messages of the last minute, by event name
+    sent: HashMap<String, VecDeque<Instant>>,
+    /// Events left out by the rate limit, by event name
+    suppressed: HashMap<String, u64>,
+    /// Set while rate-limited by Telegram
+    paused_until: Option<Instant>,
+}
+
+impl TelegramSink {
+    pub fn connect(config: &TelegramConfig) -> Result<Self> {
+        if config.token.trim().is_empty() {
+            anyhow::bail!("Telegram bot token is empty");
+        }
+        if config.chat_id.trim().is_empty() {
+            anyhow::bail!("Telegram chat ID is empty");
+        }
+        if config.template.is_some() && config.template_file.is_some() {
+            anyhow::bail!("Set either a Telegram template or a template file, not both");
+        }
+        let api_url = config.api_url.as_deref().unwrap_or(DEFAULT_API_URL).trim_end_matches('/');
+        let url = format!("{}/bot{}/sendMessage", api_url, config.token.trim());
+        reqwest::Url::parse(&url).context("Invalid Telegram API URL")?;
+
+        Ok(Self {
+            client: crate::tls::configure(reqwest::Client::builder().timeout(TIMEOUT), config.tls.as_ref())
+                .context("Invalid Telegram TLS settings")?
+                .build()?,
+            url,
+            chat_id: config.chat_id.trim().to_string(),
+            template: config.template.clone(),
+            template_file: config.template_file.as_deref().map(EventTemplate::load).transpose()?,
+            rate_limit: config.rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+            pending: VecDeque::new(),
+            sent: HashMap::new(),
+            suppressed: HashMap::new(),
+            paused_until: None,
+        })
+    }
+
+    /// Whether the rate limit allows another message for `event` now, in
+    /// which case it's counted
+    fn admit(&mut self, event: &str) -> bool {
+        let now = Instant::now();
+        let sent = self.sent.entry(event.to_string()).or_default();
+        while sent.front().is_some_and(|time| now.duration_since(*time) >= RATE_WINDOW) {
+            sent.pop_front();
+        }
+        if self.rate_limit > 0 && sent.len() >= self.rate_limit {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    fn queue(&mut self, text: String) -> Result<()> {
+        self.pending.push_back(truncate(&text, MAX_MESSAGE_LEN));
+        if self.pending.len() > MAX_PENDING {
+            let dropped = self.pending.len() - MAX_PENDING;
+            self.pending.drain(..dropped);
+            anyhow::bail!("too many messages waiting to be sent, dropped {} oldest", dropped);
+        }
+        Ok(())
+    }
+
+    /// Queue a note for every event name whose suppressed events haven't
+    /// been reported yet and whose limit allows a message again
+    fn report_suppressed(&mut self) -> Result<()> {
+        let events: Vec<String> = self.suppressed.keys().cloned().collect();
+        for event in events {
+            if self.admit(&event) {
+                let count = self.suppressed.remove(&event).unwrap_or_default();
+                self.queue(format!(
+                    "<i>{} more {} event(s) in the last minute were not sent (rate limit of {} per minute)</i>",
+                    count,
+                    escape(&event),
+                    self.rate_limit
+                ))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the oldest pending message. Rejected messages are dropped;
+    /// others stay pending.
+    async fn send_next(&mut self) -> Result<()> {
+        if self.paused_until.is_some_and(|until| Instant::now() < until) {
+            return Ok(());
+        }
+        self.paused_until = None;
+        let Some(text) = self.pending.front() else {
+            return Ok(());
+        };
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": text,
+                "parse_mode": "HTML",
+                "disable_web_page_preview": true,
+            }))
+            .send()
+            .await
+            // The URL holds the bot token
+            .map_err(|e| e.without_url())
+            .context("Failed to send message")?;
+
+        let status = response.status();
+        if status.is_success() {
+            self.pending.pop_front();
+            return Ok(());
+        }
+
+        let body: Value = response.json().await.unwrap_or_default();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = body["parameters"]["retry_after"]
+                .as_f64()
+                .map(Duration::from_secs_f64)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.paused_until = Some(Instant::now() + retry_after);
+            anyhow::bail!("rate limit hit, pausing for {:?}", retry_after);
+        }
+        let description = body["description"].as_str().unwrap_or_default();
+        if status.is_client_error() {
+            self.pending.pop_front();
+            anyhow::bail!("message rejected: {} {}", status, description);
+        }
+        anyhow::bail!("Telegram returned {} {}, will retry", status, description)
+    }
+}
+
+#[async_trait]
+impl Sink for TelegramSink {
+    fn name(&self) -> &str {
+        "Telegram"
+    }
+
+    async fn send(&mut self, event: &EventData) -> Result<()> {
+        let notification = Notification::from_event(event);
+        if !self.admit(&notification.event) {
+            *self.suppressed.entry(notification.event).or_default() += 1;
+            return Ok(());
+        }
+
+        let mut text = match (&self.template_file, &self.template) {
+            (Some(template), _) => template.render(event).unwrap_or_else(|e| {
+                warn!("{:#}; sending the default message", e);
+                default_message(&notification)
+            }),
+            (None, Some(template)) => notification.render(template, escape),
+            (None, None) => default_message(&notification),
+        };
+        if let Some(count) = self.suppressed.remove(&notification.event) {
+            text.push_str(&format!(
+                "\n<i>{} more {} event(s) were not sent (rate limit of {} per minute)</i>",
+                count,
+                escape(&notification.event),
+                self.rate_limit
+            ));
+        }
+        self.queue(text)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        self.report_suppressed()?;
+        while !self.pending.is_empty() {
+            if self.paused_until.is_some_and(|until| Instant::now() < until) {
+                anyhow::bail!("{} message(s) still waiting for the rate limit", self.pending.len());
+            }
+            self.send_next().await?;
+        }
+        Ok(())
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        self.report_suppressed()?;
+        self.send_next().await
+    }
+}
+
+/// The default template, with the fields in code style and explorer links
+fn default_message(notification: &Notification) -> String {
+    let mut text = format!("<b>{}</b>", escape(&notification.title));
+    for (name, value) in &notification.fields {
+        text.push_str(&format!("\n<b>{}</b>: <code>{}</code>", escape(name), escape(value)));
+    }
+
+    let link = |label: String, url: &Option<String>| match url {
+        Some(url) => format!("<a href=\"{}\">{}</a>", escape(url), escape(&label)),
+        None => escape(&label),
+    };
+    let short = |hex: &str| hex.get(..10).unwrap_or(hex).to_string();
+    text.push_str(&format!(
+        "\n{} · {} · {}",
+        link(format!("Tx {}", short(&notification.transaction_hash)), &notification.transaction_url),
+        link(format!("Block {}", notification.block_number), &notification.block_url),
+        link(
+            format!("Contract {}", notification.contract_name.clone().unwrap_or_else(|| short(&notification.contract))),
+            &notification.contract_url,
+        ),
+    ));
+    text
+}
+
+/// Escape the characters Telegram's HTML formatting treats as markup
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
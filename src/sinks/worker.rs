@@ -0,0 +1,176 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tracing::{info_span, warn, Instrument};
+
+use super::{Sink, SinkKind};
+use crate::config::SinkConfig;
+use crate::health;
+use crate::event::EventData;
+use crate::run::RunMetadata;
+use crate::supervisor::{supervise, RestartPolicy, SupervisorMetrics};
+
+/// Commands queued for a sink before it applies back-pressure
+const QUEUE_SIZE: usize = 1024;
+
+/// Buffering sinks are ticked this often
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+enum Command {
+    StartRun(RunMetadata),
+    Send(Box<EventData>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Commands and the current run, kept across restarts of the sink task
+struct Inbox {
+    commands: mpsc::Receiver<Command>,
+    run: Option<RunMetadata>,
+}
+
+/// A [`Sink`] running in its own supervised task.
+///
+/// Events are handed over through a bounded queue, so a slow sink slows the
+/// listener down rather than buffering without limit. When the sink panics
+/// it is reconnected from its config and told the current run again; the
+/// command it was handling is lost, and events it had buffered but not
+/// written yet are lost with it. Queued commands are kept.
+pub struct SinkWorker {
+    kind: SinkKind,
+    name: String,
+    commands: mpsc::Sender<Command>,
+    task: JoinHandle<()>,
+}
+
+impl SinkWorker {
+    /// Run `sink`, a connected sink of `kind`, under `policy`. `run` is the
+    /// run the sink has already been told about, if any.
+    pub fn spawn(
+        kind: SinkKind,
+        sink: Box<dyn Sink>,
+        config: SinkConfig,
+        run: Option<RunMetadata>,
+        policy: RestartPolicy,
+        metrics: SupervisorMetrics,
+    ) -> Self {
+        let name = format!("{} sink", sink.name());
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        let inbox = Arc::new(Mutex::new(Inbox { commands: rx, run }));
+
+        let mut connected = Some(sink);
+        let config = Arc::new(config);
+        let task = supervise(name.clone(), policy, metrics, move |_| {
+            let sink = connected.take();
+            let config = config.clone();
+            let inbox = inbox.clone();
+            async move {
+                let mut inbox = inbox.lock().await;
+                let sink = match sink {
+                    Some(sink) => sink,
+                    None => {
+                        let mut sink = kind.connect(&config).await?;
+                        if let Some(ref run) = inbox.run {
+                            sink.start_run(run).await?;
+                        }
+                        sink
+                    }
+                };
+                serve(sink, &mut inbox).await;
+                Ok(())
+            }
+        });
+
+        Self {
+            kind,
+            name,
+            commands: tx,
+            task,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn kind(&self) -> SinkKind {
+        self.kind
+    }
+
+    /// Record the run whose events follow
+    pub async fn start_run(&self, run: &RunMetadata) {
+        let _ = self.commands.send(Command::StartRun(run.clone())).await;
+    }
+
+    /// Queue `event`, waiting while the queue is full. Events for a sink
+    /// that was left stopped are dropped.
+    pub async fn send(&self, event: &EventData) {
+        let _ = self.commands.send(Command::Send(Box::new(event.clone()))).await;
+    }
+
+    /// Wait until every queued event has been handed to the sink and the
+    /// sink has been flushed
+    pub async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.commands.send(Command::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+impl Drop for SinkWorker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Hand commands to `sink` until the queue is closed. Sink errors are
+/// reported but don't stop the task; buffering sinks retry on flush.
+async fn serve(mut sink: Box<dyn Sink>, inbox: &mut Inbox) {
+    let mut tick_timer = tokio::time::interval(TICK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            command = inbox.commands.recv() => match command {
+                Some(Command::StartRun(run)) => {
+                    if let Err(e) = sink.start_run(&run).await {
+                        warn!("{} sink: {:#}", sink.name(), e);
+                    }
+                    inbox.run = Some(run);
+                }
+                Some(Command::Send(event)) => {
+                    let span = info_span!("sink.deliver", sink = sink.name(), chain = %event.chain_name);
+                    let result = sink.send(&event).instrument(span).await;
+                    health::sink_result(sink.name(), &result);
+                    if let Err(e) = result {
+                        warn!("{} sink: {:#}", sink.name(), e);
+                    }
+                }
+                Some(Command::Flush(done)) => {
+                    flush(sink.as_mut()).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    flush(sink.as_mut()).await;
+                    return;
+                }
+            },
+            _ = tick_timer.tick() => {
+                // Most ticks have nothing to write, so only failures count
+                if let Err(e) = sink.tick().await {
+                    warn!("{} sink flush failed: {:#}", sink.name(), e);
+                    health::sink_failed(sink.name(), &e);
+                }
+            }
+        }
+    }
+}
+
+async fn flush(sink: &mut dyn Sink) {
+    let span = info_span!("sink.flush", sink = sink.name());
+    let result = sink.flush().instrument(span).await;
+    health::sink_result(sink.name(), &result);
+    if let Err(e) = result {
+        warn!("{} sink flush failed: {:#}", sink.name(), e);
+    }
+}
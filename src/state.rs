@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Listener state persisted across restarts in `--state-file`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ListenerState {
+    /// Hash of the last record emitted with `--hash-chain`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash_chain_head: Option<String>,
+    /// Where each contract resumes, as checkpointed on shutdown
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cursors: Vec<Cursor>,
+}
+
+/// The block a contract's listeners resume from
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>,
+    /// `None` for the listeners of a chain without a contract address
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contract: Option<Address>,
+    pub block: u64,
+}
+
+/// A [`ListenerState`] bound to the file it is persisted in
+pub struct StateFile {
+    path: PathBuf,
+    pub state: ListenerState,
+}
+
+impl StateFile {
+    /// Load the state file, starting fresh if it doesn't exist yet
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let state = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid state file {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ListenerState::default(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read state file {}", path.display()))
+            }
+        };
+
+        Ok(Self { path, state })
+    }
+
+    /// Write the state atomically (temp file + rename) so a crash never
+    /// leaves a truncated state file behind
+    pub fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(&self.state)?)
+            .with_context(|| format!("Failed to write state file {}", tmp.display()))?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write state file {}", self.path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+//! Supervised background tasks.
+//!
+//! Every listener and sink runs in its own task started with [`supervise`].
+//! When a task panics or fails it is restarted after a backoff, as allowed by
+//! its [`RestartPolicy`], while the rest of the process keeps running. Crashes
+//! and restarts are counted per task in [`SupervisorMetrics`].
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// A task that ran this long before crashing counts as healthy again,
+/// resetting its backoff and consecutive crash count
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// When and how often a crashed task is restarted
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RestartPolicy {
+    /// Consecutive crashes after which the task is left stopped;
+    /// `None` restarts it forever and `Some(0)` never restarts it
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart, doubled after every consecutive crash
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: Some(10),
+            backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Delay before restarting after `crashes` consecutive crashes (0-based)
+    pub fn backoff(&self, crashes: u32) -> Duration {
+        let delay = self
+            .backoff_ms
+            .saturating_mul(2u64.saturating_pow(crashes))
+            .min(self.max_backoff_ms);
+        Duration::from_millis(delay)
+    }
+}
+
+/// Crash and restart counts of one supervised task
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct TaskMetrics {
+    pub panics: u64,
+    /// Times the task returned an error
+    pub failures: u64,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+    /// The task exceeded its restart limit and is stopped
+    pub gave_up: bool,
+}
+
+impl TaskMetrics {
+    pub fn crashes(&self) -> u64 {
+        self.panics + self.failures
+    }
+}
+
+/// Crash counters of every supervised task by name, shared by all clones
+#[derive(Debug, Clone, Default)]
+pub struct SupervisorMetrics {
+    tasks: Arc<Mutex<BTreeMap<String, TaskMetrics>>>,
+}
+
+impl SupervisorMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current counters of every task started so far
+    pub fn snapshot(&self) -> BTreeMap<String, TaskMetrics> {
+        self.lock().clone()
+    }
+
+    fn update(&self, task: &str, update: impl FnOnce(&mut TaskMetrics)) {
+        update(self.lock().entry(task.to_string()).or_default());
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, TaskMetrics>> {
+        // The map only holds plain counters, so it is still usable after a panic
+        self.tasks.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Run the future returned by `start` as a task named `name`, calling
+/// `start` again with the attempt number (0 for the first run) whenever the
+/// task panics or returns an error, until `policy` gives up.
+///
+/// The supervisor ends when the task returns `Ok`. Aborting the returned
+/// handle also aborts the running attempt.
+pub fn supervise<F, Fut>(
+    name: impl Into<String>,
+    policy: RestartPolicy,
+    metrics: SupervisorMetrics,
+    mut start: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(u32) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    let name = name.into();
+    metrics.update(&name, |_| {});
+
+    tokio::spawn(async move {
+        let mut consecutive = 0;
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            let mut task = AbortOnDrop(tokio::spawn(start(attempt)));
+
+            let reason = match (&mut task.0).await {
+                Ok(Ok(())) => return,
+                Ok(Err(e)) => {
+                    let reason = format!("failed: {:#}", e);
+                    metrics.update(&name, |m| {
+                        m.failures += 1;
+                        m.last_error = Some(reason.clone());
+                    });
+                    reason
+                }
+                Err(e) if e.is_panic() => {
+                    let reason = format!("panicked: {}", panic_message(e.into_panic()));
+                    metrics.update(&name, |m| {
+                        m.panics += 1;
+                        m.last_error = Some(reason.clone());
+                    });
+                    reason
+                }
+                // Cancelled by the runtime shutting down
+                Err(_) => return,
+            };
+
+            if started.elapsed() >= STABLE_AFTER {
+                consecutive = 0;
+            }
+            if policy.max_restarts.is_some_and(|max| consecutive >= max) {
+                warn!("{} {}; restart limit reached, leaving it stopped", name, reason);
+                metrics.update(&name, |m| m.gave_up = true);
+                return;
+            }
+
+            let delay = policy.backoff(consecutive);
+            warn!("{} {}; restarting in {:.1}s", name, reason, delay.as_secs_f64());
+            tokio::time::sleep(delay).await;
+
+            consecutive += 1;
+            attempt += 1;
+            metrics.update(&name, |m| m.restarts += 1);
+        }
+    })
+}
+
+/// Aborts the task when the supervisor itself is aborted
+struct AbortOnDrop(JoinHandle<Result<()>>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
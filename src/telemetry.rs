@@ -0,0 +1,217 @@
+//! OpenTelemetry export of traces and metrics over OTLP (`otel` feature),
+//! configured by the standard `OTEL_*` environment variables.
+//!
+//! Export starts when `OTEL_EXPORTER_OTLP_ENDPOINT`, or the endpoint of one
+//! of the signals, is set and `OTEL_SDK_DISABLED` isn't `true`. Only gRPC is
+//! spoken; headers, timeout, compression, sampler, and resource attributes
+//! are read from their usual variables.
+//!
+//! - spans: `poll` for each range a listener scans, `sink.deliver` and
+//!   `sink.flush` for each hand-off to a sink, and `webhook.deliver` for
+//!   each webhook delivery, retries included
+//! - metrics: those of [`crate::metrics`] under dotted names, e.g.
+//!   `listener.events` and `listener.delivery.latency`
+
+use anyhow::{Context, Result};
+use opentelemetry::metrics::{Histogram, Meter, MeterProvider as _};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithTonicConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use tonic::transport::ClientTlsConfig;
+use tracing::{warn, Subscriber};
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::metrics::{self, LATENCY_BUCKETS};
+
+const ENDPOINT: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+const TRACES_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT";
+const METRICS_ENDPOINT: &str = "OTEL_EXPORTER_OTLP_METRICS_ENDPOINT";
+
+const SERVICE_NAME: &str = "listener";
+
+static LATENCY: OnceLock<Histogram<f64>> = OnceLock::new();
+
+/// Whether the environment asks for OTLP export
+pub fn configured() -> bool {
+    let disabled = std::env::var("OTEL_SDK_DISABLED").is_ok_and(|value| value.trim().eq_ignore_ascii_case("true"));
+    !disabled && [ENDPOINT, TRACES_ENDPOINT, METRICS_ENDPOINT].iter().any(|var| env(var).is_some())
+}
+
+/// The exporters; flushed and shut down when dropped
+pub struct Telemetry {
+    tracer: SdkTracerProvider,
+    meter: SdkMeterProvider,
+}
+
+impl Telemetry {
+    /// Start exporting. Must be called from within the Tokio runtime.
+    pub fn init() -> Result<Self> {
+        let resource = match env("OTEL_SERVICE_NAME") {
+            Some(_) => Resource::builder().build(),
+            None => Resource::builder().with_service_name(SERVICE_NAME).build(),
+        };
+
+        let mut spans = SpanExporter::builder().with_tonic();
+        if let Some(tls) = tls_config(TRACES_ENDPOINT) {
+            spans = spans.with_tls_config(tls);
+        }
+        let tracer = SdkTracerProvider::builder()
+            .with_batch_exporter(spans.build().context("Failed to create the OTLP span exporter")?)
+            .with_resource(resource.clone())
+            .build();
+
+        let mut measurements = MetricExporter::builder().with_tonic();
+        if let Some(tls) = tls_config(METRICS_ENDPOINT) {
+            measurements = measurements.with_tls_config(tls);
+        }
+        let reader = PeriodicReader::builder(measurements.build().context("Failed to create the OTLP metric exporter")?)
+            .build();
+        let meter = SdkMeterProvider::builder().with_reader(reader).with_resource(resource).build();
+
+        metrics::enable();
+        register(&meter.meter(SERVICE_NAME));
+        Ok(Self { tracer, meter })
+    }
+
+    /// A layer sending the spans it sees to the collector
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, SdkTracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer.tracer(SERVICE_NAME))
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Err(e) = self.tracer.shutdown() {
+            warn!("Failed to export the remaining spans: {}", e);
+        }
+        if let Err(e) = self.meter.shutdown() {
+            warn!("Failed to export the remaining metrics: {}", e);
+        }
+    }
+}
+
+/// Observe the delivery latency of an event, in seconds
+pub fn delivery_latency(seconds: f64) {
+    if let Some(histogram) = LATENCY.get() {
+        histogram.record(seconds, &[]);
+    }
+}
+
+/// The instruments, read from [`crate::metrics`] on each export
+fn register(meter: &Meter) {
+    meter
+        .u64_observable_counter("listener.events")
+        .with_description("Events delivered to the outputs")
+        .with_callback(|observer| {
+            for ((chain, contract, event), count) in metrics::samples().events {
+                let attributes = [
+                    KeyValue::new("chain", chain),
+                    KeyValue::new("contract", contract),
+                    KeyValue::new("event", event),
+                ];
+                observer.observe(count, &attributes);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_counter("listener.events.deduplicated")
+        .with_description("Events dropped as already delivered")
+        .with_callback(|observer| {
+            for (chain, count) in metrics::samples().deduplicated {
+                observer.observe(count, &[KeyValue::new("chain", chain)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_gauge("listener.block_lag")
+        .with_description("Blocks between the chain head and the last scanned block")
+        .with_callback(|observer| {
+            for ((chain, contract), lag) in metrics::samples().block_lag {
+                observer.observe(lag, &[KeyValue::new("chain", chain), KeyValue::new("contract", contract)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_gauge("listener.chain_head")
+        .with_description("Latest block seen on the chain")
+        .with_callback(|observer| {
+            for (chain, block) in metrics::samples().chain_head {
+                observer.observe(block, &[KeyValue::new("chain", chain)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_counter("listener.rpc_errors")
+        .with_description("Failed RPC requests, before failover and retries")
+        .with_callback(|observer| {
+            for (endpoint, count) in metrics::samples().rpc_errors {
+                observer.observe(count, &[KeyValue::new("endpoint", endpoint)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_counter("listener.webhook.failures")
+        .with_description("Failed webhook delivery attempts")
+        .with_callback(|observer| observer.observe(metrics::samples().webhook_failures, &[]))
+        .build();
+    meter
+        .u64_observable_counter("listener.webhook.dead_letters")
+        .with_description("Events whose webhook delivery was given up on")
+        .with_callback(|observer| observer.observe(metrics::samples().webhook_dead_letters, &[]))
+        .build();
+    meter
+        .u64_observable_counter("listener.webhook.rate_limited")
+        .with_description("Webhook responses that asked for a pause with Retry-After")
+        .with_callback(|observer| {
+            for (receiver, count) in metrics::samples().webhook_rate_limited {
+                observer.observe(count, &[KeyValue::new("receiver", receiver)]);
+            }
+        })
+        .build();
+    meter
+        .f64_observable_gauge("listener.webhook.backoff")
+        .with_unit("s")
+        .with_description("Seconds left of a webhook receiver's pause")
+        .with_callback(|observer| {
+            for (receiver, seconds) in metrics::samples().webhook_backoff {
+                observer.observe(seconds, &[KeyValue::new("receiver", receiver)]);
+            }
+        })
+        .build();
+    meter
+        .u64_observable_gauge("listener.pipeline.queued")
+        .with_description("Events waiting in front of a processing stage")
+        .with_callback(|observer| {
+            for (stage, queued) in metrics::samples().pipeline_queued {
+                observer.observe(queued, &[KeyValue::new("stage", stage)]);
+            }
+        })
+        .build();
+
+    let latency = meter
+        .f64_histogram("listener.delivery.latency")
+        .with_unit("s")
+        .with_description("Time from detecting an event to handing it to the outputs")
+        .with_boundaries(LATENCY_BUCKETS.to_vec())
+        .build();
+    let _ = LATENCY.set(latency);
+}
+
+/// Verification of the collector's certificate against the web PKI roots,
+/// when the endpoint of the signal is https
+fn tls_config(signal_endpoint: &str) -> Option<ClientTlsConfig> {
+    let endpoint = env(signal_endpoint).or_else(|| env(ENDPOINT))?;
+    endpoint.starts_with("https://").then(|| ClientTlsConfig::new().with_webpki_roots())
+}
+
+fn env(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|value| !value.trim().is_empty())
+}
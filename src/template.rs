@@ -0,0 +1,489 @@
+//! Protocol templates: reusable sets of contracts, event filters, and ABIs
+//! shared as TOML files, e.g. "Uniswap v3 on Arbitrum".
+//!
+//! ```toml
+//! name = "uniswap-v3-arbitrum"
+//! description = "Uniswap v3 factory and position manager"
+//! chain_id = 42161
+//!
+//! [[contracts]]
+//! name = "UniswapV3Factory"
+//! address = "0x1F98431c8aD98523631AE4a59f267346ea31F984"
+//! events = ["PoolCreated(address,address,uint24,int24,address)"]
+//! abi = "abis/UniswapV3Factory.json"
+//! ```
+//!
+//! A config references templates by name (looked up as `<name>.toml` in the
+//! template directory), by path, or by `http(s)` URL. Relative `abi` paths
+//! are resolved against the template's own location, and downloaded when
+//! the template was fetched from a URL.
+//!
+//! Templates can also be added to a local [`Registry`] in the template
+//! directory with `listener template add <url>` and referenced as
+//! `name@version`. Every file is pinned by its SHA-256 when it's added, so a
+//! template that changes upstream or on disk is rejected rather than
+//! silently listened to, and a template signed by its publisher can be
+//! checked against the publisher's Ethereum address. The template lists the
+//! SHA-256 of each ABI under `abi_sha256`, so pinning or signing the
+//! template file covers its ABIs too.
+
+use anyhow::{Context, Result};
+use ethers::types::{Address, Signature};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+use crate::config::{Config, ContractConfig};
+
+/// Directory templates referenced by name are looked up in (default for
+/// `template_dir`)
+pub const DEFAULT_TEMPLATE_DIR: &str = "templates";
+
+/// Index of the templates added to the registry, in the template directory
+pub const REGISTRY_FILE: &str = "registry.toml";
+
+/// Name of a registered template's file in its version directory
+const TEMPLATE_FILE: &str = "template.toml";
+
+/// Longest a template or ABI download may take
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A protocol template as written in its TOML file
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub name: String,
+    /// Required for templates added to the registry, e.g. `1.2.0`
+    pub version: Option<String>,
+    pub description: Option<String>,
+    /// Chain the contracts are deployed on; a reference's `chain_id`
+    /// overrides it for protocols deployed at the same addresses elsewhere
+    pub chain_id: Option<u64>,
+    #[serde(default)]
+    pub contracts: Vec<ContractConfig>,
+    /// Hex SHA-256 of each ABI file, by its `abi` path; required for every
+    /// ABI of a template added with `--sha256` or `--signer`
+    #[serde(default)]
+    pub abi_sha256: BTreeMap<String, String>,
+}
+
+/// Where a template reference points
+enum Source {
+    Url(reqwest::Url),
+    File(PathBuf),
+}
+
+impl Source {
+    fn parse(source: &str, template_dir: &Path) -> Result<Self> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            let url = source.parse().with_context(|| format!("Invalid template URL: {}", source))?;
+            return Ok(Source::Url(url));
+        }
+        // Bare names live in the template directory; anything path-like is a file
+        let is_path = source.contains('/') || source.contains('\\') || source.ends_with(".toml");
+        if is_path {
+            return Ok(Source::File(PathBuf::from(source)));
+        }
+
+        // `name@version`, or a name without a file of its own: the registry
+        let file = template_dir.join(format!("{}.toml", source));
+        let (name, version) = match source.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None if file.exists() => return Ok(Source::File(file)),
+            None => (source, None),
+        };
+        let registry = Registry::open(template_dir)?;
+        let entry = registry.find(name, version).with_context(|| match version {
+            Some(version) => format!("Template {} {} isn't in the registry {}", name, version, registry.path().display()),
+            None => format!("No template {} (neither {} nor in the registry)", name, file.display()),
+        })?;
+        registry.verify(entry)?;
+        Ok(Source::File(registry.template_path(entry)))
+    }
+}
+
+impl Template {
+    /// Load the template `source` names, with the `abi` paths of its
+    /// contracts made loadable from the working directory
+    pub async fn load(source: &str, template_dir: &Path) -> Result<Self> {
+        match Source::parse(source, template_dir)? {
+            Source::File(path) => {
+                let contents = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read template {}", path.display()))?;
+                let mut template: Template = toml::from_str(&contents)
+                    .with_context(|| format!("Invalid template {}", path.display()))?;
+                let dir = path.parent().unwrap_or(Path::new(""));
+                for contract in &mut template.contracts {
+                    if let Some(ref mut abi) = contract.abi {
+                        if Path::new(abi.as_str()).is_relative() {
+                            *abi = dir.join(&*abi).to_string_lossy().into_owned();
+                        }
+                    }
+                }
+                Ok(template)
+            }
+            Source::Url(url) => {
+                let client = http_client()?;
+                let contents = fetch(&client, &url).await?;
+                let mut template: Template =
+                    toml::from_str(&contents).with_context(|| format!("Invalid template {}", url))?;
+                let mut contracts = std::mem::take(&mut template.contracts);
+                for contract in &mut contracts {
+                    if let Some(ref mut abi) = contract.abi {
+                        let abi_url = url.join(abi).with_context(|| format!("Invalid ABI location {} in {}", abi, url))?;
+                        let abi_contents = fetch(&client, &abi_url).await?;
+                        template.check_abi(abi, abi_contents.as_bytes(), false)?;
+                        *abi = save_abi(&abi_url, &abi_contents)?.to_string_lossy().into_owned();
+                    }
+                }
+                template.contracts = contracts;
+                Ok(template)
+            }
+        }
+    }
+
+    /// Check `contents` of the ABI at `abi` against the checksum the
+    /// template lists for it; an ABI it lists no checksum for fails when
+    /// `required`
+    fn check_abi(&self, abi: &str, contents: &[u8], required: bool) -> Result<()> {
+        let actual = sha256_hex(contents);
+        match self.abi_sha256.get(abi) {
+            Some(expected) if expected.trim().trim_start_matches("0x").eq_ignore_ascii_case(&actual) => Ok(()),
+            Some(expected) => anyhow::bail!(
+                "Checksum mismatch for ABI {} of template {}: expected {}, got {}",
+                abi,
+                self.name,
+                expected.trim(),
+                actual
+            ),
+            None if required => anyhow::bail!(
+                "ABI {} of template {} has no checksum under abi_sha256, so the template's checksum or signature doesn't cover it",
+                abi,
+                self.name
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+fn http_client() -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .user_agent(concat!("listener/", env!("CARGO_PKG_VERSION")))
+        .timeout(TIMEOUT)
+        .build()?)
+}
+
+/// Body of a successful GET of `url`
+async fn fetch(client: &reqwest::Client, url: &reqwest::Url) -> Result<String> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if !response.status().is_success() {
+        anyhow::bail!("Failed to fetch {}: HTTP {}", url, response.status());
+    }
+    Ok(response.text().await?)
+}
+
+/// Save `contents`, the ABI downloaded from `url`, into the temporary
+/// directory, named by the hash of its URL, and return its path
+fn save_abi(url: &reqwest::Url, contents: &str) -> Result<PathBuf> {
+    let dir = std::env::temp_dir().join("listener-templates");
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(format!("{}.json", sha256_hex(url.as_str().as_bytes())));
+    std::fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// A template added to a config by [`apply_templates`]
+#[derive(Debug, Clone)]
+pub struct AppliedTemplate {
+    pub name: String,
+    pub version: Option<String>,
+    pub description: Option<String>,
+    pub chain_id: Option<u64>,
+    /// Contracts added; those the config already listed aren't counted
+    pub contracts: usize,
+}
+
+/// Add the contracts of every template `config` references to its chains.
+/// Contracts the config already lists on the same chain keep their own
+/// settings.
+pub async fn apply_templates(config: &mut Config) -> Result<Vec<AppliedTemplate>> {
+    let template_dir = PathBuf::from(config.template_dir.as_deref().unwrap_or(DEFAULT_TEMPLATE_DIR));
+
+    let mut applied = Vec::new();
+    for reference in config.templates.clone() {
+        let template = Template::load(reference.source(), &template_dir)
+            .await
+            .with_context(|| format!("Failed to load template {}", reference.source()))?;
+        let chain_id = reference.chain_id().or(template.chain_id);
+        let chain = config.chain_mut(chain_id);
+
+        let mut added = 0;
+        for mut contract in template.contracts {
+            let configured = |c: &ContractConfig| match (&c.address, &contract.address) {
+                (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+                _ => false,
+            };
+            if chain.contracts.iter().any(configured) {
+                continue;
+            }
+            if contract.webhook_url.is_none() {
+                contract.webhook_url = reference.webhook_url().map(String::from);
+            }
+            chain.contracts.push(contract);
+            added += 1;
+        }
+
+        applied.push(AppliedTemplate {
+            name: template.name,
+            version: template.version,
+            description: template.description,
+            chain_id,
+            contracts: added,
+        });
+    }
+    Ok(applied)
+}
+
+/// A template in the [`Registry`], with the checksums it was pinned to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryEntry {
+    pub name: String,
+    pub version: String,
+    /// URL the template was downloaded from
+    pub source: String,
+    /// Hex SHA-256 of each of the template's files, by path relative to its
+    /// version directory
+    pub files: BTreeMap<String, String>,
+    /// Address whose signature of the template was verified when it was added
+    pub signer: Option<String>,
+    /// When the template was added (RFC 3339)
+    pub added: String,
+}
+
+/// What a template must match to be added
+#[derive(Debug, Clone, Default)]
+pub struct Pin {
+    /// Expected hex SHA-256 of the template file
+    pub sha256: Option<String>,
+    /// Address that must have signed the template (EIP-191, as with
+    /// `cast wallet sign` or `personal_sign`)
+    pub signer: Option<Address>,
+    /// Where the signature is published (default: the template URL with
+    /// `.sig` appended)
+    pub signature_url: Option<String>,
+}
+
+/// Templates downloaded into the template directory, each version in
+/// `<name>/<version>/` next to an index of their checksums
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Registry {
+    #[serde(default)]
+    templates: Vec<RegistryEntry>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl Registry {
+    /// The registry in `dir`; empty if nothing has been added yet
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let path = dir.join(REGISTRY_FILE);
+        let mut registry: Registry = match std::fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).with_context(|| format!("Invalid registry {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Registry::default(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read registry {}", path.display())),
+        };
+        registry.dir = dir;
+        Ok(registry)
+    }
+
+    /// The index file
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(REGISTRY_FILE)
+    }
+
+    pub fn entries(&self) -> &[RegistryEntry] {
+        &self.templates
+    }
+
+    /// `version` of the template `name`, or its highest version
+    pub fn find(&self, name: &str, version: Option<&str>) -> Option<&RegistryEntry> {
+        let versions = self.templates.iter().filter(|entry| entry.name == name);
+        match version {
+            Some(version) => versions.into_iter().find(|entry| entry.version == version.trim_start_matches('v')),
+            None => versions.max_by(|a, b| match (Version::parse(&a.version), Version::parse(&b.version)) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.version.cmp(&b.version),
+            }),
+        }
+    }
+
+    /// The template file of `entry`
+    pub fn template_path(&self, entry: &RegistryEntry) -> PathBuf {
+        self.version_dir(&entry.name, &entry.version).join(TEMPLATE_FILE)
+    }
+
+    fn version_dir(&self, name: &str, version: &str) -> PathBuf {
+        self.dir.join(name).join(version)
+    }
+
+    /// Check that the files of `entry` still have the checksums they were
+    /// added with
+    pub fn verify(&self, entry: &RegistryEntry) -> Result<()> {
+        let dir = self.version_dir(&entry.name, &entry.version);
+        for (file, expected) in &entry.files {
+            let path = dir.join(file);
+            let contents = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            if sha256_hex(&contents) != *expected {
+                anyhow::bail!(
+                    "{} of template {} {} was modified after it was added; remove it and add it again",
+                    path.display(),
+                    entry.name,
+                    entry.version
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Download the template at `url` and its ABIs, check the template
+    /// against `pin` and the ABIs against the checksums the template lists,
+    /// and add them. With a pin, every ABI must have a checksum in the
+    /// template. A version that is already registered is only accepted
+    /// again with the same contents.
+    pub async fn add(&mut self, url: &str, pin: &Pin) -> Result<RegistryEntry> {
+        let client = http_client()?;
+        let url: reqwest::Url = url.parse().with_context(|| format!("Invalid template URL: {}", url))?;
+        let contents = fetch(&client, &url).await?;
+        let checksum = sha256_hex(contents.as_bytes());
+
+        if let Some(ref expected) = pin.sha256 {
+            if !expected.trim().trim_start_matches("0x").eq_ignore_ascii_case(&checksum) {
+                anyhow::bail!("Checksum mismatch for {}: expected {}, got {}", url, expected.trim(), checksum);
+            }
+        }
+        if let Some(signer) = pin.signer {
+            let signature_url = match pin.signature_url {
+                Some(ref signature_url) => signature_url.parse().with_context(|| format!("Invalid signature URL: {}", signature_url))?,
+                None => format!("{}.sig", url).parse()?,
+            };
+            let signature: Signature = fetch(&client, &signature_url)
+                .await?
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid signature at {}", signature_url))?;
+            let recovered = signature
+                .recover(contents.as_bytes())
+                .with_context(|| format!("Invalid signature at {}", signature_url))?;
+            if recovered != signer {
+                anyhow::bail!("{} is signed by {:?}, not by {:?}", url, recovered, signer);
+            }
+        }
+
+        let template: Template = toml::from_str(&contents).with_context(|| format!("Invalid template {}", url))?;
+        let version = template
+            .version
+            .as_deref()
+            .with_context(|| format!("Template {} has no version", template.name))?
+            .trim_start_matches('v')
+            .to_string();
+        for part in [&template.name, &version] {
+            if part.is_empty() || part.contains(['/', '\\', '@']) || part.starts_with('.') {
+                anyhow::bail!("Invalid template name or version: {:?}", part);
+            }
+        }
+
+        // A pinned template vouches for its ABIs only through abi_sha256
+        let pinned = pin.sha256.is_some() || pin.signer.is_some();
+        let mut files = BTreeMap::from([(TEMPLATE_FILE.to_string(), checksum)]);
+        let mut downloads = vec![(PathBuf::from(TEMPLATE_FILE), contents)];
+        for contract in &template.contracts {
+            let Some(ref abi) = contract.abi else { continue };
+            // Kept inside the version directory, where the template resolves it
+            let path = Path::new(abi);
+            if abi.contains("://") || !path.components().all(|c| matches!(c, Component::Normal(_))) {
+                anyhow::bail!("ABI {} of template {} must be a relative path below the template", abi, template.name);
+            }
+            let abi_url = url.join(abi).with_context(|| format!("Invalid ABI location {} in {}", abi, url))?;
+            let abi_contents = fetch(&client, &abi_url).await?;
+            template.check_abi(abi, abi_contents.as_bytes(), pinned)?;
+            files.insert(abi.clone(), sha256_hex(abi_contents.as_bytes()));
+            downloads.push((path.to_path_buf(), abi_contents));
+        }
+
+        if let Some(existing) = self.find(&template.name, Some(&version)) {
+            if existing.files == files {
+                return Ok(existing.clone());
+            }
+            anyhow::bail!(
+                "Template {} {} is already registered with different contents; remove it first to replace it",
+                template.name,
+                version
+            );
+        }
+
+        let dir = self.version_dir(&template.name, &version);
+        for (path, contents) in downloads {
+            let path = dir.join(path);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).with_context(|| format!("Failed to create {}", parent.display()))?;
+            }
+            std::fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+
+        let entry = RegistryEntry {
+            name: template.name,
+            version,
+            source: url.to_string(),
+            files,
+            signer: pin.signer.map(|signer| format!("{:?}", signer)),
+            added: chrono::Utc::now().to_rfc3339(),
+        };
+        self.templates.push(entry.clone());
+        self.save()?;
+        Ok(entry)
+    }
+
+    /// Remove `version` of the template `name` and its files
+    pub fn remove(&mut self, name: &str, version: &str) -> Result<RegistryEntry> {
+        let version = version.trim_start_matches('v');
+        let index = self
+            .templates
+            .iter()
+            .position(|entry| entry.name == name && entry.version == version)
+            .with_context(|| format!("Template {} {} isn't in the registry", name, version))?;
+        let entry = self.templates.remove(index);
+
+        let dir = self.version_dir(&entry.name, &entry.version);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        // Leave no empty directory behind for the last version
+        let _ = std::fs::remove_dir(self.dir.join(&entry.name));
+        self.save()?;
+        Ok(entry)
+    }
+
+    fn save(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.dir).with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let path = self.path();
+        let tmp = path.with_extension("toml.tmp");
+        std::fs::write(&tmp, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
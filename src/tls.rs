@@ -0,0 +1,104 @@
+//! Client certificates (mTLS) and private CAs for the HTTP integrations:
+//! webhooks, Slack/Discord, Telegram, and PagerDuty/Opsgenie; and the
+//! rustls configuration of the syslog, PostgreSQL, and MQTT connections.
+
+use anyhow::Result;
+#[cfg(any(feature = "rustls", feature = "native-tls", feature = "postgres", feature = "mqtt"))]
+use anyhow::Context;
+
+use crate::config::ClientTlsConfig;
+
+/// `builder` presenting the configured client certificate and trusting the
+/// configured CA in addition to the system roots
+pub fn configure(builder: reqwest::ClientBuilder, tls: Option<&ClientTlsConfig>) -> Result<reqwest::ClientBuilder> {
+    let Some(tls) = tls else {
+        return Ok(builder);
+    };
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    {
+        let mut builder = builder;
+        if let Some(ref path) = tls.ca_cert {
+            let certificates = reqwest::Certificate::from_pem_bundle(&read(path)?)
+                .with_context(|| format!("Invalid CA certificate {}", path))?;
+            if certificates.is_empty() {
+                anyhow::bail!("No certificates in {}", path);
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        match (&tls.client_cert, &tls.client_key) {
+            (Some(cert), Some(key)) => builder = builder.identity(identity(cert, key)?),
+            (None, None) => {}
+            _ => anyhow::bail!("A client certificate needs both client_cert and client_key"),
+        }
+        Ok(builder)
+    }
+    #[cfg(not(any(feature = "rustls", feature = "native-tls")))]
+    {
+        let _ = (builder, tls);
+        anyhow::bail!("Client certificates need the rustls or native-tls feature")
+    }
+}
+
+/// The certificate chain and private key, both PEM. With native-tls the key
+/// has to be PKCS#8; rustls also takes RSA and SEC1 (EC) keys.
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+fn identity(cert: &str, key: &str) -> Result<reqwest::Identity> {
+    let (chain, private_key) = (read(cert)?, read(key)?);
+    #[cfg(feature = "native-tls")]
+    let identity = reqwest::Identity::from_pkcs8_pem(&chain, &private_key);
+    #[cfg(not(feature = "native-tls"))]
+    let identity = reqwest::Identity::from_pem(&[private_key, chain].concat());
+    identity.with_context(|| format!("Invalid client certificate {} or key {}", cert, key))
+}
+
+/// rustls client configuration trusting the Mozilla roots and the
+/// configured CA, and presenting the configured client certificate
+#[cfg(any(feature = "rustls", feature = "postgres", feature = "mqtt"))]
+pub fn rustls_client_config(tls: Option<&ClientTlsConfig>) -> Result<rustls::ClientConfig> {
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::PrivateKeyDer;
+
+    let mut roots = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+    };
+    if let Some(path) = tls.and_then(|tls| tls.ca_cert.as_deref()) {
+        let certificates = certificates(path)?;
+        if certificates.is_empty() {
+            anyhow::bail!("No certificates in {}", path);
+        }
+        for certificate in certificates {
+            roots.add(certificate).with_context(|| format!("Invalid CA certificate {}", path))?;
+        }
+    }
+    let builder = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    let client_cert = tls.map_or((None, None), |tls| (tls.client_cert.as_deref(), tls.client_key.as_deref()));
+    Ok(match client_cert {
+        (Some(cert), Some(key)) => {
+            let private_key = PrivateKeyDer::from_pem_slice(&read(key)?)
+                .with_context(|| format!("Invalid client key {}", key))?;
+            builder
+                .with_client_auth_cert(certificates(cert)?, private_key)
+                .with_context(|| format!("Invalid client certificate {} or key {}", cert, key))?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => anyhow::bail!("A client certificate needs both client_cert and client_key"),
+    })
+}
+
+#[cfg(any(feature = "rustls", feature = "postgres", feature = "mqtt"))]
+fn certificates(path: &str) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>> {
+    use rustls::pki_types::pem::PemObject;
+
+    rustls::pki_types::CertificateDer::pem_slice_iter(&read(path)?)
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Invalid certificate {}", path))
+}
+
+#[cfg(any(feature = "rustls", feature = "native-tls", feature = "postgres", feature = "mqtt"))]
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path))
+}
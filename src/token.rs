@@ -0,0 +1,168 @@
+//! Token metadata: the `symbol()`, `name()`, and `decimals()` of the
+//! contract that emitted an event, looked up with `eth_call` once per
+//! contract.
+//!
+//! Contracts that don't implement a function (or revert) just lack that
+//! field, so NFTs get a symbol and name without decimals, and contracts
+//! that aren't tokens get no metadata at all. With decimals known, the
+//! event's `value`, `amount`, or `wad` argument is formatted in `humanized`
+//! as an amount of the token, e.g. `1,500.25 USDC`.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::abi::{ParamType, Token};
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::humanize::format_token_amount;
+use crate::provider::ProviderManager;
+
+/// Selector of `decimals()`
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+/// Selector of `symbol()`
+const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+
+/// Selector of `name()`
+const NAME: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+
+/// Decoded arguments holding an amount of the emitting token
+pub const AMOUNT_ARGUMENTS: [&str; 3] = ["value", "amount", "wad"];
+
+/// What the emitting contract reports about itself as a token
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TokenMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+}
+
+/// Identifies a token: (chain ID, contract)
+type TokenKey = (Option<u64>, Address);
+
+/// Token metadata by contract, looked up on first use
+#[derive(Default)]
+pub struct TokenCache {
+    /// `None` for contracts implementing none of the functions
+    tokens: Mutex<HashMap<TokenKey, Option<TokenMetadata>>>,
+}
+
+impl TokenCache {
+    /// The metadata of `address`, from the cache or looked up through
+    /// `provider`. RPC failures aren't cached, so the lookup is tried again
+    /// on the contract's next event.
+    pub async fn get(&self, provider: &ProviderManager, chain_id: Option<u64>, address: Address) -> Result<Option<TokenMetadata>> {
+        if let Some(token) = lock(&self.tokens).get(&(chain_id, address)) {
+            return Ok(token.clone());
+        }
+
+        let call = |selector: [u8; 4]| provider.call_contract(address, Bytes::from(selector.to_vec()));
+        let decimals = call(DECIMALS).await?.and_then(|data| decode_uint(&data));
+        let symbol = call(SYMBOL).await?.and_then(|data| decode_text(&data));
+        let name = call(NAME).await?.and_then(|data| decode_text(&data));
+
+        let token = TokenMetadata {
+            symbol,
+            name,
+            // Anything larger isn't a real decimals() result
+            decimals: decimals.filter(|decimals| *decimals <= U256::from(77)).map(|decimals| decimals.low_u32() as u8),
+        };
+        let token = (token != TokenMetadata::default()).then_some(token);
+        lock(&self.tokens).insert((chain_id, address), token.clone());
+        Ok(token)
+    }
+}
+
+/// Adds the emitting contract's [`TokenMetadata`] to every event and
+/// formats its token amounts
+pub struct TokenMetadataEnricher {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    cache: Arc<TokenCache>,
+}
+
+impl TokenMetadataEnricher {
+    /// Tokens are looked up through `providers`, by chain ID, and kept in
+    /// `cache`, which other enrichers may share
+    pub fn new(providers: HashMap<Option<u64>, Arc<ProviderManager>>, cache: Arc<TokenCache>) -> Self {
+        Self { providers, cache }
+    }
+}
+
+#[async_trait]
+impl Enricher for TokenMetadataEnricher {
+    fn name(&self) -> &str {
+        "Token metadata"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+        let address: Address = event.contract_address.parse()?;
+        let Some(token) = self.cache.get(provider, event.chain_id, address).await? else {
+            return Ok(());
+        };
+
+        if let Some(decimals) = token.decimals {
+            format_amounts(event, &AMOUNT_ARGUMENTS, decimals, token.symbol.as_deref().unwrap_or_default());
+        }
+        event.token = Some(token);
+        Ok(())
+    }
+}
+
+/// Put the decoded `arguments` of `event` into `humanized` as amounts of a
+/// token with `decimals` decimals
+pub fn format_amounts(event: &mut EventData, arguments: &[&str], decimals: u8, symbol: &str) {
+    let Some(ref decoded) = event.decoded else {
+        return;
+    };
+    let amounts: Vec<(String, String)> = arguments
+        .iter()
+        .filter_map(|name| {
+            let value = U256::from_dec_str(decoded.get(*name)?.as_str()?).ok()?;
+            Some((name.to_string(), format_token_amount(value, decimals as u32, symbol)?))
+        })
+        .collect();
+    if amounts.is_empty() {
+        return;
+    }
+    let humanized = event.humanized.get_or_insert_with(Default::default);
+    for (name, amount) in amounts {
+        humanized.insert(name, amount.into());
+    }
+}
+
+fn decode_uint(data: &[u8]) -> Option<U256> {
+    ethers::abi::decode(&[ParamType::Uint(256)], data)
+        .ok()?
+        .into_iter()
+        .next()?
+        .into_uint()
+}
+
+/// A `symbol()` or `name()` result: an ABI string, or a `bytes32` padded
+/// with zeros as some older tokens (MKR, SAI) return
+fn decode_text(data: &[u8]) -> Option<String> {
+    let text = match ethers::abi::decode(&[ParamType::String], data) {
+        Ok(tokens) => tokens.into_iter().next().and_then(Token::into_string)?,
+        Err(_) if data.len() == 32 => {
+            let end = data.iter().position(|&byte| byte == 0).unwrap_or(32);
+            std::str::from_utf8(&data[..end]).ok()?.to_string()
+        }
+        Err(_) => return None,
+    };
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
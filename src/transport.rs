@@ -0,0 +1,329 @@
+//! The JSON-RPC transport of every [`ProviderManager`](crate::ProviderManager)
+//! endpoint: plain HTTP, optionally capturing the traffic to disk or
+//! replaying it from a previous capture.
+//!
+//! A capture (`--capture-rpc dir/`) appends every request and its response
+//! to `dir/rpc.jsonl`, one JSON object per line, for debugging provider
+//! issues. Endpoint URLs are recorded without credentials: API keys in the
+//! path are masked and the query string and user info are dropped.
+//!
+//! A replay (`--replay-rpc dir/`) answers requests from such a capture
+//! without touching the network, so a run can be repeated deterministically
+//! in tests. Requests are matched on their method and parameters, whichever
+//! endpoint they were sent to; repeated requests get the captured responses
+//! in order, the last one repeating once they run out. A request that was
+//! never captured fails without being retried.
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use ethers::providers::{Http, HttpClientError, JsonRpcClient, JsonRpcError, ProviderError, RpcError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::provider::mask_api_key;
+
+/// Name of the capture file in the capture directory
+pub const CAPTURE_FILE: &str = "rpc.jsonl";
+
+const NOT_CAPTURED: &str = "request not in the RPC capture";
+
+/// Capture or replay mode of the process, set once before connecting
+static TRAFFIC: OnceLock<Traffic> = OnceLock::new();
+
+enum Traffic {
+    Capture(Capture),
+    Replay(Replay),
+}
+
+/// Record all JSON-RPC traffic of this process into `dir`, replacing an
+/// earlier capture there
+pub fn capture_to(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join(CAPTURE_FILE);
+    let file = File::create(&path).with_context(|| format!("Failed to create {}", path.display()))?;
+    install(Traffic::Capture(Capture {
+        file: Mutex::new(file),
+        seq: AtomicU64::new(0),
+    }))
+}
+
+/// Answer all JSON-RPC requests of this process from the capture in `dir`
+pub fn replay_from(dir: &Path) -> Result<usize> {
+    let replay = Replay::load(&dir.join(CAPTURE_FILE))?;
+    let count = replay.len();
+    install(Traffic::Replay(replay))?;
+    Ok(count)
+}
+
+fn install(traffic: Traffic) -> Result<()> {
+    TRAFFIC
+        .set(traffic)
+        .map_err(|_| anyhow::anyhow!("RPC capture or replay is already set up"))
+}
+
+/// One request and its response, as a line of the capture file
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    seq: u64,
+    time: String,
+    endpoint: String,
+    method: String,
+    params: Value,
+    #[serde(flatten)]
+    response: Response,
+    elapsed_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Response {
+    /// The endpoint's result
+    Result(Value),
+    /// A JSON-RPC error from the endpoint
+    Error { code: i64, message: String, data: Option<Value> },
+    /// The request didn't get a JSON-RPC response (connection refused,
+    /// timeout, HTTP error, invalid body, ...)
+    TransportError(String),
+}
+
+struct Capture {
+    file: Mutex<File>,
+    seq: AtomicU64,
+}
+
+impl Capture {
+    fn record(&self, endpoint: &str, method: &str, params: Value, response: Response, started: Instant) {
+        let exchange = Exchange {
+            seq: self.seq.fetch_add(1, Ordering::Relaxed),
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            endpoint: endpoint.to_string(),
+            method: method.to_string(),
+            params,
+            response,
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        };
+        let Ok(mut line) = serde_json::to_string(&exchange) else {
+            return;
+        };
+        line.push('\n');
+        // Capturing is a debugging aid; it never fails the request
+        if let Err(e) = lock(&self.file).write_all(line.as_bytes()) {
+            warn!("Failed to write RPC capture: {}", e);
+        }
+    }
+}
+
+struct Replay {
+    /// Captured responses by method and parameters, in capture order
+    responses: Mutex<HashMap<String, VecDeque<Response>>>,
+}
+
+impl Replay {
+    fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open RPC capture {}", path.display()))?;
+        let mut responses: HashMap<String, VecDeque<Response>> = HashMap::new();
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let exchange: Exchange = serde_json::from_str(&line)
+                .with_context(|| format!("Invalid RPC capture record at {}:{}", path.display(), index + 1))?;
+            responses
+                .entry(request_key(&exchange.method, &exchange.params))
+                .or_default()
+                .push_back(exchange.response);
+        }
+        Ok(Self {
+            responses: Mutex::new(responses),
+        })
+    }
+
+    fn len(&self) -> usize {
+        lock(&self.responses).values().map(VecDeque::len).sum()
+    }
+
+    fn respond(&self, method: &str, params: &Value) -> Result<Response, TransportError> {
+        let mut responses = lock(&self.responses);
+        let queue = responses
+            .get_mut(&request_key(method, params))
+            .ok_or_else(|| TransportError::NotCaptured(format!("{} {}", method, params)))?;
+        let response = match queue.len() {
+            1 => queue[0].clone(),
+            _ => queue.pop_front().expect("captured requests have a response"),
+        };
+        Ok(response)
+    }
+}
+
+fn request_key(method: &str, params: &Value) -> String {
+    format!("{} {}", method, params)
+}
+
+/// HTTP transport that captures or replays its traffic when the process is
+/// set up to
+#[derive(Debug, Clone)]
+pub struct RpcTransport {
+    http: Http,
+    /// The endpoint URL without credentials
+    endpoint: String,
+}
+
+impl RpcTransport {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            http: Http::from_str(url)?,
+            endpoint: sanitize_url(url),
+        })
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcTransport {
+    type Error = TransportError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: fmt::Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let traffic = match TRAFFIC.get() {
+            Some(traffic) => traffic,
+            None => return self.http.request(method, params).await.map_err(TransportError::Http),
+        };
+        let params = serde_json::to_value(&params)
+            .map_err(|err| TransportError::Http(HttpClientError::SerdeJson { err, text: format!("{:?}", params) }))?;
+
+        let response = match traffic {
+            Traffic::Replay(replay) => replay.respond(method, &params)?,
+            Traffic::Capture(capture) => {
+                let started = Instant::now();
+                let response = match self.http.request::<_, Value>(method, &params).await {
+                    Ok(value) => Response::Result(value),
+                    Err(HttpClientError::JsonRpcError(e)) => Response::Error {
+                        code: e.code,
+                        message: e.message,
+                        data: e.data,
+                    },
+                    // reqwest's message has the request URL, API key included
+                    Err(HttpClientError::ReqwestError(e)) => {
+                        let e = e.without_url();
+                        capture.record(&self.endpoint, method, params, Response::TransportError(e.to_string()), started);
+                        return Err(TransportError::Http(HttpClientError::ReqwestError(e)));
+                    }
+                    Err(e) => {
+                        capture.record(&self.endpoint, method, params, Response::TransportError(e.to_string()), started);
+                        return Err(TransportError::Http(e));
+                    }
+                };
+                capture.record(&self.endpoint, method, params, response.clone(), started);
+                response
+            }
+        };
+
+        match response {
+            Response::Result(value) => {
+                let text = value.to_string();
+                serde_json::from_value(value).map_err(|err| TransportError::Http(HttpClientError::SerdeJson { err, text }))
+            }
+            Response::Error { code, message, data } => {
+                Err(TransportError::Http(HttpClientError::JsonRpcError(JsonRpcError { code, message, data })))
+            }
+            Response::TransportError(message) => Err(TransportError::Captured(message)),
+        }
+    }
+}
+
+/// Error of an [`RpcTransport`] request
+#[derive(Debug)]
+pub enum TransportError {
+    /// The HTTP request failed or the endpoint answered with an error
+    Http(HttpClientError),
+    /// A captured request that didn't get a JSON-RPC response, replayed
+    Captured(String),
+    /// A request missing from the capture being replayed
+    NotCaptured(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Http(e) => write!(f, "{}", e),
+            TransportError::Captured(message) => write!(f, "{} (replayed)", message),
+            TransportError::NotCaptured(request) => write!(f, "{}: {}", NOT_CAPTURED, request),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TransportError::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl RpcError for TransportError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            TransportError::Http(e) => e.as_error_response(),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            TransportError::Http(e) => e.as_serde_error(),
+            _ => None,
+        }
+    }
+}
+
+impl From<TransportError> for ProviderError {
+    fn from(error: TransportError) -> Self {
+        match error {
+            TransportError::Http(e) => e.into(),
+            other => ProviderError::JsonRpcClientError(Box::new(other)),
+        }
+    }
+}
+
+/// Whether a request failed for missing from the capture being replayed;
+/// retrying won't change that
+pub fn is_not_captured(error: &ProviderError) -> bool {
+    error.to_string().contains(NOT_CAPTURED)
+}
+
+/// `url` without user info and query string, with an API key in its path
+/// masked
+pub fn sanitize_url(url: &str) -> String {
+    let url = url.split(['?', '#']).next().unwrap_or_default();
+    let url = match url.split_once("://") {
+        Some((scheme, rest)) => match rest.split_once('/') {
+            Some((authority, path)) => format!("{}://{}/{}", scheme, strip_user_info(authority), path),
+            None => format!("{}://{}", scheme, strip_user_info(rest)),
+        },
+        None => url.to_string(),
+    };
+    mask_api_key(&url)
+}
+
+fn strip_user_info(authority: &str) -> &str {
+    authority.rsplit_once('@').map_or(authority, |(_, host)| host)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,120 @@
+//! Transaction context: who sent the transaction that emitted an event, to
+//! where, with how much of the native token, and what it cost, from the
+//! transaction and its receipt.
+//!
+//! Both are fetched once per transaction and kept for recent transactions,
+//! so the logs of one transaction cost one pair of lookups.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use ethers::types::H256;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::enrich::Enricher;
+use crate::event::EventData;
+use crate::provider::ProviderManager;
+
+/// Transactions whose context is kept
+const CACHE_SIZE: usize = 10_000;
+
+/// The transaction that emitted an event
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TransactionContext {
+    /// Sender of the transaction
+    pub from: String,
+    /// Recipient; `None` for contract creations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Native token sent along, in wei
+    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_used: Option<u64>,
+    /// Price paid per unit of gas, in wei
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effective_gas_price: Option<String>,
+}
+
+/// Identifies a transaction: (chain ID, hash)
+type TransactionKey = (Option<u64>, H256);
+
+#[derive(Default)]
+struct Cache {
+    transactions: HashMap<TransactionKey, TransactionContext>,
+    /// Transactions in the order they were added, oldest first
+    order: VecDeque<TransactionKey>,
+}
+
+/// Adds the [`TransactionContext`] of the emitting transaction to every
+/// event
+pub struct TransactionEnricher {
+    providers: HashMap<Option<u64>, Arc<ProviderManager>>,
+    cache: Mutex<Cache>,
+}
+
+impl TransactionEnricher {
+    /// Transactions are looked up through `providers`, by chain ID
+    pub fn new(providers: HashMap<Option<u64>, Arc<ProviderManager>>) -> Self {
+        Self {
+            providers,
+            cache: Mutex::new(Cache::default()),
+        }
+    }
+
+    async fn context(&self, provider: &ProviderManager, chain_id: Option<u64>, hash: H256) -> Result<Option<TransactionContext>> {
+        if let Some(context) = lock(&self.cache).transactions.get(&(chain_id, hash)) {
+            return Ok(Some(context.clone()));
+        }
+        let (transaction, receipt) =
+            tokio::try_join!(provider.get_transaction(hash), provider.get_transaction_receipt(hash))?;
+        let Some(transaction) = transaction else {
+            return Ok(None);
+        };
+
+        let context = TransactionContext {
+            from: format!("{:?}", transaction.from),
+            to: transaction.to.map(|to| format!("{:?}", to)),
+            value: transaction.value.to_string(),
+            gas_used: receipt.as_ref().and_then(|receipt| receipt.gas_used).map(|gas| gas.low_u64()),
+            effective_gas_price: receipt
+                .as_ref()
+                .and_then(|receipt| receipt.effective_gas_price)
+                .map(|price| price.to_string()),
+        };
+
+        let mut cache = lock(&self.cache);
+        if cache.transactions.insert((chain_id, hash), context.clone()).is_none() {
+            cache.order.push_back((chain_id, hash));
+            if cache.order.len() > CACHE_SIZE {
+                if let Some(oldest) = cache.order.pop_front() {
+                    cache.transactions.remove(&oldest);
+                }
+            }
+        }
+        Ok(Some(context))
+    }
+}
+
+#[async_trait]
+impl Enricher for TransactionEnricher {
+    fn name(&self) -> &str {
+        "Transaction"
+    }
+
+    async fn enrich(&self, event: &mut EventData) -> Result<()> {
+        let Some(provider) = self.providers.get(&event.chain_id) else {
+            return Ok(());
+        };
+        let hash: H256 = event.transaction_hash.parse()?;
+        let Some(context) = self.context(provider, event.chain_id, hash).await? else {
+            anyhow::bail!("transaction {} not found", event.transaction_hash);
+        };
+        event.tx = Some(context);
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
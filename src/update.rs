@@ -0,0 +1,318 @@
+//! Release checks and self-update from GitHub releases.
+//!
+//! Release assets are matched to the target triple the binary was built for:
+//! a release for `x86_64-unknown-linux-gnu` carries an asset such as
+//! `listener-v0.2.0-x86_64-unknown-linux-gnu.tar.gz` holding the `listener`
+//! binary, with a `<asset>.sha256` checksum next to it.
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use semver::Version;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+/// Target triple of this build
+pub const TARGET: &str = env!("TARGET");
+
+/// Longest a release lookup or download may take
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Words that mark a line of release notes as worth upgrading for
+const FIX_KEYWORDS: [&str; 4] = ["fix", "security", "vulnerab", "data loss"];
+
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub name: Option<String>,
+    pub body: Option<String>,
+    pub html_url: String,
+    #[serde(default)]
+    pub draft: bool,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Asset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+impl Release {
+    /// Version from the tag, with or without a leading `v`
+    pub fn version(&self) -> Option<Version> {
+        Version::parse(self.tag_name.trim_start_matches('v')).ok()
+    }
+
+    /// Lines of the release notes that mention fixes or security issues
+    pub fn fixes(&self) -> Vec<&str> {
+        self.body
+            .as_deref()
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.trim().trim_start_matches(['-', '*', ' ']))
+            .filter(|line| {
+                let lower = line.to_lowercase();
+                FIX_KEYWORDS.iter().any(|keyword| lower.contains(keyword))
+            })
+            .collect()
+    }
+
+    /// The archive built for this target
+    fn asset(&self) -> Option<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name.contains(TARGET) && asset.name.ends_with(".tar.gz"))
+    }
+
+    fn checksum(&self, asset: &Asset) -> Option<&Asset> {
+        let name = format!("{}.sha256", asset.name);
+        self.assets.iter().find(|candidate| candidate.name == name)
+    }
+}
+
+/// Version of this binary
+pub fn current_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("package version is semver")
+}
+
+/// Releases of a GitHub repository
+pub struct Releases {
+    client: reqwest::Client,
+    api_url: String,
+    /// `owner/name`
+    repo: String,
+}
+
+impl Releases {
+    /// Releases of `repo` (`owner/name`), by default the repository this
+    /// binary was built from. `GITHUB_TOKEN` is used if set, which raises
+    /// GitHub's rate limit, and `GITHUB_API_URL` for GitHub Enterprise.
+    pub fn new(repo: Option<&str>) -> Result<Self> {
+        let repo = match repo {
+            Some(repo) => repo.to_string(),
+            None => env!("CARGO_PKG_REPOSITORY")
+                .trim_start_matches("https://github.com/")
+                .trim_end_matches('/')
+                .to_string(),
+        };
+        if repo.split('/').count() != 2 {
+            anyhow::bail!("Invalid repository {} (expected owner/name)", repo);
+        }
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        if let Ok(token) = std::env::var("GITHUB_TOKEN") {
+            let mut value = reqwest::header::HeaderValue::try_from(format!("Bearer {}", token))
+                .context("Invalid GITHUB_TOKEN")?;
+            value.set_sensitive(true);
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("listener/", env!("CARGO_PKG_VERSION")))
+            .default_headers(headers)
+            .timeout(TIMEOUT)
+            .build()?;
+
+        let api_url = std::env::var("GITHUB_API_URL").unwrap_or_else(|_| "https://api.github.com".to_string());
+        Ok(Self {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+            repo,
+        })
+    }
+
+    /// `owner/name` of the repository
+    pub fn repo(&self) -> &str {
+        &self.repo
+    }
+
+    /// Fail unless the repository exists (and is visible with
+    /// `GITHUB_TOKEN`, if set)
+    pub async fn check_repository(&self) -> Result<()> {
+        let url = format!("{}/repos/{}", self.api_url, self.repo);
+        let response = self
+            .client
+            .get(&url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::NOT_FOUND => anyhow::bail!("Repository {} not found on GitHub", self.repo),
+            status => anyhow::bail!("{} returned {}", url, status),
+        }
+    }
+
+    /// The newest release that isn't a draft or prerelease
+    pub async fn latest(&self) -> Result<Release> {
+        self.get("releases/latest").await
+    }
+
+    /// The release of `version`, tagged with or without a leading `v`
+    pub async fn tagged(&self, version: &str) -> Result<Release> {
+        let version = version.trim_start_matches('v');
+        match self.get(&format!("releases/tags/v{}", version)).await {
+            Ok(release) => Ok(release),
+            Err(_) => self
+                .get(&format!("releases/tags/{}", version))
+                .await
+                .with_context(|| format!("No release {} in {}", version, self.repo)),
+        }
+    }
+
+    /// Published releases newer than `version`, newest first
+    pub async fn newer_than(&self, version: &Version) -> Result<Vec<Release>> {
+        let releases: Vec<Release> = self.get("releases?per_page=100").await?;
+        let mut newer: Vec<(Version, Release)> = releases
+            .into_iter()
+            .filter(|release| !release.draft && !release.prerelease)
+            .filter_map(|release| Some((release.version()?, release)))
+            .filter(|(release_version, _)| release_version > version)
+            .collect();
+        newer.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(newer.into_iter().map(|(_, release)| release).collect())
+    }
+
+    /// Download the archive of `release` for this target, verify its
+    /// checksum, and replace the running binary with the one inside. A
+    /// release without a checksum is refused unless `allow_unverified`.
+    pub async fn install(&self, release: &Release, allow_unverified: bool) -> Result<PathBuf> {
+        let asset = release.asset().with_context(|| {
+            format!("Release {} has no build for {} ({})", release.tag_name, TARGET, release.html_url)
+        })?;
+        if release.checksum(asset).is_none() && !allow_unverified {
+            anyhow::bail!(
+                "Release {} has no checksum for {} ({}.sha256); pass --allow-unverified to install it anyway",
+                release.tag_name,
+                asset.name,
+                asset.name
+            );
+        }
+        let archive = self.download(&asset.browser_download_url).await?;
+
+        match release.checksum(asset) {
+            Some(checksum) => {
+                let expected = String::from_utf8(self.download(&checksum.browser_download_url).await?)
+                    .context("Invalid checksum file")?;
+                let expected = expected.split_whitespace().next().unwrap_or_default().to_lowercase();
+                let actual = hex::encode(Sha256::digest(&archive));
+                if expected != actual {
+                    anyhow::bail!("Checksum mismatch for {}: expected {}, got {}", asset.name, expected, actual);
+                }
+            }
+            None => warn!("Release {} has no checksum for {}; installing it unverified", release.tag_name, asset.name),
+        }
+
+        let exe = std::env::current_exe().context("Failed to locate the running binary")?;
+        let staged = exe.with_extension("update");
+        let installed = extract_binary(&archive, &staged)
+            .with_context(|| format!("Failed to unpack {}", asset.name))
+            .and_then(|()| replace(&staged, &exe));
+        if installed.is_err() {
+            let _ = std::fs::remove_file(&staged);
+        }
+        installed.map(|()| exe)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let url = format!("{}/repos/{}/{}", self.api_url, self.repo, path);
+        let response = self
+            .client
+            .get(&url)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .with_context(|| format!("Failed to reach {}", url))?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            // A missing repository looks like a missing release; tell them apart
+            self.check_repository().await?;
+        }
+        if !response.status().is_success() {
+            anyhow::bail!("{} returned {}", url, response.status());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn download(&self, url: &str) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to download {}", url))?;
+        if !response.status().is_success() {
+            anyhow::bail!("Download of {} returned {}", url, response.status());
+        }
+        Ok(response.bytes().await?.to_vec())
+    }
+}
+
+/// Write the `listener` binary in the gzipped tarball `archive` to `target`
+fn extract_binary(archive: &[u8], target: &Path) -> Result<()> {
+    let name = if cfg!(windows) { "listener.exe" } else { "listener" };
+    let mut tar = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && entry.path()?.file_name() == Some(name.as_ref()) {
+            let mut file = std::fs::File::create(target)?;
+            std::io::copy(&mut entry, &mut file)?;
+            file.sync_all()?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(target, std::fs::Permissions::from_mode(0o755))?;
+            }
+            return Ok(());
+        }
+    }
+    anyhow::bail!("Archive has no {} binary", name)
+}
+
+/// Move `staged` over the running binary `exe`. Windows can't overwrite a
+/// running binary but can rename it, so the old one is moved aside first.
+fn replace(staged: &Path, exe: &Path) -> Result<()> {
+    if cfg!(windows) {
+        let old = exe.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).with_context(|| format!("Failed to move {} aside", exe.display()))?;
+    }
+    std::fs::rename(staged, exe).with_context(|| format!("Failed to replace {}", exe.display()))
+}
+
+/// A notice to print when a newer release than this binary is available,
+/// mentioning the fixes in the releases since
+pub async fn check_for_update() -> Result<Option<String>> {
+    let current = current_version();
+    let newer = Releases::new(None)?.newer_than(&current).await?;
+    let Some(latest) = newer.first() else {
+        return Ok(None);
+    };
+
+    let mut notice = format!(
+        "⬆️  listener {} is available (running v{}",
+        latest.tag_name, current
+    );
+    if newer.len() > 1 {
+        notice.push_str(&format!(", {} releases behind", newer.len()));
+    }
+    notice.push_str(&format!("): {}", latest.html_url));
+
+    let fixes: Vec<String> = newer
+        .iter()
+        .flat_map(|release| release.fixes().into_iter().map(move |fix| format!("{}: {}", release.tag_name, fix)))
+        .collect();
+    for fix in fixes.iter().take(5) {
+        notice.push_str(&format!("\n    {}", fix));
+    }
+    if fixes.len() > 5 {
+        notice.push_str(&format!("\n    ... and {} more fixes", fixes.len() - 5));
+    }
+    notice.push_str("\n   Run `listener self-update` to install it");
+    Ok(Some(notice))
+}
@@ -0,0 +1,557 @@
+//! Webhook delivery through a persistent queue.
+//!
+//! Every delivery is written to the queue directory before it's attempted
+//! and removed once the endpoint accepts it, so events queued during an
+//! outage or before a restart are delivered later instead of lost. The
+//! deliveries of a contract are made one at a time, in the order they were
+//! queued. A failing one is retried with exponential backoff and, after the
+//! last attempt, moved to the dead-letter file so the contract's later
+//! events can go out.
+//!
+//! With batching, consecutive deliveries of a contract to the same URL are
+//! combined into one POST of a JSON array, up to a batch size and waiting a
+//! short while for more events after the first.
+//!
+//! The contracts' deliveries go out concurrently, with at most a configured
+//! number of requests in flight at once. Connections are kept open and
+//! reused per host, up to that number.
+//!
+//! A receiver that answers 429 or 503 with `Retry-After` gets no requests
+//! from any contract until that time has passed, and at least the usual
+//! backoff; up to [`MAX_RATE_LIMITED`] such responses to a delivery don't
+//! use up an attempt.
+//!
+//! With a secret, every payload is signed with HMAC-SHA256 over the exact
+//! request body and the signature sent as `X-Signature: sha256=<hex>`.
+
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinHandle;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::config::WebhookConfig;
+use crate::event::EventData;
+use crate::health;
+use crate::metrics;
+use crate::retry::RetryPolicy;
+use crate::transport::sanitize_url;
+
+pub const DEFAULT_QUEUE_DIR: &str = "webhook-queue";
+
+/// Attempts per delivery before it's dead-lettered
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+
+/// Longest a single delivery attempt may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the queued deliveries may take to go out on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Requests in flight at once across all contracts
+pub const DEFAULT_CONCURRENCY: usize = 16;
+
+/// Longest pause a receiver's `Retry-After` is honored for
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(3600);
+
+/// Rate-limited answers to one delivery that don't use up an attempt; later
+/// ones count like other failures, so a receiver that always answers 429
+/// still dead-letters the delivery
+const MAX_RATE_LIMITED: u32 = 50;
+
+/// Events per batch when only a batch wait is configured
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Wait for more events after the first of a batch when only a batch size
+/// is configured
+pub const DEFAULT_BATCH_WAIT: Duration = Duration::from_millis(200);
+
+/// A queued delivery as stored in the queue directory
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    event: EventData,
+}
+
+/// A delivery that failed on every attempt, as appended to the dead-letter file
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    failed_at: String,
+    url: &'a str,
+    attempts: u32,
+    error: &'a str,
+    event: &'a EventData,
+}
+
+/// A contract's delivery task and the deliveries queued for it
+type Lane = (mpsc::UnboundedSender<Pending>, JoinHandle<()>);
+
+struct Pending {
+    path: PathBuf,
+    delivery: Delivery,
+}
+
+/// Queue of webhook deliveries persisted in a directory
+pub struct WebhookQueue {
+    dir: PathBuf,
+    next_seq: u64,
+    /// One delivery task per contract, keyed by chain ID and address
+    lanes: HashMap<(Option<u64>, String), Lane>,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    client: reqwest::Client,
+    settings: Mutex<Settings>,
+    /// Receivers, by host and port, that asked for a pause, and until when
+    paused: Mutex<HashMap<String, Instant>>,
+}
+
+struct Settings {
+    retry: RetryPolicy,
+    dead_letter: PathBuf,
+    secret: Option<Vec<u8>>,
+    headers: HeaderMap,
+    /// Most events per POST and how long to wait for them; `None` sends
+    /// every event on its own, as an object rather than an array
+    batch: Option<(usize, Duration)>,
+    /// Permits for the requests in flight, one per request
+    in_flight: Arc<Semaphore>,
+    concurrency: usize,
+}
+
+/// Why an attempt failed; client errors other than timeouts and rate limits
+/// won't succeed on a retry
+enum Failure {
+    Transient(String),
+    /// The receiver asked for no requests for a while
+    RateLimited(String, Duration),
+    Permanent(String),
+}
+
+impl WebhookQueue {
+    /// Open the queue directory and resume the deliveries left in it by a
+    /// previous run
+    pub fn open(config: &WebhookConfig) -> Result<Self> {
+        let dir = queue_dir(config);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create webhook queue directory {}", dir.display()))?;
+
+        let settings = Settings::new(config, &dir)?;
+        let builder = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .pool_max_idle_per_host(settings.concurrency);
+        let client = crate::tls::configure(builder, config.tls.as_ref())
+            .context("Invalid webhook TLS settings")?
+            .build()?;
+        let mut queue = Self {
+            shared: Arc::new(Shared {
+                client,
+                settings: Mutex::new(settings),
+                paused: Mutex::new(HashMap::new()),
+            }),
+            dir,
+            next_seq: 0,
+            lanes: HashMap::new(),
+        };
+
+        let queued = queue.queued()?;
+        if !queued.is_empty() {
+            info!("Resuming {} queued webhook deliveries", queued.len());
+        }
+        for (seq, path) in queued {
+            queue.next_seq = seq + 1;
+            let delivery = std::fs::read(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|contents| Ok(serde_json::from_slice(&contents)?));
+            match delivery {
+                Ok(delivery) => queue.route(Pending { path, delivery }),
+                Err(e) => warn!("Skipping unreadable webhook delivery {}: {:#}", path.display(), e),
+            }
+        }
+        Ok(queue)
+    }
+
+    /// Apply changed retry, dead-letter, signing, and concurrency settings
+    /// to queued and future deliveries. The queue directory is only read on
+    /// startup.
+    pub fn reconfigure(&self, config: &WebhookConfig) -> Result<()> {
+        let mut settings = Settings::new(config, &self.dir)?;
+        if queue_dir(config) != self.dir {
+            warn!("A new webhook queue directory takes effect after a restart");
+        }
+        let mut current = self.shared.settings();
+        // Requests in flight keep their permits of the previous limit
+        if settings.concurrency == current.concurrency {
+            settings.in_flight = current.in_flight.clone();
+        }
+        *current = settings;
+        Ok(())
+    }
+
+    /// Persist a delivery of `event` to `url` and queue it behind the
+    /// contract's earlier deliveries
+    pub fn enqueue(&mut self, url: &str, event: &EventData) -> Result<()> {
+        let delivery = Delivery {
+            url: url.to_string(),
+            event: event.clone(),
+        };
+        let path = self.dir.join(format!("{:020}.json", self.next_seq));
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&delivery)?)
+            .and_then(|()| std::fs::rename(&tmp, &path))
+            .with_context(|| format!("Failed to queue webhook delivery {}", path.display()))?;
+        self.next_seq += 1;
+
+        self.route(Pending { path, delivery });
+        Ok(())
+    }
+
+    /// Deliveries in the queue directory, oldest first
+    fn queued(&self) -> Result<Vec<(u64, PathBuf)>> {
+        let mut queued = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let seq = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|seq| seq.parse::<u64>().ok());
+            if let Some(seq) = seq {
+                queued.push((seq, path));
+            }
+        }
+        queued.sort();
+        Ok(queued)
+    }
+
+    fn route(&mut self, pending: Pending) {
+        let event = &pending.delivery.event;
+        let key = (event.chain_id, event.contract_address.to_lowercase());
+        let (lane, _) = self.lanes.entry(key).or_insert_with(|| {
+            let (tx, rx) = mpsc::unbounded_channel();
+            (tx, tokio::spawn(run_lane(self.shared.clone(), rx)))
+        });
+        // Lanes run until the queue is dropped or closed
+        let _ = lane.send(pending);
+    }
+
+    /// Stop taking deliveries and give those queued a few seconds to go
+    /// out. Whatever is still queued then stays in the queue directory for
+    /// the next run.
+    pub async fn close(mut self) {
+        let (senders, lanes): (Vec<_>, Vec<_>) = std::mem::take(&mut self.lanes).into_values().unzip();
+        drop(senders);
+        if tokio::time::timeout(SHUTDOWN_GRACE, futures::future::join_all(lanes)).await.is_err() {
+            let left = self.queued().map_or(0, |queued| queued.len());
+            warn!("{} webhook deliveries left queued for the next run", left);
+        }
+    }
+}
+
+fn queue_dir(config: &WebhookConfig) -> PathBuf {
+    PathBuf::from(config.queue_dir.as_deref().unwrap_or(DEFAULT_QUEUE_DIR))
+}
+
+impl Settings {
+    fn new(config: &WebhookConfig, dir: &Path) -> Result<Self> {
+        let max_attempts = config.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1);
+        let concurrency = config.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1);
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &config.headers {
+            let name = HeaderName::try_from(name.as_str()).with_context(|| format!("Invalid webhook header name {}", name))?;
+            let mut value = HeaderValue::try_from(value.as_str())
+                .with_context(|| format!("Invalid value for webhook header {}", name))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        if config.secret.as_deref() == Some("") {
+            anyhow::bail!("Webhook secret is empty");
+        }
+
+        Ok(Self {
+            retry: RetryPolicy {
+                max_retries: max_attempts - 1,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(300),
+            },
+            dead_letter: config
+                .dead_letter_file
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| dir.join("dead-letter.jsonl")),
+            secret: config.secret.as_ref().map(|secret| secret.as_bytes().to_vec()),
+            headers,
+            batch: match (config.batch_size, config.batch_ms) {
+                (None, None) => None,
+                (size, wait) => Some((
+                    size.unwrap_or(DEFAULT_BATCH_SIZE).max(1),
+                    wait.map(Duration::from_millis).unwrap_or(DEFAULT_BATCH_WAIT),
+                )),
+            },
+            in_flight: Arc::new(Semaphore::new(concurrency)),
+            concurrency,
+        })
+    }
+}
+
+/// `sha256=` followed by the hex HMAC-SHA256 of `body` under `secret`
+pub fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Make a contract's deliveries in order, batched if configured
+async fn run_lane(shared: Arc<Shared>, mut deliveries: mpsc::UnboundedReceiver<Pending>) {
+    // A delivery to another URL that ended the previous batch
+    let mut held = None;
+    loop {
+        let first = match held.take() {
+            Some(pending) => pending,
+            None => match deliveries.recv().await {
+                Some(pending) => pending,
+                None => return,
+            },
+        };
+
+        let batch = shared.settings().batch;
+        let mut pending = vec![first];
+        if let Some((size, wait)) = batch {
+            let deadline = tokio::time::Instant::now() + wait;
+            while pending.len() < size {
+                let next = match deliveries.try_recv() {
+                    Ok(next) => Some(next),
+                    Err(_) => tokio::time::timeout_at(deadline, deliveries.recv()).await.ok().flatten(),
+                };
+                match next {
+                    Some(next) if next.delivery.url == pending[0].delivery.url => pending.push(next),
+                    Some(next) => {
+                        held = Some(next);
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        let span = info_span!("webhook.deliver", url = %sanitize_url(&pending[0].delivery.url), events = pending.len());
+        shared.deliver(&pending, batch.is_some()).instrument(span).await;
+        for pending in &pending {
+            if let Err(e) = std::fs::remove_file(&pending.path) {
+                warn!("Failed to remove delivered webhook {}: {}", pending.path.display(), e);
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn settings(&self) -> MutexGuard<'_, Settings> {
+        self.settings.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Attempt to deliver `pending`, all to the same URL, until it's
+    /// delivered or out of attempts, in which case every event is
+    /// dead-lettered. `batched` sends them as one JSON array.
+    async fn deliver(&self, pending: &[Pending], batched: bool) {
+        let url = &pending[0].delivery.url;
+        let events: Vec<&EventData> = pending.iter().map(|pending| &pending.delivery.event).collect();
+        let body = if batched {
+            serde_json::to_vec(&events)
+        } else {
+            serde_json::to_vec(events[0])
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => return self.dead_letter(url, &events, 0, &e.to_string()),
+        };
+
+        let what = match events.len() {
+            1 => "Webhook delivery".to_string(),
+            n => format!("Webhook delivery of {} events", n),
+        };
+        let mut attempts = 0;
+        let mut rate_limited = 0;
+        let error = loop {
+            attempts += 1;
+            let reason = match self.post(url, body.clone()).await {
+                Ok(()) => return health::sink_delivered("webhook"),
+                Err(Failure::Permanent(reason)) => {
+                    metrics::webhook_failure();
+                    break reason;
+                }
+                Err(Failure::Transient(reason)) => reason,
+                Err(Failure::RateLimited(reason, wait)) => {
+                    // Retried once the pause is over, which a `Retry-After` of
+                    // 0 or a past date doesn't cut below the backoff
+                    rate_limited += 1;
+                    let backoff = self.settings().retry.delay(rate_limited - 1);
+                    self.pause(url, &reason, wait.max(backoff));
+                    if rate_limited <= MAX_RATE_LIMITED {
+                        // Without using up an attempt
+                        metrics::webhook_failure();
+                        attempts -= 1;
+                        continue;
+                    }
+                    reason
+                }
+            };
+            metrics::webhook_failure();
+
+            let retry = self.settings().retry;
+            if attempts > retry.max_retries {
+                break reason;
+            }
+            if attempts == 1 {
+                warn!("{} to {} failed ({}); retrying", what, receiver(url), reason);
+            }
+            tokio::time::sleep(retry.delay(attempts - 1)).await;
+        };
+
+        error!(
+            "{} to {} failed after {} attempt(s) ({}); moved to {}",
+            what,
+            receiver(url),
+            attempts,
+            error,
+            self.settings().dead_letter.display()
+        );
+        health::sink_failed("webhook", &error);
+        self.dead_letter(url, &events, attempts, &error);
+    }
+
+    /// Append a record for each of `events` to the dead-letter file
+    fn dead_letter(&self, url: &str, events: &[&EventData], attempts: u32, error: &str) {
+        metrics::webhook_dead_letters(events.len());
+        let settings = self.settings();
+        let failed_at = chrono::Utc::now().to_rfc3339();
+        for event in events {
+            let record = DeadLetter {
+                failed_at: failed_at.clone(),
+                url,
+                attempts,
+                error,
+                event,
+            };
+            if let Err(e) = append_line(&settings.dead_letter, &record) {
+                warn!("Failed to write dead-letter file {}: {:#}", settings.dead_letter.display(), e);
+                return;
+            }
+        }
+    }
+
+    /// Hold back every request to the receiver of `url` for `wait`
+    fn pause(&self, url: &str, reason: &str, wait: Duration) {
+        let receiver = receiver(url);
+        let until = Instant::now() + wait;
+        let mut paused = self.paused.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Lanes that got the same answer at once only extend the pause
+        let current = paused.get(&receiver).copied();
+        if current.is_some_and(|current| current >= until) {
+            return;
+        }
+        if current.is_none_or(|current| Instant::now() >= current) {
+            warn!("Webhook receiver {} answered {}; pausing its deliveries for {:?}", receiver, reason, wait);
+        }
+        metrics::webhook_rate_limited(&receiver, until);
+        paused.insert(receiver, until);
+    }
+
+    /// Wait for a pause of the receiver of `url` to be over
+    async fn resume(&self, url: &str) {
+        let receiver = receiver(url);
+        loop {
+            let until = {
+                let mut paused = self.paused.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                match paused.get(&receiver) {
+                    Some(&until) if Instant::now() < until => until,
+                    Some(_) => {
+                        paused.remove(&receiver);
+                        return;
+                    }
+                    None => return,
+                }
+            };
+            // Extended pauses are picked up on the next round
+            tokio::time::sleep_until(until.into()).await;
+        }
+    }
+
+    async fn post(&self, url: &str, body: Vec<u8>) -> Result<(), Failure> {
+        self.resume(url).await;
+        let in_flight = self.settings().in_flight.clone();
+        // Held until the response arrives; the semaphore is never closed
+        let _permit = in_flight.acquire_owned().await.ok();
+
+        let mut request = self.client.post(url).header(CONTENT_TYPE, "application/json");
+        {
+            let settings = self.settings();
+            request = request.headers(settings.headers.clone());
+            if let Some(ref secret) = settings.secret {
+                request = request.header("X-Signature", signature(secret, &body));
+            }
+        }
+
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| Failure::Transient(e.without_url().to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if let Some(wait) = retry_after(&response).filter(|_| status.as_u16() == 429 || status.as_u16() == 503) {
+            Err(Failure::RateLimited(status.to_string(), wait))
+        } else if status.is_client_error() && status.as_u16() != 408 && status.as_u16() != 429 {
+            Err(Failure::Permanent(status.to_string()))
+        } else {
+            Err(Failure::Transient(status.to_string()))
+        }
+    }
+}
+
+/// The pause asked for by a response's `Retry-After`, in seconds or as an
+/// HTTP date, up to [`MAX_RETRY_AFTER`]
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    let wait = match value.parse::<f64>() {
+        Ok(seconds) if seconds.is_finite() && seconds >= 0.0 => Duration::from_secs_f64(seconds.min(MAX_RETRY_AFTER.as_secs_f64())),
+        Ok(_) => return None,
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (at.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// Host and port of `url`, which rate limits apply to and logs name it by;
+/// never has credentials
+fn receiver(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            _ => sanitize_url(url.as_str()),
+        },
+        Err(_) => sanitize_url(url),
+    }
+}
+
+fn append_line(path: &Path, record: &impl Serialize) -> Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&line)?;
+    Ok(())
+}
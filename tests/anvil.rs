@@ -0,0 +1,231 @@
+//! End-to-end tests against a local Anvil chain (see `listener::anvil`):
+//! events emitted by the test contract have to come out of the library
+//! stream and out of every sink of a running `listener --anvil`.
+//!
+//! They need Foundry's `anvil` on the `PATH`, or `ANVIL_BIN`, so they are
+//! ignored by default and run with `--ignored`, failing without it. The
+//! Postgres sink is covered too when built with the `postgres` feature and
+//! `LISTENER_TEST_POSTGRES_URL` is set.
+
+use anyhow::{Context, Result};
+use ethers::types::{Address, U256};
+use futures::StreamExt;
+use listener::anvil::{Anvil, TEST_EVENT};
+use listener::EventListener;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[tokio::test]
+#[ignore = "needs anvil (install Foundry, or set ANVIL_BIN)"]
+async fn stream_yields_emitted_events() -> Result<()> {
+    let anvil = Anvil::start().await?;
+    let mut events = EventListener::builder()
+        .rpc_url(anvil.url.clone())
+        .chain_id(anvil.chain_id)
+        .contract(anvil.contract)
+        .event_signature(TEST_EVENT)
+        .start_block(0)
+        .poll_interval(Duration::from_millis(200))
+        .build()?;
+
+    let to = Address::from_low_u64_be(0xbeef);
+    let hash = anvil.emit(to, U256::from(42)).await?;
+    let event = tokio::time::timeout(TIMEOUT, events.next())
+        .await
+        .context("No event within the timeout")?
+        .context("The stream ended")?;
+
+    assert_eq!(event.transaction_hash, format!("{:?}", hash));
+    assert_eq!(event.contract_address.to_lowercase(), format!("{:?}", anvil.contract));
+    assert_eq!(event.chain_id, Some(anvil.chain_id));
+    assert_eq!(event.topics.len(), 3);
+    assert!(event.topics[1].ends_with(&hex::encode(anvil.sender)));
+    assert!(event.topics[2].ends_with(&hex::encode(to)));
+    assert_eq!(U256::from_str_radix(&event.data, 16)?, U256::from(42));
+    Ok(())
+}
+
+/// Delivery through the sinks of a running `listener --anvil`
+#[cfg(all(unix, feature = "sqlite"))]
+mod sinks {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    /// Events the listener has to deliver before it is stopped
+    const EVENTS: usize = 3;
+
+    #[test]
+    #[ignore = "needs anvil (install Foundry, or set ANVIL_BIN)"]
+    fn listener_delivers_through_every_sink() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("listener-anvil-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let webhook = WebhookServer::start()?;
+
+        let mut command = Command::new(env!("CARGO_BIN_EXE_listener"));
+        command
+            .current_dir(&dir)
+            .args(["--anvil", "--anvil-interval", "1", "--output-format", "json"])
+            .args(["--output-file", "events.jsonl", "--sqlite", "events.db"])
+            .args(["--webhook-url", &webhook.url])
+            .env("RUST_LOG", "warn")
+            .stdout(Stdio::null());
+        let postgres = postgres_url();
+        let table = format!("anvil_events_{}", std::process::id());
+        if let Some(ref url) = postgres {
+            command.args(["--postgres-url", url, "--postgres-table", &table]);
+        }
+        let mut listener = command.spawn()?;
+
+        let started = Instant::now();
+        while lines(&dir.join("events.jsonl")).len() < EVENTS {
+            if let Some(status) = listener.try_wait()? {
+                anyhow::bail!("The listener exited early with {}", status);
+            }
+            if started.elapsed() > TIMEOUT {
+                let _ = listener.kill();
+                anyhow::bail!("Fewer than {} events delivered within the timeout", EVENTS);
+            }
+            std::thread::sleep(Duration::from_millis(200));
+        }
+        Command::new("kill").args(["-TERM", &listener.id().to_string()]).status()?;
+        let status = listener.wait()?;
+        assert!(status.success(), "the listener exited with {}", status);
+
+        // The file, with every event decoded as the test event
+        let events: Vec<serde_json::Value> = lines(&dir.join("events.jsonl"))
+            .iter()
+            .map(|line| serde_json::from_str(line))
+            .collect::<Result<_, _>>()?;
+        assert!(events.len() >= EVENTS);
+        for event in &events {
+            assert_eq!(event["event_name"], "Transfer");
+            assert!(event["transaction_hash"].is_string());
+        }
+        let hashes = |events: &[serde_json::Value]| {
+            let mut hashes: Vec<String> = events
+                .iter()
+                .map(|event| event["transaction_hash"].as_str().unwrap_or_default().to_string())
+                .collect();
+            hashes.sort();
+            hashes
+        };
+
+        // SQLite, read back with `listener query`
+        let output = Command::new(env!("CARGO_BIN_EXE_listener"))
+            .current_dir(&dir)
+            .args(["query", "--sqlite", "events.db", "--format", "json"])
+            .output()?;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stored: Vec<serde_json::Value> = String::from_utf8(output.stdout)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(hashes(&stored), hashes(&events));
+
+        // The webhook, which gets one request per event
+        let received = webhook.bodies.lock().unwrap().clone();
+        let delivered: Vec<serde_json::Value> = received
+            .iter()
+            .map(|body| serde_json::from_str(body))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(hashes(&delivered), hashes(&events));
+
+        #[cfg(feature = "postgres")]
+        if let Some(ref url) = postgres {
+            let stored = tokio::runtime::Runtime::new()?.block_on(postgres_hashes(url, &table))?;
+            let mut expected = hashes(&events);
+            expected.dedup();
+            assert_eq!(stored, expected);
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn postgres_url() -> Option<String> {
+        if cfg!(feature = "postgres") {
+            std::env::var("LISTENER_TEST_POSTGRES_URL").ok().filter(|url| !url.is_empty())
+        } else {
+            None
+        }
+    }
+
+    /// The transaction hashes in `table`, sorted; the table is dropped after
+    #[cfg(feature = "postgres")]
+    async fn postgres_hashes(url: &str, table: &str) -> Result<Vec<String>> {
+        let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls).await?;
+        tokio::spawn(connection);
+        let rows = client
+            .query(&format!("SELECT DISTINCT transaction_hash FROM {} ORDER BY 1", table), &[])
+            .await?;
+        client.batch_execute(&format!("DROP TABLE {}", table)).await?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    /// A webhook endpoint that accepts everything and keeps the request bodies
+    struct WebhookServer {
+        url: String,
+        bodies: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl WebhookServer {
+        fn start() -> Result<Self> {
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let url = format!("http://{}/hook", listener.local_addr()?);
+            let bodies = Arc::new(Mutex::new(Vec::new()));
+            let received = bodies.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let received = received.clone();
+                    std::thread::spawn(move || {
+                        let _ = serve(stream, &received);
+                    });
+                }
+            });
+            Ok(Self { url, bodies })
+        }
+    }
+
+    /// Answer the requests of one keep-alive connection with 200s
+    fn serve(stream: std::net::TcpStream, bodies: &Mutex<Vec<String>>) -> std::io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        loop {
+            let mut length = 0;
+            let mut line = String::new();
+            loop {
+                line.clear();
+                if reader.read_line(&mut line)? == 0 {
+                    return Ok(());
+                }
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body)?;
+            bodies.lock().unwrap().push(String::from_utf8_lossy(&body).into_owned());
+            writer.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! The repository release checks and self-update ask GitHub for, derived
+//! from the package's `repository` URL.
+//!
+//! `default_repository_resolves` asks GitHub and is ignored by default; run
+//! it with `cargo test --test update -- --ignored`.
+
+use anyhow::Result;
+use listener::update::Releases;
+
+#[test]
+fn default_repository_is_the_package_repository() -> Result<()> {
+    let releases = Releases::new(None)?;
+    assert_eq!(releases.repo(), "jorgect207/Smart-contract-listener-");
+    assert_eq!(
+        format!("https://github.com/{}", releases.repo()),
+        env!("CARGO_PKG_REPOSITORY").trim_end_matches('/')
+    );
+    Ok(())
+}
+
+#[tokio::test]
+#[ignore = "needs network access to api.github.com"]
+async fn default_repository_resolves() -> Result<()> {
+    Releases::new(None)?.check_repository().await
+}